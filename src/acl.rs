@@ -0,0 +1,689 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::{commands::keys, glob::glob_match};
+
+/// The commands of each ACL category. Subcommands are listed as
+/// `container|subcommand` where they belong to other categories than their
+/// container.
+const CATEGORIES: &[(&str, &[&str])] = &[
+    (
+        "keyspace",
+        &[
+            "copy",
+            "dbsize",
+            "del",
+            "exists",
+            "expire",
+            "expireat",
+            "expiretime",
+            "flushall",
+            "flushdb",
+            "object",
+            "persist",
+            "pexpire",
+            "pexpireat",
+            "pexpiretime",
+            "pttl",
+            "randomkey",
+            "rename",
+            "renamenx",
+            "scan",
+            "ttl",
+            "type",
+            "unlink",
+        ],
+    ),
+    (
+        "read",
+        &[
+            "dbsize",
+            "exists",
+            "expiretime",
+            "get",
+            "getrange",
+            "hget",
+            "hgetall",
+            "hlen",
+            "hscan",
+            "lindex",
+            "llen",
+            "lpos",
+            "lrange",
+            "mget",
+            "object",
+            "pexpiretime",
+            "pttl",
+            "randomkey",
+            "scan",
+            "scard",
+            "sismember",
+            "smembers",
+            "smismember",
+            "srandmember",
+            "sscan",
+            "strlen",
+            "ttl",
+            "type",
+            "xlen",
+            "xrange",
+            "xread",
+            "zcard",
+            "zdiff",
+            "zrandmember",
+            "zrange",
+            "zrank",
+            "zscan",
+            "zscore",
+        ],
+    ),
+    (
+        "write",
+        &[
+            "append",
+            "blpop",
+            "brpop",
+            "copy",
+            "decr",
+            "decrby",
+            "del",
+            "expire",
+            "expireat",
+            "flushall",
+            "flushdb",
+            "function|delete",
+            "function|flush",
+            "function|load",
+            "getdel",
+            "getex",
+            "getset",
+            "hdel",
+            "hset",
+            "incr",
+            "incrby",
+            "incrbyfloat",
+            "linsert",
+            "lpop",
+            "lpush",
+            "lrem",
+            "lset",
+            "ltrim",
+            "mset",
+            "msetnx",
+            "persist",
+            "pexpire",
+            "pexpireat",
+            "psetex",
+            "rename",
+            "renamenx",
+            "rpop",
+            "rpush",
+            "sadd",
+            "set",
+            "setex",
+            "setnx",
+            "setrange",
+            "smove",
+            "spop",
+            "srem",
+            "unlink",
+            "xadd",
+            "xdel",
+            "xtrim",
+            "zadd",
+            "zdiffstore",
+            "zinterstore",
+            "zrem",
+            "zremrangebylex",
+            "zremrangebyrank",
+            "zremrangebyscore",
+            "zunionstore",
+        ],
+    ),
+    (
+        "string",
+        &[
+            "append",
+            "decr",
+            "decrby",
+            "get",
+            "getdel",
+            "getex",
+            "getrange",
+            "getset",
+            "incr",
+            "incrby",
+            "incrbyfloat",
+            "mget",
+            "mset",
+            "msetnx",
+            "psetex",
+            "set",
+            "setex",
+            "setnx",
+            "setrange",
+            "strlen",
+        ],
+    ),
+    (
+        "list",
+        &[
+            "blpop", "brpop", "lindex", "linsert", "llen", "lpop", "lpos", "lpush", "lrange",
+            "lrem", "lset", "ltrim", "rpop", "rpush",
+        ],
+    ),
+    (
+        "hash",
+        &["hdel", "hget", "hgetall", "hlen", "hscan", "hset"],
+    ),
+    (
+        "set",
+        &[
+            "sadd",
+            "scard",
+            "sismember",
+            "smembers",
+            "smismember",
+            "smove",
+            "spop",
+            "srandmember",
+            "srem",
+            "sscan",
+        ],
+    ),
+    (
+        "sortedset",
+        &[
+            "zadd",
+            "zcard",
+            "zdiff",
+            "zdiffstore",
+            "zinterstore",
+            "zrandmember",
+            "zrange",
+            "zrank",
+            "zrem",
+            "zremrangebylex",
+            "zremrangebyrank",
+            "zremrangebyscore",
+            "zscan",
+            "zscore",
+            "zunionstore",
+        ],
+    ),
+    (
+        "stream",
+        &["xadd", "xdel", "xlen", "xrange", "xread", "xtrim"],
+    ),
+    (
+        "pubsub",
+        &[
+            "psubscribe",
+            "publish",
+            "pubsub",
+            "punsubscribe",
+            "subscribe",
+            "unsubscribe",
+        ],
+    ),
+    (
+        "admin",
+        &[
+            "acl|deluser",
+            "acl|getuser",
+            "acl|list",
+            "acl|setuser",
+            "bgrewriteaof",
+            "bgsave",
+            "client|kill",
+            "client|list",
+            "config",
+            "debug",
+            "failover",
+            "lastsave",
+            "latency",
+            "monitor",
+            "psync",
+            "replconf",
+            "replicaof",
+            "role",
+            "save",
+            "shutdown",
+            "slaveof",
+        ],
+    ),
+    (
+        "dangerous",
+        &[
+            "acl|deluser",
+            "acl|getuser",
+            "acl|list",
+            "acl|setuser",
+            "bgrewriteaof",
+            "bgsave",
+            "client|kill",
+            "client|list",
+            "config",
+            "debug",
+            "failover",
+            "flushall",
+            "flushdb",
+            "info",
+            "lastsave",
+            "latency",
+            "monitor",
+            "psync",
+            "replconf",
+            "replicaof",
+            "role",
+            "save",
+            "shutdown",
+            "slaveof",
+        ],
+    ),
+    (
+        "connection",
+        &[
+            "acl|whoami",
+            "auth",
+            "client",
+            "command",
+            "echo",
+            "hello",
+            "ping",
+            "quit",
+            "readonly",
+            "readwrite",
+            "reset",
+        ],
+    ),
+    ("transaction", &["discard", "exec", "multi"]),
+    (
+        "scripting",
+        &["eval", "evalsha", "fcall", "fcall_ro", "function", "script"],
+    ),
+    ("blocking", &["blpop", "brpop", "wait", "xread"]),
+];
+
+fn is_category(category: &str) -> bool {
+    category == "all" || CATEGORIES.iter().any(|(name, _)| *name == category)
+}
+
+/// Whether the command `label`, as in `get` or `client|kill`, is in
+/// `category`, either by itself or through its container.
+fn in_category(category: &str, label: &str) -> bool {
+    let name = label.split('|').next().unwrap_or(label);
+    category == "all"
+        || CATEGORIES
+            .iter()
+            .find(|(category_name, _)| *category_name == category)
+            .is_some_and(|(_, commands)| commands.contains(&label) || commands.contains(&name))
+}
+
+/// Whether the command `label` may modify the keyspace. The `write`
+/// category is all that tells, so that writes are logged and refused on
+/// replicas exactly as ACL rules see them.
+pub fn is_write(label: &str) -> bool {
+    in_category("write", label)
+}
+
+fn hash_password(password: &str) -> String {
+    format!("{:x}", Sha256::digest(password.as_bytes()))
+}
+
+/// A user as `ACL SETUSER` defines it.
+#[derive(Clone, Debug)]
+pub struct User {
+    name: String,
+    enabled: bool,
+    /// Whether any password authenticates the user.
+    nopass: bool,
+    /// SHA-256 hashes of the passwords, in hex, as Redis keeps them.
+    passwords: Vec<String>,
+    /// Rules such as `+@read` or `-get`, the first one always `+@all` or
+    /// `-@all`. The last rule naming a command decides whether the user
+    /// may run it.
+    commands: Vec<String>,
+    keys: Vec<String>,
+    channels: Vec<String>,
+}
+
+impl User {
+    /// A user that is disabled and may do nothing, like users created by
+    /// `ACL SETUSER` before their rules apply.
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: false,
+            nopass: false,
+            passwords: vec![],
+            commands: vec!["-@all".to_string()],
+            keys: vec![],
+            channels: vec![],
+        }
+    }
+
+    /// The `default` user, which may do anything without a password.
+    fn default_user() -> Self {
+        Self {
+            enabled: true,
+            nopass: true,
+            commands: vec!["+@all".to_string()],
+            keys: vec!["*".to_string()],
+            channels: vec!["*".to_string()],
+            ..Self::new("default")
+        }
+    }
+
+    /// Applies one `ACL SETUSER` rule, failing with the reason it is
+    /// invalid.
+    fn apply(&mut self, rule: &str) -> std::result::Result<(), &'static str> {
+        match rule.to_lowercase().as_str() {
+            "on" => self.enabled = true,
+            "off" => self.enabled = false,
+            "nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            }
+            "resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            }
+            "allkeys" => self.keys = vec!["*".to_string()],
+            "resetkeys" => self.keys.clear(),
+            "allchannels" => self.channels = vec!["*".to_string()],
+            "resetchannels" => self.channels.clear(),
+            "allcommands" => self.commands = vec!["+@all".to_string()],
+            "nocommands" => self.commands = vec!["-@all".to_string()],
+            "reset" => *self = Self::new(&self.name),
+            _ => {
+                let Some(kind) = rule.chars().next() else {
+                    return Err("Syntax error");
+                };
+                let value = &rule[kind.len_utf8()..];
+                match kind {
+                    '>' | '#' => {
+                        let hash = if kind == '>' {
+                            hash_password(value)
+                        } else if value.len() == 64
+                            && value
+                                .bytes()
+                                .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+                        {
+                            value.to_string()
+                        } else {
+                            return Err(
+                                "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+                            );
+                        };
+                        if !self.passwords.contains(&hash) {
+                            self.passwords.push(hash);
+                        }
+                        self.nopass = false;
+                    }
+                    '<' | '!' => {
+                        let hash = if kind == '<' {
+                            hash_password(value)
+                        } else {
+                            value.to_string()
+                        };
+                        let count = self.passwords.len();
+                        self.passwords.retain(|password| *password != hash);
+                        if self.passwords.len() == count {
+                            return Err(
+                                "The password you are trying to remove from the user does not exist",
+                            );
+                        }
+                    }
+                    '~' => add_pattern(&mut self.keys, value),
+                    '&' => add_pattern(&mut self.channels, value),
+                    '+' | '-' => {
+                        let target = value.to_lowercase();
+                        let known = match target.strip_prefix('@') {
+                            Some(category) => is_category(category),
+                            None => {
+                                let mut parts = target.splitn(2, '|');
+                                let name = parts.next().unwrap_or_default();
+                                keys::is_command(name)
+                                    && parts.next().is_none_or(|subcommand| !subcommand.is_empty())
+                            }
+                        };
+                        if !known {
+                            return Err("Unknown command or category name in ACL");
+                        }
+                        let rule = format!("{kind}{target}");
+                        if target == "@all" {
+                            self.commands = vec![rule];
+                        } else {
+                            self.commands.push(rule);
+                        }
+                    }
+                    _ => return Err("Syntax error"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn can_run(&self, label: &str) -> bool {
+        let name = label.split('|').next().unwrap_or(label);
+        let mut allowed = false;
+        for rule in &self.commands {
+            let (kind, target) = rule.split_at(1);
+            let matches = match target.strip_prefix('@') {
+                Some(category) => in_category(category, label),
+                None => target == label || target == name,
+            };
+            if matches {
+                allowed = kind == "+";
+            }
+        }
+        allowed
+    }
+
+    fn can_access_key(&self, key: &[u8]) -> bool {
+        self.keys
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), key))
+    }
+
+    fn can_access_channel(&self, channel: &[u8]) -> bool {
+        self.channels
+            .iter()
+            .any(|pattern| glob_match(pattern.as_bytes(), channel))
+    }
+
+    /// Patterns given to `PSUBSCRIBE` must be among the user's patterns,
+    /// rather than match them, unless every channel is allowed.
+    fn can_access_pattern(&self, pattern: &[u8]) -> bool {
+        self.channels
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.as_bytes() == pattern)
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    pub fn commands(&self) -> String {
+        self.commands.join(" ")
+    }
+
+    pub fn keys(&self) -> String {
+        describe_patterns('~', &self.keys)
+    }
+
+    pub fn channels(&self) -> String {
+        describe_patterns('&', &self.channels)
+    }
+
+    /// The user as `ACL LIST` shows it: the rules that would create it.
+    pub fn describe(&self) -> String {
+        let mut rules = vec![format!("user {}", self.name)];
+        rules.extend(self.flags().iter().map(|flag| flag.to_string()));
+        rules.extend(self.passwords.iter().map(|hash| format!("#{hash}")));
+        if !self.keys.is_empty() {
+            rules.push(self.keys());
+        }
+        if self.channels.is_empty() {
+            rules.push("resetchannels".to_string());
+        } else {
+            rules.push(self.channels());
+        }
+        rules.push(self.commands());
+        rules.join(" ")
+    }
+}
+
+/// Adds a key or channel pattern, where `*` stands for all the others.
+fn add_pattern(patterns: &mut Vec<String>, pattern: &str) {
+    if pattern == "*" {
+        *patterns = vec![pattern.to_string()];
+    } else if !patterns
+        .iter()
+        .any(|existing| existing == pattern || existing == "*")
+    {
+        patterns.push(pattern.to_string());
+    }
+}
+
+fn describe_patterns(prefix: char, patterns: &[String]) -> String {
+    patterns
+        .iter()
+        .map(|pattern| format!("{prefix}{pattern}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The users of the server, shared by the connections and the `Db` behind
+/// their own lock like the client registry, so that every command can be
+/// checked without the `Db`.
+#[derive(Debug)]
+pub struct Acl {
+    users: Mutex<BTreeMap<String, User>>,
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        let default_user = User::default_user();
+        Self {
+            users: Mutex::new(BTreeMap::from([(default_user.name.clone(), default_user)])),
+        }
+    }
+}
+
+impl Acl {
+    /// Creates or changes the user `name` with `ACL SETUSER` rules. Either
+    /// every rule applies or, when one is invalid, none does.
+    pub fn set_user(&self, name: &str, rules: &[String]) -> Result<()> {
+        let mut users = self.lock();
+        let mut user = users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        for rule in rules {
+            user.apply(rule)
+                .map_err(|e| anyhow!("ERR Error in ACL SETUSER modifier '{rule}': {e}"))?;
+        }
+        users.insert(name.to_string(), user);
+        Ok(())
+    }
+
+    pub fn get_user(&self, name: &str) -> Option<User> {
+        self.lock().get(name).cloned()
+    }
+
+    /// Removes the users among `names`, returning how many existed.
+    pub fn delete_users(&self, names: &[String]) -> Result<usize> {
+        if names.iter().any(|name| name == "default") {
+            bail!("ERR The 'default' user cannot be removed");
+        }
+        let mut users = self.lock();
+        Ok(names
+            .iter()
+            .filter(|name| users.remove(name.as_str()).is_some())
+            .count())
+    }
+
+    /// Every user, ordered by name.
+    pub fn list(&self) -> Vec<User> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Whether `password` authenticates the enabled user `username`.
+    pub fn authenticate(&self, username: &str, password: &str) -> bool {
+        self.lock().get(username).is_some_and(|user| {
+            user.enabled && (user.nopass || user.passwords.contains(&hash_password(password)))
+        })
+    }
+
+    /// Whether new connections are authenticated as the `default` user
+    /// without a password.
+    pub fn is_default_open(&self) -> bool {
+        self.lock()
+            .get("default")
+            .is_some_and(|user| user.enabled && user.nopass)
+    }
+
+    /// Makes `password` the only one of the `default` user, as setting
+    /// `requirepass` does, or lets it in without any when empty.
+    pub fn set_requirepass(&self, password: &str) {
+        let mut users = self.lock();
+        let user = users
+            .entry("default".to_string())
+            .or_insert_with(User::default_user);
+        user.passwords.clear();
+        user.nopass = password.is_empty();
+        if !password.is_empty() {
+            user.passwords.push(hash_password(password));
+        }
+    }
+
+    /// Checks that `username` may run the command line `argv`, named
+    /// `label` as in `client|kill`, on the keys and channels it names.
+    pub fn check(&self, username: &str, label: &str, argv: &[Bytes]) -> Result<()> {
+        let users = self.lock();
+        let user = users
+            .get(username)
+            .filter(|user| user.can_run(label))
+            .ok_or_else(|| {
+                anyhow!("NOPERM User {username} has no permissions to run the '{label}' command")
+            })?;
+        if let Ok(keys) = keys::command_keys(argv)
+            && !keys.iter().all(|key| user.can_access_key(key))
+        {
+            bail!("NOPERM No permissions to access a key");
+        }
+        let channels_allowed = match label {
+            "publish" => argv
+                .get(1)
+                .is_none_or(|channel| user.can_access_channel(channel)),
+            "subscribe" => argv[1..]
+                .iter()
+                .all(|channel| user.can_access_channel(channel)),
+            "psubscribe" => argv[1..]
+                .iter()
+                .all(|pattern| user.can_access_pattern(pattern)),
+            _ => true,
+        };
+        if !channels_allowed {
+            bail!("NOPERM No permissions to access a channel");
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<String, User>> {
+        self.users.lock().expect("ACL lock poisoned")
+    }
+}
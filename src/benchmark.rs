@@ -0,0 +1,373 @@
+//! A load generator in the manner of `redis-benchmark`, run with
+//! `--benchmark`: clients send the commands of each test, several at a time
+//! when pipelining, as fast as the server answers, and the throughput and
+//! latency percentiles of every test are reported.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::resp::{Codec, Decoded, RespValue};
+
+/// Replaced in the arguments of commands by a random number below the
+/// keyspace length, so that they spread over several keys.
+const RAND_INT: &str = "__rand_int__";
+
+/// The tests run when `-t` does not choose.
+const DEFAULT_TESTS: &[&str] = &[
+    "ping", "set", "get", "incr", "lpush", "rpush", "lpop", "rpop", "sadd", "hset", "zadd", "mset",
+];
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub host: String,
+    pub port: u16,
+    /// The number of connections sending commands concurrently.
+    pub clients: usize,
+    /// The number of commands sent by every test.
+    pub requests: u64,
+    /// The size of the values written, in bytes.
+    pub data_size: usize,
+    /// How many commands a client sends before reading their replies.
+    pub pipeline: usize,
+    /// How many keys `__rand_int__` spreads over, 0 for a single one.
+    pub keyspace: u64,
+    pub tests: Vec<String>,
+    /// A command to run instead of the tests.
+    pub command: Vec<String>,
+    /// Only report the throughput of every test.
+    pub quiet: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 6379,
+            clients: 50,
+            requests: 100_000,
+            data_size: 3,
+            pipeline: 1,
+            keyspace: 0,
+            tests: DEFAULT_TESTS.iter().map(|test| test.to_string()).collect(),
+            command: vec![],
+            quiet: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses `redis-benchmark` style options: `-h <host>`, `-p <port>`,
+    /// `-c <clients>`, `-n <requests>`, `-d <size>`, `-P <pipeline>`,
+    /// `-r <keyspace>`, `-t <test,...>` and `-q`, then optionally the
+    /// command to run.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with('-') {
+                options.command.push(arg);
+                options.command.extend(args.by_ref());
+                break;
+            }
+            if arg == "-q" {
+                options.quiet = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow!("Missing value for option {arg}"))?;
+            let number = || {
+                value
+                    .parse::<u64>()
+                    .map_err(|_| anyhow!("Invalid value '{value}' for option {arg}"))
+            };
+            match arg.as_str() {
+                "-h" => options.host = value.clone(),
+                "-p" => {
+                    options.port = value
+                        .parse()
+                        .map_err(|_| anyhow!("Invalid port '{value}'"))?
+                }
+                "-c" => options.clients = number()?.max(1) as usize,
+                "-n" => options.requests = number()?,
+                "-d" => options.data_size = number()? as usize,
+                "-P" => options.pipeline = number()?.max(1) as usize,
+                "-r" => options.keyspace = number()?,
+                "-t" => {
+                    options.tests = value
+                        .split(',')
+                        .map(|test| test.trim().to_lowercase())
+                        .filter(|test| !test.is_empty())
+                        .collect();
+                    if let Some(test) = options
+                        .tests
+                        .iter()
+                        .find(|test| commands(test, 0).is_none())
+                    {
+                        bail!("Unknown test {test}");
+                    }
+                }
+                _ => bail!("Unknown option {arg}"),
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// The commands a test sends, cycled through by every client.
+fn commands(test: &str, data_size: usize) -> Option<Vec<Vec<String>>> {
+    let data = "x".repeat(data_size);
+    let key = format!("key:{RAND_INT}");
+    let element = format!("element:{RAND_INT}");
+    let command = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect();
+    let commands = match test {
+        "ping" => vec![command(&["PING"])],
+        "set" => vec![command(&["SET", &key, &data])],
+        "get" => vec![command(&["GET", &key])],
+        "incr" => vec![command(&["INCR", &format!("counter:{RAND_INT}")])],
+        "lpush" => vec![command(&["LPUSH", "mylist", &data])],
+        "rpush" => vec![command(&["RPUSH", "mylist", &data])],
+        "lpop" => vec![command(&["LPOP", "mylist"])],
+        "rpop" => vec![command(&["RPOP", "mylist"])],
+        "sadd" => vec![command(&["SADD", "myset", &element])],
+        "hset" => vec![command(&["HSET", "myhash", &element, &data])],
+        "zadd" => vec![command(&["ZADD", "myzset", "0", &element])],
+        "mset" => {
+            let mut args = vec!["MSET".to_string()];
+            for _ in 0..10 {
+                args.extend([key.clone(), data.clone()]);
+            }
+            vec![args]
+        }
+        _ => return None,
+    };
+    Some(commands)
+}
+
+/// What a test measured.
+struct Report {
+    name: String,
+    requests: u64,
+    errors: u64,
+    elapsed: Duration,
+    /// The latency of every request, sorted.
+    latencies: Vec<Duration>,
+}
+
+impl Report {
+    fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64()
+    }
+
+    fn percentile(&self, percentile: f64) -> Duration {
+        let index = ((self.latencies.len() as f64 * percentile / 100.0).ceil() as usize)
+            .clamp(1, self.latencies.len());
+        self.latencies[index - 1]
+    }
+
+    fn print(&self, options: &Options) {
+        if options.quiet {
+            println!(
+                "{}: {:.2} requests per second",
+                self.name,
+                self.throughput()
+            );
+            return;
+        }
+        let msec = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        let average = self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32;
+        println!("====== {} ======", self.name);
+        println!(
+            "  {} requests completed in {:.2} seconds",
+            self.requests,
+            self.elapsed.as_secs_f64()
+        );
+        println!("  {} parallel clients", options.clients);
+        println!("  {} bytes payload", options.data_size);
+        println!("  {} commands per pipeline", options.pipeline);
+        if self.errors > 0 {
+            println!("  {} error replies", self.errors);
+        }
+        println!();
+        println!(
+            "throughput summary: {:.2} requests per second",
+            self.throughput()
+        );
+        println!("latency summary (msec):");
+        println!(
+            "{:>10} {:>9} {:>9} {:>9} {:>9} {:>9} {:>9}",
+            "avg", "min", "p50", "p95", "p99", "p99.9", "max"
+        );
+        println!(
+            "{:>10.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3} {:>9.3}",
+            msec(average),
+            msec(self.latencies[0]),
+            msec(self.percentile(50.0)),
+            msec(self.percentile(95.0)),
+            msec(self.percentile(99.0)),
+            msec(self.percentile(99.9)),
+            msec(*self.latencies.last().expect("at least one request")),
+        );
+        println!();
+    }
+}
+
+/// Runs every test, or the command given, and prints what it measured.
+pub async fn run(options: Options) -> Result<()> {
+    let tests: Vec<(String, Vec<Vec<String>>)> = if options.command.is_empty() {
+        options
+            .tests
+            .iter()
+            .map(|test| {
+                let commands = commands(test, options.data_size).expect("tests were checked");
+                (test.to_uppercase(), commands)
+            })
+            .collect()
+    } else {
+        vec![(options.command.join(" "), vec![options.command.clone()])]
+    };
+    if options.requests == 0 {
+        return Ok(());
+    }
+    for (name, commands) in tests {
+        let report = run_test(&options, name, Arc::new(commands)).await?;
+        report.print(&options);
+    }
+    Ok(())
+}
+
+async fn run_test(
+    options: &Options,
+    name: String,
+    commands: Arc<Vec<Vec<String>>>,
+) -> Result<Report> {
+    let remaining = Arc::new(AtomicU64::new(options.requests));
+    let errors = Arc::new(AtomicU64::new(0));
+    let mut connections = vec![];
+    for _ in 0..options.clients {
+        let stream = TcpStream::connect((options.host.as_str(), options.port))
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Could not connect to {}:{}: {e}",
+                    options.host,
+                    options.port
+                )
+            })?;
+        stream.set_nodelay(true)?;
+        connections.push(stream);
+    }
+    let started = Instant::now();
+    let clients: Vec<_> = connections
+        .into_iter()
+        .map(|stream| {
+            tokio::spawn(run_client(
+                stream,
+                commands.clone(),
+                options.pipeline,
+                options.keyspace,
+                remaining.clone(),
+                errors.clone(),
+            ))
+        })
+        .collect();
+    let mut latencies = vec![];
+    for client in clients {
+        latencies.extend(client.await??);
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+    Ok(Report {
+        name,
+        requests: latencies.len() as u64,
+        errors: errors.load(Ordering::Relaxed),
+        elapsed,
+        latencies,
+    })
+}
+
+/// Sends batches of `pipeline` commands until the test has sent all of its
+/// requests, returning the latency of each: the time from its batch being
+/// sent to its reply.
+async fn run_client(
+    mut stream: TcpStream,
+    commands: Arc<Vec<Vec<String>>>,
+    pipeline: usize,
+    keyspace: u64,
+    remaining: Arc<AtomicU64>,
+    errors: Arc<AtomicU64>,
+) -> Result<Vec<Duration>> {
+    let mut latencies = vec![];
+    let mut buffer = BytesMut::with_capacity(4096);
+    let mut output = vec![];
+    let mut next = 0;
+    loop {
+        let batch = remaining
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                Some(remaining.saturating_sub(pipeline as u64))
+            })
+            .map_or(0, |before| before.min(pipeline as u64));
+        if batch == 0 {
+            return Ok(latencies);
+        }
+        output.clear();
+        for _ in 0..batch {
+            let command = &commands[next % commands.len()];
+            next += 1;
+            output.extend(encode(command, keyspace).serialize());
+        }
+        let sent = Instant::now();
+        stream.write_all(&output).await?;
+        let mut replies = 0;
+        while replies < batch {
+            loop {
+                let reply = match Codec::replies().decode(&mut buffer) {
+                    Decoded::Complete(reply, _) => reply,
+                    Decoded::Incomplete => break,
+                    Decoded::Error(e) => return Err(e.into()),
+                };
+                if matches!(reply, RespValue::SimpleError(_)) {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                }
+                latencies.push(sent.elapsed());
+                replies += 1;
+            }
+            if replies < batch && stream.read_buf(&mut buffer).await? == 0 {
+                bail!("Server closed the connection");
+            }
+        }
+    }
+}
+
+/// Encodes `command` with its `__rand_int__` placeholders replaced.
+fn encode(command: &[String], keyspace: u64) -> RespValue {
+    RespValue::Array(
+        command
+            .iter()
+            .map(|arg| {
+                let arg = if arg.contains(RAND_INT) {
+                    let number = match keyspace {
+                        0 => 0,
+                        keyspace => rand::random_range(0..keyspace),
+                    };
+                    arg.replace(RAND_INT, &format!("{number:012}"))
+                } else {
+                    arg.clone()
+                };
+                RespValue::BulkString(Bytes::from(arg))
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,776 @@
+pub(crate) mod tracking;
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use tokio::{
+    sync::{Notify, RwLock, watch},
+    time::Instant,
+};
+use tracing::{debug, warn};
+
+use self::tracking::{INVALIDATE_CHANNEL, Tracking, TrackingOptions};
+use crate::{
+    commands::keys,
+    config::parse_memory,
+    db::{Db, pubsub::MessageSender},
+    resp::{Protocol, RespValue},
+};
+
+/// How often clients idle for longer than `timeout` are looked for.
+const IDLE_CHECK_PERIOD: Duration = Duration::from_secs(1);
+
+/// The kinds of clients `CLIENT LIST TYPE` filters on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClientType {
+    Normal,
+    Master,
+    Replica,
+    Pubsub,
+}
+
+impl FromStr for ClientType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "normal" => Ok(ClientType::Normal),
+            "master" => Ok(ClientType::Master),
+            "replica" | "slave" => Ok(ClientType::Replica),
+            "pubsub" => Ok(ClientType::Pubsub),
+            _ => Err(anyhow!("ERR Unknown client type '{s}'")),
+        }
+    }
+}
+
+/// How much output may wait for a client that does not read it: closed as
+/// soon as `hard` bytes are pending, or once more than `soft` bytes were
+/// for over `soft_seconds`. 0 disables a limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimit {
+    pub hard: usize,
+    pub soft: usize,
+    pub soft_seconds: u64,
+}
+
+impl OutputBufferLimit {
+    const NONE: OutputBufferLimit = OutputBufferLimit {
+        hard: 0,
+        soft: 0,
+        soft_seconds: 0,
+    };
+
+    /// Whether a client with `pending` bytes of output is over the limit,
+    /// where `soft_since` records when it went over the soft one.
+    fn is_exceeded(&self, pending: usize, soft_since: &mut Option<Instant>, now: Instant) -> bool {
+        if self.hard > 0 && pending >= self.hard {
+            return true;
+        }
+        if self.soft == 0 || pending < self.soft {
+            *soft_since = None;
+            return false;
+        }
+        let since = *soft_since.get_or_insert(now);
+        now.duration_since(since).as_secs() > self.soft_seconds
+    }
+}
+
+/// The `client-output-buffer-limit` of each class of clients: normal ones,
+/// replicas and pub/sub subscribers. Only the last two are limited by
+/// default, as they are sent data they did not ask for one reply at a time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputBufferLimits {
+    pub normal: OutputBufferLimit,
+    pub replica: OutputBufferLimit,
+    pub pubsub: OutputBufferLimit,
+}
+
+impl Default for OutputBufferLimits {
+    fn default() -> Self {
+        Self {
+            normal: OutputBufferLimit::NONE,
+            replica: OutputBufferLimit {
+                hard: 256 * 1024 * 1024,
+                soft: 64 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+            pubsub: OutputBufferLimit {
+                hard: 32 * 1024 * 1024,
+                soft: 8 * 1024 * 1024,
+                soft_seconds: 60,
+            },
+        }
+    }
+}
+
+impl OutputBufferLimits {
+    /// Applies `<class> <hard> <soft> <soft seconds>` groups, as given to
+    /// `CONFIG SET`, to the current limits. `None` if any group is invalid.
+    pub fn update(&self, value: &str) -> Option<Self> {
+        let words: Vec<&str> = value.split_whitespace().collect();
+        if words.is_empty() || !words.len().is_multiple_of(4) {
+            return None;
+        }
+        let mut limits = *self;
+        for group in words.chunks(4) {
+            let limit = OutputBufferLimit {
+                hard: parse_memory(group[1])?,
+                soft: parse_memory(group[2])?,
+                soft_seconds: group[3].parse().ok()?,
+            };
+            match group[0].to_lowercase().as_str() {
+                "normal" => limits.normal = limit,
+                "replica" | "slave" => limits.replica = limit,
+                "pubsub" => limits.pubsub = limit,
+                _ => return None,
+            }
+        }
+        Some(limits)
+    }
+}
+
+impl fmt::Display for OutputBufferLimits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let classes = [
+            ("normal", self.normal),
+            ("slave", self.replica),
+            ("pubsub", self.pubsub),
+        ];
+        for (index, (class, limit)) in classes.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(
+                f,
+                "{class} {} {} {}",
+                limit.hard, limit.soft, limit.soft_seconds
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// A connected client as `CLIENT LIST` shows it. The connection updates it
+/// as it runs commands, without the `Db` lock, and the registry in the
+/// `Db` makes every client reachable from the others.
+#[derive(Debug)]
+pub struct ClientInfo {
+    pub id: u64,
+    pub addr: SocketAddr,
+    pub laddr: SocketAddr,
+    created: Instant,
+    details: Mutex<ClientDetails>,
+    /// Signalled by `CLIENT KILL`, which the connection waits on alongside
+    /// its reads and commands.
+    kill: Notify,
+    /// Set once killed, until the connection is gone from the registry.
+    killed: AtomicBool,
+    /// Bytes of out-of-band frames queued for the client and not yet taken
+    /// by its connection to be written.
+    output: AtomicUsize,
+    output_limits: watch::Receiver<OutputBufferLimits>,
+}
+
+/// The parts of a client that change while it runs.
+#[derive(Debug)]
+pub struct ClientDetails {
+    pub name: String,
+    /// The ACL user the client authenticated as.
+    pub user: String,
+    pub last_active: Instant,
+    /// The last command, with its subcommand as in `client|list`.
+    pub last_command: String,
+    pub channels: usize,
+    pub patterns: usize,
+    /// How many commands are queued, when in a `MULTI` block.
+    pub multi: Option<usize>,
+    pub protocol: Protocol,
+    /// Set once the client asked for the replication stream with `PSYNC`.
+    pub replica: bool,
+    /// Set once the client asked for every command with `MONITOR`.
+    pub monitor: bool,
+    /// Set by `READONLY` in cluster mode, cleared by `READWRITE`.
+    pub readonly: bool,
+    /// Set while `CLIENT TRACKING` is on.
+    pub tracking: bool,
+    /// Set while a command runs, which takes long only when it blocks, as
+    /// `BLPOP` or `WAIT` do.
+    pub in_command: bool,
+    /// When the pending output went over the soft limit, if it still is.
+    soft_limit_since: Option<Instant>,
+}
+
+impl ClientInfo {
+    pub fn new(
+        id: u64,
+        addr: SocketAddr,
+        laddr: SocketAddr,
+        output_limits: watch::Receiver<OutputBufferLimits>,
+    ) -> Self {
+        let now = Instant::now();
+        Self {
+            id,
+            addr,
+            laddr,
+            created: now,
+            details: Mutex::new(ClientDetails {
+                name: String::new(),
+                user: "default".to_string(),
+                last_active: now,
+                last_command: "NULL".to_string(),
+                channels: 0,
+                patterns: 0,
+                multi: None,
+                protocol: Protocol::Resp2,
+                replica: false,
+                monitor: false,
+                readonly: false,
+                tracking: false,
+                in_command: false,
+                soft_limit_since: None,
+            }),
+            kill: Notify::new(),
+            killed: AtomicBool::new(false),
+            output: AtomicUsize::new(0),
+            output_limits,
+        }
+    }
+
+    /// Asks the connection to close, even if it is waiting for a request or
+    /// blocked in a command.
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        self.kill.notify_one();
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Relaxed)
+    }
+
+    /// Completes once the client was killed.
+    pub async fn killed(&self) {
+        self.kill.notified().await;
+    }
+
+    pub fn details(&self) -> MutexGuard<'_, ClientDetails> {
+        self.details.lock().expect("client details lock poisoned")
+    }
+
+    pub fn client_type(&self) -> ClientType {
+        let details = self.details();
+        if details.replica {
+            ClientType::Replica
+        } else if details.protocol == Protocol::Resp2 && details.channels + details.patterns > 0 {
+            ClientType::Pubsub
+        } else {
+            ClientType::Normal
+        }
+    }
+
+    /// Counts `size` more bytes queued for the client, closing it once they
+    /// are over the output buffer limit of its class.
+    pub fn output_queued(&self, size: usize) {
+        let pending = self.output.fetch_add(size, Ordering::Relaxed) + size;
+        let limits = *self.output_limits.borrow();
+        let limit = match self.client_type() {
+            ClientType::Replica => limits.replica,
+            _ if self.is_subscribed() => limits.pubsub,
+            _ => limits.normal,
+        };
+        let exceeded = limit.is_exceeded(
+            pending,
+            &mut self.details().soft_limit_since,
+            Instant::now(),
+        );
+        if exceeded && !self.is_killed() {
+            warn!(
+                client = self.id,
+                addr = %self.addr,
+                pending,
+                "Closing client over its output buffer limit"
+            );
+            self.kill();
+        }
+    }
+
+    /// Counts `size` bytes taken by the connection to be written.
+    pub fn output_taken(&self, size: usize) {
+        self.output.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Whether the client is subscribed to any channel or pattern, with
+    /// either protocol.
+    fn is_subscribed(&self) -> bool {
+        let details = self.details();
+        details.channels + details.patterns > 0
+    }
+
+    /// Whether the client sent nothing for longer than `timeout`. Like in
+    /// Redis, replicas, monitors, subscribers and clients blocked in a
+    /// command never count as idle, as they wait for the server rather than
+    /// the other way around.
+    fn is_idle(&self, timeout: Duration, now: Instant) -> bool {
+        let details = self.details();
+        !details.replica
+            && !details.monitor
+            && !details.in_command
+            && details.channels + details.patterns == 0
+            && now.duration_since(details.last_active) > timeout
+    }
+
+    /// The line describing the client in `CLIENT LIST` and `CLIENT INFO`.
+    pub fn line(&self) -> String {
+        let now = Instant::now();
+        let client_type = self.client_type();
+        let details = self.details();
+        let mut flags = String::new();
+        if client_type == ClientType::Replica {
+            flags.push('S');
+        }
+        if client_type == ClientType::Pubsub {
+            flags.push('P');
+        }
+        if details.monitor {
+            flags.push('O');
+        }
+        if details.multi.is_some() {
+            flags.push('x');
+        }
+        if details.readonly {
+            flags.push('r');
+        }
+        if details.tracking {
+            flags.push('t');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={flags} db=0 sub={} psub={} multi={} omem={} cmd={} user={} resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            details.name,
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(details.last_active).as_secs(),
+            details.channels,
+            details.patterns,
+            details.multi.map_or(-1, |queued| queued as i64),
+            self.output.load(Ordering::Relaxed),
+            details.last_command,
+            details.user,
+            match details.protocol {
+                Protocol::Resp2 => 2,
+                Protocol::Resp3 => 3,
+            },
+        )
+    }
+}
+
+/// The clients `CLIENT KILL` closes: those matching every filter given.
+#[derive(Debug)]
+pub struct KillFilter {
+    pub id: Option<u64>,
+    pub addr: Option<String>,
+    pub laddr: Option<String>,
+    pub client_type: Option<ClientType>,
+    /// Whether the client running the command is spared, which it is
+    /// unless `SKIPME no` is given.
+    pub skip_me: bool,
+}
+
+impl Default for KillFilter {
+    fn default() -> Self {
+        Self {
+            id: None,
+            addr: None,
+            laddr: None,
+            client_type: None,
+            skip_me: true,
+        }
+    }
+}
+
+impl KillFilter {
+    pub fn matches(&self, client: &ClientInfo, me: u64) -> bool {
+        !client.is_killed()
+            && (!self.skip_me || client.id != me)
+            && self.id.is_none_or(|id| client.id == id)
+            && self
+                .addr
+                .as_ref()
+                .is_none_or(|addr| client.addr.to_string() == *addr)
+            && self
+                .laddr
+                .as_ref()
+                .is_none_or(|laddr| client.laddr.to_string() == *laddr)
+            && self
+                .client_type
+                .is_none_or(|client_type| client.client_type() == client_type)
+    }
+}
+
+/// Every connected client by id, shared by the connections and the `Db`
+/// behind its own lock, so that clients come and go even while the `Db` is
+/// held by a busy script.
+#[derive(Debug)]
+pub struct ClientRegistry {
+    /// Every client, reached through where its out-of-band frames go.
+    clients: Mutex<BTreeMap<u64, MessageSender>>,
+    /// Where to send the commands run to the clients in `MONITOR` mode.
+    monitors: Mutex<BTreeMap<u64, MessageSender>>,
+    /// The `client-output-buffer-limit` of the config, which every client
+    /// checks as frames are queued for it.
+    output_limits: watch::Sender<OutputBufferLimits>,
+    /// Set once the server is shutting down, for the connections to close
+    /// after their current command and blocked commands to give up.
+    shutting_down: watch::Sender<bool>,
+    /// Set while writes are paused, as during a `FAILOVER`: clients about
+    /// to run a write wait until it is cleared.
+    writes_paused: watch::Sender<bool>,
+    tracking: Mutex<Tracking>,
+}
+
+impl Default for ClientRegistry {
+    fn default() -> Self {
+        Self {
+            clients: Mutex::default(),
+            monitors: Mutex::default(),
+            output_limits: watch::Sender::new(OutputBufferLimits::default()),
+            shutting_down: watch::Sender::new(false),
+            writes_paused: watch::Sender::new(false),
+            tracking: Mutex::default(),
+        }
+    }
+}
+
+impl ClientRegistry {
+    pub fn set_output_limits(&self, limits: OutputBufferLimits) {
+        self.output_limits.send_replace(limits);
+    }
+
+    pub fn watch_output_limits(&self) -> watch::Receiver<OutputBufferLimits> {
+        self.output_limits.subscribe()
+    }
+
+    /// Also ends any pause of writes, for paused clients to close.
+    pub fn shut_down(&self) {
+        self.shutting_down.send_replace(true);
+        self.writes_paused.send_replace(false);
+    }
+
+    /// Completes once the server is shutting down.
+    pub async fn shutting_down(&self) {
+        let _ = self.shutting_down.subscribe().wait_for(|down| *down).await;
+    }
+
+    pub fn pause_writes(&self, paused: bool) {
+        self.writes_paused.send_replace(paused);
+    }
+
+    pub fn writes_paused(&self) -> bool {
+        *self.writes_paused.borrow()
+    }
+
+    /// Completes once writes are not paused.
+    pub async fn writes_unpaused(&self) {
+        let _ = self
+            .writes_paused
+            .subscribe()
+            .wait_for(|paused| !*paused)
+            .await;
+    }
+
+    pub fn register(&self, sender: MessageSender) {
+        self.lock().insert(sender.client().id, sender);
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.lock().remove(&id);
+        self.remove_monitor(id);
+        self.lock_tracking().disable(id);
+    }
+
+    /// Turns `CLIENT TRACKING` on for client `id`, or changes its options.
+    pub fn enable_tracking(&self, id: u64, options: TrackingOptions) -> Result<()> {
+        let clients = self.lock();
+        if let Some(redirect) = options.redirect
+            && !clients.contains_key(&redirect)
+        {
+            bail!("ERR The client ID you want redirect to does not exist");
+        }
+        let mut tracking = self.lock_tracking();
+        let current = tracking.options(id);
+        if let Some(current) = current {
+            if current.bcast != options.bcast {
+                bail!(
+                    "ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it."
+                );
+            }
+            if current.optin != options.optin || current.optout != options.optout {
+                bail!(
+                    "ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode."
+                );
+            }
+        }
+        // A key must not fall under two prefixes of the same client, which
+        // would tell it twice of the key changing.
+        let overlap = |a: &Bytes, b: &Bytes| a.starts_with(b) || b.starts_with(a);
+        let registered = current.map_or(&[][..], |current| &current.prefixes);
+        for (i, prefix) in options.prefixes.iter().enumerate() {
+            if let Some(other) = registered
+                .iter()
+                .find(|other| *other != prefix && overlap(prefix, other))
+            {
+                bail!(
+                    "ERR Prefix '{}' overlaps with an existing prefix '{}'. Prefixes for a single client must not overlap.",
+                    String::from_utf8_lossy(prefix),
+                    String::from_utf8_lossy(other)
+                );
+            }
+            if let Some(other) = options.prefixes[i + 1..]
+                .iter()
+                .find(|other| overlap(prefix, other))
+            {
+                bail!(
+                    "ERR Prefix '{}' overlaps with another provided prefix '{}'. Prefixes for a single client must not overlap.",
+                    String::from_utf8_lossy(prefix),
+                    String::from_utf8_lossy(other)
+                );
+            }
+        }
+        tracking.enable(id, options);
+        if let Some(sender) = clients.get(&id) {
+            sender.client().details().tracking = true;
+        }
+        Ok(())
+    }
+
+    pub fn disable_tracking(&self, id: u64) {
+        self.lock_tracking().disable(id);
+        if let Some(sender) = self.lock().get(&id) {
+            sender.client().details().tracking = false;
+        }
+    }
+
+    /// The options of client `id`, if it is tracked.
+    pub fn tracking(&self, id: u64) -> Option<TrackingOptions> {
+        self.lock_tracking().options(id).cloned()
+    }
+
+    /// The number of tracked clients, of keys remembered for them and of
+    /// prefixes registered in `BCAST` mode.
+    pub fn tracking_stats(&self) -> (usize, usize, usize) {
+        let tracking = self.lock_tracking();
+        (
+            tracking.tracking_clients(),
+            tracking.tracked_keys(),
+            tracking.tracked_prefixes(),
+        )
+    }
+
+    /// Remembers the keys of the command `argv` for the client running it,
+    /// when it tracks the keys it reads.
+    pub fn remember_reads(&self, argv: &[RespValue]) {
+        let Some(caller) = tracking::caller().filter(|caller| caller.tracks_reads) else {
+            return;
+        };
+        let argv: Vec<Bytes> = argv.iter().cloned().map(Bytes::from).collect();
+        if let Ok(keys) = keys::command_keys(&argv) {
+            self.lock_tracking().remember(caller.id, keys);
+        }
+    }
+
+    /// Tells the clients that read `key` that it changed.
+    pub fn invalidate(&self, key: &[u8]) {
+        let changed_by = tracking::caller().map(|caller| caller.id);
+        let invalidated = self.lock_tracking().invalidate(key, changed_by);
+        for (id, options) in invalidated {
+            let keys = RespValue::Array(vec![RespValue::BulkString(Bytes::copy_from_slice(key))]);
+            self.send_invalidation(id, &options, keys);
+        }
+    }
+
+    /// Sends the clients in `BCAST` mode the keys changed under their
+    /// prefixes since last sent, once a command ran or keys expired.
+    pub fn send_broadcasts(&self) {
+        let broadcasts = self.lock_tracking().take_broadcasts();
+        for (id, options, keys) in broadcasts {
+            let keys = RespValue::Array(keys.into_iter().map(RespValue::BulkString).collect());
+            self.send_invalidation(id, &options, keys);
+        }
+    }
+
+    /// Tells every tracked client that all keys changed, as the keyspace
+    /// was flushed.
+    pub fn invalidate_all(&self) {
+        let invalidated = self.lock_tracking().invalidate_all();
+        for (id, options) in invalidated {
+            self.send_invalidation(id, &options, RespValue::NullBulkString);
+        }
+    }
+
+    /// Sends an `invalidate` push to the client, or to the client it
+    /// redirects to. A RESP2 client only gets invalidations redirected to
+    /// it, as a message of `__redis__:invalidate` once subscribed.
+    fn send_invalidation(&self, id: u64, options: &TrackingOptions, keys: RespValue) {
+        let clients = self.lock();
+        let target = options.redirect.unwrap_or(id);
+        let Some(sender) = clients.get(&target) else {
+            if let Some(sender) = clients.get(&id)
+                && sender.client().details().protocol == Protocol::Resp3
+            {
+                let _ = sender.send(RespValue::Push(vec![
+                    RespValue::BulkString("tracking-redir-broken".into()),
+                    RespValue::Integer(target as i64),
+                ]));
+            }
+            return;
+        };
+        let frame = {
+            let details = sender.client().details();
+            match details.protocol {
+                Protocol::Resp3 => {
+                    RespValue::Push(vec![RespValue::BulkString("invalidate".into()), keys])
+                }
+                Protocol::Resp2
+                    if options.redirect.is_some() && details.channels + details.patterns > 0 =>
+                {
+                    RespValue::Push(vec![
+                        RespValue::BulkString("message".into()),
+                        RespValue::BulkString(INVALIDATE_CHANNEL.into()),
+                        keys,
+                    ])
+                }
+                Protocol::Resp2 => return,
+            }
+        };
+        let _ = sender.send(frame);
+    }
+
+    pub fn add_monitor(&self, id: u64, sender: MessageSender) {
+        self.lock_monitors().insert(id, sender);
+    }
+
+    pub fn remove_monitor(&self, id: u64) {
+        self.lock_monitors().remove(&id);
+    }
+
+    /// Shows `argv` to every monitor as run by `source`, the client's
+    /// address or `lua` for scripts.
+    pub fn feed_monitors(&self, source: &str, argv: &[RespValue]) {
+        let monitors = self.lock_monitors();
+        if monitors.is_empty() {
+            return;
+        }
+        let line = monitor_line(source, argv);
+        for sender in monitors.values() {
+            let _ = sender.send(RespValue::SimpleString(line.clone()));
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Every client, ordered by id.
+    pub fn list(&self) -> Vec<Arc<ClientInfo>> {
+        self.lock()
+            .values()
+            .map(|sender| sender.client().clone())
+            .collect()
+    }
+
+    /// Closes the clients idle for longer than `timeout`, returning how
+    /// many.
+    pub fn close_idle(&self, timeout: Duration) -> usize {
+        let now = Instant::now();
+        let idle: Vec<Arc<ClientInfo>> = self
+            .list()
+            .into_iter()
+            .filter(|client| !client.is_killed() && client.is_idle(timeout, now))
+            .collect();
+        for client in &idle {
+            debug!(client = client.id, addr = %client.addr, "Closing idle client");
+            client.kill();
+        }
+        idle.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, MessageSender>> {
+        self.clients.lock().expect("client registry lock poisoned")
+    }
+
+    fn lock_tracking(&self) -> MutexGuard<'_, Tracking> {
+        self.tracking.lock().expect("tracking lock poisoned")
+    }
+
+    fn lock_monitors(&self) -> MutexGuard<'_, BTreeMap<u64, MessageSender>> {
+        self.monitors.lock().expect("monitors lock poisoned")
+    }
+}
+
+/// Spawns the background task closing the clients idle for longer than
+/// `timeout` seconds, when it is not 0.
+pub fn spawn_idle_timeout(db: Arc<RwLock<Db>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(IDLE_CHECK_PERIOD);
+        loop {
+            interval.tick().await;
+            let (timeout, clients) = {
+                let db_g = db.read().await;
+                (db_g.config().timeout, db_g.clients().clone())
+            };
+            if timeout > 0 {
+                clients.close_idle(Duration::from_secs(timeout));
+            }
+        }
+    });
+}
+
+/// A command as `MONITOR` shows it, as in
+/// `1700000000.123456 [0 127.0.0.1:50000] "set" "key" "value"`, each
+/// argument quoted with its special characters escaped.
+fn monitor_line(source: &str, argv: &[RespValue]) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut line = format!("{}.{:06} [0 {source}]", now.as_secs(), now.subsec_micros());
+    for arg in argv {
+        line.push_str(" \"");
+        for &byte in Bytes::from(arg.clone()).iter() {
+            match byte {
+                b'\\' => line.push_str("\\\\"),
+                b'"' => line.push_str("\\\""),
+                b'\n' => line.push_str("\\n"),
+                b'\r' => line.push_str("\\r"),
+                b'\t' => line.push_str("\\t"),
+                0x07 => line.push_str("\\a"),
+                0x08 => line.push_str("\\b"),
+                b' '..=b'~' => line.push(byte as char),
+                _ => {
+                    let _ = write!(line, "\\x{byte:02x}");
+                }
+            }
+        }
+        line.push('"');
+    }
+    line
+}
+
+/// Whether `name` may be given with `CLIENT SETNAME`, which excludes
+/// spaces and special characters so that `CLIENT LIST` stays parseable.
+pub fn is_valid_name(name: &str) -> bool {
+    name.bytes().all(|byte| (b'!'..=b'~').contains(&byte))
+}
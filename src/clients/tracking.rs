@@ -0,0 +1,219 @@
+//! Server-assisted client-side caching. A client that turned `CLIENT
+//! TRACKING` on has the keys it reads remembered, and is sent an
+//! `invalidate` push the next time one of them changes, after which the key
+//! is forgotten until the client reads it again. In `BCAST` mode nothing is
+//! remembered: the client is told of every key changed under the prefixes it
+//! registered instead, the keys changed by a command gathered into a
+//! single push once it ran.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    future::Future,
+};
+
+use bytes::Bytes;
+
+/// The channel RESP2 clients subscribe to when other clients redirect
+/// their invalidations to them.
+pub const INVALIDATE_CHANNEL: &str = "__redis__:invalidate";
+
+/// How a client asked to be tracked with `CLIENT TRACKING ON`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TrackingOptions {
+    /// The client invalidations are sent to instead, such as a RESP2
+    /// client subscribed to `__redis__:invalidate`.
+    pub redirect: Option<u64>,
+    /// Only the keys of commands following `CLIENT CACHING yes` are
+    /// remembered.
+    pub optin: bool,
+    /// The keys of commands following `CLIENT CACHING no` are not
+    /// remembered.
+    pub optout: bool,
+    /// Keys the client changes itself are not invalidated for it.
+    pub noloop: bool,
+    /// Every key changed under `prefixes` is invalidated, read or not.
+    pub bcast: bool,
+    /// The prefixes of the keys invalidated in `BCAST` mode, all keys when
+    /// there are none.
+    pub prefixes: Vec<Bytes>,
+}
+
+impl TrackingOptions {
+    /// Whether the keys of the next command are remembered, given what
+    /// `CLIENT CACHING` said before it, if anything.
+    pub fn tracks_reads(&self, caching: Option<bool>) -> bool {
+        if self.bcast {
+            false
+        } else if self.optin {
+            caching == Some(true)
+        } else if self.optout {
+            caching != Some(false)
+        } else {
+            true
+        }
+    }
+}
+
+/// The client running the current command, as its connection sets it.
+#[derive(Clone, Copy, Debug)]
+pub struct Caller {
+    pub id: u64,
+    /// Whether the keys the command reads are to be remembered for it.
+    pub tracks_reads: bool,
+}
+
+tokio::task_local! {
+    static CALLER: Caller;
+}
+
+/// Runs `future`, a command of `caller`, which the keys it reads and
+/// changes are attributed to.
+pub async fn scope<F: Future>(caller: Caller, future: F) -> F::Output {
+    CALLER.scope(caller, future).await
+}
+
+/// The client running the current command, if a client runs it.
+pub fn caller() -> Option<Caller> {
+    CALLER.try_with(|caller| *caller).ok()
+}
+
+#[derive(Debug, Default)]
+pub struct Tracking {
+    clients: HashMap<u64, TrackingOptions>,
+    /// The clients that read each key since it last changed.
+    keys: HashMap<Bytes, HashSet<u64>>,
+    /// The clients in `BCAST` mode registered for each prefix.
+    prefixes: BTreeMap<Bytes, HashSet<u64>>,
+    /// The keys changed under the prefixes of each client in `BCAST` mode,
+    /// not yet sent.
+    broadcasts: BTreeMap<u64, BTreeSet<Bytes>>,
+}
+
+impl Tracking {
+    /// Tracks the client with `options`. A client already in `BCAST` mode
+    /// keeps the prefixes it registered before.
+    pub fn enable(&mut self, id: u64, mut options: TrackingOptions) {
+        if options.bcast {
+            if options.prefixes.is_empty() {
+                options.prefixes.push(Bytes::new());
+            }
+            for prefix in &options.prefixes {
+                self.prefixes.entry(prefix.clone()).or_default().insert(id);
+            }
+            if let Some(current) = self.clients.get(&id) {
+                let added: Vec<Bytes> = options
+                    .prefixes
+                    .drain(..)
+                    .filter(|prefix| !current.prefixes.contains(prefix))
+                    .collect();
+                options.prefixes = current.prefixes.iter().cloned().chain(added).collect();
+            }
+        }
+        self.clients.insert(id, options);
+    }
+
+    /// Stops tracking the client. The keys it read are forgotten as they
+    /// change.
+    pub fn disable(&mut self, id: u64) {
+        self.broadcasts.remove(&id);
+        let Some(options) = self.clients.remove(&id) else {
+            return;
+        };
+        for prefix in options.prefixes {
+            if let Some(clients) = self.prefixes.get_mut(&prefix) {
+                clients.remove(&id);
+                if clients.is_empty() {
+                    self.prefixes.remove(&prefix);
+                }
+            }
+        }
+    }
+
+    pub fn options(&self, id: u64) -> Option<&TrackingOptions> {
+        self.clients.get(&id)
+    }
+
+    pub fn remember(&mut self, id: u64, keys: Vec<Bytes>) {
+        if !self.clients.contains_key(&id) {
+            return;
+        }
+        for key in keys {
+            self.keys.entry(key).or_default().insert(id);
+        }
+    }
+
+    /// The clients to tell that `key` changed, with their options, which
+    /// forget they read it. The clients registered for a prefix of the key
+    /// are told by `take_broadcasts` instead. With `NOLOOP`, the client
+    /// that changed it is not told.
+    pub fn invalidate(
+        &mut self,
+        key: &[u8],
+        changed_by: Option<u64>,
+    ) -> Vec<(u64, TrackingOptions)> {
+        let told =
+            |id: &u64, options: &TrackingOptions| !(options.noloop && changed_by == Some(*id));
+        for (prefix, registered) in &self.prefixes {
+            if !key.starts_with(prefix) {
+                continue;
+            }
+            for id in registered {
+                if self
+                    .clients
+                    .get(id)
+                    .is_some_and(|options| told(id, options))
+                {
+                    self.broadcasts
+                        .entry(*id)
+                        .or_default()
+                        .insert(Bytes::copy_from_slice(key));
+                }
+            }
+        }
+        let Some(readers) = self.keys.remove(key) else {
+            return vec![];
+        };
+        readers
+            .into_iter()
+            .filter_map(|id| Some((id, self.clients.get(&id)?.clone())))
+            .filter(|(id, options)| told(id, options))
+            .collect()
+    }
+
+    /// The keys changed under the prefixes of clients in `BCAST` mode since
+    /// last taken, with the clients to tell and their options.
+    pub fn take_broadcasts(&mut self) -> Vec<(u64, TrackingOptions, Vec<Bytes>)> {
+        std::mem::take(&mut self.broadcasts)
+            .into_iter()
+            .filter_map(|(id, keys)| {
+                let options = self.clients.get(&id)?.clone();
+                Some((id, options, keys.into_iter().collect()))
+            })
+            .collect()
+    }
+
+    /// Forgets every key, as the keyspace was flushed, returning every
+    /// client tracked.
+    pub fn invalidate_all(&mut self) -> Vec<(u64, TrackingOptions)> {
+        self.keys.clear();
+        self.broadcasts.clear();
+        self.clients
+            .iter()
+            .map(|(id, options)| (*id, options.clone()))
+            .collect()
+    }
+
+    /// The number of keys remembered for some client.
+    pub fn tracked_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// The number of prefixes registered by some client in `BCAST` mode.
+    pub fn tracked_prefixes(&self) -> usize {
+        self.prefixes.len()
+    }
+
+    pub fn tracking_clients(&self) -> usize {
+        self.clients.len()
+    }
+}
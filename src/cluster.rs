@@ -0,0 +1,850 @@
+pub(crate) mod bus;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+    str::FromStr,
+    sync::{
+        RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use tracing::{info, warn};
+
+use self::bus::{Gossip, Heartbeat, HeartbeatKind, Message};
+use crate::{config::Config, persistence::unix_time_millis};
+
+/// The number of hash slots keys are spread over.
+pub const SLOTS: u16 = 16384;
+
+/// How long a forgotten node is kept from being added back by gossip.
+const FORGET_PERIOD: Duration = Duration::from_secs(60);
+
+/// The slot of `key`: the CRC16 of its hash tag, the part between the first
+/// `{` and the next `}` when not empty, or else of the whole key, so that
+/// related keys such as `{user:1}:name` and `{user:1}:age` share a slot.
+pub fn key_slot(key: &[u8]) -> u16 {
+    let tagged = key.iter().position(|&byte| byte == b'{').and_then(|open| {
+        let tag = &key[open + 1..];
+        let close = tag.iter().position(|&byte| byte == b'}')?;
+        (close > 0).then(|| &tag[..close])
+    });
+    crc16(tagged.unwrap_or(key)) % SLOTS
+}
+
+/// CRC16-CCITT (XMODEM), as Redis Cluster uses.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Slots from `start` to `end` included, served by this node or, with an
+/// `owner`, by the node at that address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SlotRange {
+    pub start: u16,
+    pub end: u16,
+    pub owner: Option<(String, u16)>,
+}
+
+impl FromStr for SlotRange {
+    type Err = anyhow::Error;
+
+    /// Parses `<start>-<end>` or a single `<slot>`, followed by
+    /// `@<host>:<port>` for slots of another node.
+    fn from_str(s: &str) -> Result<Self> {
+        let (slots, owner) = match s.split_once('@') {
+            Some((slots, address)) => {
+                let (host, port) = address
+                    .rsplit_once(':')
+                    .ok_or_else(|| anyhow!("Invalid node address {address}"))?;
+                let port = port
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid node address {address}"))?;
+                (slots, Some((host.to_string(), port)))
+            }
+            None => (s, None),
+        };
+        let (start, end) = slots.split_once('-').unwrap_or((slots, slots));
+        let (start, end) = (parse_slot(start)?, parse_slot(end)?);
+        if start > end {
+            bail!("Invalid slot range {slots}");
+        }
+        Ok(SlotRange { start, end, owner })
+    }
+}
+
+impl fmt::Display for SlotRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)?;
+        if let Some((host, port)) = &self.owner {
+            write!(f, "@{host}:{port}")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn parse_slot(slot: &str) -> Result<u16> {
+    slot.parse::<u16>()
+        .ok()
+        .filter(|&slot| slot < SLOTS)
+        .ok_or_else(|| anyhow!("Invalid or out of range slot"))
+}
+
+/// A node of the cluster, as this one sees it.
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    /// The port of its cluster bus.
+    pub cport: u16,
+    /// Set for this node.
+    pub myself: bool,
+    /// Met but not answered yet, so its id is still a made up one.
+    pub handshake: bool,
+    /// Did not answer pings for `cluster-node-timeout`, as far as this
+    /// node can tell.
+    pub pfail: bool,
+    /// Failing for the majority of the nodes serving slots.
+    pub fail: bool,
+    /// The epoch its slots were claimed at, which settles which node a
+    /// slot two of them claim belongs to.
+    pub config_epoch: u64,
+    /// When the oldest ping it did not answer was sent, 0 for none, and
+    /// when it last answered one, in UNIX milliseconds.
+    pub ping_sent: u64,
+    pub pong_received: u64,
+    /// Whether this node has a connection to its bus.
+    pub connected: bool,
+    /// The slot ranges it serves, in order.
+    pub slots: Vec<(u16, u16)>,
+    /// When it was added, in UNIX milliseconds.
+    created: u64,
+    /// The nodes serving slots that reported it failing, with when they
+    /// last did.
+    fail_reports: HashMap<String, u64>,
+}
+
+impl Node {
+    fn new(id: String, host: String, port: u16, cport: u16) -> Self {
+        Self {
+            id,
+            host,
+            port,
+            cport,
+            myself: false,
+            handshake: false,
+            pfail: false,
+            fail: false,
+            config_epoch: 0,
+            ping_sent: 0,
+            pong_received: 0,
+            connected: false,
+            slots: vec![],
+            created: unix_time_millis(),
+            fail_reports: HashMap::new(),
+        }
+    }
+
+    fn flags(&self) -> String {
+        let mut flags = vec![];
+        if self.myself {
+            flags.push("myself");
+        }
+        if self.handshake {
+            flags.push("handshake");
+        } else {
+            flags.push("master");
+        }
+        if self.fail {
+            flags.push("fail");
+        } else if self.pfail {
+            flags.push("fail?");
+        }
+        flags.join(",")
+    }
+
+    /// Whether it serves slots, which gives its opinion on failures a say.
+    fn is_voter(&self) -> bool {
+        !self.slots.is_empty()
+    }
+}
+
+/// The nodes of the cluster and who serves each slot.
+#[derive(Debug)]
+struct State {
+    /// This node first, then the others in the order they were met.
+    nodes: Vec<Node>,
+    /// The index in `nodes` of the node serving each slot.
+    owners: Vec<Option<usize>>,
+    /// The highest epoch seen in the cluster.
+    current_epoch: u64,
+    node_timeout: Duration,
+    /// Whether every slot is served by a node not failing.
+    ok: bool,
+    /// Nodes recently forgotten, with when they were, in UNIX milliseconds.
+    forgotten: HashMap<String, u64>,
+}
+
+impl State {
+    fn find(&self, id: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.id == id)
+    }
+
+    fn find_address(&self, host: &str, cport: u16) -> Option<usize> {
+        self.nodes
+            .iter()
+            .position(|node| !node.myself && node.host == host && node.cport == cport)
+    }
+
+    /// Adds a node to start a handshake with, unless one is already at that
+    /// address.
+    fn meet(&mut self, host: &str, port: u16, cport: u16) -> usize {
+        if let Some(index) = self.find_address(host, cport) {
+            return index;
+        }
+        info!(host, port, "Starting a handshake with a cluster node");
+        let mut node = Node::new(new_node_id(), host.to_string(), port, cport);
+        node.handshake = true;
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.nodes.remove(index);
+        for owner in self.owners.iter_mut() {
+            *owner = match *owner {
+                Some(current) if current == index => None,
+                Some(current) if current > index => Some(current - 1),
+                owner => owner,
+            };
+        }
+        self.refresh();
+    }
+
+    /// Recomputes the ranges every node serves and the cluster state.
+    fn refresh(&mut self) {
+        for node in self.nodes.iter_mut() {
+            node.slots.clear();
+        }
+        let mut slot = 0;
+        while slot < SLOTS {
+            let owner = self.owners[slot as usize];
+            let start = slot;
+            while slot + 1 < SLOTS && self.owners[slot as usize + 1] == owner {
+                slot += 1;
+            }
+            if let Some(index) = owner {
+                self.nodes[index].slots.push((start, slot));
+            }
+            slot += 1;
+        }
+        let ok = self
+            .owners
+            .iter()
+            .all(|owner| owner.is_some_and(|index| !self.nodes[index].fail));
+        if ok != self.ok {
+            info!("Cluster state changed: {}", if ok { "ok" } else { "fail" });
+        }
+        self.ok = ok;
+    }
+
+    /// One bit per slot `index` serves.
+    fn slot_bitmap(&self, index: usize) -> Vec<u8> {
+        let mut bitmap = vec![0; SLOTS as usize / 8];
+        for (slot, owner) in self.owners.iter().enumerate() {
+            if *owner == Some(index) {
+                bitmap[slot / 8] |= 1 << (slot % 8);
+            }
+        }
+        bitmap
+    }
+
+    fn heartbeat(&self, kind: HeartbeatKind) -> Heartbeat {
+        let myself = &self.nodes[0];
+        Heartbeat {
+            kind,
+            sender: myself.id.clone(),
+            port: myself.port,
+            cport: myself.cport,
+            current_epoch: self.current_epoch,
+            config_epoch: myself.config_epoch,
+            slots: self.slot_bitmap(0),
+            gossip: self.nodes[1..]
+                .iter()
+                .filter(|node| !node.handshake)
+                .map(|node| Gossip {
+                    id: node.id.clone(),
+                    host: node.host.clone(),
+                    port: node.port,
+                    cport: node.cport,
+                    pfail: node.pfail,
+                    fail: node.fail,
+                })
+                .collect(),
+        }
+    }
+
+    /// Takes in what the node at `sender` claims: the slots it serves at
+    /// its config epoch, and how it sees the other nodes.
+    fn update_from(&mut self, sender: usize, heartbeat: &Heartbeat) {
+        self.current_epoch = self.current_epoch.max(heartbeat.current_epoch);
+        let node = &mut self.nodes[sender];
+        node.port = heartbeat.port;
+        node.cport = heartbeat.cport;
+        node.config_epoch = heartbeat.config_epoch;
+
+        let (mut changed, mut lost) = (false, 0);
+        for slot in 0..SLOTS as usize {
+            let claimed = heartbeat.slots[slot / 8] & (1 << (slot % 8)) != 0;
+            match self.owners[slot] {
+                Some(owner) if owner == sender && !claimed => {
+                    self.owners[slot] = None;
+                    changed = true;
+                }
+                Some(owner) if owner == sender => {}
+                // A claim at a higher config epoch wins, even over ours.
+                Some(owner)
+                    if claimed && self.nodes[owner].config_epoch < heartbeat.config_epoch =>
+                {
+                    lost += (owner == 0) as usize;
+                    self.owners[slot] = Some(sender);
+                    changed = true;
+                }
+                None if claimed => {
+                    self.owners[slot] = Some(sender);
+                    changed = true;
+                }
+                _ => {}
+            }
+        }
+        if lost > 0 {
+            warn!(
+                slots = lost,
+                node = heartbeat.sender,
+                "Slots taken over by another node"
+            );
+        }
+        if changed {
+            self.refresh();
+        }
+
+        // Two nodes serving slots must not share a config epoch, or their
+        // claims could not be told apart: the one with the lower id moves
+        // to a new epoch.
+        let myself = &self.nodes[0];
+        if heartbeat.config_epoch == myself.config_epoch
+            && !heartbeat.slots.iter().all(|&byte| byte == 0)
+            && myself.is_voter()
+            && myself.id < heartbeat.sender
+        {
+            self.current_epoch += 1;
+            self.nodes[0].config_epoch = self.current_epoch;
+            info!(
+                epoch = self.current_epoch,
+                "Config epoch collision resolved"
+            );
+        }
+
+        let now = unix_time_millis();
+        let voter = self.nodes[sender].is_voter();
+        for gossip in &heartbeat.gossip {
+            if gossip.id == self.nodes[0].id || self.forgotten.contains_key(&gossip.id) {
+                continue;
+            }
+            match self.find(&gossip.id) {
+                Some(index) if voter => {
+                    let reports = &mut self.nodes[index].fail_reports;
+                    if gossip.pfail || gossip.fail {
+                        reports.insert(heartbeat.sender.clone(), now);
+                    } else {
+                        reports.remove(&heartbeat.sender);
+                    }
+                }
+                Some(_) => {}
+                // Nodes are met through the ones already known.
+                None => {
+                    self.meet(&gossip.host, gossip.port, gossip.cport);
+                }
+            }
+        }
+    }
+}
+
+/// The cluster this node is part of when `cluster-enabled`. It starts
+/// serving the slots `cluster-slots` gives it, then learns of other nodes
+/// and their slots over the cluster bus (`src/cluster/bus.rs`).
+#[derive(Debug)]
+pub struct Cluster {
+    enabled: bool,
+    state: RwLock<State>,
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+}
+
+impl Cluster {
+    pub fn new(config: &Config) -> Self {
+        let mut myself = Node::new(
+            new_node_id(),
+            announced_host(config),
+            config.port,
+            config.cluster_bus_port(),
+        );
+        myself.myself = true;
+        myself.connected = true;
+        let mut state = State {
+            nodes: vec![myself],
+            owners: vec![None; SLOTS as usize],
+            current_epoch: 0,
+            node_timeout: config.cluster_node_timeout,
+            ok: false,
+            forgotten: HashMap::new(),
+        };
+        assign_configured(&mut state, &config.cluster_slots);
+        Self {
+            enabled: config.cluster_enabled,
+            state: RwLock::new(state),
+            messages_sent: AtomicU64::new(0),
+            messages_received: AtomicU64::new(0),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The server mode as `HELLO` and `INFO` report it.
+    pub fn mode(&self) -> &'static str {
+        if self.enabled {
+            "cluster"
+        } else {
+            "standalone"
+        }
+    }
+
+    /// Applies a new `cluster-slots`.
+    pub fn set_configured_slots(&self, ranges: &[SlotRange]) {
+        assign_configured(&mut self.write(), ranges);
+    }
+
+    pub fn set_node_timeout(&self, timeout: Duration) {
+        self.write().node_timeout = timeout;
+    }
+
+    pub fn myself(&self) -> Node {
+        self.read().nodes[0].clone()
+    }
+
+    /// Every known node, this one first.
+    pub fn nodes(&self) -> Vec<Node> {
+        self.read().nodes.clone()
+    }
+
+    /// `CLUSTER MEET`: starts a handshake with the node at `host:port`,
+    /// whose bus listens on `cport`.
+    pub fn meet(&self, host: &str, port: u16, cport: u16) {
+        self.write().meet(host, port, cport);
+    }
+
+    /// `CLUSTER FORGET`: drops a node, which gossip will not bring back for
+    /// a minute.
+    pub fn forget(&self, id: &str) -> Result<()> {
+        let mut state = self.write();
+        let index = state
+            .find(id)
+            .ok_or_else(|| anyhow!("ERR Unknown node {id}"))?;
+        if index == 0 {
+            bail!("ERR I tried hard but I can't forget myself...");
+        }
+        state.remove(index);
+        state.forgotten.insert(id.to_string(), unix_time_millis());
+        Ok(())
+    }
+
+    /// `CLUSTER ADDSLOTS`: serves `slots`, none of which may be taken.
+    pub fn add_slots(&self, slots: &[u16]) -> Result<()> {
+        let mut state = self.write();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| state.owners[slot as usize].is_some())
+        {
+            bail!("ERR Slot {slot} is already busy");
+        }
+        for &slot in slots {
+            state.owners[slot as usize] = Some(0);
+        }
+        state.refresh();
+        Ok(())
+    }
+
+    /// `CLUSTER DELSLOTS`: stops serving `slots`, which must be assigned.
+    pub fn del_slots(&self, slots: &[u16]) -> Result<()> {
+        let mut state = self.write();
+        if let Some(slot) = slots
+            .iter()
+            .find(|&&slot| state.owners[slot as usize].is_none())
+        {
+            bail!("ERR Slot {slot} is already unassigned");
+        }
+        for &slot in slots {
+            state.owners[slot as usize] = None;
+        }
+        state.refresh();
+        Ok(())
+    }
+
+    pub fn count_failure_reports(&self, id: &str) -> Result<usize> {
+        let state = self.read();
+        let index = state
+            .find(id)
+            .ok_or_else(|| anyhow!("ERR Unknown node {id}"))?;
+        Ok(state.nodes[index].fail_reports.len())
+    }
+
+    /// The fields of `CLUSTER INFO`.
+    pub fn info(&self) -> Vec<(&'static str, String)> {
+        let state = self.read();
+        let (mut assigned, mut pfail, mut fail) = (0, 0, 0);
+        for index in state.owners.iter().flatten() {
+            let node = &state.nodes[*index];
+            assigned += 1;
+            if node.fail {
+                fail += 1;
+            } else if node.pfail {
+                pfail += 1;
+            }
+        }
+        vec![
+            (
+                "cluster_state",
+                if state.ok { "ok" } else { "fail" }.to_string(),
+            ),
+            ("cluster_slots_assigned", assigned.to_string()),
+            ("cluster_slots_ok", (assigned - pfail - fail).to_string()),
+            ("cluster_slots_pfail", pfail.to_string()),
+            ("cluster_slots_fail", fail.to_string()),
+            ("cluster_known_nodes", state.nodes.len().to_string()),
+            (
+                "cluster_size",
+                state
+                    .nodes
+                    .iter()
+                    .filter(|node| node.is_voter())
+                    .count()
+                    .to_string(),
+            ),
+            ("cluster_current_epoch", state.current_epoch.to_string()),
+            ("cluster_my_epoch", state.nodes[0].config_epoch.to_string()),
+            (
+                "cluster_stats_messages_sent",
+                self.messages_sent.load(Ordering::Relaxed).to_string(),
+            ),
+            (
+                "cluster_stats_messages_received",
+                self.messages_received.load(Ordering::Relaxed).to_string(),
+            ),
+        ]
+    }
+
+    /// `CLUSTER NODES`: a line per node with its id, address, flags, ping
+    /// times, config epoch, link state and slots.
+    pub fn describe_nodes(&self) -> String {
+        let mut lines = String::new();
+        for node in &self.read().nodes {
+            let _ = write!(
+                lines,
+                "{} {}:{}@{} {} - {} {} {} {}",
+                node.id,
+                node.host,
+                node.port,
+                node.cport,
+                node.flags(),
+                node.ping_sent,
+                node.pong_received,
+                node.config_epoch,
+                if node.connected {
+                    "connected"
+                } else {
+                    "disconnected"
+                }
+            );
+            for &(start, end) in &node.slots {
+                if start == end {
+                    let _ = write!(lines, " {start}");
+                } else {
+                    let _ = write!(lines, " {start}-{end}");
+                }
+            }
+            lines.push('\n');
+        }
+        lines
+    }
+
+    /// Checks that `keys` may be served here: all in one slot, which this
+    /// node serves. Otherwise fails with the `-MOVED` redirection to the
+    /// node that does, `-CROSSSLOT`, or `-CLUSTERDOWN` unless every slot
+    /// is served.
+    pub fn route(&self, keys: &[Bytes]) -> Result<()> {
+        let Some((first, rest)) = keys.split_first() else {
+            return Ok(());
+        };
+        let slot = key_slot(first);
+        if rest.iter().any(|key| key_slot(key) != slot) {
+            bail!("CROSSSLOT Keys in request don't hash to the same slot");
+        }
+        let state = self.read();
+        if !state.ok {
+            bail!("CLUSTERDOWN The cluster is down");
+        }
+        match state.owners[slot as usize] {
+            Some(0) | None => Ok(()),
+            Some(index) => {
+                let node = &state.nodes[index];
+                bail!("MOVED {slot} {}:{}", node.host, node.port)
+            }
+        }
+    }
+
+    /// The next heartbeat for the node whose bus is at `host:cport`: a
+    /// `MEET` while in handshake, a `PING` otherwise. `None` once the node
+    /// is gone.
+    pub(crate) fn ping(&self, host: &str, cport: u16) -> Option<Message> {
+        let mut state = self.write();
+        let index = state.find_address(host, cport)?;
+        let node = &mut state.nodes[index];
+        if node.ping_sent == 0 {
+            node.ping_sent = unix_time_millis();
+        }
+        let kind = if node.handshake {
+            HeartbeatKind::Meet
+        } else {
+            HeartbeatKind::Ping
+        };
+        Some(Message::Heartbeat(state.heartbeat(kind)))
+    }
+
+    /// The addresses of the buses of the other nodes, which this node
+    /// keeps a link to.
+    pub(crate) fn link_targets(&self) -> HashSet<(String, u16)> {
+        self.read().nodes[1..]
+            .iter()
+            .map(|node| (node.host.clone(), node.cport))
+            .collect()
+    }
+
+    pub(crate) fn set_connected(&self, host: &str, cport: u16, connected: bool) {
+        let mut state = self.write();
+        if let Some(index) = state.find_address(host, cport) {
+            state.nodes[index].connected = connected;
+        }
+    }
+
+    pub(crate) fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Handles a message from `peer`, over the link this node opened to the
+    /// bus at `link` or one the peer opened. Returns the reply, the `PONG`
+    /// to a `MEET` or `PING`.
+    pub(crate) fn receive(
+        &self,
+        message: Message,
+        peer: &str,
+        link: Option<(&str, u16)>,
+    ) -> Option<Message> {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        let mut state = self.write();
+        match message {
+            Message::Heartbeat(heartbeat) => {
+                if heartbeat.sender == state.nodes[0].id {
+                    return None;
+                }
+                if heartbeat.kind == HeartbeatKind::Pong
+                    && let Some((host, cport)) = link
+                    && let Some(index) = state.find_address(host, cport)
+                {
+                    if state.nodes[index].handshake {
+                        // The node met answers with its actual id.
+                        if state.find(&heartbeat.sender).is_some() {
+                            state.remove(index);
+                            return None;
+                        }
+                        info!(host, node = heartbeat.sender, "Handshake completed");
+                        let node = &mut state.nodes[index];
+                        node.id = heartbeat.sender.clone();
+                        node.handshake = false;
+                    }
+                    let node = &mut state.nodes[index];
+                    node.pong_received = unix_time_millis();
+                    node.ping_sent = 0;
+                    if node.pfail || node.fail {
+                        info!(node = node.id, "Cluster node is reachable again");
+                        node.pfail = false;
+                        node.fail = false;
+                        node.fail_reports.clear();
+                        state.refresh();
+                    }
+                }
+                let sender = match state.find(&heartbeat.sender) {
+                    Some(index) => Some(index),
+                    None if heartbeat.kind == HeartbeatKind::Meet => {
+                        info!(peer, node = heartbeat.sender, "Met by a cluster node");
+                        state.nodes.push(Node::new(
+                            heartbeat.sender.clone(),
+                            peer.to_string(),
+                            heartbeat.port,
+                            heartbeat.cport,
+                        ));
+                        Some(state.nodes.len() - 1)
+                    }
+                    None => None,
+                };
+                if let Some(sender) = sender {
+                    if link.is_none() {
+                        state.nodes[sender].host = peer.to_string();
+                    }
+                    state.update_from(sender, &heartbeat);
+                }
+                (heartbeat.kind != HeartbeatKind::Pong)
+                    .then(|| Message::Heartbeat(state.heartbeat(HeartbeatKind::Pong)))
+            }
+            Message::Fail { sender, failing } => {
+                if state.find(&sender).is_some()
+                    && let Some(index) = state.find(&failing)
+                    && index != 0
+                    && !state.nodes[index].fail
+                {
+                    warn!(
+                        node = failing,
+                        reporter = sender,
+                        "Cluster node marked as failing"
+                    );
+                    state.nodes[index].fail = true;
+                    state.refresh();
+                }
+                None
+            }
+        }
+    }
+
+    /// Runs every bus cron tick: marks nodes that stopped answering as
+    /// possibly failing, and as failing once most nodes serving slots
+    /// agree, and gives up handshakes that went unanswered. Returns the
+    /// `FAIL` messages to broadcast.
+    pub(crate) fn cron(&self) -> Vec<Message> {
+        let mut state = self.write();
+        let now = unix_time_millis();
+        let timeout = state.node_timeout.as_millis() as u64;
+        state
+            .forgotten
+            .retain(|_, at| now.saturating_sub(*at) < FORGET_PERIOD.as_millis() as u64);
+
+        // Handshakes with nodes named in `cluster-slots` are retried for
+        // as long as it names them.
+        if let Some(index) = state.nodes.iter().position(|node| {
+            node.handshake
+                && node.slots.is_empty()
+                && now.saturating_sub(node.created) > timeout.max(1000)
+        }) {
+            let node = &state.nodes[index];
+            warn!(
+                host = node.host,
+                port = node.port,
+                "Cluster handshake timed out"
+            );
+            state.remove(index);
+        }
+
+        let voters = state.nodes.iter().filter(|node| node.is_voter()).count();
+        let myself_votes = state.nodes[0].is_voter() as usize;
+        let mut failed = vec![];
+        for node in state.nodes[1..].iter_mut().filter(|node| !node.handshake) {
+            if node.ping_sent != 0 && now.saturating_sub(node.ping_sent) > timeout && !node.pfail {
+                warn!(node = node.id, "Cluster node is not answering");
+                node.pfail = true;
+            }
+            node.fail_reports
+                .retain(|_, at| now.saturating_sub(*at) <= timeout * 2);
+            if node.pfail && !node.fail && node.fail_reports.len() + myself_votes > voters / 2 {
+                warn!(node = node.id, "Cluster node marked as failing");
+                node.fail = true;
+                failed.push(node.id.clone());
+            }
+        }
+        if !failed.is_empty() {
+            state.refresh();
+        }
+        let sender = state.nodes[0].id.clone();
+        failed
+            .into_iter()
+            .map(|failing| Message::Fail {
+                sender: sender.clone(),
+                failing,
+            })
+            .collect()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Serves the slots of `ranges` without an owner, and starts a handshake
+/// with the nodes named for the others. Slots this node served that are no
+/// longer configured are let go.
+fn assign_configured(state: &mut State, ranges: &[SlotRange]) {
+    for owner in state.owners.iter_mut() {
+        if *owner == Some(0) {
+            *owner = None;
+        }
+    }
+    for range in ranges {
+        let index = match &range.owner {
+            None => 0,
+            Some((host, port)) => state.meet(host, *port, port.saturating_add(10000)),
+        };
+        for slot in range.start..=range.end {
+            state.owners[slot as usize] = Some(index);
+        }
+    }
+    state.refresh();
+}
+
+/// A random id for this node, drawn on startup, or for a node being met
+/// until it tells its own.
+fn new_node_id() -> String {
+    sha1_smol::Sha1::from(uuid::Uuid::new_v4().as_bytes())
+        .digest()
+        .to_string()
+}
+
+/// The address clients reach this node at: the first `bind` address unless
+/// that is a wildcard.
+fn announced_host(config: &Config) -> String {
+    config
+        .bind
+        .first()
+        .map(|address| address.trim_start_matches('-'))
+        .filter(|address| !matches!(*address, "*" | "::*" | "0.0.0.0" | "::"))
+        .unwrap_or("127.0.0.1")
+        .to_string()
+}
@@ -0,0 +1,326 @@
+//! The cluster bus: the connections nodes keep to each other on their bus
+//! port, the client port plus 10000 unless `cluster-port` says otherwise.
+//! Every node sends the others a `PING` every second and answers theirs with
+//! a `PONG`, both carrying the slots it serves and what it knows of other
+//! nodes, which is how slots and nodes propagate. Messages are RESP arrays
+//! of bulk strings, so links reuse `RespHandler`.
+
+use std::{collections::HashMap, fmt, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::broadcast,
+    task::JoinHandle,
+    time::{self, MissedTickBehavior},
+};
+use tracing::{debug, warn};
+
+use super::{Cluster, SLOTS};
+use crate::resp::{ProtocolLimits, RespHandler, RespValue};
+
+/// How often nodes are pinged.
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+/// How often failures are checked for and links to new nodes opened.
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+/// How long connecting to a node may take, and how long to wait before
+/// trying again.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeartbeatKind {
+    /// Asks a node to join the cluster of the sender.
+    Meet,
+    Ping,
+    Pong,
+}
+
+impl FromStr for HeartbeatKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "MEET" => Ok(HeartbeatKind::Meet),
+            "PING" => Ok(HeartbeatKind::Ping),
+            "PONG" => Ok(HeartbeatKind::Pong),
+            _ => bail!("Unknown cluster message {s}"),
+        }
+    }
+}
+
+impl fmt::Display for HeartbeatKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            HeartbeatKind::Meet => "MEET",
+            HeartbeatKind::Ping => "PING",
+            HeartbeatKind::Pong => "PONG",
+        })
+    }
+}
+
+/// What a node says about itself every time it pings or answers.
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub kind: HeartbeatKind,
+    pub sender: String,
+    pub port: u16,
+    pub cport: u16,
+    pub current_epoch: u64,
+    pub config_epoch: u64,
+    /// One bit per slot the sender serves.
+    pub slots: Vec<u8>,
+    pub gossip: Vec<Gossip>,
+}
+
+/// What a node knows of another.
+#[derive(Clone, Debug)]
+pub struct Gossip {
+    pub id: String,
+    pub host: String,
+    pub port: u16,
+    pub cport: u16,
+    pub pfail: bool,
+    pub fail: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Message {
+    Heartbeat(Heartbeat),
+    /// Tells every node that `failing` is failing, once the majority of the
+    /// nodes serving slots agree on it.
+    Fail {
+        sender: String,
+        failing: String,
+    },
+}
+
+impl From<Message> for RespValue {
+    fn from(message: Message) -> Self {
+        let fields: Vec<Bytes> = match message {
+            Message::Heartbeat(heartbeat) => {
+                let mut fields: Vec<Bytes> = vec![
+                    heartbeat.kind.to_string().into(),
+                    heartbeat.sender.into(),
+                    heartbeat.port.to_string().into(),
+                    heartbeat.cport.to_string().into(),
+                    heartbeat.current_epoch.to_string().into(),
+                    heartbeat.config_epoch.to_string().into(),
+                    heartbeat.slots.into(),
+                ];
+                for gossip in heartbeat.gossip {
+                    fields.extend([
+                        gossip.id.into(),
+                        gossip.host.into(),
+                        gossip.port.to_string().into(),
+                        gossip.cport.to_string().into(),
+                        Bytes::from_static(if gossip.pfail { b"1" } else { b"0" }),
+                        Bytes::from_static(if gossip.fail { b"1" } else { b"0" }),
+                    ]);
+                }
+                fields
+            }
+            Message::Fail { sender, failing } => {
+                vec!["FAIL".into(), sender.into(), failing.into()]
+            }
+        };
+        RespValue::Array(fields.into_iter().map(RespValue::BulkString).collect())
+    }
+}
+
+impl TryFrom<RespValue> for Message {
+    type Error = anyhow::Error;
+
+    fn try_from(value: RespValue) -> Result<Self> {
+        let RespValue::Array(fields) = value else {
+            bail!("Cluster message is not an array");
+        };
+        let fields = fields
+            .into_iter()
+            .map(|field| match field {
+                RespValue::BulkString(field) => Ok(field),
+                _ => Err(anyhow!("Cluster message field is not a bulk string")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let text = |field: &Bytes| {
+            String::from_utf8(field.to_vec()).map_err(|_| anyhow!("Invalid cluster message"))
+        };
+        let number = |field: &Bytes| -> Result<u64> {
+            text(field)?
+                .parse()
+                .map_err(|_| anyhow!("Invalid cluster message"))
+        };
+        let port = |field: &Bytes| -> Result<u16> {
+            u16::try_from(number(field)?).map_err(|_| anyhow!("Invalid cluster message"))
+        };
+        match fields.as_slice() {
+            [kind, sender, failing] if kind.as_ref() == b"FAIL" => Ok(Message::Fail {
+                sender: text(sender)?,
+                failing: text(failing)?,
+            }),
+            [
+                kind,
+                sender,
+                node_port,
+                cport,
+                current_epoch,
+                config_epoch,
+                slots,
+                gossip @ ..,
+            ] => {
+                if slots.len() != SLOTS as usize / 8 || !gossip.len().is_multiple_of(6) {
+                    bail!("Invalid cluster message");
+                }
+                Ok(Message::Heartbeat(Heartbeat {
+                    kind: text(kind)?.parse()?,
+                    sender: text(sender)?,
+                    port: port(node_port)?,
+                    cport: port(cport)?,
+                    current_epoch: number(current_epoch)?,
+                    config_epoch: number(config_epoch)?,
+                    slots: slots.to_vec(),
+                    gossip: gossip
+                        .chunks(6)
+                        .map(|gossip| {
+                            Ok(Gossip {
+                                id: text(&gossip[0])?,
+                                host: text(&gossip[1])?,
+                                port: port(&gossip[2])?,
+                                cport: port(&gossip[3])?,
+                                pfail: gossip[4].as_ref() == b"1",
+                                fail: gossip[5].as_ref() == b"1",
+                            })
+                        })
+                        .collect::<Result<_>>()?,
+                }))
+            }
+            _ => bail!("Invalid cluster message"),
+        }
+    }
+}
+
+/// Accepts links from other nodes on `listeners`, and keeps a link to every
+/// node known, over which it pings them and spreads failures.
+pub fn spawn(cluster: Arc<Cluster>, listeners: Vec<TcpListener>) {
+    for listener in listeners {
+        tokio::spawn(accept_loop(cluster.clone(), listener));
+    }
+    tokio::spawn(async move {
+        let (failures, _) = broadcast::channel(16);
+        let mut links: HashMap<(String, u16), JoinHandle<()>> = HashMap::new();
+        let mut interval = time::interval(CRON_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            for message in cluster.cron() {
+                let _ = failures.send(message);
+            }
+            links.retain(|_, link| !link.is_finished());
+            for (host, cport) in cluster.link_targets() {
+                links.entry((host.clone(), cport)).or_insert_with(|| {
+                    tokio::spawn(link(cluster.clone(), host, cport, failures.subscribe()))
+                });
+            }
+        }
+    });
+}
+
+async fn accept_loop(cluster: Arc<Cluster>, listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                let cluster = cluster.clone();
+                tokio::spawn(async move {
+                    let peer = addr.ip().to_canonical().to_string();
+                    if let Err(e) = serve_link(&cluster, stream, &peer).await {
+                        debug!("Cluster link from {peer} closed: {e}");
+                    }
+                });
+            }
+            Err(e) => warn!("Error accepting cluster link: {e}"),
+        }
+    }
+}
+
+/// Answers the messages a node sends over the link it opened to this one.
+async fn serve_link(cluster: &Cluster, stream: TcpStream, peer: &str) -> Result<()> {
+    let mut handler = RespHandler::new(stream, ProtocolLimits::default());
+    while let Some(values) = handler.read_values().await? {
+        for value in values {
+            if let Some(reply) = cluster.receive(Message::try_from(value)?, peer, None) {
+                handler.queue_value(reply.into());
+                cluster.message_sent();
+            }
+        }
+        handler.flush().await?;
+    }
+    Ok(())
+}
+
+/// Keeps a link to the bus of the node at `host:cport`, reconnecting when it
+/// drops, until the node is forgotten.
+async fn link(
+    cluster: Arc<Cluster>,
+    host: String,
+    cport: u16,
+    mut failures: broadcast::Receiver<Message>,
+) {
+    loop {
+        // While the node cannot be reached its pings count as unanswered,
+        // so that it is eventually deemed failing.
+        if cluster.ping(&host, cport).is_none() {
+            return;
+        }
+        match time::timeout(CONNECT_TIMEOUT, TcpStream::connect((host.as_str(), cport))).await {
+            Ok(Ok(stream)) => {
+                cluster.set_connected(&host, cport, true);
+                if let Err(e) = run_link(&cluster, stream, &host, cport, &mut failures).await {
+                    debug!("Cluster link to {host}:{cport} closed: {e}");
+                }
+                cluster.set_connected(&host, cport, false);
+            }
+            Ok(Err(e)) => debug!("Error connecting to cluster node {host}:{cport}: {e}"),
+            Err(_) => debug!("Timed out connecting to cluster node {host}:{cport}"),
+        }
+        time::sleep(CONNECT_TIMEOUT).await;
+    }
+}
+
+/// Pings the node every second and forwards failures to it, taking in its
+/// answers, until the link drops. Returns `Ok` once the node is forgotten.
+async fn run_link(
+    cluster: &Cluster,
+    stream: TcpStream,
+    host: &str,
+    cport: u16,
+    failures: &mut broadcast::Receiver<Message>,
+) -> Result<()> {
+    let mut handler = RespHandler::new(stream, ProtocolLimits::default());
+    let mut interval = time::interval(PING_INTERVAL);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let Some(ping) = cluster.ping(host, cport) else {
+                    return Ok(());
+                };
+                handler.write_value(ping.into()).await?;
+                cluster.message_sent();
+            }
+            failure = failures.recv() => {
+                if let Ok(failure) = failure {
+                    handler.write_value(failure.into()).await?;
+                    cluster.message_sent();
+                }
+            }
+            values = handler.read_values() => {
+                let Some(values) = values? else {
+                    bail!("Connection closed");
+                };
+                for value in values {
+                    cluster.receive(Message::try_from(value)?, host, Some((host, cport)));
+                }
+            }
+        }
+    }
+}
@@ -1,326 +1,669 @@
-pub(crate) mod parser;
+pub(crate) mod error;
+pub(crate) mod info;
+pub(crate) mod keys;
+pub mod parser;
 pub(crate) mod xstream_helpers;
 
 use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use anyhow::Result;
-use tokio::sync::{Mutex, mpsc};
+use bytes::Bytes;
+use tokio::{
+    runtime::{Handle, RuntimeFlavor},
+    sync::{RwLock, mpsc},
+    time::Instant,
+};
+use tracing::{error, warn};
 
 use crate::{
+    acl,
+    clients::{ClientType, KillFilter, tracking::TrackingOptions},
+    cluster,
     db::{
-        Db, DbValue,
+        Db, DbValue, ExpireCondition, ListEnd,
         blocking::{ListNotification, StreamNotification},
+        clock,
+        notifications::EventClass,
+        scan::ScanOptions,
+        sorted_set::{Aggregate, ZaddOptions, ZsetOperation, ZsetRange, format_score},
+        stream_types::{StreamId, StreamItem, TrimStrategy},
     },
+    glob::glob_match,
+    persistence, replication,
     resp::RespValue,
+    scripting, shutdown,
 };
 
-use self::xstream_helpers::{XreadDuration, XreadStartId, derive_new_stream_id};
+use self::xstream_helpers::{XaddId, XreadDuration, XreadStartId, derive_new_stream_id};
 
 #[derive(Debug)]
 pub enum Command {
     Ping,
     Echo {
-        message: String,
+        message: Bytes,
     },
     Set {
-        key: String,
-        value: String,
-        expiry_millis: Option<u64>,
+        key: Bytes,
+        value: Bytes,
+        condition: Option<SetCondition>,
+        get: bool,
+        keep_ttl: bool,
+        expiration: Option<Expiration>,
+    },
+    Mget {
+        keys: Vec<Bytes>,
+    },
+    Mset {
+        pairs: Vec<(Bytes, Bytes)>,
+        nx: bool,
+    },
+    Getex {
+        key: Bytes,
+        expiration: Option<Expiration>,
+        persist: bool,
+    },
+    Getdel {
+        key: Bytes,
+    },
+    Incrby {
+        key: Bytes,
+        delta: i64,
+    },
+    Incrbyfloat {
+        key: Bytes,
+        delta: f64,
+    },
+    Append {
+        key: Bytes,
+        value: Bytes,
+    },
+    Strlen {
+        key: Bytes,
+    },
+    Getrange {
+        key: Bytes,
+        start: i64,
+        end: i64,
+    },
+    Setrange {
+        key: Bytes,
+        offset: usize,
+        value: Bytes,
     },
     Rpush {
-        key: String,
-        values: Vec<String>,
+        key: Bytes,
+        values: Vec<Bytes>,
     },
     Lpush {
-        key: String,
-        values: Vec<String>,
+        key: Bytes,
+        values: Vec<Bytes>,
     },
-    Lpop {
-        key: String,
-        count: usize,
+    Pop {
+        key: Bytes,
+        end: ListEnd,
+        count: Option<usize>,
     },
-    Blpop {
-        key: String,
+    Bpop {
+        keys: Vec<Bytes>,
+        end: ListEnd,
         timeout_seconds: f64,
     },
     Llen {
-        key: String,
+        key: Bytes,
+    },
+    Linsert {
+        key: Bytes,
+        after: bool,
+        pivot: Bytes,
+        element: Bytes,
+    },
+    Lset {
+        key: Bytes,
+        index: i64,
+        element: Bytes,
+    },
+    Lindex {
+        key: Bytes,
+        index: i64,
+    },
+    Lrem {
+        key: Bytes,
+        count: i64,
+        element: Bytes,
+    },
+    Ltrim {
+        key: Bytes,
+        start: i64,
+        stop: i64,
+    },
+    Lpos {
+        key: Bytes,
+        element: Bytes,
+        rank: i64,
+        count: Option<usize>,
+        maxlen: usize,
     },
     Get {
-        key: String,
+        key: Bytes,
+    },
+    Expire {
+        /// The command's lowercase name, for its errors.
+        name: String,
+        key: Bytes,
+        expiration: Expiration,
+        condition: ExpireCondition,
+    },
+    Ttl {
+        key: Bytes,
+    },
+    Pttl {
+        key: Bytes,
+    },
+    Expiretime {
+        key: Bytes,
+    },
+    Pexpiretime {
+        key: Bytes,
+    },
+    Persist {
+        key: Bytes,
+    },
+    Del {
+        keys: Vec<Bytes>,
+    },
+    Unlink {
+        keys: Vec<Bytes>,
+    },
+    Exists {
+        keys: Vec<Bytes>,
+    },
+    Rename {
+        key: Bytes,
+        new_key: Bytes,
+        nx: bool,
+    },
+    Copy {
+        source: Bytes,
+        destination: Bytes,
+        replace: bool,
+    },
+    Randomkey,
+    Dbsize,
+    Flush {
+        lazy: bool,
+    },
+    ObjectEncoding {
+        key: Bytes,
+    },
+    ObjectIdletime {
+        key: Bytes,
+    },
+    ObjectFreq {
+        key: Bytes,
+    },
+    Scan {
+        cursor: u64,
+        options: ScanOptions,
+        type_name: Option<String>,
     },
     Lrange {
-        key: String,
-        start: isize,
-        stop: isize,
+        key: Bytes,
+        start: i64,
+        stop: i64,
     },
     Type {
-        key: String,
+        key: Bytes,
     },
     Xadd {
-        key: String,
-        id: String,
-        field_value_pairs: Vec<(String, String)>,
+        key: Bytes,
+        id: XaddId,
+        field_value_pairs: Vec<(Bytes, Bytes)>,
     },
     Xrange {
-        key: String,
-        start: Option<String>,
-        end: Option<String>,
+        key: Bytes,
+        start: StreamId,
+        end: StreamId,
     },
     Xread {
-        streams: Vec<(String, XreadStartId)>,
+        streams: Vec<(Bytes, XreadStartId)>,
         duration: XreadDuration,
     },
+    Xlen {
+        key: Bytes,
+    },
+    Xdel {
+        key: Bytes,
+        ids: Vec<StreamId>,
+    },
+    Xtrim {
+        key: Bytes,
+        strategy: TrimStrategy,
+        approximate: bool,
+    },
+    Hset {
+        key: Bytes,
+        field_value_pairs: Vec<(Bytes, Bytes)>,
+    },
+    Hget {
+        key: Bytes,
+        field: Bytes,
+    },
+    Hdel {
+        key: Bytes,
+        fields: Vec<Bytes>,
+    },
+    Hgetall {
+        key: Bytes,
+    },
+    Hlen {
+        key: Bytes,
+    },
+    Hscan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+        novalues: bool,
+    },
+    Sadd {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Srem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Smembers {
+        key: Bytes,
+    },
+    Sismember {
+        key: Bytes,
+        member: Bytes,
+    },
+    Smismember {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Spop {
+        key: Bytes,
+        count: Option<usize>,
+    },
+    Srandmember {
+        key: Bytes,
+        count: Option<i64>,
+    },
+    Smove {
+        source: Bytes,
+        destination: Bytes,
+        member: Bytes,
+    },
+    Scard {
+        key: Bytes,
+    },
+    Sscan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    Zadd {
+        key: Bytes,
+        score_member_pairs: Vec<(f64, Bytes)>,
+        options: ZaddOptions,
+    },
+    Zstore {
+        operation: ZsetOperation,
+        destination: Bytes,
+        keys: Vec<Bytes>,
+        weights: Vec<f64>,
+        aggregate: Aggregate,
+    },
+    Zdiff {
+        keys: Vec<Bytes>,
+        with_scores: bool,
+    },
+    Eval {
+        script: Bytes,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+    },
+    Evalsha {
+        sha1: String,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+    },
+    ScriptLoad {
+        script: Bytes,
+    },
+    ScriptExists {
+        sha1s: Vec<String>,
+    },
+    ScriptFlush,
+    ScriptKill,
+    Fcall {
+        function: String,
+        keys: Vec<Bytes>,
+        args: Vec<Bytes>,
+        read_only: bool,
+    },
+    FunctionLoad {
+        code: Bytes,
+        replace: bool,
+    },
+    FunctionDelete {
+        library: String,
+    },
+    FunctionFlush,
+    FunctionList {
+        pattern: Option<Bytes>,
+        with_code: bool,
+    },
+    FunctionStats,
+    Zremrange {
+        key: Bytes,
+        range: ZsetRange,
+    },
+    Zrandmember {
+        key: Bytes,
+        count: Option<i64>,
+        with_scores: bool,
+    },
+    Zrem {
+        key: Bytes,
+        members: Vec<Bytes>,
+    },
+    Zscore {
+        key: Bytes,
+        member: Bytes,
+    },
+    Zrank {
+        key: Bytes,
+        member: Bytes,
+    },
+    Zcard {
+        key: Bytes,
+    },
+    Zscan {
+        key: Bytes,
+        cursor: u64,
+        options: ScanOptions,
+    },
+    Zrange {
+        key: Bytes,
+        start: isize,
+        stop: isize,
+        with_scores: bool,
+    },
+    Multi,
+    Exec,
+    Discard,
+    Subscribe {
+        channels: Vec<Bytes>,
+    },
+    Unsubscribe {
+        channels: Vec<Bytes>,
+    },
+    Psubscribe {
+        patterns: Vec<Bytes>,
+    },
+    Punsubscribe {
+        patterns: Vec<Bytes>,
+    },
+    Publish {
+        channel: Bytes,
+        message: Bytes,
+    },
+    PubsubChannels {
+        pattern: Option<Bytes>,
+    },
+    PubsubNumsub {
+        channels: Vec<Bytes>,
+    },
+    PubsubNumpat,
+    Save,
+    Bgsave,
+    Bgrewriteaof,
+    Lastsave,
+    Role,
+    /// `save` forces an RDB snapshot before exiting, or skips it, rather
+    /// than take one only when save points are configured.
+    Shutdown {
+        save: Option<bool>,
+    },
+    ConfigGet {
+        patterns: Vec<String>,
+    },
+    /// Parameter and value pairs, set all at once.
+    ConfigSet {
+        parameters: Vec<(String, String)>,
+    },
+    Info {
+        sections: Vec<String>,
+    },
+    /// The command line whose keys `COMMAND GETKEYS` reports.
+    Getkeys {
+        argv: Vec<Bytes>,
+    },
+    /// Creates or changes a user with the rules given, in order.
+    AclSetuser {
+        username: String,
+        rules: Vec<String>,
+    },
+    AclGetuser {
+        username: String,
+    },
+    AclDeluser {
+        usernames: Vec<String>,
+    },
+    AclList,
+    AclWhoami,
+    LatencyLatest,
+    LatencyHistory {
+        event: String,
+    },
+    /// Forgets the spikes of `events`, or of every event when empty.
+    LatencyReset {
+        events: Vec<String>,
+    },
+    /// The histograms of `commands`, or of every command when empty.
+    LatencyHistogram {
+        commands: Vec<String>,
+    },
+    ClusterInfo,
+    ClusterMyid,
+    ClusterNodes,
+    ClusterSlots,
+    ClusterShards,
+    ClusterKeyslot {
+        key: Bytes,
+    },
+    ClusterMeet {
+        host: String,
+        port: u16,
+        bus_port: u16,
+    },
+    ClusterForget {
+        id: String,
+    },
+    ClusterAddslots {
+        slots: Vec<u16>,
+    },
+    ClusterDelslots {
+        slots: Vec<u16>,
+    },
+    ClusterCountFailureReports {
+        id: String,
+    },
+    ClientId,
+    ClientSetname {
+        name: String,
+    },
+    ClientGetname,
+    /// The clients of `client_type`, or with one of `ids`, or all of them.
+    ClientList {
+        client_type: Option<ClientType>,
+        ids: Vec<u64>,
+    },
+    ClientInfo,
+    /// `legacy` for the `CLIENT KILL <addr>` form, which replies `OK` rather
+    /// than the number of clients killed.
+    ClientKill {
+        filter: KillFilter,
+        legacy: bool,
+    },
+    /// `CLIENT TRACKING ON` with its options, or `OFF` for `None`.
+    ClientTracking {
+        options: Option<TrackingOptions>,
+    },
+    ClientCaching {
+        enabled: bool,
+    },
+    ClientGetredir,
+    ClientTrackinginfo,
+    Replconf {
+        args: Vec<String>,
+    },
+    ReplconfAck {
+        offset: u64,
+    },
+    ReplconfGetack,
+    Psync {
+        replid: String,
+        /// Set by a master handing over to this replica with `FAILOVER`.
+        failover: bool,
+    },
+    /// Lets a cluster client read from replicas.
+    Readonly,
+    Readwrite,
+    Failover {
+        target: Option<(String, u16)>,
+        /// Fail over to `target` even if it did not catch up in time.
+        force: bool,
+        timeout_millis: Option<u64>,
+    },
+    FailoverAbort,
+    Wait {
+        numreplicas: u64,
+        timeout_millis: u64,
+    },
+    Replicaof {
+        master: Option<(String, u16)>,
+    },
+    Hello {
+        protover: Option<u8>,
+        /// The username and password to authenticate with.
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+    },
+    Reset,
+    Auth {
+        username: Option<String>,
+        password: String,
+    },
+    Quit,
+    Monitor,
+    /// Holds the `Db` for `duration`, as a busy server would.
+    DebugSleep {
+        duration: Duration,
+    },
+    DebugObject {
+        key: Bytes,
+    },
+    DebugSetActiveExpire {
+        enabled: bool,
+    },
+    /// Whether the client's socket acknowledges segments right away.
+    DebugQuickack {
+        enabled: bool,
+    },
+}
+
+/// The `NX` and `XX` flags of `SET`: only set the key if it does not
+/// already exist, or only if it does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetCondition {
+    Nx,
+    Xx,
+}
+
+/// When an `EXPIRE`-family command, or `SET`, makes its key expire.
+#[derive(Clone, Copy, Debug)]
+pub enum Expiration {
+    /// Milliseconds from the time the command runs.
+    In(i64),
+    /// A UNIX time in milliseconds.
+    At(i64),
+}
+
+impl Expiration {
+    /// The UNIX time in milliseconds the key expires at, for the command
+    /// `name`.
+    fn unix_millis(self, now_millis: i64, name: &str) -> Result<i64> {
+        match self {
+            Expiration::In(millis) => now_millis
+                .checked_add(millis)
+                .ok_or_else(|| anyhow::anyhow!("ERR invalid expire time in '{name}' command")),
+            Expiration::At(millis) => Ok(millis),
+        }
+    }
 }
 
 impl Command {
-    pub async fn execute(self, db: Arc<Mutex<Db>>) -> Result<RespValue> {
+    pub async fn execute(self, db: Arc<RwLock<Db>>, argv: Vec<RespValue>) -> Result<RespValue> {
         match self {
-            Command::Ping => Ok(RespValue::SimpleString("PONG".to_string())),
-            Command::Echo { message } => Ok(RespValue::BulkString(message)),
-            Command::Set {
-                key,
-                value,
-                expiry_millis,
-            } => {
-                let mut db = db.lock().await;
-                if let Some(millis) = expiry_millis {
-                    db.set_expiration(&key, millis);
-                }
-                db.insert(&key, DbValue::Atom(value));
-                Ok(RespValue::SimpleString("OK".to_string()))
-            }
-            Command::Rpush { key, values } => {
-                let length = db.lock().await.rpush(&key, values)?;
-                Ok(RespValue::Integer(length))
-            }
-            Command::Lpush { key, values } => {
-                let length = db.lock().await.lpush(&key, values)?;
-                Ok(RespValue::Integer(length))
-            }
-            Command::Lpop { key, count } => {
-                let poped_list = db.lock().await.lpop(&key, count);
-                if poped_list.is_empty() {
-                    Ok(RespValue::NullBulkString)
-                } else if poped_list.len() == 1 {
-                    Ok(RespValue::BulkString(poped_list[0].clone()))
-                } else {
-                    Ok(RespValue::Array(
-                        poped_list.into_iter().map(RespValue::BulkString).collect(),
-                    ))
-                }
-            }
-            Command::Blpop {
-                key,
+            Command::Bpop {
+                keys,
+                end,
                 timeout_seconds,
             } => {
-                let initial_lpop_result = {
-                    let mut db_g = db.lock().await;
-                    db_g.lpop(&key, 1)
-                };
-
-                if !initial_lpop_result.is_empty() {
-                    return Ok(RespValue::Array(
-                        std::iter::once(RespValue::BulkString(key))
-                            .chain(initial_lpop_result.into_iter().map(RespValue::BulkString))
-                            .collect(),
-                    ));
-                }
-
-                if timeout_seconds == 0.0 {
-                    return Ok(RespValue::NullArray);
-                }
-
+                // A client is handed a single element, popped for it by the
+                // command that pushed it.
                 let (sender, mut receiver) = mpsc::channel::<ListNotification>(1);
+                let clients = db.read().await.clients().clone();
                 let client_id = {
-                    let mut db_g = db.lock().await;
-                    db_g.add_blocked_lpop_client(key.clone(), sender)
-                };
-
-                let timeout_duration = Duration::from_secs_f64(timeout_seconds);
-
-                tokio::select! {
-                    _ = tokio::time::sleep(timeout_duration) => {
-                        let mut db_g = db.lock().await;
-                        db_g.remove_blocked_client(&client_id, &key);
-                        Ok(RespValue::NullArray)
-                    },
-                    Some(_notification) = receiver.recv() => {
-                        let mut db_g = db.lock().await;
-                        db_g.remove_blocked_client(&client_id, &key);
-                        let results = db_g.lpop(&key, 1);
-
-                        if !results.is_empty() {
-                            Ok(RespValue::Array(
-                                std::iter::once(RespValue::BulkString(key))
-                                    .chain(results.into_iter().map(RespValue::BulkString))
-                                    .collect(),
-                            ))
-                        } else {
-                            Ok(RespValue::NullArray)
+                    let mut db_g = db.write().await;
+                    for key in &keys {
+                        let popped = db_g.pop(key, end, 1)?;
+                        if !popped.is_empty() {
+                            db_g.propagate(&pop_argv(key, end));
+                            return Ok(bpop_reply(key.clone(), popped));
                         }
                     }
-                }
-            }
-            Command::Llen { key } => {
-                let length = db.lock().await.llen(&key);
-                Ok(RespValue::Integer(length))
-            }
-            Command::Get { key } => {
-                let (value, is_expired) = {
-                    let mut db_g = db.lock().await;
-                    let is_expired = db_g.is_expired(&key);
-                    let value = db_g.get(&key);
-                    if is_expired {
-                        db_g.expire(&key);
-                    }
-                    (value, is_expired)
-                };
-
-                match (value, is_expired) {
-                    (Some(value), false) => match value {
-                        DbValue::Atom(v) => Ok(RespValue::BulkString(v.to_string())),
-                        DbValue::List(_) => Ok(RespValue::NullBulkString),
-                        DbValue::Stream(_) => Ok(RespValue::NullBulkString),
-                    },
-                    _ => Ok(RespValue::NullBulkString),
-                }
-            }
-            Command::Lrange { key, start, stop } => {
-                let db_result = db.lock().await.lrange(&key, start, stop);
-
-                if let DbValue::List(l) = db_result {
-                    let v = l.into_iter().map(RespValue::BulkString).collect();
-                    Ok(RespValue::Array(v))
-                } else {
-                    Ok(RespValue::NullBulkString)
-                }
-            }
-            Command::Type { key } => {
-                let db_result = db.lock().await.get(&key);
-                if let Some(result) = db_result {
-                    match result {
-                        DbValue::Atom(_) => Ok(RespValue::SimpleString("string".to_string())),
-                        DbValue::List(_) => Ok(RespValue::SimpleString("list".to_string())),
-                        DbValue::Stream(_) => Ok(RespValue::SimpleString("stream".to_string())),
-                    }
-                } else {
-                    Ok(RespValue::SimpleString("none".to_string()))
-                }
-            }
-            Command::Xadd {
-                key,
-                id,
-                field_value_pairs,
-            } => {
-                let mut db_g = db.lock().await;
-
-                let last_item_id_option = if let Some(DbValue::Stream(stream_list)) = db_g.get(&key)
-                {
-                    stream_list.0.last().map(|item| item.id.clone())
-                } else {
-                    None
+                    db_g.add_blocked_list_client(keys.clone(), end, sender)
                 };
 
-                let new_id = derive_new_stream_id(&id, last_item_id_option.as_ref())?;
-
-                db_g.xadd(
-                    &key,
-                    &new_id,
-                    field_value_pairs
-                        .into_iter()
-                        .collect::<HashMap<String, String>>(),
-                )?;
-                Ok(RespValue::BulkString(new_id))
-            }
-
-            Command::Xrange {
-                key,
-                start: start_opt,
-                end: end_opt,
-            } => {
-                let mut db_g = db.lock().await;
-
-                let start_id = start_opt.map_or_else(
-                    || db_g.xfirst(&key).unwrap().id.clone(),
-                    |start_val| {
-                        if start_val == "-" {
-                            db_g.xfirst(&key).unwrap().id.clone()
-                        } else {
-                            start_val
-                        }
-                    },
-                );
-
-                let end_id = end_opt.map_or_else(
-                    || db_g.xlast(&key).unwrap().id.clone(),
-                    |end_val| {
-                        if end_val == "+" {
-                            db_g.xlast(&key).unwrap().id.clone()
-                        } else {
-                            end_val
+                // A zero timeout blocks until one of the keys is pushed to.
+                let deadline = (timeout_seconds > 0.0)
+                    .then(|| Instant::now() + Duration::from_secs_f64(timeout_seconds));
+                // Shutting down, the client gets the reply of a timeout.
+                let notification = tokio::select! {
+                    notification = async {
+                        match deadline {
+                            Some(deadline) => {
+                                tokio::time::timeout_at(deadline, receiver.recv())
+                                    .await
+                                    .ok()
+                                    .flatten()
+                            }
+                            None => receiver.recv().await,
                         }
-                    },
-                );
-
-                let streams = db_g
-                    .xrange(&key, &start_id, &end_id)
-                    .map_err(|e| anyhow::anyhow!(e.to_string()))?;
-
-                let resp = streams
-                    .iter()
-                    .map(|item| {
-                        let values_array_items: Vec<RespValue> = item
-                            .values
-                            .iter()
-                            .flat_map(|(key, value)| {
-                                vec![
-                                    RespValue::BulkString(key.clone()),
-                                    RespValue::BulkString(value.clone()),
-                                ]
-                            })
-                            .collect();
-
-                        let inner_values_resp_array = RespValue::Array(values_array_items);
+                    } => notification,
+                    _ = clients.shutting_down() => None,
+                };
 
-                        RespValue::Array(vec![
-                            RespValue::BulkString(item.id.clone()),
-                            inner_values_resp_array,
-                        ])
-                    })
-                    .collect::<Vec<RespValue>>();
-                Ok(RespValue::Array(resp))
+                let mut db_g = db.write().await;
+                // An element may have been handed over while timing out.
+                let notification = notification.or_else(|| receiver.try_recv().ok());
+                for key in &keys {
+                    db_g.remove_blocked_client(&client_id, key);
+                }
+                Ok(match notification {
+                    Some(ListNotification { key, element }) => bpop_reply(key, vec![element]),
+                    None => RespValue::NullArray,
+                })
             }
             Command::Xread { streams, duration } => {
                 {
-                    let mut db_g = db.lock().await;
-
-                    let initial_stream_responses = streams
-                        .iter()
-                        .filter_map(|(key, start)| {
-                            let last_id_for_stream = db_g.xlast(key).map(|item| item.id.clone());
-                            let start_id_str =
-                                start.to_str(last_id_for_stream.as_deref().unwrap_or("0-0"));
-
-                            db_g.xread(key, &start_id_str)
-                                .ok()
-                                .and_then(|stream_items| {
-                                    let resp_stream_content = stream_items
-                                        .iter()
-                                        .map(|stream_item| stream_item.to_resp())
-                                        .collect::<Vec<RespValue>>();
-                                    if !resp_stream_content.is_empty() {
-                                        Some(RespValue::Array(vec![
-                                            RespValue::BulkString(key.to_string()),
-                                            RespValue::Array(resp_stream_content),
-                                        ]))
-                                    } else {
-                                        None
-                                    }
-                                })
-                        })
-                        .collect::<Vec<RespValue>>();
-
+                    let db_g = db.read().await;
+                    let initial_stream_responses = xread_available(&db_g, &streams)?;
                     if !initial_stream_responses.is_empty() {
                         return Ok(RespValue::Array(initial_stream_responses));
                     }
@@ -332,15 +675,12 @@ impl Command {
                         let (sender, mut receiver) = mpsc::channel::<StreamNotification>(100);
                         let stream = streams[0].clone();
                         let (key, start) = stream;
-                        let start_id_str = {
-                            let db_g = db.lock().await;
-                            let last_id = db_g.xlast(&key).map(|item| item.id.clone());
-                            start.to_str(last_id.as_deref().unwrap_or("0-0"))
-                        };
+                        let start_id = start.resolve(db.read().await.xlast_id(&key)?);
 
-                        let client_id = db.lock().await.add_blocked_xread_client(
+                        let clients = db.read().await.clients().clone();
+                        let client_id = db.write().await.add_blocked_xread_client(
                             key.clone(),
-                            start_id_str.clone(),
+                            start_id,
                             sender,
                         );
 
@@ -366,18 +706,21 @@ impl Command {
                             Some(_notification) = receiver.recv() => {
                                 // Notification received
                             }
+                            _ = clients.shutting_down() => {
+                                // Server shutting down, reply as on timeout
+                            }
                         }
-                        let mut db_g = db.lock().await;
+                        let mut db_g = db.write().await;
                         db_g.remove_blocked_client(&client_id, &key);
 
-                        let stream_items = db_g.xread(&key, &start_id_str)?;
+                        let stream_items = db_g.xread(&key, start_id)?;
                         if !stream_items.is_empty() {
                             let resp_stream_content = stream_items
                                 .iter()
                                 .map(|stream_item| stream_item.to_resp())
                                 .collect::<Vec<RespValue>>();
                             return Ok(RespValue::Array(vec![RespValue::Array(vec![
-                                RespValue::BulkString(key.to_string()),
+                                RespValue::BulkString(key.clone()),
                                 RespValue::Array(resp_stream_content),
                             ])]));
                         }
@@ -385,6 +728,1460 @@ impl Command {
                 }
                 Ok(RespValue::NullArray)
             }
+            Command::Replicaof { master } => {
+                let mut db_g = db.write().await;
+                match master {
+                    Some((host, port)) => {
+                        if !replication::replicaof(db.clone(), &mut db_g, host, port) {
+                            return Ok(RespValue::SimpleString(
+                                "OK Already connected to specified master".to_string(),
+                            ));
+                        }
+                    }
+                    None => db_g.replication_mut().stop_replication(),
+                }
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Failover {
+                target,
+                force,
+                timeout_millis,
+            } => {
+                let mut db_g = db.write().await;
+                let timeout = timeout_millis.map(Duration::from_millis);
+                replication::failover(db.clone(), &mut db_g, target, timeout, force)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::FailoverAbort => {
+                replication::abort_failover(&mut *db.write().await)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Bgrewriteaof => {
+                let mut db_g = db.write().await;
+                persistence::aof::bgrewrite(db.clone(), &mut db_g)?;
+                Ok(RespValue::SimpleString(
+                    "Background append only file rewriting started".to_string(),
+                ))
+            }
+            command if command.is_read_only() => command.apply_shared(&*db.read().await, &argv),
+            command => {
+                let mut db_g = db.write().await;
+                let result = command.apply_and_propagate(&mut db_g, argv);
+                serve_blocked_clients(&mut db_g);
+                result
+            }
+        }
+    }
+
+    /// Whether the command only reads the `Db`, and so may run under a
+    /// shared lock alongside other reads.
+    pub fn is_read_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Ping
+                | Command::Echo { .. }
+                | Command::Get { .. }
+                | Command::Mget { .. }
+                | Command::Strlen { .. }
+                | Command::Getrange { .. }
+                | Command::Exists { .. }
+                | Command::Type { .. }
+                | Command::Ttl { .. }
+                | Command::Pttl { .. }
+                | Command::Expiretime { .. }
+                | Command::Pexpiretime { .. }
+                | Command::Dbsize
+                | Command::ObjectEncoding { .. }
+                | Command::ObjectIdletime { .. }
+                | Command::ObjectFreq { .. }
+                | Command::Lrange { .. }
+                | Command::Llen { .. }
+                | Command::Lindex { .. }
+                | Command::Lpos { .. }
+                | Command::Xrange { .. }
+                | Command::Xlen { .. }
+                | Command::Hget { .. }
+                | Command::Hgetall { .. }
+                | Command::Hlen { .. }
+                | Command::Smembers { .. }
+                | Command::Sismember { .. }
+                | Command::Smismember { .. }
+                | Command::Scard { .. }
+                | Command::Zscore { .. }
+                | Command::Zrank { .. }
+                | Command::Zcard { .. }
+                | Command::Zrange { .. }
+                | Command::Scan { .. }
+                | Command::Hscan { .. }
+                | Command::Sscan { .. }
+                | Command::Zscan { .. }
+        )
+    }
+
+    /// Runs a command that only reads under a shared lock, timing it like
+    /// `apply_and_propagate` does. There is nothing to propagate.
+    pub fn apply_shared(self, db: &Db, argv: &[RespValue]) -> Result<RespValue> {
+        let label = command_label(argv);
+        let started = Instant::now();
+        let result = self.apply_read(db);
+        record_command_latency(db, &label, started.elapsed());
+        if result.is_ok() {
+            db.clients().remember_reads(argv);
         }
+        result
     }
+
+    /// Runs the command and, if it is a write that succeeded, propagates
+    /// `argv` while the lock is still held so the log keeps execution order.
+    pub fn apply_and_propagate(
+        mut self,
+        db: &mut Db,
+        mut argv: Vec<RespValue>,
+    ) -> Result<RespValue> {
+        // Relative expirations are propagated as absolute ones, so replaying
+        // them later does not extend the time to live.
+        if let Command::Expire {
+            name, expiration, ..
+        } = &mut self
+        {
+            let at = expiration.unix_millis(clock::now_millis() as i64, name)?;
+            *expiration = Expiration::At(at);
+            argv[0] = RespValue::BulkString("PEXPIREAT".into());
+            argv[2] = RespValue::BulkString(at.to_string().into());
+        }
+        match &mut self {
+            Command::Set {
+                key,
+                value,
+                expiration: Some(expiration),
+                ..
+            } => {
+                let name = String::from(argv[0].clone()).to_lowercase();
+                if name == "set" {
+                    make_absolute(expiration, &mut argv[3..], &name)?;
+                } else {
+                    // SETEX and PSETEX are propagated as the SET they stand for.
+                    let at = make_absolute(expiration, &mut [], &name)?;
+                    argv = ["SET".into(), key.clone(), value.clone(), "PXAT".into()]
+                        .into_iter()
+                        .chain([Bytes::from(at.to_string())])
+                        .map(RespValue::BulkString)
+                        .collect();
+                }
+            }
+            Command::Getex {
+                expiration: Some(expiration),
+                ..
+            } => {
+                make_absolute(expiration, &mut argv[2..], "getex")?;
+            }
+            _ => {}
+        }
+        let is_write = is_write(&argv);
+        let is_xadd = matches!(self, Command::Xadd { .. });
+        let bpop_end = match &self {
+            Command::Bpop { end, .. } => Some(*end),
+            _ => None,
+        };
+        let spop_key = match &self {
+            Command::Spop { key, .. } => Some(key.clone()),
+            _ => None,
+        };
+        let xtrim_key = match &self {
+            Command::Xtrim { key, .. } => Some(key.clone()),
+            _ => None,
+        };
+        let label = command_label(&argv);
+        db.set_counting_lookups(!is_write);
+        let started = Instant::now();
+        let result = self.apply(db);
+        let elapsed = started.elapsed();
+        db.set_counting_lookups(true);
+        db.unhide_expired();
+        db.clients().send_broadcasts();
+        record_command_latency(db, &label, elapsed);
+        let result = result?;
+
+        if !is_write {
+            db.clients().remember_reads(&argv);
+        }
+        if is_write {
+            // Auto-generated stream IDs must be replayed verbatim.
+            if is_xadd && let RespValue::BulkString(id) = &result {
+                argv[2] = RespValue::BulkString(id.clone());
+            }
+            // Blocking pops are replayed as the pop on the key that had
+            // elements.
+            if let Some(end) = bpop_end {
+                let RespValue::Array(reply) = &result else {
+                    return Ok(result);
+                };
+                argv = pop_argv(&Bytes::from(reply[0].clone()), end);
+            }
+            // The members picked at random are replayed as an SREM of them.
+            if let Some(key) = spop_key {
+                let popped = match &result {
+                    RespValue::BulkString(member) => vec![RespValue::BulkString(member.clone())],
+                    RespValue::Set(members) if !members.is_empty() => members.clone(),
+                    _ => return Ok(result),
+                };
+                argv = [
+                    RespValue::BulkString("SREM".into()),
+                    RespValue::BulkString(key),
+                ]
+                .into_iter()
+                .chain(popped)
+                .collect();
+            }
+            // Approximate trimming depends on how the entries are chunked,
+            // which a rewritten log does not reproduce, so the length it
+            // left is replayed instead.
+            if let Some(key) = xtrim_key {
+                let length = db.xlen(&key)?;
+                argv = [
+                    "XTRIM".into(),
+                    key,
+                    "MAXLEN".into(),
+                    length.to_string().into(),
+                ]
+                .into_iter()
+                .map(RespValue::BulkString)
+                .collect();
+            }
+            db.propagate(&argv);
+        }
+        Ok(result)
+    }
+
+    /// Runs the command against an already locked `Db`. Blocking commands
+    /// behave as their non-blocking counterpart, which is what `EXEC` needs.
+    pub fn apply(self, db: &mut Db) -> Result<RespValue> {
+        match self {
+            Command::Set {
+                key,
+                value,
+                condition,
+                get,
+                keep_ttl,
+                expiration,
+            } => {
+                let exists = db.exists(&key);
+                let previous = if get {
+                    db.get_string(&key)?
+                        .map_or(RespValue::NullBulkString, RespValue::BulkString)
+                } else {
+                    RespValue::SimpleString("OK".to_string())
+                };
+                let skipped = match condition {
+                    Some(SetCondition::Nx) => exists,
+                    Some(SetCondition::Xx) => !exists,
+                    None => false,
+                };
+                if skipped {
+                    return Ok(if get {
+                        previous
+                    } else {
+                        RespValue::NullBulkString
+                    });
+                }
+
+                let now_millis = clock::now_millis() as i64;
+                let at = expiration
+                    .map(|expiration| expiration.unix_millis(now_millis, "set"))
+                    .transpose()?;
+                db.insert(&key, DbValue::Atom(value));
+                match at {
+                    // An absolute time in the past leaves nothing to set.
+                    Some(at) if at <= now_millis => {
+                        db.del(&key);
+                        return Ok(previous);
+                    }
+                    Some(at) => db.set_expiration(&key, at as u64),
+                    None if !keep_ttl => db.remove_expiration(&key),
+                    None => {}
+                }
+                db.notify_keyspace_event(EventClass::String, "set", &key);
+                if at.is_some() {
+                    db.notify_keyspace_event(EventClass::Generic, "expire", &key);
+                }
+                Ok(previous)
+            }
+            Command::Rpush { key, values } => {
+                let length = db.rpush(&key, values)?;
+                Ok(RespValue::Integer(length as i64))
+            }
+            Command::Lpush { key, values } => {
+                let length = db.lpush(&key, values)?;
+                Ok(RespValue::Integer(length as i64))
+            }
+            Command::Pop { key, end, count } => {
+                let popped = db.pop(&key, end, count.unwrap_or(1))?;
+                Ok(match count {
+                    None => popped
+                        .into_iter()
+                        .next()
+                        .map_or(RespValue::NullBulkString, RespValue::BulkString),
+                    Some(0) if db.exists(&key) => RespValue::Array(vec![]),
+                    Some(_) if popped.is_empty() => RespValue::NullArray,
+                    Some(_) => {
+                        RespValue::Array(popped.into_iter().map(RespValue::BulkString).collect())
+                    }
+                })
+            }
+            Command::Bpop { keys, end, .. } => {
+                for key in keys {
+                    let popped = db.pop(&key, end, 1)?;
+                    if !popped.is_empty() {
+                        return Ok(bpop_reply(key, popped));
+                    }
+                }
+                Ok(RespValue::NullArray)
+            }
+            Command::Mset { pairs, nx } => {
+                // MSETNX sets all the keys or none of them.
+                if nx && pairs.iter().any(|(key, _)| db.exists(key)) {
+                    return Ok(RespValue::Integer(0));
+                }
+                for (key, value) in pairs {
+                    db.insert(&key, DbValue::Atom(value));
+                    db.remove_expiration(&key);
+                    db.notify_keyspace_event(EventClass::String, "set", &key);
+                }
+                if nx {
+                    Ok(RespValue::Integer(1))
+                } else {
+                    Ok(RespValue::SimpleString("OK".to_string()))
+                }
+            }
+            Command::Getex {
+                key,
+                expiration,
+                persist,
+            } => {
+                let Some(value) = db.get_string(&key)? else {
+                    return Ok(RespValue::NullBulkString);
+                };
+                if let Some(expiration) = expiration {
+                    let at = expiration.unix_millis(clock::now_millis() as i64, "getex")?;
+                    db.expire_at(&key, at, ExpireCondition::default());
+                } else if persist {
+                    db.persist(&key);
+                }
+                Ok(RespValue::BulkString(value))
+            }
+            Command::Getdel { key } => {
+                let value = db.get_string(&key)?;
+                if value.is_some() {
+                    db.del(&key);
+                }
+                Ok(value.map_or(RespValue::NullBulkString, RespValue::BulkString))
+            }
+            Command::Incrby { key, delta } => Ok(RespValue::Integer(db.incr_by(&key, delta)?)),
+            Command::Incrbyfloat { key, delta } => {
+                Ok(RespValue::BulkString(db.incr_by_float(&key, delta)?))
+            }
+            Command::Append { key, value } => {
+                Ok(RespValue::Integer(db.append(&key, &value)? as i64))
+            }
+            Command::Setrange { key, offset, value } => {
+                Ok(RespValue::Integer(db.setrange(&key, offset, &value)? as i64))
+            }
+            Command::Linsert {
+                key,
+                after,
+                pivot,
+                element,
+            } => Ok(RespValue::Integer(
+                db.linsert(&key, after, &pivot, element)?,
+            )),
+            Command::Lset {
+                key,
+                index,
+                element,
+            } => {
+                db.lset(&key, index, element)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Lrem {
+                key,
+                count,
+                element,
+            } => Ok(RespValue::Integer(db.lrem(&key, count, &element)? as i64)),
+            Command::Ltrim { key, start, stop } => {
+                db.ltrim(&key, start, stop)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Expire {
+                name,
+                key,
+                expiration,
+                condition,
+            } => {
+                let at = expiration.unix_millis(clock::now_millis() as i64, &name)?;
+                Ok(RespValue::Integer(db.expire_at(&key, at, condition) as i64))
+            }
+            Command::Persist { key } => Ok(RespValue::Integer(db.persist(&key) as i64)),
+            Command::Del { keys } => Ok(RespValue::Integer(
+                keys.iter().filter(|key| db.del(key).is_some()).count() as i64,
+            )),
+            Command::Unlink { keys } => Ok(RespValue::Integer(
+                keys.iter().filter(|key| db.unlink(key)).count() as i64,
+            )),
+            Command::Rename { key, new_key, nx } => {
+                let renamed = db.rename(&key, &new_key, !nx)?;
+                if nx {
+                    Ok(RespValue::Integer(renamed as i64))
+                } else {
+                    Ok(RespValue::SimpleString("OK".to_string()))
+                }
+            }
+            Command::Copy {
+                source,
+                destination,
+                replace,
+            } => Ok(RespValue::Integer(
+                db.copy(&source, &destination, replace) as i64
+            )),
+            Command::Randomkey => Ok(db
+                .random_key()
+                .map_or(RespValue::NullBulkString, RespValue::BulkString)),
+            Command::Flush { lazy } => {
+                db.clear(lazy);
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Xadd {
+                key,
+                id,
+                field_value_pairs,
+            } => {
+                let new_id = derive_new_stream_id(id, db.xlast_id(&key)?)?;
+
+                db.xadd(
+                    &key,
+                    new_id,
+                    field_value_pairs
+                        .into_iter()
+                        .collect::<HashMap<Bytes, Bytes>>(),
+                )?;
+                Ok(RespValue::BulkString(new_id.to_string().into()))
+            }
+
+            Command::Xdel { key, ids } => Ok(RespValue::Integer(db.xdel(&key, &ids)? as i64)),
+            Command::Xtrim {
+                key,
+                strategy,
+                approximate,
+            } => Ok(RespValue::Integer(
+                db.xtrim(&key, strategy, approximate)? as i64
+            )),
+            Command::Xread { streams, .. } => {
+                let stream_responses = xread_available(db, &streams)?;
+                if stream_responses.is_empty() {
+                    Ok(RespValue::NullArray)
+                } else {
+                    Ok(RespValue::Array(stream_responses))
+                }
+            }
+            Command::Hset {
+                key,
+                field_value_pairs,
+            } => Ok(RespValue::Integer(db.hset(&key, field_value_pairs)? as i64)),
+            Command::Hdel { key, fields } => Ok(RespValue::Integer(db.hdel(&key, &fields)? as i64)),
+            Command::Sadd { key, members } => {
+                Ok(RespValue::Integer(db.sadd(&key, members)? as i64))
+            }
+            Command::Srem { key, members } => {
+                Ok(RespValue::Integer(db.srem(&key, &members)? as i64))
+            }
+            Command::Spop { key, count } => {
+                let popped = db.spop(&key, count.unwrap_or(1))?;
+                Ok(match count {
+                    Some(_) => {
+                        RespValue::Set(popped.into_iter().map(RespValue::BulkString).collect())
+                    }
+                    None => popped
+                        .into_iter()
+                        .next()
+                        .map_or(RespValue::NullBulkString, RespValue::BulkString),
+                })
+            }
+            Command::Srandmember { key, count } => {
+                let members = db.srandmember(&key, count.unwrap_or(1))?;
+                Ok(match count {
+                    Some(_) => {
+                        RespValue::Array(members.into_iter().map(RespValue::BulkString).collect())
+                    }
+                    None => members
+                        .into_iter()
+                        .next()
+                        .map_or(RespValue::NullBulkString, RespValue::BulkString),
+                })
+            }
+            Command::Smove {
+                source,
+                destination,
+                member,
+            } => Ok(RespValue::Integer(
+                db.smove(&source, &destination, member)? as i64
+            )),
+            Command::Zadd {
+                key,
+                score_member_pairs,
+                options,
+            } => {
+                let (changed, score) = db.zadd(&key, score_member_pairs, options)?;
+                Ok(if options.incr {
+                    score.map_or(RespValue::NullBulkString, RespValue::Double)
+                } else {
+                    RespValue::Integer(changed as i64)
+                })
+            }
+            Command::Zstore {
+                operation,
+                destination,
+                keys,
+                weights,
+                aggregate,
+            } => {
+                let result = db.zcombine(operation, &keys, &weights, aggregate)?;
+                let event = match operation {
+                    ZsetOperation::Union => "zunionstore",
+                    ZsetOperation::Inter => "zinterstore",
+                    ZsetOperation::Diff => "zdiffstore",
+                };
+                Ok(RespValue::Integer(
+                    db.zstore(&destination, result, event) as i64
+                ))
+            }
+            Command::Zdiff { keys, with_scores } => {
+                let weights = vec![1.0; keys.len()];
+                let result =
+                    db.zcombine(ZsetOperation::Diff, &keys, &weights, Aggregate::default())?;
+                Ok(RespValue::Array(
+                    result
+                        .iter()
+                        .flat_map(|(member, score)| {
+                            let mut items = vec![RespValue::BulkString(member.clone())];
+                            if with_scores {
+                                items.push(RespValue::BulkString(format_score(score).into()));
+                            }
+                            items
+                        })
+                        .collect(),
+                ))
+            }
+            Command::Eval { script, keys, args } => {
+                db.scripts_mut().insert(script.clone());
+                scripting::eval(db, &script, keys, args)
+            }
+            Command::Evalsha { sha1, keys, args } => {
+                let script = db.scripts().get(&sha1).ok_or_else(|| {
+                    anyhow::anyhow!("NOSCRIPT No matching script. Please use EVAL.")
+                })?;
+                scripting::eval(db, &script, keys, args)
+            }
+            Command::ScriptLoad { script } => {
+                scripting::compile(&script)?;
+                Ok(RespValue::BulkString(
+                    db.scripts_mut().insert(script).into(),
+                ))
+            }
+            Command::ScriptExists { sha1s } => Ok(RespValue::Array(
+                sha1s
+                    .iter()
+                    .map(|sha1| RespValue::Integer(db.scripts().contains(sha1) as i64))
+                    .collect(),
+            )),
+            Command::ScriptFlush => {
+                db.scripts_mut().clear();
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            // No script runs while the `Db` is locked for this one.
+            Command::ScriptKill => {
+                scripting::kill()?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Fcall {
+                function,
+                keys,
+                args,
+                read_only,
+            } => {
+                let (library, function) = db
+                    .functions()
+                    .get(&function)
+                    .ok_or_else(|| anyhow::anyhow!("ERR Function not found"))?;
+                if read_only && !function.is_read_only() {
+                    return Err(anyhow::anyhow!(
+                        "ERR Can not execute a script with write flag using *_ro command."
+                    ));
+                }
+                let (code, name, read_only) = (
+                    library.code.clone(),
+                    function.name.clone(),
+                    function.is_read_only(),
+                );
+                scripting::fcall(db, &code, &name, keys, args, read_only)
+            }
+            Command::FunctionLoad { code, replace } => Ok(RespValue::BulkString(
+                db.functions_mut().load(code, replace)?.into(),
+            )),
+            Command::FunctionDelete { library } => {
+                db.functions_mut().delete(&library)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::FunctionFlush => {
+                db.functions_mut().clear();
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::FunctionList { pattern, with_code } => Ok(RespValue::Array(
+                db.functions()
+                    .libraries()
+                    .filter(|library| {
+                        pattern
+                            .as_ref()
+                            .is_none_or(|pattern| glob_match(pattern, library.name.as_bytes()))
+                    })
+                    .map(|library| {
+                        let functions = library
+                            .functions
+                            .iter()
+                            .map(|function| {
+                                map_reply([
+                                    ("name", RespValue::BulkString(function.name.clone().into())),
+                                    (
+                                        "description",
+                                        function
+                                            .description
+                                            .clone()
+                                            .map_or(RespValue::NullBulkString, |description| {
+                                                RespValue::BulkString(description.into())
+                                            }),
+                                    ),
+                                    (
+                                        "flags",
+                                        RespValue::Set(
+                                            function
+                                                .flags
+                                                .iter()
+                                                .map(|flag| {
+                                                    RespValue::BulkString(flag.clone().into())
+                                                })
+                                                .collect(),
+                                        ),
+                                    ),
+                                ])
+                            })
+                            .collect();
+                        let mut fields = vec![
+                            (
+                                "library_name",
+                                RespValue::BulkString(library.name.clone().into()),
+                            ),
+                            ("engine", RespValue::BulkString("LUA".into())),
+                            ("functions", RespValue::Array(functions)),
+                        ];
+                        if with_code {
+                            fields.push((
+                                "library_code",
+                                RespValue::BulkString(library.code.clone()),
+                            ));
+                        }
+                        map_reply(fields)
+                    })
+                    .collect(),
+            )),
+            // Other clients are answered `-BUSY` while a function runs, so
+            // there is never a running script to report.
+            Command::FunctionStats => Ok(map_reply([
+                ("running_script", RespValue::NullBulkString),
+                (
+                    "engines",
+                    map_reply([(
+                        "LUA",
+                        map_reply([
+                            (
+                                "libraries_count",
+                                RespValue::Integer(db.functions().libraries().count() as i64),
+                            ),
+                            (
+                                "functions_count",
+                                RespValue::Integer(db.functions().function_count() as i64),
+                            ),
+                        ]),
+                    )]),
+                ),
+            ])),
+            Command::Zremrange { key, range } => {
+                Ok(RespValue::Integer(db.zremrange(&key, &range)? as i64))
+            }
+            Command::Zrandmember {
+                key,
+                count,
+                with_scores,
+            } => {
+                let members = db.zrandmember(&key, count.unwrap_or(1))?;
+                if count.is_none() {
+                    return Ok(members
+                        .into_iter()
+                        .next()
+                        .map_or(RespValue::NullBulkString, |(member, _)| {
+                            RespValue::BulkString(member)
+                        }));
+                }
+                Ok(RespValue::Array(
+                    members
+                        .into_iter()
+                        .flat_map(|(member, score)| {
+                            let mut items = vec![RespValue::BulkString(member)];
+                            if with_scores {
+                                items.push(RespValue::BulkString(format_score(score).into()));
+                            }
+                            items
+                        })
+                        .collect(),
+                ))
+            }
+            Command::Zrem { key, members } => {
+                Ok(RespValue::Integer(db.zrem(&key, &members)? as i64))
+            }
+            Command::Publish { channel, message } => {
+                Ok(RespValue::Integer(db.publish(&channel, &message) as i64))
+            }
+            Command::PubsubChannels { pattern } => Ok(RespValue::Array(
+                db.pubsub_channels(pattern.as_deref())
+                    .into_iter()
+                    .map(RespValue::BulkString)
+                    .collect(),
+            )),
+            Command::PubsubNumsub { channels } => Ok(RespValue::Array(
+                channels
+                    .into_iter()
+                    .flat_map(|channel| {
+                        let count = db.pubsub_numsub(&channel);
+                        [
+                            RespValue::BulkString(channel),
+                            RespValue::Integer(count as i64),
+                        ]
+                    })
+                    .collect(),
+            )),
+            Command::PubsubNumpat => Ok(RespValue::Integer(db.pubsub_numpat() as i64)),
+            Command::Save => {
+                persistence::save(db)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Shutdown { save } => {
+                warn!("User requested shutdown...");
+                if let Err(e) = shutdown::prepare(db, save) {
+                    error!("{e}");
+                    return Err(anyhow::anyhow!(
+                        "ERR Errors trying to SHUTDOWN. Check logs."
+                    ));
+                }
+                // The `Db` stays locked until the process is gone, so no
+                // write is lost after the snapshot.
+                std::process::exit(0)
+            }
+            Command::Bgsave => {
+                persistence::bgsave(db)?;
+                Ok(RespValue::SimpleString(
+                    "Background saving started".to_string(),
+                ))
+            }
+            Command::Lastsave => Ok(RespValue::Integer(db.rdb().last_save() as i64)),
+            Command::Role => Ok(db.replication().role()),
+            Command::Getkeys { argv } => Ok(RespValue::Array(
+                keys::command_keys(&argv)?
+                    .into_iter()
+                    .map(RespValue::BulkString)
+                    .collect(),
+            )),
+            Command::Info { sections } => {
+                Ok(RespValue::BulkString(info::info(db, &sections).into()))
+            }
+            Command::ConfigGet { patterns } => {
+                let mut pairs: Vec<(&str, String)> = vec![];
+                for pattern in patterns {
+                    for (name, value) in db.config().get(&pattern) {
+                        if !pairs.iter().any(|(seen, _)| *seen == name) {
+                            pairs.push((name, value));
+                        }
+                    }
+                }
+                Ok(RespValue::Map(
+                    pairs
+                        .into_iter()
+                        .map(|(name, value)| {
+                            (
+                                RespValue::BulkString(name.into()),
+                                RespValue::BulkString(value.into()),
+                            )
+                        })
+                        .collect(),
+                ))
+            }
+            Command::ConfigSet { parameters } => {
+                db.set_config(&parameters)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::AclSetuser { username, rules } => {
+                db.acl().set_user(&username, &rules)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::AclGetuser { username } => {
+                let Some(user) = db.acl().get_user(&username) else {
+                    return Ok(RespValue::NullBulkString);
+                };
+                let bulk = |value: String| RespValue::BulkString(value.into());
+                Ok(RespValue::Map(
+                    [
+                        (
+                            "flags",
+                            RespValue::Array(
+                                user.flags()
+                                    .into_iter()
+                                    .map(|flag| bulk(flag.to_string()))
+                                    .collect(),
+                            ),
+                        ),
+                        (
+                            "passwords",
+                            RespValue::Array(user.passwords().iter().cloned().map(bulk).collect()),
+                        ),
+                        ("commands", bulk(user.commands())),
+                        ("keys", bulk(user.keys())),
+                        ("channels", bulk(user.channels())),
+                        ("selectors", RespValue::Array(vec![])),
+                    ]
+                    .into_iter()
+                    .map(|(field, value)| (bulk(field.to_string()), value))
+                    .collect(),
+                ))
+            }
+            Command::AclDeluser { usernames } => {
+                let deleted = db.acl().delete_users(&usernames)?;
+                // Clients authenticated as a deleted user are disconnected.
+                for client in db.clients().list() {
+                    if usernames.contains(&client.details().user) {
+                        client.kill();
+                    }
+                }
+                Ok(RespValue::Integer(deleted as i64))
+            }
+            Command::AclList => Ok(RespValue::Array(
+                db.acl()
+                    .list()
+                    .iter()
+                    .map(|user| RespValue::BulkString(user.describe().into()))
+                    .collect(),
+            )),
+            Command::AclWhoami => Err(anyhow::anyhow!(
+                "ACL WHOAMI must be handled by the connection"
+            )),
+            Command::LatencyLatest => Ok(RespValue::Array(
+                db.latency()
+                    .latest()
+                    .into_iter()
+                    .map(|(event, time, latest, max)| {
+                        RespValue::Array(vec![
+                            RespValue::BulkString(event.into()),
+                            RespValue::Integer(time as i64),
+                            RespValue::Integer(latest as i64),
+                            RespValue::Integer(max as i64),
+                        ])
+                    })
+                    .collect(),
+            )),
+            Command::LatencyHistory { event } => Ok(RespValue::Array(
+                db.latency()
+                    .history(&event)
+                    .into_iter()
+                    .map(|(time, millis)| {
+                        RespValue::Array(vec![
+                            RespValue::Integer(time as i64),
+                            RespValue::Integer(millis as i64),
+                        ])
+                    })
+                    .collect(),
+            )),
+            Command::LatencyReset { events } => {
+                Ok(RespValue::Integer(db.latency().reset(&events) as i64))
+            }
+            Command::LatencyHistogram { commands } => Ok(RespValue::Map(
+                db.latency()
+                    .histograms(&commands)
+                    .into_iter()
+                    .map(|histogram| {
+                        let buckets = histogram
+                            .buckets
+                            .into_iter()
+                            .map(|(micros, count)| {
+                                (
+                                    RespValue::Integer(micros as i64),
+                                    RespValue::Integer(count as i64),
+                                )
+                            })
+                            .collect();
+                        (
+                            RespValue::BulkString(histogram.command.into()),
+                            map_reply([
+                                ("calls", RespValue::Integer(histogram.calls as i64)),
+                                ("histogram_usec", RespValue::Map(buckets)),
+                            ]),
+                        )
+                    })
+                    .collect(),
+            )),
+            Command::ClusterInfo
+            | Command::ClusterMyid
+            | Command::ClusterNodes
+            | Command::ClusterSlots
+            | Command::ClusterShards
+            | Command::ClusterKeyslot { .. }
+            | Command::ClusterMeet { .. }
+            | Command::ClusterForget { .. }
+            | Command::ClusterAddslots { .. }
+            | Command::ClusterDelslots { .. }
+            | Command::ClusterCountFailureReports { .. }
+                if !db.cluster().is_enabled() =>
+            {
+                Err(anyhow::anyhow!(
+                    "ERR This instance has cluster support disabled"
+                ))
+            }
+            Command::ClusterInfo => Ok(RespValue::BulkString(
+                db.cluster()
+                    .info()
+                    .iter()
+                    .map(|(name, value)| format!("{name}:{value}\r\n"))
+                    .collect::<String>()
+                    .into(),
+            )),
+            Command::ClusterMyid => Ok(RespValue::BulkString(db.cluster().myself().id.into())),
+            Command::ClusterNodes => {
+                Ok(RespValue::BulkString(db.cluster().describe_nodes().into()))
+            }
+            Command::ClusterMeet {
+                host,
+                port,
+                bus_port,
+            } => {
+                db.cluster().meet(&host, port, bus_port);
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::ClusterForget { id } => {
+                db.cluster().forget(&id)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::ClusterAddslots { slots } => {
+                db.cluster().add_slots(&slots)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::ClusterDelslots { slots } => {
+                db.cluster().del_slots(&slots)?;
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::ClusterCountFailureReports { id } => Ok(RespValue::Integer(
+                db.cluster().count_failure_reports(&id)? as i64,
+            )),
+            Command::ClusterSlots => {
+                let mut ranges: Vec<(u16, u16, RespValue)> = vec![];
+                for node in db.cluster().nodes() {
+                    let endpoint = RespValue::Array(vec![
+                        RespValue::BulkString(node.host.clone().into()),
+                        RespValue::Integer(node.port as i64),
+                        RespValue::BulkString(node.id.clone().into()),
+                    ]);
+                    for &(start, end) in &node.slots {
+                        ranges.push((start, end, endpoint.clone()));
+                    }
+                }
+                ranges.sort_by_key(|(start, ..)| *start);
+                Ok(RespValue::Array(
+                    ranges
+                        .into_iter()
+                        .map(|(start, end, endpoint)| {
+                            RespValue::Array(vec![
+                                RespValue::Integer(start as i64),
+                                RespValue::Integer(end as i64),
+                                endpoint,
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+            Command::ClusterShards => {
+                let offset = db.replication().offset() as i64;
+                Ok(RespValue::Array(
+                    db.cluster()
+                        .nodes()
+                        .into_iter()
+                        .filter(|node| !node.handshake)
+                        .map(|node| {
+                            let slots = node
+                                .slots
+                                .iter()
+                                .flat_map(|&(start, end)| [start, end])
+                                .map(|slot| RespValue::Integer(slot as i64))
+                                .collect();
+                            // The offset of other nodes is not known.
+                            let offset = if node.myself { offset } else { 0 };
+                            let health = if node.fail || node.pfail {
+                                "fail"
+                            } else {
+                                "online"
+                            };
+                            let node = map_reply([
+                                ("id", RespValue::BulkString(node.id.into())),
+                                ("port", RespValue::Integer(node.port as i64)),
+                                ("ip", RespValue::BulkString(node.host.clone().into())),
+                                ("endpoint", RespValue::BulkString(node.host.into())),
+                                ("role", RespValue::BulkString("master".into())),
+                                ("replication-offset", RespValue::Integer(offset)),
+                                ("health", RespValue::BulkString(health.into())),
+                            ]);
+                            map_reply([
+                                ("slots", RespValue::Array(slots)),
+                                ("nodes", RespValue::Array(vec![node])),
+                            ])
+                        })
+                        .collect(),
+                ))
+            }
+            Command::ClusterKeyslot { key } => {
+                Ok(RespValue::Integer(cluster::key_slot(&key) as i64))
+            }
+            Command::Replconf { args } => {
+                for option in args.iter().step_by(2) {
+                    match option.to_lowercase().as_str() {
+                        "listening-port" | "ip-address" | "capa" => {}
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "ERR Unrecognized REPLCONF option: {option}"
+                            ));
+                        }
+                    }
+                }
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            // Inside a transaction WAIT never blocks.
+            Command::Wait { .. } => Ok(RespValue::Integer(
+                db.replication().acked_replicas(db.replication().offset()) as i64,
+            )),
+            Command::Psync { .. } | Command::ReplconfAck { .. } | Command::ReplconfGetack => Err(
+                anyhow::anyhow!("ERR Replication commands must be handled by the connection"),
+            ),
+            Command::Bgrewriteaof => Err(anyhow::anyhow!(
+                "ERR BGREWRITEAOF is not allowed in this context"
+            )),
+            Command::Replicaof { .. } => Err(anyhow::anyhow!(
+                "ERR REPLICAOF is not allowed in this context"
+            )),
+            Command::Failover { .. } | Command::FailoverAbort => Err(anyhow::anyhow!(
+                "ERR FAILOVER is not allowed in this context"
+            )),
+            Command::Hello { .. } => {
+                Err(anyhow::anyhow!("HELLO must be handled by the connection"))
+            }
+            Command::Readonly | Command::Readwrite => Err(anyhow::anyhow!(
+                "READONLY and READWRITE must be handled by the connection"
+            )),
+            Command::Reset => Err(anyhow::anyhow!("RESET must be handled by the connection")),
+            Command::Auth { .. } => Err(anyhow::anyhow!("AUTH must be handled by the connection")),
+            Command::Quit => Err(anyhow::anyhow!("QUIT must be handled by the connection")),
+            Command::Monitor => Err(anyhow::anyhow!("MONITOR must be handled by the connection")),
+            Command::DebugSleep { duration } => {
+                // The server is meant to stall, but on a multi-threaded
+                // runtime the other tasks of this worker can move elsewhere.
+                if !duration.is_zero() {
+                    match Handle::try_current() {
+                        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                            tokio::task::block_in_place(|| std::thread::sleep(duration));
+                        }
+                        _ => std::thread::sleep(duration),
+                    }
+                }
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::DebugObject { key } => {
+                let (value, key_access) = db
+                    .peek(&key)
+                    .ok_or_else(|| anyhow::anyhow!("ERR no such key"))?;
+                let idle = key_access.idle_time(Instant::now()).as_secs();
+                // Redis' LRU clock: the time of the last access in seconds,
+                // on 24 bits.
+                let lru = (persistence::unix_time_millis() / 1000).saturating_sub(idle) & 0xff_ffff;
+                Ok(RespValue::SimpleString(format!(
+                    "Value at:{value:p} refcount:1 encoding:{} serializedlength:{} lru:{lru} lru_seconds_idle:{idle}",
+                    value.encoding(),
+                    persistence::rdb::serialized_length(value),
+                )))
+            }
+            Command::DebugSetActiveExpire { enabled } => {
+                db.set_active_expire(enabled);
+                Ok(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::DebugQuickack { .. } => Err(anyhow::anyhow!(
+                "DEBUG QUICKACK must be handled by the connection"
+            )),
+            Command::ClientId
+            | Command::ClientSetname { .. }
+            | Command::ClientGetname
+            | Command::ClientList { .. }
+            | Command::ClientInfo
+            | Command::ClientKill { .. }
+            | Command::ClientTracking { .. }
+            | Command::ClientCaching { .. }
+            | Command::ClientGetredir
+            | Command::ClientTrackinginfo => {
+                Err(anyhow::anyhow!("CLIENT must be handled by the connection"))
+            }
+            Command::Multi | Command::Exec | Command::Discard => Err(anyhow::anyhow!(
+                "Transaction commands must be handled by the connection"
+            )),
+            Command::Subscribe { .. }
+            | Command::Unsubscribe { .. }
+            | Command::Psubscribe { .. }
+            | Command::Punsubscribe { .. } => Err(anyhow::anyhow!(
+                "Subscription commands must be handled by the connection"
+            )),
+            command => command.apply_read(db),
+        }
+    }
+
+    /// Runs a command for which `is_read_only` holds.
+    fn apply_read(self, db: &Db) -> Result<RespValue> {
+        match self {
+            Command::Ping => Ok(RespValue::SimpleString("PONG".to_string())),
+            Command::Echo { message } => Ok(RespValue::BulkString(message)),
+            Command::Mget { keys } => Ok(RespValue::Array(
+                keys.iter()
+                    .map(|key| match db.get(key) {
+                        Some(DbValue::Atom(value)) => RespValue::BulkString(value),
+                        _ => RespValue::NullBulkString,
+                    })
+                    .collect(),
+            )),
+            Command::Strlen { key } => Ok(RespValue::Integer(db.strlen(&key)? as i64)),
+            Command::Getrange { key, start, end } => {
+                Ok(RespValue::BulkString(db.getrange(&key, start, end)?))
+            }
+            Command::Lindex { key, index } => Ok(db
+                .lindex(&key, index)?
+                .map_or(RespValue::NullBulkString, RespValue::BulkString)),
+            Command::Lpos {
+                key,
+                element,
+                rank,
+                count,
+                maxlen,
+            } => {
+                let positions = db.lpos(&key, &element, rank, count.unwrap_or(1), maxlen)?;
+                let mut positions = positions
+                    .into_iter()
+                    .map(|index| RespValue::Integer(index as i64));
+                Ok(match count {
+                    Some(_) => RespValue::Array(positions.collect()),
+                    None => positions.next().unwrap_or(RespValue::NullBulkString),
+                })
+            }
+            Command::Llen { key } => {
+                let length = db.llen(&key)?;
+                Ok(RespValue::Integer(length as i64))
+            }
+            Command::Get { key } => match db.lookup_as::<Bytes>(&key)? {
+                Some(value) => Ok(RespValue::BulkString(value.clone())),
+                None => {
+                    db.notify_keyspace_event(EventClass::KeyMiss, "keymiss", &key);
+                    Ok(RespValue::NullBulkString)
+                }
+            },
+            Command::Ttl { key } => Ok(RespValue::Integer(match db.pttl(&key) {
+                millis if millis < 0 => millis,
+                millis => (millis + 500) / 1000,
+            })),
+            Command::Pttl { key } => Ok(RespValue::Integer(db.pttl(&key))),
+            Command::Expiretime { key } => Ok(RespValue::Integer(match db.pexpiretime(&key) {
+                millis if millis < 0 => millis,
+                millis => millis / 1000,
+            })),
+            Command::Pexpiretime { key } => Ok(RespValue::Integer(db.pexpiretime(&key))),
+            Command::Dbsize => Ok(RespValue::Integer(db.len() as i64)),
+            Command::ObjectEncoding { key } => Ok(db
+                .peek(&key)
+                .map_or(RespValue::NullBulkString, |(value, _)| {
+                    RespValue::BulkString(value.encoding().into())
+                })),
+            Command::ObjectIdletime { key } => {
+                Ok(db
+                    .peek(&key)
+                    .map_or(RespValue::NullBulkString, |(_, key_access)| {
+                        RespValue::Integer(key_access.idle_time(Instant::now()).as_secs() as i64)
+                    }))
+            }
+            Command::ObjectFreq { key } => Ok(db
+                .peek(&key)
+                .map_or(RespValue::NullBulkString, |(_, key_access)| {
+                    RespValue::Integer(key_access.frequency(Instant::now()) as i64)
+                })),
+            Command::Exists { keys } => Ok(RespValue::Integer(
+                keys.iter().filter(|key| db.exists(key)).count() as i64,
+            )),
+            Command::Lrange { key, start, stop } => Ok(RespValue::Array(
+                db.lrange(&key, start, stop)?
+                    .into_iter()
+                    .map(RespValue::BulkString)
+                    .collect(),
+            )),
+            Command::Type { key } => Ok(RespValue::SimpleString(
+                db.get(&key)
+                    .map_or("none", |value| value.type_name())
+                    .to_string(),
+            )),
+            Command::Xrange { key, start, end } => {
+                let items = db.xrange(&key, start, end)?;
+                Ok(RespValue::Array(
+                    items.iter().map(StreamItem::to_resp).collect(),
+                ))
+            }
+            Command::Xlen { key } => Ok(RespValue::Integer(db.xlen(&key)? as i64)),
+            Command::Hget { key, field } => Ok(db
+                .hget(&key, &field)?
+                .map_or(RespValue::NullBulkString, RespValue::BulkString)),
+            Command::Hgetall { key } => Ok(RespValue::Map(
+                db.hgetall(&key)?
+                    .into_iter()
+                    .map(|(field, value)| {
+                        (RespValue::BulkString(field), RespValue::BulkString(value))
+                    })
+                    .collect(),
+            )),
+            Command::Hlen { key } => Ok(RespValue::Integer(db.hlen(&key)? as i64)),
+            Command::Scan {
+                cursor,
+                options,
+                type_name,
+            } => {
+                let (cursor, keys) = db.scan(cursor, &options, type_name.as_deref());
+                Ok(scan_reply(
+                    cursor,
+                    keys.into_iter().map(RespValue::BulkString).collect(),
+                ))
+            }
+            Command::Hscan {
+                key,
+                cursor,
+                options,
+                novalues,
+            } => {
+                let (cursor, pairs) = db.hscan(&key, cursor, &options)?;
+                Ok(scan_reply(
+                    cursor,
+                    pairs
+                        .into_iter()
+                        .flat_map(|(field, value)| {
+                            let mut items = vec![RespValue::BulkString(field)];
+                            if !novalues {
+                                items.push(RespValue::BulkString(value));
+                            }
+                            items
+                        })
+                        .collect(),
+                ))
+            }
+            Command::Sscan {
+                key,
+                cursor,
+                options,
+            } => {
+                let (cursor, members) = db.sscan(&key, cursor, &options)?;
+                Ok(scan_reply(
+                    cursor,
+                    members.into_iter().map(RespValue::BulkString).collect(),
+                ))
+            }
+            Command::Zscan {
+                key,
+                cursor,
+                options,
+            } => {
+                let (cursor, members) = db.zscan(&key, cursor, &options)?;
+                Ok(scan_reply(
+                    cursor,
+                    members
+                        .into_iter()
+                        .flat_map(|(member, score)| {
+                            [
+                                RespValue::BulkString(member),
+                                RespValue::BulkString(format_score(score).into()),
+                            ]
+                        })
+                        .collect(),
+                ))
+            }
+            Command::Smembers { key } => Ok(RespValue::Set(
+                db.smembers(&key)?
+                    .into_iter()
+                    .map(RespValue::BulkString)
+                    .collect(),
+            )),
+            Command::Sismember { key, member } => {
+                Ok(RespValue::Integer(db.sismember(&key, &member)? as i64))
+            }
+            Command::Smismember { key, members } => Ok(RespValue::Array(
+                db.smismember(&key, &members)?
+                    .into_iter()
+                    .map(|is_member| RespValue::Integer(is_member as i64))
+                    .collect(),
+            )),
+            Command::Scard { key } => Ok(RespValue::Integer(db.scard(&key)? as i64)),
+            Command::Zscore { key, member } => Ok(db
+                .zscore(&key, &member)?
+                .map_or(RespValue::NullBulkString, RespValue::Double)),
+            Command::Zrank { key, member } => Ok(db
+                .zrank(&key, &member)?
+                .map_or(RespValue::NullBulkString, |rank| {
+                    RespValue::Integer(rank as i64)
+                })),
+            Command::Zcard { key } => Ok(RespValue::Integer(db.zcard(&key)? as i64)),
+            Command::Zrange {
+                key,
+                start,
+                stop,
+                with_scores,
+            } => Ok(RespValue::Array(
+                db.zrange(&key, start, stop)?
+                    .into_iter()
+                    .flat_map(|(member, score)| {
+                        let mut items = vec![RespValue::BulkString(member)];
+                        if with_scores {
+                            items.push(RespValue::BulkString(format_score(score).into()));
+                        }
+                        items
+                    })
+                    .collect(),
+            )),
+            _ => unreachable!("only commands that do not write are applied shared"),
+        }
+    }
+}
+
+/// Commands whose first argument is a subcommand, which `CLIENT LIST`
+/// shows along with them.
+const CONTAINER_COMMANDS: [&str; 13] = [
+    "acl", "client", "cluster", "command", "config", "debug", "function", "latency", "memory",
+    "object", "pubsub", "script", "xinfo",
+];
+
+/// Counts a run of a command in its latency histogram and as a `command`
+/// latency spike when it was slow enough.
+fn record_command_latency(db: &Db, label: &str, elapsed: Duration) {
+    db.latency().add_command(label, elapsed);
+    db.record_latency("command", elapsed);
+}
+
+/// Whether the command line `argv` may modify the keyspace, and so must be
+/// logged to the append-only file, as its ACL `write` category says.
+pub fn is_write(argv: &[RespValue]) -> bool {
+    acl::is_write(&command_label(argv))
+}
+
+/// The command name as `CLIENT LIST` shows it, as in `client|list`.
+pub fn command_label(argv: &[RespValue]) -> String {
+    let Some(name) = argv.first() else {
+        return "NULL".to_string();
+    };
+    let name = String::from(name.clone()).to_lowercase();
+    match argv.get(1) {
+        Some(subcommand) if CONTAINER_COMMANDS.contains(&name.as_str()) => {
+            format!("{name}|{}", String::from(subcommand.clone()).to_lowercase())
+        }
+        _ => name,
+    }
+}
+
+fn xread_available(db: &Db, streams: &[(Bytes, XreadStartId)]) -> Result<Vec<RespValue>> {
+    let mut responses = vec![];
+    for (key, start) in streams {
+        let start_id = start.resolve(db.xlast_id(key)?);
+        let stream_items = db.xread(key, start_id)?;
+        if !stream_items.is_empty() {
+            responses.push(RespValue::Array(vec![
+                RespValue::BulkString(key.clone()),
+                RespValue::Array(stream_items.iter().map(StreamItem::to_resp).collect()),
+            ]));
+        }
+    }
+    Ok(responses)
+}
+
+/// Turns the expiration of a `SET` or `GETEX`, named `name`, into a UNIX
+/// time, which is returned, and the `EX`/`PX`/`EXAT`/`PXAT` option among
+/// `options` into the matching `PXAT`.
+fn make_absolute(
+    expiration: &mut Expiration,
+    options: &mut [RespValue],
+    name: &str,
+) -> Result<i64> {
+    let at = expiration.unix_millis(clock::now_millis() as i64, name)?;
+    *expiration = Expiration::At(at);
+    if let Some(index) = options.iter().position(|option| {
+        let option = String::from(option.clone()).to_uppercase();
+        matches!(option.as_str(), "EX" | "PX" | "EXAT" | "PXAT")
+    }) {
+        options[index] = RespValue::BulkString("PXAT".into());
+        options[index + 1] = RespValue::BulkString(at.to_string().into());
+    }
+    Ok(at)
+}
+
+/// The `[cursor, items]` reply of the `SCAN` family.
+fn scan_reply(cursor: u64, items: Vec<RespValue>) -> RespValue {
+    RespValue::Array(vec![
+        RespValue::BulkString(cursor.to_string().into()),
+        RespValue::Array(items),
+    ])
+}
+
+/// Hands the elements pushed to lists clients are blocked on to them, once
+/// the command pushing them ran, propagating the pops made for them.
+pub fn serve_blocked_clients(db: &mut Db) {
+    for (key, end) in db.serve_blocked_clients() {
+        db.propagate(&pop_argv(&key, end));
+    }
+}
+
+/// A blocking pop is propagated as the plain pop it ends up doing.
+fn pop_argv(key: &Bytes, end: ListEnd) -> Vec<RespValue> {
+    let name = match end {
+        ListEnd::Left => "LPOP",
+        ListEnd::Right => "RPOP",
+    };
+    vec![
+        RespValue::BulkString(name.into()),
+        RespValue::BulkString(key.clone()),
+    ]
+}
+
+/// The `[key, element]` reply of `BLPOP`/`BRPOP`, or a null array when
+/// nothing was popped.
+fn bpop_reply(key: Bytes, popped: Vec<Bytes>) -> RespValue {
+    match popped.into_iter().next() {
+        Some(element) => RespValue::Array(vec![
+            RespValue::BulkString(key),
+            RespValue::BulkString(element),
+        ]),
+        None => RespValue::NullArray,
+    }
+}
+
+/// A map reply with string keys.
+fn map_reply(fields: impl IntoIterator<Item = (&'static str, RespValue)>) -> RespValue {
+    RespValue::Map(
+        fields
+            .into_iter()
+            .map(|(key, value)| (RespValue::BulkString(key.into()), value))
+            .collect(),
+    )
 }
@@ -0,0 +1,83 @@
+use std::{error::Error, fmt};
+
+/// How many bytes of its arguments an unknown command is reported with.
+const REPORTED_ARGS_LEN: usize = 128;
+
+/// A command rejected before it ran, worded as Redis words it so that
+/// client libraries matching on the messages recognise it.
+#[derive(Debug)]
+pub enum CommandError {
+    UnknownCommand {
+        name: String,
+        args: Vec<String>,
+    },
+    /// The command, or `container|subcommand`, got a number of arguments
+    /// it does not accept.
+    WrongArity(String),
+    UnknownSubcommand {
+        command: String,
+        subcommand: String,
+    },
+    Syntax,
+    NotAnInteger,
+    NotAFloat,
+    NoAuth,
+}
+
+impl CommandError {
+    pub fn wrong_arity(name: &str) -> Self {
+        CommandError::WrongArity(name.to_lowercase())
+    }
+
+    pub fn unknown_subcommand(command: &str, subcommand: &str) -> Self {
+        CommandError::UnknownSubcommand {
+            command: command.to_string(),
+            subcommand: subcommand.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand { name, args } => {
+                write!(
+                    f,
+                    "ERR unknown command '{name}', with args beginning with: "
+                )?;
+                // Like Redis, the arguments are cut once 128 bytes of them
+                // were reported.
+                let mut reported = 0;
+                for arg in args {
+                    if reported >= REPORTED_ARGS_LEN {
+                        break;
+                    }
+                    let end = (0..=arg.len().min(REPORTED_ARGS_LEN - reported))
+                        .rev()
+                        .find(|&end| arg.is_char_boundary(end))
+                        .unwrap_or(0);
+                    write!(f, "'{}' ", &arg[..end])?;
+                    reported += end + 3;
+                }
+                Ok(())
+            }
+            CommandError::WrongArity(name) => {
+                write!(f, "ERR wrong number of arguments for '{name}' command")
+            }
+            CommandError::UnknownSubcommand {
+                command,
+                subcommand,
+            } => write!(
+                f,
+                "ERR unknown subcommand '{subcommand}'. Try {} HELP.",
+                command.to_uppercase()
+            ),
+            CommandError::Syntax => write!(f, "ERR syntax error"),
+            CommandError::NotAnInteger => write!(f, "ERR value is not an integer or out of range"),
+            CommandError::NotAFloat => write!(f, "ERR value is not a valid float"),
+            CommandError::NoAuth => write!(f, "NOAUTH Authentication required."),
+        }
+    }
+}
+
+impl Error for CommandError {}
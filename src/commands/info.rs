@@ -0,0 +1,273 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    db::Db,
+    memory,
+    persistence::unix_time_millis,
+    replication::LinkState,
+    stats::{RUN_ID, STARTED, STATS},
+};
+
+/// The sections `INFO` reports, in order, when none is asked for.
+const SECTIONS: [&str; 8] = [
+    "server",
+    "clients",
+    "memory",
+    "persistence",
+    "stats",
+    "replication",
+    "cluster",
+    "keyspace",
+];
+
+/// The `INFO` report of the requested sections, or of all of them when
+/// `sections` is empty or holds `all`, `default` or `everything`. Unknown
+/// sections are skipped.
+pub fn info(db: &Db, sections: &[String]) -> String {
+    let all = sections.is_empty()
+        || sections.iter().any(|section| {
+            ["all", "default", "everything"]
+                .iter()
+                .any(|name| section.eq_ignore_ascii_case(name))
+        });
+    let mut report = String::new();
+    for section in SECTIONS {
+        if !all
+            && !sections
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(section))
+        {
+            continue;
+        }
+        let fields = match section {
+            "server" => server(db),
+            "clients" => clients(db),
+            "memory" => memory(db),
+            "persistence" => persistence(db),
+            "stats" => stats(db),
+            "replication" => replication(db),
+            "cluster" => cluster(db),
+            _ => keyspace(db),
+        };
+        if !report.is_empty() {
+            report.push_str("\r\n");
+        }
+        let mut title = section.to_string();
+        title[..1].make_ascii_uppercase();
+        let _ = write!(report, "# {title}\r\n");
+        for (name, value) in fields {
+            let _ = write!(report, "{name}:{value}\r\n");
+        }
+    }
+    report
+}
+
+type Fields = Vec<(String, String)>;
+
+fn fields<const N: usize>(fields: [(&str, String); N]) -> Fields {
+    fields
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+}
+
+fn server(db: &Db) -> Fields {
+    let uptime = STARTED.elapsed().as_secs();
+    fields([
+        ("redis_version", "7.2.0".to_string()),
+        ("redis_mode", db.cluster().mode().to_string()),
+        (
+            "os",
+            format!("{} {}", std::env::consts::OS, std::env::consts::ARCH),
+        ),
+        ("arch_bits", (usize::BITS).to_string()),
+        ("process_id", std::process::id().to_string()),
+        ("run_id", RUN_ID.clone()),
+        ("tcp_port", db.config().port.to_string()),
+        ("server_time_usec", (unix_time_millis() * 1000).to_string()),
+        ("uptime_in_seconds", uptime.to_string()),
+        ("uptime_in_days", (uptime / 86400).to_string()),
+        ("hz", "10".to_string()),
+    ])
+}
+
+fn clients(db: &Db) -> Fields {
+    let (tracking_clients, _, _) = db.clients().tracking_stats();
+    fields([
+        ("connected_clients", db.clients().count().to_string()),
+        ("tracking_clients", tracking_clients.to_string()),
+    ])
+}
+
+fn memory(db: &Db) -> Fields {
+    let used = memory::used_memory();
+    let peak = memory::peak_memory();
+    let rss = memory::rss_memory().unwrap_or(used);
+    let maxmemory = db.config().maxmemory;
+    fields([
+        ("used_memory", used.to_string()),
+        ("used_memory_human", memory::human_bytes(used)),
+        ("used_memory_rss", rss.to_string()),
+        ("used_memory_rss_human", memory::human_bytes(rss)),
+        ("used_memory_peak", peak.to_string()),
+        ("used_memory_peak_human", memory::human_bytes(peak)),
+        ("maxmemory", maxmemory.to_string()),
+        ("maxmemory_human", memory::human_bytes(maxmemory)),
+        ("maxmemory_policy", db.config().maxmemory_policy.to_string()),
+        (
+            "mem_fragmentation_ratio",
+            format!("{:.2}", rss as f64 / used.max(1) as f64),
+        ),
+        ("mem_allocator", "libc".to_string()),
+    ])
+}
+
+fn persistence(db: &Db) -> Fields {
+    let rdb = db.rdb();
+    fields([
+        ("loading", "0".to_string()),
+        ("rdb_changes_since_last_save", rdb.changes().to_string()),
+        (
+            "rdb_bgsave_in_progress",
+            (rdb.bgsave_in_progress() as u8).to_string(),
+        ),
+        ("rdb_last_save_time", rdb.last_save().to_string()),
+        (
+            "rdb_last_bgsave_status",
+            if rdb.last_bgsave_ok() { "ok" } else { "err" }.to_string(),
+        ),
+        ("aof_enabled", (db.aof().is_some() as u8).to_string()),
+        (
+            "aof_rewrite_in_progress",
+            (db.aof().is_some_and(|aof| aof.rewrite_in_progress()) as u8).to_string(),
+        ),
+    ])
+}
+
+fn stats(db: &Db) -> Fields {
+    let (_, tracked_keys, tracked_prefixes) = db.clients().tracking_stats();
+    let counter = |counter: &AtomicU64| counter.load(Ordering::Relaxed).to_string();
+    fields([
+        (
+            "total_connections_received",
+            counter(&STATS.connections_received),
+        ),
+        (
+            "total_commands_processed",
+            counter(&STATS.commands_processed),
+        ),
+        ("expired_keys", counter(&STATS.expired_keys)),
+        ("evicted_keys", "0".to_string()),
+        ("keyspace_hits", counter(&STATS.keyspace_hits)),
+        ("keyspace_misses", counter(&STATS.keyspace_misses)),
+        (
+            "pubsub_channels",
+            db.pubsub_channels(None).len().to_string(),
+        ),
+        ("pubsub_patterns", db.pubsub_numpat().to_string()),
+        ("tracking_total_keys", tracked_keys.to_string()),
+        ("tracking_total_prefixes", tracked_prefixes.to_string()),
+    ])
+}
+
+fn replication(db: &Db) -> Fields {
+    let replication = db.replication();
+    let mut fields = match replication.master() {
+        Some(master) => {
+            let link_up = master.state == LinkState::Connected;
+            let mut fields = fields([
+                ("role", "slave".to_string()),
+                ("master_host", master.host.clone()),
+                ("master_port", master.port.to_string()),
+                (
+                    "master_link_status",
+                    if link_up { "up" } else { "down" }.to_string(),
+                ),
+                (
+                    "master_last_io_seconds_ago",
+                    if link_up {
+                        master.last_io.elapsed().as_secs() as i64
+                    } else {
+                        -1
+                    }
+                    .to_string(),
+                ),
+                (
+                    "master_sync_in_progress",
+                    ((master.state == LinkState::Sync) as u8).to_string(),
+                ),
+                ("slave_read_repl_offset", replication.offset().to_string()),
+                ("slave_repl_offset", replication.offset().to_string()),
+            ]);
+            if !link_up {
+                fields.push((
+                    "master_link_down_since_seconds".to_string(),
+                    master.down_since.elapsed().as_secs().to_string(),
+                ));
+            }
+            fields.extend(self::fields([
+                ("slave_priority", "100".to_string()),
+                (
+                    "slave_read_only",
+                    (db.config().replica_read_only as u8).to_string(),
+                ),
+                ("replica_announced", "1".to_string()),
+            ]));
+            fields
+        }
+        None => fields([("role", "master".to_string())]),
+    };
+    let replicas = replication.replicas();
+    fields.push(("connected_slaves".to_string(), replicas.len().to_string()));
+    for (index, replica) in replicas.into_iter().enumerate() {
+        fields.push((
+            format!("slave{index}"),
+            format!(
+                "ip={},port={},state=online,offset={},lag={}",
+                replica.ip,
+                replica.listening_port.unwrap_or(0),
+                replica.ack_offset,
+                replica.ack_time.elapsed().as_secs()
+            ),
+        ));
+    }
+    // There is no backlog for partial resynchronizations, nor a previous
+    // replication ID to continue from.
+    fields.extend(self::fields([
+        (
+            "master_failover_state",
+            replication
+                .failover_state()
+                .map_or("no-failover".to_string(), |state| state.to_string()),
+        ),
+        ("master_replid", replication.replid().to_string()),
+        ("master_replid2", "0".repeat(40)),
+        ("master_repl_offset", replication.offset().to_string()),
+        ("second_repl_offset", "-1".to_string()),
+        ("repl_backlog_active", "0".to_string()),
+    ]));
+    fields
+}
+
+fn cluster(db: &Db) -> Fields {
+    fields([(
+        "cluster_enabled",
+        (db.cluster().is_enabled() as u8).to_string(),
+    )])
+}
+
+/// The single database, reported only once it holds keys, as Redis does.
+fn keyspace(db: &Db) -> Fields {
+    let keys = db.len();
+    if keys == 0 {
+        return vec![];
+    }
+    let (expires, avg_ttl) = db.volatile_keys();
+    vec![(
+        "db0".to_string(),
+        format!("keys={keys},expires={expires},avg_ttl={avg_ttl}"),
+    )]
+}
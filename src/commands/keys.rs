@@ -0,0 +1,320 @@
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+
+use super::error::CommandError;
+
+/// Where keys are among the arguments of a command, like the key specs of
+/// `COMMAND INFO`. Indexes count the command name as 0.
+#[derive(Clone, Copy, Debug)]
+enum KeySpec {
+    /// The arguments from `first` to `last` every `step`, where a negative
+    /// `last` counts from the end.
+    Range {
+        first: usize,
+        last: isize,
+        step: usize,
+    },
+    /// The number of keys at `numkeys`, followed by the keys.
+    Keynum { numkeys: usize },
+    /// The first half of the arguments after `STREAMS`, as in `XREAD`.
+    Streams,
+}
+
+struct CommandSpec {
+    name: &'static str,
+    /// The number of arguments, the command name included, or minus the
+    /// minimum for commands taking a variable number, as in Redis.
+    arity: isize,
+    keys: &'static [KeySpec],
+}
+
+const KEY: &[KeySpec] = &[KeySpec::Range {
+    first: 1,
+    last: 1,
+    step: 1,
+}];
+const ALL_KEYS: &[KeySpec] = &[KeySpec::Range {
+    first: 1,
+    last: -1,
+    step: 1,
+}];
+const TWO_KEYS: &[KeySpec] = &[KeySpec::Range {
+    first: 1,
+    last: 2,
+    step: 1,
+}];
+const SUBCOMMAND_KEY: &[KeySpec] = &[KeySpec::Range {
+    first: 2,
+    last: 2,
+    step: 1,
+}];
+const KEYS_BEFORE_TIMEOUT: &[KeySpec] = &[KeySpec::Range {
+    first: 1,
+    last: -2,
+    step: 1,
+}];
+const KEY_VALUE_PAIRS: &[KeySpec] = &[KeySpec::Range {
+    first: 1,
+    last: -1,
+    step: 2,
+}];
+const SCRIPT_KEYS: &[KeySpec] = &[KeySpec::Keynum { numkeys: 2 }];
+const DESTINATION_AND_KEYS: &[KeySpec] = &[
+    KeySpec::Range {
+        first: 1,
+        last: 1,
+        step: 1,
+    },
+    KeySpec::Keynum { numkeys: 2 },
+];
+const NO_KEYS: &[KeySpec] = &[];
+
+const fn spec(name: &'static str, arity: isize, keys: &'static [KeySpec]) -> CommandSpec {
+    CommandSpec { name, arity, keys }
+}
+
+/// Every command, with its subcommands as `container|subcommand` where
+/// those differ in their keys.
+const COMMANDS: &[CommandSpec] = &[
+    spec("acl", -2, NO_KEYS),
+    spec("append", 3, KEY),
+    spec("auth", -2, NO_KEYS),
+    spec("bgrewriteaof", 1, NO_KEYS),
+    spec("bgsave", -1, NO_KEYS),
+    spec("blpop", -3, KEYS_BEFORE_TIMEOUT),
+    spec("brpop", -3, KEYS_BEFORE_TIMEOUT),
+    spec("client", -2, NO_KEYS),
+    spec("cluster", -2, NO_KEYS),
+    spec("command", -1, NO_KEYS),
+    spec("config", -2, NO_KEYS),
+    spec("copy", -3, TWO_KEYS),
+    spec("dbsize", 1, NO_KEYS),
+    spec("debug", -2, NO_KEYS),
+    spec("decr", 2, KEY),
+    spec("decrby", 3, KEY),
+    spec("del", -2, ALL_KEYS),
+    spec("discard", 1, NO_KEYS),
+    spec("echo", 2, NO_KEYS),
+    spec("eval", -3, SCRIPT_KEYS),
+    spec("evalsha", -3, SCRIPT_KEYS),
+    spec("exec", 1, NO_KEYS),
+    spec("exists", -2, ALL_KEYS),
+    spec("expire", -3, KEY),
+    spec("expireat", -3, KEY),
+    spec("expiretime", 2, KEY),
+    spec("failover", -1, NO_KEYS),
+    spec("fcall", -3, SCRIPT_KEYS),
+    spec("fcall_ro", -3, SCRIPT_KEYS),
+    spec("flushall", -1, NO_KEYS),
+    spec("flushdb", -1, NO_KEYS),
+    spec("function", -2, NO_KEYS),
+    spec("get", 2, KEY),
+    spec("getdel", 2, KEY),
+    spec("getex", -2, KEY),
+    spec("getrange", 4, KEY),
+    spec("getset", 3, KEY),
+    spec("hdel", -3, KEY),
+    spec("hello", -1, NO_KEYS),
+    spec("hget", 3, KEY),
+    spec("hgetall", 2, KEY),
+    spec("hlen", 2, KEY),
+    spec("hscan", -3, KEY),
+    spec("hset", -4, KEY),
+    spec("incr", 2, KEY),
+    spec("incrby", 3, KEY),
+    spec("incrbyfloat", 3, KEY),
+    spec("info", -1, NO_KEYS),
+    spec("lastsave", 1, NO_KEYS),
+    spec("latency", -2, NO_KEYS),
+    spec("lindex", 3, KEY),
+    spec("linsert", 5, KEY),
+    spec("llen", 2, KEY),
+    spec("lpop", -2, KEY),
+    spec("lpos", -3, KEY),
+    spec("lpush", -3, KEY),
+    spec("lrange", 4, KEY),
+    spec("lrem", 4, KEY),
+    spec("lset", 4, KEY),
+    spec("ltrim", 4, KEY),
+    spec("mget", -2, ALL_KEYS),
+    spec("monitor", 1, NO_KEYS),
+    spec("mset", -3, KEY_VALUE_PAIRS),
+    spec("msetnx", -3, KEY_VALUE_PAIRS),
+    spec("multi", 1, NO_KEYS),
+    spec("object", -2, NO_KEYS),
+    spec("object|encoding", 3, SUBCOMMAND_KEY),
+    spec("object|freq", 3, SUBCOMMAND_KEY),
+    spec("object|idletime", 3, SUBCOMMAND_KEY),
+    spec("persist", 2, KEY),
+    spec("pexpire", -3, KEY),
+    spec("pexpireat", -3, KEY),
+    spec("pexpiretime", 2, KEY),
+    spec("ping", -1, NO_KEYS),
+    spec("psetex", 4, KEY),
+    spec("psubscribe", -2, NO_KEYS),
+    spec("psync", -3, NO_KEYS),
+    spec("pttl", 2, KEY),
+    spec("publish", 3, NO_KEYS),
+    spec("pubsub", -2, NO_KEYS),
+    spec("punsubscribe", -1, NO_KEYS),
+    spec("quit", -1, NO_KEYS),
+    spec("randomkey", 1, NO_KEYS),
+    spec("rename", 3, TWO_KEYS),
+    spec("renamenx", 3, TWO_KEYS),
+    spec("readonly", 1, NO_KEYS),
+    spec("readwrite", 1, NO_KEYS),
+    spec("replconf", -1, NO_KEYS),
+    spec("replicaof", 3, NO_KEYS),
+    spec("reset", 1, NO_KEYS),
+    spec("role", 1, NO_KEYS),
+    spec("rpop", -2, KEY),
+    spec("rpush", -3, KEY),
+    spec("sadd", -3, KEY),
+    spec("save", 1, NO_KEYS),
+    spec("scan", -2, NO_KEYS),
+    spec("scard", 2, KEY),
+    spec("script", -2, NO_KEYS),
+    spec("set", -3, KEY),
+    spec("setex", 4, KEY),
+    spec("setnx", 3, KEY),
+    spec("setrange", 4, KEY),
+    spec("shutdown", -1, NO_KEYS),
+    spec("sismember", 3, KEY),
+    spec("slaveof", 3, NO_KEYS),
+    spec("smembers", 2, KEY),
+    spec("smismember", -3, KEY),
+    spec("smove", 4, TWO_KEYS),
+    spec("spop", -2, KEY),
+    spec("srandmember", -2, KEY),
+    spec("srem", -3, KEY),
+    spec("sscan", -3, KEY),
+    spec("strlen", 2, KEY),
+    spec("subscribe", -2, NO_KEYS),
+    spec("ttl", 2, KEY),
+    spec("type", 2, KEY),
+    spec("unlink", -2, ALL_KEYS),
+    spec("unsubscribe", -1, NO_KEYS),
+    spec("wait", 3, NO_KEYS),
+    spec("xadd", -5, KEY),
+    spec("xdel", -3, KEY),
+    spec("xlen", 2, KEY),
+    spec("xrange", -4, KEY),
+    spec("xread", -4, &[KeySpec::Streams]),
+    spec("xtrim", -4, KEY),
+    spec("zadd", -4, KEY),
+    spec("zcard", 2, KEY),
+    spec("zdiff", -3, &[KeySpec::Keynum { numkeys: 1 }]),
+    spec("zdiffstore", -4, DESTINATION_AND_KEYS),
+    spec("zinterstore", -4, DESTINATION_AND_KEYS),
+    spec("zrandmember", -2, KEY),
+    spec("zrange", -4, KEY),
+    spec("zrank", -3, KEY),
+    spec("zrem", -3, KEY),
+    spec("zremrangebylex", 4, KEY),
+    spec("zremrangebyrank", 4, KEY),
+    spec("zremrangebyscore", 4, KEY),
+    spec("zscan", -3, KEY),
+    spec("zscore", 3, KEY),
+    spec("zunionstore", -4, DESTINATION_AND_KEYS),
+];
+
+/// Whether the server knows the command `name`, given in lowercase.
+pub fn is_command(name: &str) -> bool {
+    COMMANDS.iter().any(|spec| spec.name == name)
+}
+
+/// Whether `argv` calls a command the server knows with a number of
+/// arguments it accepts, all Redis checks of a command before queuing it in
+/// a transaction.
+pub fn is_valid_call(argv: &[Bytes]) -> bool {
+    find_spec(argv).is_some_and(|spec| spec.accepts(argv.len()))
+}
+
+impl CommandSpec {
+    fn accepts(&self, argc: usize) -> bool {
+        let argc = argc as isize;
+        if self.arity > 0 {
+            argc == self.arity
+        } else {
+            argc >= -self.arity
+        }
+    }
+}
+
+/// Checks that the command `name`, given in lowercase, is called with a
+/// number of arguments it accepts, `argc` counting the command name, the
+/// subcommand's own arity applying where it has one.
+pub fn check_arity(name: &str, subcommand: Option<&str>, argc: usize) -> Result<(), CommandError> {
+    match named_spec(name, subcommand) {
+        Some(spec) if !spec.accepts(argc) => Err(CommandError::wrong_arity(spec.name)),
+        _ => Ok(()),
+    }
+}
+
+fn find_spec(argv: &[Bytes]) -> Option<&'static CommandSpec> {
+    let name = String::from_utf8_lossy(argv.first()?).to_lowercase();
+    let subcommand = argv
+        .get(1)
+        .map(|subcommand| String::from_utf8_lossy(subcommand));
+    named_spec(&name, subcommand.as_deref())
+}
+
+fn named_spec(name: &str, subcommand: Option<&str>) -> Option<&'static CommandSpec> {
+    let find = |name: &str| COMMANDS.iter().find(|spec| spec.name == name);
+    let subcommand =
+        subcommand.and_then(|subcommand| find(&format!("{name}|{}", subcommand.to_lowercase())));
+    subcommand.or_else(|| find(name))
+}
+
+/// The keys the command line `argv` would touch, as `COMMAND GETKEYS`
+/// reports them.
+pub fn command_keys(argv: &[Bytes]) -> Result<Vec<Bytes>> {
+    let spec = find_spec(argv).ok_or_else(|| anyhow!("ERR Invalid command specified"))?;
+    if !spec.accepts(argv.len()) {
+        bail!("ERR Invalid number of arguments specified for command");
+    }
+    let argc = argv.len() as isize;
+    if spec.keys.is_empty() {
+        bail!("ERR The command has no key arguments");
+    }
+    let invalid = || anyhow!("ERR Invalid arguments specified for command");
+
+    let mut keys = vec![];
+    for key_spec in spec.keys {
+        match *key_spec {
+            KeySpec::Range { first, last, step } => {
+                let last = if last < 0 { argc + last } else { last };
+                keys.extend(
+                    (first as isize..=last.min(argc - 1))
+                        .step_by(step)
+                        .map(|index| argv[index as usize].clone()),
+                );
+            }
+            KeySpec::Keynum { numkeys } => {
+                let count: usize = std::str::from_utf8(&argv[numkeys])
+                    .ok()
+                    .and_then(|count| count.parse().ok())
+                    .ok_or_else(invalid)?;
+                let named = argv[numkeys + 1..].get(..count).ok_or_else(invalid)?;
+                keys.extend_from_slice(named);
+            }
+            KeySpec::Streams => {
+                let streams = argv
+                    .iter()
+                    .position(|arg| arg.eq_ignore_ascii_case(b"STREAMS"))
+                    .ok_or_else(invalid)?;
+                let rest = &argv[streams + 1..];
+                if rest.is_empty() || !rest.len().is_multiple_of(2) {
+                    return Err(invalid());
+                }
+                keys.extend_from_slice(&rest[..rest.len() / 2]);
+            }
+        }
+    }
+    // Only a command line naming no key at all, such as `EVAL` with zero
+    // keys, gets here without any.
+    if keys.is_empty() {
+        return Err(invalid());
+    }
+    Ok(keys)
+}
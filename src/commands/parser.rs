@@ -1,225 +1,732 @@
 use super::{
-    Command,
-    xstream_helpers::{XreadDuration, XreadStartId},
+    Command, Expiration, SetCondition,
+    error::CommandError,
+    keys,
+    xstream_helpers::{XaddId, XreadDuration, XreadStartId},
+};
+use crate::{
+    clients::{KillFilter, tracking::TrackingOptions},
+    cluster,
+    db::{
+        ExpireCondition, ListEnd,
+        scan::ScanOptions,
+        sorted_set::{Aggregate, LexBound, ScoreBound, ZaddOptions, ZsetOperation, ZsetRange},
+        stream_types::{StreamId, TrimStrategy},
+    },
+    resp::RespValue,
 };
-use crate::resp::RespValue;
 use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use std::time::Duration;
 
 pub fn parse_command(command_name: String, args: Vec<RespValue>) -> Result<Command> {
+    let subcommand = args.first().cloned().map(String::from);
+    keys::check_arity(
+        &command_name.to_lowercase(),
+        subcommand.as_deref(),
+        args.len() + 1,
+    )?;
     match command_name.to_uppercase().as_str() {
         "PING" => {
             if !args.is_empty() {
-                return Err(anyhow!("PING command takes no arguments"));
+                return Err(CommandError::wrong_arity("ping").into());
             }
             Ok(Command::Ping)
         }
         "ECHO" => {
-            let message: String = args
+            let message: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("ECHO command requires an argument"))?
+                .ok_or_else(|| CommandError::wrong_arity("echo"))?
                 .clone()
                 .into();
             Ok(Command::Echo { message })
         }
         "SET" => {
-            let key: String = args
-                .first()
-                .ok_or_else(|| anyhow!("SET command requires a key"))?
-                .clone()
-                .into();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("set").into());
+            }
+            let key: Bytes = args[0].clone().into();
+            let value: Bytes = args[1].clone().into();
 
-            let value: String = args
-                .get(1)
-                .ok_or_else(|| anyhow!("SET command requires a value"))?
-                .clone()
-                .into();
-
-            let mut expiry_millis: Option<u64> = None;
-
-            if let Some(px_arg) = args.get(2) {
-                let px_str: String = px_arg.clone().into();
-                if px_str.to_uppercase() == "PX" {
-                    let millis_str: String = args
-                        .get(3)
-                        .ok_or_else(|| anyhow!("Missing milliseconds value for PX"))?
-                        .clone()
-                        .into();
-                    expiry_millis = Some(
-                        millis_str
-                            .parse::<u64>()
-                            .map_err(|e| anyhow!("Invalid PX value: {}", e))?,
-                    );
-                    if args.len() > 4 {
-                        return Err(anyhow!("Too many arguments for SET command"));
+            let mut condition = None;
+            let mut get = false;
+            let mut keep_ttl = false;
+            let mut expiration = None;
+            let mut options = args[2..].iter().cloned();
+            while let Some(option) = options.next() {
+                let option = String::from(option).to_uppercase();
+                match option.as_str() {
+                    "NX" | "XX" if condition.is_none() => {
+                        condition = Some(if option == "NX" {
+                            SetCondition::Nx
+                        } else {
+                            SetCondition::Xx
+                        });
                     }
-                } else {
-                    return Err(anyhow!(
-                        "Unknown argument after value. Expected 'PX' or end of command."
-                    ));
+                    "GET" => get = true,
+                    "KEEPTTL" if expiration.is_none() => keep_ttl = true,
+                    "EX" | "PX" | "EXAT" | "PXAT" if expiration.is_none() && !keep_ttl => {
+                        let time = options.next().ok_or(CommandError::Syntax)?;
+                        expiration = Some(parse_expiration(&option, time, "set")?);
+                    }
+                    _ => return Err(CommandError::Syntax.into()),
                 }
-            } else if args.len() > 2 {
-                return Err(anyhow!("Too many arguments for SET command"));
             }
 
             Ok(Command::Set {
                 key,
                 value,
-                expiry_millis,
+                condition,
+                get,
+                keep_ttl,
+                expiration,
             })
         }
         "RPUSH" => {
             let key = args
                 .first()
-                .ok_or_else(|| anyhow!("RPUSH command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("rpush"))?
                 .clone()
                 .into();
             if args.len() < 2 {
-                return Err(anyhow!("RPUSH command requires at least one value"));
+                return Err(CommandError::wrong_arity("rpush").into());
             }
 
             let values = args[1..]
                 .iter()
                 .map(|resp_value| resp_value.clone().into())
-                .collect::<Vec<String>>();
+                .collect::<Vec<Bytes>>();
 
             Ok(Command::Rpush { key, values })
         }
         "LPUSH" => {
             let key = args
                 .first()
-                .ok_or_else(|| anyhow!("LPUSH command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("lpush"))?
                 .clone()
                 .into();
             if args.len() < 2 {
-                return Err(anyhow!("LPUSH command requires at least one value"));
+                return Err(CommandError::wrong_arity("lpush").into());
             }
 
             let values = args[1..]
                 .iter()
                 .map(|resp_value| resp_value.clone().into())
-                .collect::<Vec<String>>();
+                .collect::<Vec<Bytes>>();
 
             Ok(Command::Lpush { key, values })
         }
-        "LPOP" => {
-            let key: String = args
-                .first()
-                .ok_or_else(|| anyhow!("LPOP command requires a key"))?
-                .clone()
-                .into();
+        "LPOP" | "RPOP" => {
+            let name = command_name.to_lowercase();
+            let (key, count) = match args.as_slice() {
+                [key] => (key.clone(), None),
+                [key, count] => {
+                    let count = usize::try_from(parse_integer(count.clone())?)
+                        .map_err(|_| anyhow!("ERR value is out of range, must be positive"))?;
+                    (key.clone(), Some(count))
+                }
+                _ => {
+                    return Err(CommandError::wrong_arity(&name).into());
+                }
+            };
 
-            let count: usize = args.get(1).map(|v| v.clone().into()).unwrap_or(1);
+            Ok(Command::Pop {
+                key: key.into(),
+                end: list_end(&name),
+                count,
+            })
+        }
+        "BLPOP" | "BRPOP" => {
+            let name = command_name.to_lowercase();
+            let Some((timeout, keys)) = args.split_last() else {
+                return Err(CommandError::wrong_arity(&name).into());
+            };
+            if keys.is_empty() {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            let timeout_seconds = String::from(timeout.clone())
+                .parse::<f64>()
+                .ok()
+                .filter(|timeout| timeout.is_finite())
+                .ok_or_else(|| anyhow!("ERR timeout is not a float or out of range"))?;
+            if timeout_seconds < 0.0 {
+                return Err(anyhow!("ERR timeout is negative"));
+            }
 
-            if args.len() > 2 {
-                return Err(anyhow!("Too many arguments for LPOP command"));
+            Ok(Command::Bpop {
+                keys: keys.iter().cloned().map(Bytes::from).collect(),
+                end: list_end(&name),
+                timeout_seconds,
+            })
+        }
+        // The legacy forms of `SET key value EX|PX time` and `SET key value NX`,
+        // the latter replying like `MSETNX`.
+        "SETEX" | "PSETEX" => {
+            let name = command_name.to_lowercase();
+            let [key, time, value] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity(&name))?;
+            let option = if name == "setex" { "EX" } else { "PX" };
+
+            Ok(Command::Set {
+                key: key.into(),
+                value: value.into(),
+                condition: None,
+                get: false,
+                keep_ttl: false,
+                expiration: Some(parse_expiration(option, time, &name)?),
+            })
+        }
+        "SETNX" => {
+            let [key, value] =
+                <[RespValue; 2]>::try_from(args).map_err(|_| CommandError::wrong_arity("setnx"))?;
+            Ok(Command::Mset {
+                pairs: vec![(key.into(), value.into())],
+                nx: true,
+            })
+        }
+        "MGET" => {
+            if args.is_empty() {
+                return Err(CommandError::wrong_arity("mget").into());
             }
+            Ok(Command::Mget {
+                keys: args.into_iter().map(Bytes::from).collect(),
+            })
+        }
+        "MSET" | "MSETNX" => {
+            let name = command_name.to_lowercase();
+            if args.is_empty() || !args.len().is_multiple_of(2) {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            let pairs = args
+                .chunks_exact(2)
+                .map(|pair| (pair[0].clone().into(), pair[1].clone().into()))
+                .collect();
 
-            Ok(Command::Lpop { key, count })
+            Ok(Command::Mset {
+                pairs,
+                nx: name == "msetnx",
+            })
         }
-        "BLPOP" => {
-            let key: String = args
+        "GETEX" => {
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("BLPOP command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("getex"))?
                 .clone()
                 .into();
+            let (expiration, persist) = match &args[1..] {
+                [] => (None, false),
+                [option] if String::from(option.clone()).to_uppercase() == "PERSIST" => {
+                    (None, true)
+                }
+                [option, time] => {
+                    let option = String::from(option.clone()).to_uppercase();
+                    if !matches!(option.as_str(), "EX" | "PX" | "EXAT" | "PXAT") {
+                        return Err(CommandError::Syntax.into());
+                    }
+                    (
+                        Some(parse_expiration(&option, time.clone(), "getex")?),
+                        false,
+                    )
+                }
+                _ => return Err(CommandError::Syntax.into()),
+            };
+
+            Ok(Command::Getex {
+                key,
+                expiration,
+                persist,
+            })
+        }
+        "GETDEL" => {
+            let [key] = <[RespValue; 1]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("getdel"))?;
+            Ok(Command::Getdel { key: key.into() })
+        }
+        // The legacy form of `SET key value GET`.
+        "GETSET" => {
+            let [key, value] = <[RespValue; 2]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("getset"))?;
+            Ok(Command::Set {
+                key: key.into(),
+                value: value.into(),
+                condition: None,
+                get: true,
+                keep_ttl: false,
+                expiration: None,
+            })
+        }
+        "INCR" | "DECR" | "INCRBY" | "DECRBY" => {
+            let name = command_name.to_lowercase();
+            let wrong_arity = || CommandError::wrong_arity(&name);
+            let (key, delta) = match (name.ends_with("by"), args.as_slice()) {
+                (false, [key]) => (key.clone(), 1),
+                (true, [key, delta]) => (key.clone(), parse_integer(delta.clone())?),
+                _ => return Err(wrong_arity().into()),
+            };
+            let delta = if name.starts_with("decr") {
+                delta
+                    .checked_neg()
+                    .ok_or_else(|| anyhow!("ERR decrement would overflow"))?
+            } else {
+                delta
+            };
 
-            let timeout_seconds: f64 = args.get(1).map(|v| v.clone().into()).unwrap_or(0.0);
+            Ok(Command::Incrby {
+                key: key.into(),
+                delta,
+            })
+        }
+        "INCRBYFLOAT" => {
+            let [key, delta] = <[RespValue; 2]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("incrbyfloat"))?;
 
-            if args.len() > 2 {
-                return Err(anyhow!("Too many arguments for BLPOP command"));
+            Ok(Command::Incrbyfloat {
+                key: key.into(),
+                delta: parse_score(delta.into())?,
+            })
+        }
+        "APPEND" => {
+            let [key, value] = <[RespValue; 2]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("append"))?;
+            Ok(Command::Append {
+                key: key.into(),
+                value: value.into(),
+            })
+        }
+        "STRLEN" => {
+            let [key] = <[RespValue; 1]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("strlen"))?;
+            Ok(Command::Strlen { key: key.into() })
+        }
+        "GETRANGE" => {
+            let [key, start, end] = <[RespValue; 3]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("getrange"))?;
+            Ok(Command::Getrange {
+                key: key.into(),
+                start: parse_integer(start)?,
+                end: parse_integer(end)?,
+            })
+        }
+        "SETRANGE" => {
+            let [key, offset, value] = <[RespValue; 3]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("setrange"))?;
+            let offset = usize::try_from(parse_integer(offset)?)
+                .map_err(|_| anyhow!("ERR offset is out of range"))?;
+            Ok(Command::Setrange {
+                key: key.into(),
+                offset,
+                value: value.into(),
+            })
+        }
+        "LINSERT" => {
+            let [key, position, pivot, element] = <[RespValue; 4]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("linsert"))?;
+            let after = match String::from(position).to_uppercase().as_str() {
+                "BEFORE" => false,
+                "AFTER" => true,
+                _ => return Err(CommandError::Syntax.into()),
+            };
+
+            Ok(Command::Linsert {
+                key: key.into(),
+                after,
+                pivot: pivot.into(),
+                element: element.into(),
+            })
+        }
+        "LSET" => {
+            let [key, index, element] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity("lset"))?;
+            Ok(Command::Lset {
+                key: key.into(),
+                index: parse_integer(index)?,
+                element: element.into(),
+            })
+        }
+        "LINDEX" => {
+            let [key, index] = <[RespValue; 2]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("lindex"))?;
+            Ok(Command::Lindex {
+                key: key.into(),
+                index: parse_integer(index)?,
+            })
+        }
+        "LREM" => {
+            let [key, count, element] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity("lrem"))?;
+            Ok(Command::Lrem {
+                key: key.into(),
+                count: parse_integer(count)?,
+                element: element.into(),
+            })
+        }
+        "LTRIM" => {
+            let [key, start, stop] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity("ltrim"))?;
+            Ok(Command::Ltrim {
+                key: key.into(),
+                start: parse_integer(start)?,
+                stop: parse_integer(stop)?,
+            })
+        }
+        "LPOS" => {
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("lpos").into());
+            }
+            let mut args = args.into_iter();
+            let key = args.next().unwrap().into();
+            let element = args.next().unwrap().into();
+            let (mut rank, mut count, mut maxlen) = (1, None, 0);
+            while let Some(option) = args.next() {
+                let option = String::from(option).to_uppercase();
+                let value = match option.as_str() {
+                    "RANK" | "COUNT" | "MAXLEN" => {
+                        parse_integer(args.next().ok_or(CommandError::Syntax)?)?
+                    }
+                    _ => return Err(CommandError::Syntax.into()),
+                };
+                match option.as_str() {
+                    "RANK" if value == 0 || value == i64::MIN => {
+                        return Err(anyhow!(
+                            "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list"
+                        ));
+                    }
+                    "RANK" => rank = value,
+                    _ if value < 0 => {
+                        return Err(anyhow!("ERR {option} can't be negative"));
+                    }
+                    "COUNT" => count = Some(value as usize),
+                    _ => maxlen = value as usize,
+                }
             }
 
-            Ok(Command::Blpop {
+            Ok(Command::Lpos {
                 key,
-                timeout_seconds,
+                element,
+                rank,
+                count,
+                maxlen,
             })
         }
         "LLEN" => {
-            let key: String = args
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("LLEN command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("llen"))?
                 .clone()
                 .into();
 
             if args.len() > 1 {
-                return Err(anyhow!("Too many arguments for LLEN command"));
+                return Err(CommandError::wrong_arity("llen").into());
             }
 
             Ok(Command::Llen { key })
         }
         "GET" => {
-            let key: String = args
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("GET command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("get"))?
                 .clone()
                 .into();
 
             if args.len() > 1 {
-                return Err(anyhow!("Too many arguments for GET command"));
+                return Err(CommandError::wrong_arity("get").into());
             }
 
             Ok(Command::Get { key })
         }
-        "LRANGE" => {
-            let key: String = args
+        "EXPIRE" | "PEXPIRE" | "EXPIREAT" | "PEXPIREAT" => {
+            let name = command_name.to_lowercase();
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("LRANGE command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity(&name))?
                 .clone()
                 .into();
-
-            let start: isize = args
+            let time: String = args
                 .get(1)
-                .ok_or_else(|| anyhow!("LRANGE command requires a start value"))?
+                .ok_or_else(|| CommandError::wrong_arity(&name))?
                 .clone()
                 .into();
+            let time = time
+                .parse::<i64>()
+                .map_err(|_| CommandError::NotAnInteger)?;
+            let millis = if name.starts_with('p') {
+                Some(time)
+            } else {
+                time.checked_mul(1000)
+            }
+            .ok_or_else(|| anyhow!("ERR invalid expire time in '{name}' command"))?;
+            let expiration = if name.ends_with("at") {
+                Expiration::At(millis)
+            } else {
+                Expiration::In(millis)
+            };
+
+            let mut condition = ExpireCondition::default();
+            for flag in &args[2..] {
+                let flag: String = flag.clone().into();
+                match flag.to_uppercase().as_str() {
+                    "NX" => condition.nx = true,
+                    "XX" => condition.xx = true,
+                    "GT" => condition.gt = true,
+                    "LT" => condition.lt = true,
+                    _ => return Err(anyhow!("ERR Unsupported option {flag}")),
+                }
+            }
+            if condition.nx && (condition.xx || condition.gt || condition.lt) {
+                return Err(anyhow!(
+                    "ERR NX and XX, GT or LT options at the same time are not compatible"
+                ));
+            }
+            if condition.gt && condition.lt {
+                return Err(anyhow!(
+                    "ERR GT and LT options at the same time are not compatible"
+                ));
+            }
 
-            let stop: isize = args
-                .get(2)
-                .ok_or_else(|| anyhow!("LRANGE command requires a stop value"))?
+            Ok(Command::Expire {
+                name,
+                key,
+                expiration,
+                condition,
+            })
+        }
+        "TTL" | "PTTL" => {
+            let name = command_name.to_lowercase();
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity(&name))?
                 .clone()
                 .into();
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            if name == "ttl" {
+                Ok(Command::Ttl { key })
+            } else {
+                Ok(Command::Pttl { key })
+            }
+        }
+        "EXPIRETIME" | "PEXPIRETIME" | "PERSIST" => {
+            let name = command_name.to_lowercase();
+            let [key] =
+                <[RespValue; 1]>::try_from(args).map_err(|_| CommandError::wrong_arity(&name))?;
+            let key = key.into();
+            Ok(match name.as_str() {
+                "expiretime" => Command::Expiretime { key },
+                "pexpiretime" => Command::Pexpiretime { key },
+                _ => Command::Persist { key },
+            })
+        }
+        "DEL" | "UNLINK" | "EXISTS" => {
+            let name = command_name.to_lowercase();
+            if args.is_empty() {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            let keys = args
+                .into_iter()
+                .map(|resp_value| resp_value.into())
+                .collect::<Vec<Bytes>>();
+            match name.as_str() {
+                "del" => Ok(Command::Del { keys }),
+                "unlink" => Ok(Command::Unlink { keys }),
+                _ => Ok(Command::Exists { keys }),
+            }
+        }
+        "RENAME" | "RENAMENX" => {
+            let name = command_name.to_lowercase();
+            let [key, new_key] =
+                <[RespValue; 2]>::try_from(args).map_err(|_| CommandError::wrong_arity(&name))?;
 
-            if args.len() > 3 {
-                return Err(anyhow!("Too many arguments for LRANGE command"));
+            Ok(Command::Rename {
+                key: key.into(),
+                new_key: new_key.into(),
+                nx: name == "renamenx",
+            })
+        }
+        "COPY" => {
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("copy").into());
+            }
+            let source: Bytes = args[0].clone().into();
+            let destination: Bytes = args[1].clone().into();
+            let mut replace = false;
+            let mut options = args[2..].iter().cloned();
+            while let Some(option) = options.next() {
+                match String::from(option).to_uppercase().as_str() {
+                    "REPLACE" => replace = true,
+                    // There is a single database, so it is the only valid
+                    // destination.
+                    "DB" => {
+                        let db = options.next().ok_or(CommandError::Syntax)?;
+                        let db = String::from(db)
+                            .parse::<i64>()
+                            .map_err(|_| CommandError::NotAnInteger)?;
+                        if db != 0 {
+                            return Err(anyhow!("ERR DB index is out of range"));
+                        }
+                    }
+                    _ => return Err(CommandError::Syntax.into()),
+                }
+            }
+            if source == destination {
+                return Err(anyhow!("ERR source and destination objects are the same"));
+            }
+
+            Ok(Command::Copy {
+                source,
+                destination,
+                replace,
+            })
+        }
+        "RANDOMKEY" | "DBSIZE" => {
+            let name = command_name.to_lowercase();
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            if name == "randomkey" {
+                Ok(Command::Randomkey)
+            } else {
+                Ok(Command::Dbsize)
             }
+        }
+        // With a single database, FLUSHDB and FLUSHALL are the same.
+        "FLUSHDB" | "FLUSHALL" => {
+            let lazy = match args.as_slice() {
+                [] => false,
+                [mode] => match String::from(mode.clone()).to_uppercase().as_str() {
+                    "ASYNC" => true,
+                    "SYNC" => false,
+                    _ => return Err(CommandError::Syntax.into()),
+                },
+                _ => return Err(CommandError::Syntax.into()),
+            };
+            Ok(Command::Flush { lazy })
+        }
+        "OBJECT" => {
+            let subcommand: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("object"))?
+                .clone()
+                .into();
+            let subcommand = subcommand.to_lowercase();
+            let key: Bytes = match args.as_slice() {
+                [_, key] => key.clone().into(),
+                _ => {
+                    return Err(anyhow!(
+                        "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try OBJECT HELP."
+                    ));
+                }
+            };
+
+            match subcommand.as_str() {
+                "encoding" => Ok(Command::ObjectEncoding { key }),
+                "idletime" => Ok(Command::ObjectIdletime { key }),
+                "freq" => Ok(Command::ObjectFreq { key }),
+                _ => Err(anyhow!(
+                    "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try OBJECT HELP."
+                )),
+            }
+        }
+        "SCAN" => {
+            let cursor = parse_cursor(
+                args.first()
+                    .ok_or_else(|| CommandError::wrong_arity("scan"))?
+                    .clone(),
+            )?;
+            let mut type_name = None;
+            let options = parse_scan_options(&args[1..], |option, values| match option {
+                "TYPE" => {
+                    let value = values.next().ok_or(CommandError::Syntax)?;
+                    type_name = Some(String::from(value).to_lowercase());
+                    Ok(())
+                }
+                _ => Err(CommandError::Syntax.into()),
+            })?;
+
+            Ok(Command::Scan {
+                cursor,
+                options,
+                type_name,
+            })
+        }
+        "HSCAN" | "SSCAN" | "ZSCAN" => {
+            let name = command_name.to_lowercase();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            let key: Bytes = args[0].clone().into();
+            let cursor = parse_cursor(args[1].clone())?;
+            let mut novalues = false;
+            let options = parse_scan_options(&args[2..], |option, _| match option {
+                "NOVALUES" if name == "hscan" => {
+                    novalues = true;
+                    Ok(())
+                }
+                _ => Err(CommandError::Syntax.into()),
+            })?;
 
-            Ok(Command::Lrange { key, start, stop })
+            match name.as_str() {
+                "hscan" => Ok(Command::Hscan {
+                    key,
+                    cursor,
+                    options,
+                    novalues,
+                }),
+                "sscan" => Ok(Command::Sscan {
+                    key,
+                    cursor,
+                    options,
+                }),
+                _ => Ok(Command::Zscan {
+                    key,
+                    cursor,
+                    options,
+                }),
+            }
+        }
+        "LRANGE" => {
+            let [key, start, stop] = <[RespValue; 3]>::try_from(args)
+                .map_err(|_| CommandError::wrong_arity("lrange"))?;
+            Ok(Command::Lrange {
+                key: key.into(),
+                start: parse_integer(start)?,
+                stop: parse_integer(stop)?,
+            })
         }
         "TYPE" => {
-            let key: String = args
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("TYPE command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("type"))?
                 .clone()
                 .into();
 
             Ok(Command::Type { key })
         }
         "XADD" => {
-            let key: String = args
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("XADD command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("xadd"))?
                 .clone()
                 .into();
 
-            let id: String = args
-                .get(1)
-                .ok_or_else(|| anyhow!("XADD command requires an id"))?
-                .clone()
-                .into();
+            let id: XaddId = String::from(
+                args.get(1)
+                    .ok_or_else(|| CommandError::wrong_arity("xadd"))?
+                    .clone(),
+            )
+            .parse()?;
 
             let remaining_args = &args[2..];
 
             if !remaining_args.len().is_multiple_of(2) {
-                return Err(anyhow!(
-                    "XADD command requires an even number of field-value pairs"
-                ));
+                return Err(CommandError::wrong_arity("xadd").into());
             }
 
-            let field_value_pairs: Vec<(String, String)> = remaining_args
+            let field_value_pairs: Vec<(Bytes, Bytes)> = remaining_args
                 .chunks_exact(2)
                 .map(|chunk| {
-                    let field: String = chunk[0].clone().into();
-                    let value: String = chunk[1].clone().into();
+                    let field: Bytes = chunk[0].clone().into();
+                    let value: Bytes = chunk[1].clone().into();
                     (field, value)
                 })
                 .collect();
@@ -232,34 +739,89 @@ pub fn parse_command(command_name: String, args: Vec<RespValue>) -> Result<Comma
         }
 
         "XRANGE" => {
-            let key: String = args
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("XRANGE command requires a key"))?
+                .ok_or_else(|| CommandError::wrong_arity("xrange"))?
                 .clone()
                 .into();
 
-            let start = args.get(1).map(|s| s.clone().into());
-            let end = args.get(2).map(|s| s.clone().into());
+            // `-` and `+` stand for the first and last possible IDs, and a
+            // bound without a sequence number covers the whole millisecond.
+            let bound = |index: usize, open: &str, unbounded: StreamId| -> Result<StreamId> {
+                match args.get(index).map(|arg| String::from(arg.clone())) {
+                    Some(arg) if arg != open => StreamId::parse_bound(&arg, unbounded.seq),
+                    _ => Ok(unbounded),
+                }
+            };
+            let start = bound(1, "-", StreamId::MIN)?;
+            let end = bound(2, "+", StreamId::MAX)?;
 
             Ok(Command::Xrange { key, start, end })
         }
 
-        "XREAD" => {
-            let first_arg: String = args
+        "XLEN" => {
+            let key: Bytes = args
                 .first()
-                .ok_or_else(|| anyhow!("XREAD command requires stream or block as first arg"))?
+                .ok_or_else(|| CommandError::wrong_arity("xlen"))?
                 .clone()
                 .into();
 
+            Ok(Command::Xlen { key })
+        }
+        "XDEL" => {
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("xdel").into());
+            }
+            let mut args = args.into_iter();
+            let key = args.next().unwrap().into();
+            let ids = args
+                .map(|id| String::from(id).parse())
+                .collect::<Result<_>>()?;
+
+            Ok(Command::Xdel { key, ids })
+        }
+        "XTRIM" => {
+            if args.len() < 3 {
+                return Err(CommandError::wrong_arity("xtrim").into());
+            }
+            let mut args = args.into_iter();
+            let key = args.next().unwrap().into();
+            let strategy = String::from(args.next().unwrap()).to_uppercase();
+            let mut threshold = String::from(args.next().unwrap());
+            let approximate = threshold == "~";
+            if approximate || threshold == "=" {
+                threshold = String::from(args.next().ok_or(CommandError::Syntax)?);
+            }
+            if args.next().is_some() {
+                return Err(CommandError::Syntax.into());
+            }
+            let strategy = match strategy.as_str() {
+                "MAXLEN" => TrimStrategy::MaxLen(
+                    threshold
+                        .parse()
+                        .map_err(|_| anyhow!("ERR The MAXLEN argument must be >= 0."))?,
+                ),
+                "MINID" => TrimStrategy::MinId(StreamId::parse_bound(&threshold, 0)?),
+                _ => return Err(CommandError::Syntax.into()),
+            };
+
+            Ok(Command::Xtrim {
+                key,
+                strategy,
+                approximate,
+            })
+        }
+        "XREAD" => {
+            let first_arg: String = args.first().ok_or(CommandError::Syntax)?.clone().into();
+
             let is_firt_arg_block = first_arg.to_uppercase() == "BLOCK";
             let duration = if is_firt_arg_block {
-                let duration: u64 = args
-                    .get(1)
-                    .ok_or_else(|| {
-                        anyhow!("XREAD command requires duration in millis after block")
-                    })?
-                    .clone()
-                    .into();
+                let duration = parse_integer(args.get(1).ok_or(CommandError::Syntax)?.clone())
+                    .map_err(|_| anyhow!("ERR timeout is not an integer or out of range"))?;
+                if duration < 0 {
+                    return Err(anyhow!("ERR timeout is negative"));
+                }
+                let duration = duration as u64;
                 if duration == 0 {
                     XreadDuration::Inifnity
                 } else {
@@ -277,18 +839,18 @@ pub fn parse_command(command_name: String, args: Vec<RespValue>) -> Result<Comma
 
             let stream_arg: String = remaining_args
                 .first()
-                .ok_or_else(|| anyhow!("XREAD command requires stream or block as first arg"))?
+                .ok_or(CommandError::Syntax)?
                 .clone()
                 .into();
 
             if stream_arg.to_uppercase() != "STREAMS" {
-                return Err(anyhow!("Expected 'streams' keyword"));
+                return Err(CommandError::Syntax.into());
             }
 
             let remaining_args = &remaining_args[1..];
             if !remaining_args.len().is_multiple_of(2) {
                 return Err(anyhow!(
-                    "XREAD STREAMS requires an even number of key-id pairs"
+                    "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
                 ));
             }
 
@@ -296,49 +858,1522 @@ pub fn parse_command(command_name: String, args: Vec<RespValue>) -> Result<Comma
             let keys_slice = &remaining_args[0..num_streams];
             let ids_slice = &remaining_args[num_streams..];
 
-            let streams: Vec<(String, XreadStartId)> = keys_slice
+            let streams: Vec<(Bytes, XreadStartId)> = keys_slice
                 .iter()
                 .zip(ids_slice.iter())
                 .map(|(key_resp, id_resp)| {
-                    let key: String = key_resp.clone().into();
+                    let key: Bytes = key_resp.clone().into();
                     let start_str: String = id_resp.clone().into();
                     let start = if start_str == "$" {
                         XreadStartId::Last
                     } else {
-                        XreadStartId::Normal(start_str)
+                        XreadStartId::Normal(StreamId::parse_bound(&start_str, 0)?)
                     };
-                    (key, start)
+                    Ok((key, start))
                 })
-                .collect();
+                .collect::<Result<_>>()?;
 
             Ok(Command::Xread { streams, duration })
         }
 
-        c => Err(anyhow!("Unknown command: {}", c)),
-    }
-}
+        "HSET" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("hset"))?
+                .clone()
+                .into();
 
-pub fn extract_command(value: RespValue) -> Result<(String, Vec<RespValue>)> {
-    match value {
-        RespValue::Array(a) => {
-            if a.is_empty() {
-                return Err(anyhow!("Empty array received as command"));
+            let remaining_args = &args[1..];
+            if remaining_args.is_empty() || !remaining_args.len().is_multiple_of(2) {
+                return Err(CommandError::wrong_arity("hset").into());
             }
-            Ok((
-                unpack_bulk_str(a.first().unwrap().clone())?,
-                a.into_iter().skip(1).collect(),
-            ))
+
+            let field_value_pairs: Vec<(Bytes, Bytes)> = remaining_args
+                .chunks_exact(2)
+                .map(|chunk| (chunk[0].clone().into(), chunk[1].clone().into()))
+                .collect();
+
+            Ok(Command::Hset {
+                key,
+                field_value_pairs,
+            })
         }
-        _ => Err(anyhow!("Unexpected command format")),
-    }
-}
+        "HGET" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("hget"))?
+                .clone()
+                .into();
 
-fn unpack_bulk_str(value: RespValue) -> Result<String> {
-    match value {
-        RespValue::BulkString(s) => Ok(s),
-        RespValue::SimpleString(s) => Ok(s),
-        _ => Err(anyhow!(
-            "Expected command name to be a bulk or simple string"
-        )),
+            let field: Bytes = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("hget"))?
+                .clone()
+                .into();
+
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("hget").into());
+            }
+
+            Ok(Command::Hget { key, field })
+        }
+        "HDEL" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("hdel"))?
+                .clone()
+                .into();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("hdel").into());
+            }
+
+            let fields = args[1..]
+                .iter()
+                .map(|resp_value| resp_value.clone().into())
+                .collect::<Vec<Bytes>>();
+
+            Ok(Command::Hdel { key, fields })
+        }
+        "HGETALL" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("hgetall"))?
+                .clone()
+                .into();
+
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("hgetall").into());
+            }
+
+            Ok(Command::Hgetall { key })
+        }
+        "HLEN" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("hlen"))?
+                .clone()
+                .into();
+
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("hlen").into());
+            }
+
+            Ok(Command::Hlen { key })
+        }
+        "SADD" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("sadd"))?
+                .clone()
+                .into();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("sadd").into());
+            }
+
+            let members = args[1..]
+                .iter()
+                .map(|resp_value| resp_value.clone().into())
+                .collect::<Vec<Bytes>>();
+
+            Ok(Command::Sadd { key, members })
+        }
+        "SREM" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("srem"))?
+                .clone()
+                .into();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("srem").into());
+            }
+
+            let members = args[1..]
+                .iter()
+                .map(|resp_value| resp_value.clone().into())
+                .collect::<Vec<Bytes>>();
+
+            Ok(Command::Srem { key, members })
+        }
+        "SMEMBERS" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("smembers"))?
+                .clone()
+                .into();
+
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("smembers").into());
+            }
+
+            Ok(Command::Smembers { key })
+        }
+        "SISMEMBER" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("sismember"))?
+                .clone()
+                .into();
+
+            let member: Bytes = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("sismember"))?
+                .clone()
+                .into();
+
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("sismember").into());
+            }
+
+            Ok(Command::Sismember { key, member })
+        }
+        "SMISMEMBER" => {
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("smismember").into());
+            }
+            let mut args = args.into_iter().map(Bytes::from);
+            Ok(Command::Smismember {
+                key: args.next().unwrap(),
+                members: args.collect(),
+            })
+        }
+        "SPOP" | "SRANDMEMBER" => {
+            let name = command_name.to_lowercase();
+            let mut args = args.into_iter();
+            let (Some(key), count, None) = (args.next(), args.next(), args.next()) else {
+                return Err(CommandError::wrong_arity(&name).into());
+            };
+            let key = key.into();
+            let count = count.map(parse_integer).transpose()?;
+
+            if name == "spop" {
+                if count.is_some_and(|count| count < 0) {
+                    return Err(anyhow!("ERR value is out of range, must be positive"));
+                }
+                Ok(Command::Spop {
+                    key,
+                    count: count.map(|count| count as usize),
+                })
+            } else {
+                if count.is_some_and(|count| count < -(i64::MAX / 2)) {
+                    return Err(anyhow!("ERR value is out of range"));
+                }
+                Ok(Command::Srandmember { key, count })
+            }
+        }
+        "SMOVE" => {
+            let [source, destination, member] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity("smove"))?;
+            Ok(Command::Smove {
+                source: source.into(),
+                destination: destination.into(),
+                member: member.into(),
+            })
+        }
+        "SCARD" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("scard"))?
+                .clone()
+                .into();
+
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("scard").into());
+            }
+
+            Ok(Command::Scard { key })
+        }
+        "ZADD" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zadd"))?
+                .clone()
+                .into();
+
+            let mut options = ZaddOptions::default();
+            let mut remaining_args = &args[1..];
+            while let Some((flag, rest)) = remaining_args.split_first() {
+                match String::from(flag.clone()).to_uppercase().as_str() {
+                    "NX" => options.nx = true,
+                    "XX" => options.xx = true,
+                    "GT" => options.gt = true,
+                    "LT" => options.lt = true,
+                    "CH" => options.ch = true,
+                    "INCR" => options.incr = true,
+                    _ => break,
+                }
+                remaining_args = rest;
+            }
+            if options.nx && options.xx {
+                return Err(anyhow!(
+                    "ERR XX and NX options at the same time are not compatible"
+                ));
+            }
+            if [options.nx, options.gt, options.lt]
+                .into_iter()
+                .filter(|flag| *flag)
+                .count()
+                > 1
+            {
+                return Err(anyhow!(
+                    "ERR GT, LT, and/or NX options at the same time are not compatible"
+                ));
+            }
+            if remaining_args.is_empty() || !remaining_args.len().is_multiple_of(2) {
+                return Err(CommandError::Syntax.into());
+            }
+            if options.incr && remaining_args.len() > 2 {
+                return Err(anyhow!(
+                    "ERR INCR option supports a single increment-element pair"
+                ));
+            }
+
+            let score_member_pairs = remaining_args
+                .chunks_exact(2)
+                .map(|chunk| {
+                    let score = parse_score(chunk[0].clone().into())?;
+                    let member: Bytes = chunk[1].clone().into();
+                    Ok((score, member))
+                })
+                .collect::<Result<Vec<(f64, Bytes)>>>()?;
+
+            Ok(Command::Zadd {
+                key,
+                score_member_pairs,
+                options,
+            })
+        }
+        "ZUNIONSTORE" | "ZINTERSTORE" | "ZDIFFSTORE" => {
+            let name = command_name.to_lowercase();
+            let Some((destination, args)) = args.split_first() else {
+                return Err(CommandError::wrong_arity(&name).into());
+            };
+            let (keys, options) = parse_numkeys(args, &name)?;
+            let operation = match name.as_str() {
+                "zunionstore" => ZsetOperation::Union,
+                "zinterstore" => ZsetOperation::Inter,
+                _ => ZsetOperation::Diff,
+            };
+
+            let mut weights = vec![1.0; keys.len()];
+            let mut aggregate = Aggregate::default();
+            let mut options = options.iter().cloned();
+            while let Some(option) = options.next() {
+                let option = String::from(option).to_uppercase();
+                match option.as_str() {
+                    "WEIGHTS" if operation != ZsetOperation::Diff => {
+                        for weight in weights.iter_mut() {
+                            let value = options.next().ok_or(CommandError::Syntax)?;
+                            *weight = String::from(value)
+                                .parse::<f64>()
+                                .ok()
+                                .filter(|weight| !weight.is_nan())
+                                .ok_or_else(|| anyhow!("ERR weight value is not a float"))?;
+                        }
+                    }
+                    "AGGREGATE" if operation != ZsetOperation::Diff => {
+                        let value = options.next().ok_or(CommandError::Syntax)?;
+                        aggregate = match String::from(value).to_uppercase().as_str() {
+                            "SUM" => Aggregate::Sum,
+                            "MIN" => Aggregate::Min,
+                            "MAX" => Aggregate::Max,
+                            _ => return Err(CommandError::Syntax.into()),
+                        };
+                    }
+                    _ => return Err(CommandError::Syntax.into()),
+                }
+            }
+
+            Ok(Command::Zstore {
+                operation,
+                destination: destination.clone().into(),
+                keys,
+                weights,
+                aggregate,
+            })
+        }
+        "ZDIFF" => {
+            let (keys, options) = parse_numkeys(&args, "zdiff")?;
+            let with_scores = match options {
+                [] => false,
+                [option] if String::from(option.clone()).eq_ignore_ascii_case("WITHSCORES") => true,
+                _ => return Err(CommandError::Syntax.into()),
+            };
+
+            Ok(Command::Zdiff { keys, with_scores })
+        }
+        "EVAL" | "EVALSHA" | "FCALL" | "FCALL_RO" => {
+            let name = command_name.to_lowercase();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity(&name).into());
+            }
+            let mut args = args.into_iter();
+            let script = args.next().unwrap();
+            let numkeys = parse_integer(args.next().unwrap())?;
+            let args: Vec<Bytes> = args.map(Bytes::from).collect();
+            if numkeys < 0 {
+                return Err(anyhow!("ERR Number of keys can't be negative"));
+            }
+            if numkeys as usize > args.len() {
+                return Err(anyhow!(
+                    "ERR Number of keys can't be greater than number of args"
+                ));
+            }
+            let (keys, args) = args.split_at(numkeys as usize);
+            let (keys, args) = (keys.to_vec(), args.to_vec());
+
+            match name.as_str() {
+                "eval" => Ok(Command::Eval {
+                    script: script.into(),
+                    keys,
+                    args,
+                }),
+                "evalsha" => Ok(Command::Evalsha {
+                    sha1: script.into(),
+                    keys,
+                    args,
+                }),
+                _ => Ok(Command::Fcall {
+                    function: script.into(),
+                    keys,
+                    args,
+                    read_only: name == "fcall_ro",
+                }),
+            }
+        }
+        "SCRIPT" => {
+            let subcommand = args
+                .first()
+                .map(|subcommand| String::from(subcommand.clone()))
+                .ok_or_else(|| CommandError::wrong_arity("script"))?;
+            let args = &args[1..];
+            match (subcommand.to_uppercase().as_str(), args) {
+                ("LOAD", [script]) => Ok(Command::ScriptLoad {
+                    script: script.clone().into(),
+                }),
+                ("EXISTS", [_, ..]) => Ok(Command::ScriptExists {
+                    sha1s: args.iter().cloned().map(String::from).collect(),
+                }),
+                ("FLUSH", []) => Ok(Command::ScriptFlush),
+                ("FLUSH", [mode])
+                    if ["ASYNC", "SYNC"]
+                        .iter()
+                        .any(|flush| String::from(mode.clone()).eq_ignore_ascii_case(flush)) =>
+                {
+                    Ok(Command::ScriptFlush)
+                }
+                ("KILL", []) => Ok(Command::ScriptKill),
+                ("LOAD" | "EXISTS" | "FLUSH" | "KILL", _) => {
+                    Err(CommandError::wrong_arity(&format!("script|{subcommand}")).into())
+                }
+                _ => Err(CommandError::unknown_subcommand("script", &subcommand).into()),
+            }
+        }
+        "FUNCTION" => {
+            let subcommand = args
+                .first()
+                .map(|subcommand| String::from(subcommand.clone()))
+                .ok_or_else(|| CommandError::wrong_arity("function"))?;
+            let code = args.last().cloned().map(Bytes::from);
+            let args: Vec<String> = args[1..].iter().cloned().map(String::from).collect();
+            let options: Vec<String> = args.iter().map(|arg| arg.to_uppercase()).collect();
+            match (subcommand.to_uppercase().as_str(), options.as_slice()) {
+                ("LOAD", [.., _]) => {
+                    let replace = match &args[..args.len() - 1] {
+                        [] => false,
+                        [option] if option.eq_ignore_ascii_case("REPLACE") => true,
+                        [option] => {
+                            return Err(anyhow!("ERR Unknown option given: {option}"));
+                        }
+                        _ => return Err(CommandError::Syntax.into()),
+                    };
+                    Ok(Command::FunctionLoad {
+                        code: code.unwrap(),
+                        replace,
+                    })
+                }
+                ("DELETE", [_]) => Ok(Command::FunctionDelete {
+                    library: args[0].clone(),
+                }),
+                ("FLUSH", [] | [_])
+                    if options.iter().all(|mode| mode == "ASYNC" || mode == "SYNC") =>
+                {
+                    Ok(Command::FunctionFlush)
+                }
+                ("FLUSH", [_]) => Err(anyhow!(
+                    "ERR FUNCTION FLUSH only supports SYNC|ASYNC option"
+                )),
+                ("LIST", _) => {
+                    let mut pattern = None;
+                    let mut with_code = false;
+                    let mut index = 0;
+                    while index < options.len() {
+                        match options[index].as_str() {
+                            "WITHCODE" if !with_code => with_code = true,
+                            "WITHCODE" => {
+                                return Err(anyhow!(
+                                    "ERR Unknown argument withcode, withcode given more than once"
+                                ));
+                            }
+                            "LIBRARYNAME" if pattern.is_none() => {
+                                index += 1;
+                                let library = args.get(index).ok_or_else(|| {
+                                    anyhow!("ERR library name argument was not given")
+                                })?;
+                                pattern = Some(library.clone().into());
+                            }
+                            "LIBRARYNAME" => {
+                                return Err(anyhow!(
+                                    "ERR Unknown argument libraryname, libraryname given more than once"
+                                ));
+                            }
+                            _ => {
+                                return Err(anyhow!("ERR Unknown argument {}", args[index]));
+                            }
+                        }
+                        index += 1;
+                    }
+                    Ok(Command::FunctionList { pattern, with_code })
+                }
+                ("STATS", []) => Ok(Command::FunctionStats),
+                // There is a single running script at most, be it a function
+                // or not.
+                ("KILL", []) => Ok(Command::ScriptKill),
+                ("LOAD" | "DELETE" | "FLUSH" | "STATS" | "KILL", _) => {
+                    Err(CommandError::wrong_arity(&format!("function|{subcommand}")).into())
+                }
+                _ => Err(CommandError::unknown_subcommand("function", &subcommand).into()),
+            }
+        }
+        "ZREMRANGEBYRANK" | "ZREMRANGEBYSCORE" | "ZREMRANGEBYLEX" => {
+            let name = command_name.to_lowercase();
+            let [key, min, max] =
+                <[RespValue; 3]>::try_from(args).map_err(|_| CommandError::wrong_arity(&name))?;
+            let range = match name.as_str() {
+                "zremrangebyrank" => ZsetRange::Rank(parse_integer(min)?, parse_integer(max)?),
+                "zremrangebyscore" => {
+                    ZsetRange::Score(parse_score_bound(min)?, parse_score_bound(max)?)
+                }
+                _ => ZsetRange::Lex(parse_lex_bound(min)?, parse_lex_bound(max)?),
+            };
+
+            Ok(Command::Zremrange {
+                key: key.into(),
+                range,
+            })
+        }
+        "ZRANDMEMBER" => {
+            let mut args = args.into_iter();
+            let (Some(key), count, with_scores, None) =
+                (args.next(), args.next(), args.next(), args.next())
+            else {
+                return Err(CommandError::wrong_arity("zrandmember").into());
+            };
+            let count = count.map(parse_integer).transpose()?;
+            let with_scores = match with_scores {
+                Some(option) if String::from(option.clone()).eq_ignore_ascii_case("WITHSCORES") => {
+                    true
+                }
+                Some(_) => return Err(CommandError::Syntax.into()),
+                None => false,
+            };
+            if count.is_some_and(|count| count < -(i64::MAX / 2)) {
+                return Err(anyhow!("ERR value is out of range"));
+            }
+
+            Ok(Command::Zrandmember {
+                key: key.into(),
+                count,
+                with_scores,
+            })
+        }
+        "ZREM" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zrem"))?
+                .clone()
+                .into();
+            if args.len() < 2 {
+                return Err(CommandError::wrong_arity("zrem").into());
+            }
+
+            let members = args[1..]
+                .iter()
+                .map(|resp_value| resp_value.clone().into())
+                .collect::<Vec<Bytes>>();
+
+            Ok(Command::Zrem { key, members })
+        }
+        "ZSCORE" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zscore"))?
+                .clone()
+                .into();
+
+            let member: Bytes = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("zscore"))?
+                .clone()
+                .into();
+
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("zscore").into());
+            }
+
+            Ok(Command::Zscore { key, member })
+        }
+        "ZRANK" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zrank"))?
+                .clone()
+                .into();
+
+            let member: Bytes = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("zrank"))?
+                .clone()
+                .into();
+
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("zrank").into());
+            }
+
+            Ok(Command::Zrank { key, member })
+        }
+        "ZCARD" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zcard"))?
+                .clone()
+                .into();
+
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("zcard").into());
+            }
+
+            Ok(Command::Zcard { key })
+        }
+        "ZRANGE" => {
+            let key: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("zrange"))?
+                .clone()
+                .into();
+
+            let start = parse_integer(
+                args.get(1)
+                    .ok_or_else(|| CommandError::wrong_arity("zrange"))?
+                    .clone(),
+            )? as isize;
+
+            let stop = parse_integer(
+                args.get(2)
+                    .ok_or_else(|| CommandError::wrong_arity("zrange"))?
+                    .clone(),
+            )? as isize;
+
+            let with_scores = match args.get(3) {
+                Some(arg) => {
+                    let arg: String = arg.clone().into();
+                    if arg.to_uppercase() != "WITHSCORES" {
+                        return Err(CommandError::Syntax.into());
+                    }
+                    true
+                }
+                None => false,
+            };
+
+            if args.len() > 4 {
+                return Err(CommandError::wrong_arity("zrange").into());
+            }
+
+            Ok(Command::Zrange {
+                key,
+                start,
+                stop,
+                with_scores,
+            })
+        }
+        "MULTI" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("multi").into());
+            }
+            Ok(Command::Multi)
+        }
+        "EXEC" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("exec").into());
+            }
+            Ok(Command::Exec)
+        }
+        "DISCARD" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("discard").into());
+            }
+            Ok(Command::Discard)
+        }
+        "SUBSCRIBE" => {
+            if args.is_empty() {
+                return Err(CommandError::wrong_arity("subscribe").into());
+            }
+            let channels = args.into_iter().map(|v| v.into()).collect();
+            Ok(Command::Subscribe { channels })
+        }
+        "UNSUBSCRIBE" => {
+            let channels = args.into_iter().map(|v| v.into()).collect();
+            Ok(Command::Unsubscribe { channels })
+        }
+        "PSUBSCRIBE" => {
+            if args.is_empty() {
+                return Err(CommandError::wrong_arity("psubscribe").into());
+            }
+            let patterns = args.into_iter().map(|v| v.into()).collect();
+            Ok(Command::Psubscribe { patterns })
+        }
+        "PUNSUBSCRIBE" => {
+            let patterns = args.into_iter().map(|v| v.into()).collect();
+            Ok(Command::Punsubscribe { patterns })
+        }
+        "PUBLISH" => {
+            let channel: Bytes = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("publish"))?
+                .clone()
+                .into();
+
+            let message: Bytes = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("publish"))?
+                .clone()
+                .into();
+
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("publish").into());
+            }
+
+            Ok(Command::Publish { channel, message })
+        }
+        "PUBSUB" => {
+            let subcommand: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("pubsub"))?
+                .clone()
+                .into();
+
+            match subcommand.to_uppercase().as_str() {
+                "CHANNELS" => {
+                    if args.len() > 2 {
+                        return Err(CommandError::wrong_arity("pubsub|channels").into());
+                    }
+                    let pattern = args.get(1).map(|v| v.clone().into());
+                    Ok(Command::PubsubChannels { pattern })
+                }
+                "NUMSUB" => {
+                    let channels = args[1..].iter().map(|v| v.clone().into()).collect();
+                    Ok(Command::PubsubNumsub { channels })
+                }
+                "NUMPAT" => {
+                    if args.len() > 1 {
+                        return Err(CommandError::wrong_arity("pubsub|numpat").into());
+                    }
+                    Ok(Command::PubsubNumpat)
+                }
+                _ => Err(CommandError::unknown_subcommand("pubsub", &subcommand).into()),
+            }
+        }
+        "SAVE" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("save").into());
+            }
+            Ok(Command::Save)
+        }
+        "SHUTDOWN" => {
+            let mut save = None;
+            for arg in args.into_iter().map(String::from) {
+                let value = match arg.to_uppercase().as_str() {
+                    "SAVE" => true,
+                    "NOSAVE" => false,
+                    _ => return Err(CommandError::Syntax.into()),
+                };
+                if save.is_some_and(|save| save != value) {
+                    return Err(CommandError::Syntax.into());
+                }
+                save = Some(value);
+            }
+            Ok(Command::Shutdown { save })
+        }
+        "BGSAVE" => {
+            if args.len() > 1 {
+                return Err(CommandError::wrong_arity("bgsave").into());
+            }
+            Ok(Command::Bgsave)
+        }
+        "BGREWRITEAOF" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("bgrewriteaof").into());
+            }
+            Ok(Command::Bgrewriteaof)
+        }
+        "LASTSAVE" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("lastsave").into());
+            }
+            Ok(Command::Lastsave)
+        }
+        "ROLE" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("role").into());
+            }
+            Ok(Command::Role)
+        }
+        "INFO" => Ok(Command::Info {
+            sections: args.into_iter().map(String::from).collect(),
+        }),
+        "CONFIG" => {
+            let mut args = args.into_iter().map(String::from);
+            let subcommand = args
+                .next()
+                .ok_or_else(|| CommandError::wrong_arity("config"))?;
+            let args: Vec<String> = args.collect();
+            match subcommand.to_uppercase().as_str() {
+                "GET" if !args.is_empty() => Ok(Command::ConfigGet { patterns: args }),
+                "SET" if !args.is_empty() && args.len().is_multiple_of(2) => {
+                    let mut parameters: Vec<(String, String)> = vec![];
+                    for pair in args.chunks(2) {
+                        if parameters
+                            .iter()
+                            .any(|(name, _)| name.eq_ignore_ascii_case(&pair[0]))
+                        {
+                            return Err(anyhow!(
+                                "ERR CONFIG SET failed (possibly related to argument '{}') - duplicate parameter",
+                                pair[0]
+                            ));
+                        }
+                        parameters.push((pair[0].clone(), pair[1].clone()));
+                    }
+                    Ok(Command::ConfigSet { parameters })
+                }
+                "GET" | "SET" => {
+                    Err(CommandError::wrong_arity(&format!("config|{subcommand}")).into())
+                }
+                _ => Err(CommandError::unknown_subcommand("config", &subcommand).into()),
+            }
+        }
+        "REPLCONF" => {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            match args.first().map(|option| option.to_uppercase()).as_deref() {
+                Some("ACK") => {
+                    let offset = args
+                        .get(1)
+                        .ok_or(CommandError::Syntax)?
+                        .parse::<u64>()
+                        .map_err(|_| CommandError::NotAnInteger)?;
+                    Ok(Command::ReplconfAck { offset })
+                }
+                Some("GETACK") => Ok(Command::ReplconfGetack),
+                _ => Ok(Command::Replconf { args }),
+            }
+        }
+        "WAIT" => {
+            let numreplicas: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("wait"))?
+                .clone()
+                .into();
+            let timeout_millis: String = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("wait"))?
+                .clone()
+                .into();
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("wait").into());
+            }
+            let numreplicas = numreplicas
+                .parse::<u64>()
+                .map_err(|_| CommandError::NotAnInteger)?;
+            let timeout_millis = timeout_millis
+                .parse::<u64>()
+                .map_err(|_| anyhow!("ERR timeout is not an integer or out of range"))?;
+            Ok(Command::Wait {
+                numreplicas,
+                timeout_millis,
+            })
+        }
+        "COMMAND" => {
+            let subcommand: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("command"))?
+                .clone()
+                .into();
+            match subcommand.to_uppercase().as_str() {
+                "GETKEYS" if args.len() < 2 => {
+                    Err(CommandError::wrong_arity("command|getkeys").into())
+                }
+                "GETKEYS" => Ok(Command::Getkeys {
+                    argv: args[1..].iter().cloned().map(Bytes::from).collect(),
+                }),
+                _ => Err(CommandError::unknown_subcommand("command", &subcommand).into()),
+            }
+        }
+        "ACL" => {
+            let mut args = args.into_iter().map(String::from);
+            let subcommand = args
+                .next()
+                .ok_or_else(|| CommandError::wrong_arity("acl"))?;
+            let args: Vec<String> = args.collect();
+            let wrong_arity = || CommandError::wrong_arity(&format!("acl|{subcommand}"));
+            match subcommand.to_uppercase().as_str() {
+                "SETUSER" => {
+                    let (username, rules) = args.split_first().ok_or_else(wrong_arity)?;
+                    Ok(Command::AclSetuser {
+                        username: username.clone(),
+                        rules: rules.to_vec(),
+                    })
+                }
+                "GETUSER" => match args.as_slice() {
+                    [username] => Ok(Command::AclGetuser {
+                        username: username.clone(),
+                    }),
+                    _ => Err(wrong_arity().into()),
+                },
+                "DELUSER" if args.is_empty() => Err(wrong_arity().into()),
+                "DELUSER" => Ok(Command::AclDeluser { usernames: args }),
+                "LIST" | "WHOAMI" if !args.is_empty() => Err(wrong_arity().into()),
+                "LIST" => Ok(Command::AclList),
+                "WHOAMI" => Ok(Command::AclWhoami),
+                _ => Err(CommandError::unknown_subcommand("acl", &subcommand).into()),
+            }
+        }
+        "DEBUG" => {
+            let mut args = args.into_iter().map(String::from);
+            let subcommand = args
+                .next()
+                .ok_or_else(|| CommandError::wrong_arity("debug"))?;
+            let args: Vec<String> = args.collect();
+            let [arg] = args.as_slice() else {
+                return Err(anyhow!(
+                    "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try DEBUG HELP."
+                ));
+            };
+            let flag = || -> Result<bool> {
+                arg.parse::<i64>()
+                    .map(|flag| flag != 0)
+                    .map_err(|_| CommandError::NotAnInteger.into())
+            };
+            match subcommand.to_uppercase().as_str() {
+                "SLEEP" => {
+                    let duration = arg
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+                        .ok_or(CommandError::NotAFloat)?;
+                    Ok(Command::DebugSleep { duration })
+                }
+                "OBJECT" => Ok(Command::DebugObject {
+                    key: Bytes::from(arg.clone()),
+                }),
+                "SET-ACTIVE-EXPIRE" => Ok(Command::DebugSetActiveExpire { enabled: flag()? }),
+                "QUICKACK" => Ok(Command::DebugQuickack { enabled: flag()? }),
+                _ => Err(CommandError::unknown_subcommand("debug", &subcommand).into()),
+            }
+        }
+        "LATENCY" => {
+            let mut args = args.into_iter().map(String::from);
+            let subcommand = args
+                .next()
+                .ok_or_else(|| CommandError::wrong_arity("latency"))?;
+            let args: Vec<String> = args.collect();
+            match subcommand.to_uppercase().as_str() {
+                "LATEST" if args.is_empty() => Ok(Command::LatencyLatest),
+                "HISTORY" => match args.as_slice() {
+                    [event] => Ok(Command::LatencyHistory {
+                        event: event.to_lowercase(),
+                    }),
+                    _ => Err(CommandError::wrong_arity("latency|history").into()),
+                },
+                "RESET" => Ok(Command::LatencyReset {
+                    events: args.iter().map(|event| event.to_lowercase()).collect(),
+                }),
+                "HISTOGRAM" => Ok(Command::LatencyHistogram {
+                    commands: args.iter().map(|command| command.to_lowercase()).collect(),
+                }),
+                _ => Err(anyhow!(
+                    "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try LATENCY HELP."
+                )),
+            }
+        }
+        "CLUSTER" => {
+            let mut args = args.into_iter();
+            let subcommand = args
+                .next()
+                .map(String::from)
+                .ok_or_else(|| CommandError::wrong_arity("cluster"))?;
+            let args: Vec<Bytes> = args.map(Bytes::from).collect();
+            let slots = |args: &[Bytes]| -> Result<Vec<u16>> {
+                args.iter()
+                    .map(|slot| {
+                        cluster::parse_slot(&String::from_utf8_lossy(slot))
+                            .map_err(|e| anyhow!("ERR {e}"))
+                    })
+                    .collect()
+            };
+            let slot_ranges = |args: &[Bytes]| -> Result<Vec<u16>> {
+                let bounds = slots(args)?;
+                let mut slots = vec![];
+                for range in bounds.chunks(2) {
+                    let &[start, end] = range else {
+                        unreachable!("an even number of bounds")
+                    };
+                    if start > end {
+                        return Err(anyhow!(
+                            "ERR start slot number {start} is greater than end slot number {end}"
+                        ));
+                    }
+                    slots.extend(start..=end);
+                }
+                Ok(slots)
+            };
+            let port = |port: &Bytes| {
+                String::from_utf8_lossy(port)
+                    .parse::<u16>()
+                    .map_err(|_| anyhow!("ERR Invalid base port specified: {port:?}"))
+            };
+            match (subcommand.to_uppercase().as_str(), args.as_slice()) {
+                ("INFO", []) => Ok(Command::ClusterInfo),
+                ("MYID", []) => Ok(Command::ClusterMyid),
+                ("NODES", []) => Ok(Command::ClusterNodes),
+                ("SLOTS", []) => Ok(Command::ClusterSlots),
+                ("SHARDS", []) => Ok(Command::ClusterShards),
+                ("KEYSLOT", [key]) => Ok(Command::ClusterKeyslot { key: key.clone() }),
+                ("MEET", [host, base_port]) | ("MEET", [host, base_port, _]) => {
+                    let base_port = port(base_port)?;
+                    let bus_port = match args.get(2) {
+                        Some(bus_port) => port(bus_port)?,
+                        None => base_port.saturating_add(10000),
+                    };
+                    Ok(Command::ClusterMeet {
+                        host: String::from_utf8_lossy(host).into_owned(),
+                        port: base_port,
+                        bus_port,
+                    })
+                }
+                ("FORGET", [id]) => Ok(Command::ClusterForget {
+                    id: String::from_utf8_lossy(id).into_owned(),
+                }),
+                ("ADDSLOTS", [_, ..]) => Ok(Command::ClusterAddslots {
+                    slots: slots(&args)?,
+                }),
+                ("DELSLOTS", [_, ..]) => Ok(Command::ClusterDelslots {
+                    slots: slots(&args)?,
+                }),
+                ("ADDSLOTSRANGE", [_, _, ..]) if args.len().is_multiple_of(2) => {
+                    Ok(Command::ClusterAddslots {
+                        slots: slot_ranges(&args)?,
+                    })
+                }
+                ("DELSLOTSRANGE", [_, _, ..]) if args.len().is_multiple_of(2) => {
+                    Ok(Command::ClusterDelslots {
+                        slots: slot_ranges(&args)?,
+                    })
+                }
+                ("COUNT-FAILURE-REPORTS", [id]) => Ok(Command::ClusterCountFailureReports {
+                    id: String::from_utf8_lossy(id).into_owned(),
+                }),
+                _ => Err(anyhow!(
+                    "ERR unknown subcommand or wrong number of arguments for '{subcommand}'. Try CLUSTER HELP."
+                )),
+            }
+        }
+        "CLIENT" => {
+            let mut args = args.into_iter().map(String::from);
+            let subcommand = args
+                .next()
+                .ok_or_else(|| CommandError::wrong_arity("client"))?;
+            let args: Vec<String> = args.collect();
+            let wrong_arity = || CommandError::wrong_arity(&format!("client|{subcommand}"));
+            match subcommand.to_uppercase().as_str() {
+                "ID" | "GETNAME" | "INFO" | "GETREDIR" | "TRACKINGINFO" if !args.is_empty() => {
+                    Err(wrong_arity().into())
+                }
+                "ID" => Ok(Command::ClientId),
+                "GETNAME" => Ok(Command::ClientGetname),
+                "INFO" => Ok(Command::ClientInfo),
+                "GETREDIR" => Ok(Command::ClientGetredir),
+                "TRACKINGINFO" => Ok(Command::ClientTrackinginfo),
+                "TRACKING" => {
+                    let (enabled, options) = args.split_first().ok_or_else(wrong_arity)?;
+                    let enabled = match enabled.to_uppercase().as_str() {
+                        "ON" => true,
+                        "OFF" => false,
+                        _ => return Err(CommandError::Syntax.into()),
+                    };
+                    let mut tracking = TrackingOptions::default();
+                    let mut options = options.iter();
+                    while let Some(option) = options.next() {
+                        match option.to_uppercase().as_str() {
+                            "REDIRECT" => {
+                                let id = options.next().ok_or(CommandError::Syntax)?;
+                                tracking.redirect =
+                                    Some(id.parse().map_err(|_| CommandError::NotAnInteger)?);
+                            }
+                            "OPTIN" => tracking.optin = true,
+                            "OPTOUT" => tracking.optout = true,
+                            "NOLOOP" => tracking.noloop = true,
+                            "BCAST" => tracking.bcast = true,
+                            "PREFIX" => {
+                                let prefix = options.next().ok_or(CommandError::Syntax)?;
+                                tracking.prefixes.push(prefix.clone().into());
+                            }
+                            _ => return Err(CommandError::Syntax.into()),
+                        }
+                    }
+                    if !tracking.bcast && !tracking.prefixes.is_empty() {
+                        return Err(anyhow!(
+                            "ERR PREFIX option requires BCAST mode to be enabled"
+                        ));
+                    }
+                    if tracking.optin && tracking.optout {
+                        return Err(anyhow!("ERR You can't use both OPTIN and OPTOUT"));
+                    }
+                    if tracking.bcast && (tracking.optin || tracking.optout) {
+                        return Err(anyhow!(
+                            "ERR OPTIN and OPTOUT are not compatible with BCAST"
+                        ));
+                    }
+                    Ok(Command::ClientTracking {
+                        options: enabled.then_some(tracking),
+                    })
+                }
+                "CACHING" => match args.as_slice() {
+                    [enabled] => match enabled.to_uppercase().as_str() {
+                        "YES" => Ok(Command::ClientCaching { enabled: true }),
+                        "NO" => Ok(Command::ClientCaching { enabled: false }),
+                        _ => Err(CommandError::Syntax.into()),
+                    },
+                    _ => Err(wrong_arity().into()),
+                },
+                "SETNAME" => match args.as_slice() {
+                    [name] => Ok(Command::ClientSetname { name: name.clone() }),
+                    _ => Err(wrong_arity().into()),
+                },
+                "LIST" => {
+                    let mut client_type = None;
+                    let mut ids = vec![];
+                    let mut args = args.iter();
+                    while let Some(option) = args.next() {
+                        match option.to_uppercase().as_str() {
+                            "TYPE" => {
+                                let value = args.next().ok_or(CommandError::Syntax)?;
+                                client_type = Some(value.parse()?);
+                            }
+                            "ID" => {
+                                let rest: Vec<&String> = args.by_ref().collect();
+                                if rest.is_empty() {
+                                    return Err(CommandError::Syntax.into());
+                                }
+                                for id in rest {
+                                    ids.push(
+                                        id.parse::<u64>()
+                                            .ok()
+                                            .filter(|&id| id > 0)
+                                            .ok_or_else(|| anyhow!("ERR Invalid client ID"))?,
+                                    );
+                                }
+                            }
+                            _ => return Err(CommandError::Syntax.into()),
+                        }
+                    }
+                    Ok(Command::ClientList { client_type, ids })
+                }
+                "KILL" => match args.as_slice() {
+                    [] => Err(wrong_arity().into()),
+                    [addr] => Ok(Command::ClientKill {
+                        filter: KillFilter {
+                            addr: Some(addr.clone()),
+                            skip_me: false,
+                            ..KillFilter::default()
+                        },
+                        legacy: true,
+                    }),
+                    args if !args.len().is_multiple_of(2) => Err(CommandError::Syntax.into()),
+                    args => {
+                        let mut filter = KillFilter::default();
+                        for pair in args.chunks(2) {
+                            let value = pair[1].clone();
+                            match pair[0].to_uppercase().as_str() {
+                                "ID" => {
+                                    filter.id = Some(
+                                        value.parse::<u64>().ok().filter(|&id| id > 0).ok_or_else(
+                                            || anyhow!("ERR client-id should be greater than 0"),
+                                        )?,
+                                    )
+                                }
+                                "ADDR" => filter.addr = Some(value),
+                                "LADDR" => filter.laddr = Some(value),
+                                "TYPE" => filter.client_type = Some(value.parse()?),
+                                "SKIPME" => {
+                                    filter.skip_me = match value.to_lowercase().as_str() {
+                                        "yes" => true,
+                                        "no" => false,
+                                        _ => return Err(CommandError::Syntax.into()),
+                                    }
+                                }
+                                _ => return Err(CommandError::Syntax.into()),
+                            }
+                        }
+                        Ok(Command::ClientKill {
+                            filter,
+                            legacy: false,
+                        })
+                    }
+                },
+                _ => Err(CommandError::unknown_subcommand("client", &subcommand).into()),
+            }
+        }
+        "HELLO" => {
+            let args: Vec<String> = args.into_iter().map(String::from).collect();
+            let Some((protover, options)) = args.split_first() else {
+                return Ok(Command::Hello {
+                    protover: None,
+                    auth: None,
+                    setname: None,
+                });
+            };
+            let protover = protover
+                .parse::<u8>()
+                .map_err(|_| anyhow!("ERR Protocol version is not an integer or out of range"))?;
+            let mut auth = None;
+            let mut setname = None;
+            let mut options = options.iter();
+            while let Some(option) = options.next() {
+                match option.to_uppercase().as_str() {
+                    "AUTH" => {
+                        let (Some(username), Some(password)) = (options.next(), options.next())
+                        else {
+                            return Err(anyhow!("ERR Syntax error in HELLO option '{option}'"));
+                        };
+                        auth = Some((username.clone(), password.clone()));
+                    }
+                    "SETNAME" => {
+                        let name = options.next().ok_or_else(|| {
+                            anyhow!("ERR Syntax error in HELLO option '{option}'")
+                        })?;
+                        setname = Some(name.clone());
+                    }
+                    _ => return Err(anyhow!("ERR Syntax error in HELLO option '{option}'")),
+                }
+            }
+            Ok(Command::Hello {
+                protover: Some(protover),
+                auth,
+                setname,
+            })
+        }
+        "AUTH" => {
+            let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+            match args.len() {
+                0 => Err(CommandError::wrong_arity("auth").into()),
+                1 => Ok(Command::Auth {
+                    username: None,
+                    password: args.remove(0),
+                }),
+                2 => {
+                    let password = args.remove(1);
+                    Ok(Command::Auth {
+                        username: Some(args.remove(0)),
+                        password,
+                    })
+                }
+                _ => Err(CommandError::Syntax.into()),
+            }
+        }
+        "QUIT" => Ok(Command::Quit),
+        "MONITOR" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("monitor").into());
+            }
+            Ok(Command::Monitor)
+        }
+        "RESET" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity("reset").into());
+            }
+            Ok(Command::Reset)
+        }
+        "REPLICAOF" | "SLAVEOF" => {
+            let host: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("replicaof"))?
+                .clone()
+                .into();
+            let port: String = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("replicaof"))?
+                .clone()
+                .into();
+            if args.len() > 2 {
+                return Err(CommandError::wrong_arity("replicaof").into());
+            }
+            if host.eq_ignore_ascii_case("no") && port.eq_ignore_ascii_case("one") {
+                return Ok(Command::Replicaof { master: None });
+            }
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| anyhow!("ERR Invalid master port"))?;
+            Ok(Command::Replicaof {
+                master: Some((host, port)),
+            })
+        }
+        "PSYNC" => {
+            // Only full resynchronizations are supported, so the requested
+            // replication ID only matters to `FAILOVER`.
+            let replid: String = args
+                .first()
+                .ok_or_else(|| CommandError::wrong_arity("psync"))?
+                .clone()
+                .into();
+            let offset: String = args
+                .get(1)
+                .ok_or_else(|| CommandError::wrong_arity("psync"))?
+                .clone()
+                .into();
+            offset
+                .parse::<i64>()
+                .map_err(|_| CommandError::NotAnInteger)?;
+            let failover = match args.get(2).map(|arg| String::from(arg.clone())) {
+                None => false,
+                Some(option) if option.eq_ignore_ascii_case("FAILOVER") => true,
+                Some(_) => return Err(CommandError::Syntax.into()),
+            };
+            if args.len() > 3 {
+                return Err(CommandError::wrong_arity("psync").into());
+            }
+            Ok(Command::Psync { replid, failover })
+        }
+        "READONLY" | "READWRITE" => {
+            if !args.is_empty() {
+                return Err(CommandError::wrong_arity(&command_name).into());
+            }
+            Ok(if command_name.eq_ignore_ascii_case("READONLY") {
+                Command::Readonly
+            } else {
+                Command::Readwrite
+            })
+        }
+        "FAILOVER" => {
+            let mut target = None;
+            let mut force = false;
+            let mut timeout_millis = None;
+            let mut abort = false;
+            let mut options = args.into_iter().map(String::from);
+            while let Some(option) = options.next() {
+                match option.to_uppercase().as_str() {
+                    "TO" if target.is_none() => {
+                        let (Some(host), Some(port)) = (options.next(), options.next()) else {
+                            return Err(CommandError::Syntax.into());
+                        };
+                        let port = port
+                            .parse::<u16>()
+                            .map_err(|_| CommandError::NotAnInteger)?;
+                        target = Some((host, port));
+                    }
+                    "FORCE" => force = true,
+                    "TIMEOUT" if timeout_millis.is_none() => {
+                        let timeout = options
+                            .next()
+                            .ok_or(CommandError::Syntax)?
+                            .parse::<i64>()
+                            .map_err(|_| CommandError::NotAnInteger)?;
+                        if timeout <= 0 {
+                            return Err(anyhow!("ERR FAILOVER timeout must be greater than 0"));
+                        }
+                        timeout_millis = Some(timeout as u64);
+                    }
+                    "ABORT" => abort = true,
+                    _ => return Err(CommandError::Syntax.into()),
+                }
+            }
+            if abort {
+                if target.is_some() || force || timeout_millis.is_some() {
+                    return Err(anyhow!(
+                        "ERR FAILOVER abort cannot be used with other options."
+                    ));
+                }
+                return Ok(Command::FailoverAbort);
+            }
+            if force && (target.is_none() || timeout_millis.is_none()) {
+                return Err(anyhow!(
+                    "ERR FAILOVER with force option requires both a timeout and target HOST and IP."
+                ));
+            }
+            Ok(Command::Failover {
+                target,
+                force,
+                timeout_millis,
+            })
+        }
+
+        _ => Err(CommandError::UnknownCommand {
+            name: command_name,
+            args: args.into_iter().map(String::from).collect(),
+        }
+        .into()),
+    }
+}
+
+pub fn extract_command(value: RespValue) -> Result<(String, Vec<RespValue>)> {
+    match value {
+        RespValue::Array(a) => {
+            if a.is_empty() {
+                return Err(anyhow!("ERR Protocol error: empty command"));
+            }
+            Ok((
+                unpack_bulk_str(a.first().unwrap().clone())?,
+                a.into_iter().skip(1).collect(),
+            ))
+        }
+        _ => Err(anyhow!(
+            "ERR Protocol error: expected an array of arguments"
+        )),
+    }
+}
+
+fn unpack_bulk_str(value: RespValue) -> Result<String> {
+    match value {
+        RespValue::BulkString(s) => Ok(String::from_utf8_lossy(&s).into_owned()),
+        RespValue::SimpleString(s) => Ok(s),
+        _ => Err(anyhow!(
+            "ERR Protocol error: expected the command name as a string"
+        )),
+    }
+}
+
+/// The end of the list a `LPOP`/`RPOP`-style command works on, from its
+/// lowercase name.
+fn list_end(name: &str) -> ListEnd {
+    if name.trim_start_matches('b').starts_with('l') {
+        ListEnd::Left
+    } else {
+        ListEnd::Right
+    }
+}
+
+fn parse_integer(value: RespValue) -> Result<i64> {
+    String::from(value)
+        .parse()
+        .map_err(|_| CommandError::NotAnInteger.into())
+}
+
+/// The time given to an `EX`, `PX`, `EXAT` or `PXAT` option, which must be
+/// positive.
+fn parse_expiration(option: &str, time: RespValue, command: &str) -> Result<Expiration> {
+    let time = parse_integer(time)?;
+    let millis = if option.starts_with('P') {
+        Some(time)
+    } else {
+        time.checked_mul(1000)
+    }
+    .filter(|millis| *millis > 0)
+    .ok_or_else(|| anyhow!("ERR invalid expire time in '{command}' command"))?;
+    Ok(if option.ends_with("AT") {
+        Expiration::At(millis)
+    } else {
+        Expiration::In(millis)
+    })
+}
+
+fn parse_cursor(value: RespValue) -> Result<u64> {
+    String::from(value)
+        .parse()
+        .map_err(|_| anyhow!("ERR invalid cursor"))
+}
+
+/// Parses the options of the `SCAN` family, handing the ones besides
+/// `MATCH` and `COUNT` to `other` along with the remaining arguments.
+fn parse_scan_options(
+    args: &[RespValue],
+    mut other: impl FnMut(&str, &mut dyn Iterator<Item = RespValue>) -> Result<()>,
+) -> Result<ScanOptions> {
+    let mut options = ScanOptions::default();
+    let mut args = args.iter().cloned();
+    while let Some(option) = args.next() {
+        let option = String::from(option).to_uppercase();
+        match option.as_str() {
+            "MATCH" | "COUNT" => {
+                let value = args.next().ok_or(CommandError::Syntax)?;
+                if option == "MATCH" {
+                    options.pattern = Some(value.into());
+                } else {
+                    options.count = parse_count(value)?;
+                }
+            }
+            _ => other(&option, &mut args)?,
+        }
+    }
+    Ok(options)
+}
+
+/// Splits `numkeys key [key ...] ...` into the keys and the arguments
+/// following them.
+fn parse_numkeys<'a>(args: &'a [RespValue], name: &str) -> Result<(Vec<Bytes>, &'a [RespValue])> {
+    let (numkeys, rest) = args
+        .split_first()
+        .ok_or_else(|| CommandError::wrong_arity(name))?;
+    let numkeys = parse_integer(numkeys.clone())?;
+    if numkeys <= 0 {
+        return Err(anyhow!(
+            "ERR at least 1 input key is needed for '{name}' command"
+        ));
+    }
+    if numkeys as usize > rest.len() {
+        return Err(CommandError::Syntax.into());
+    }
+    let (keys, options) = rest.split_at(numkeys as usize);
+    Ok((keys.iter().cloned().map(Bytes::from).collect(), options))
+}
+
+/// The `COUNT` of the `SCAN` family, which must be positive.
+fn parse_count(value: RespValue) -> Result<usize> {
+    match String::from(value).parse::<i64>() {
+        Ok(count) if count >= 1 => Ok(count as usize),
+        Ok(_) => Err(CommandError::Syntax.into()),
+        Err(_) => Err(CommandError::NotAnInteger.into()),
+    }
+}
+
+/// A `min` or `max` score, `(` excluding it from the range.
+fn parse_score_bound(value: RespValue) -> Result<ScoreBound> {
+    let value = String::from(value);
+    let (score, exclusive) = match value.strip_prefix('(') {
+        Some(score) => (score, true),
+        None => (value.as_str(), false),
+    };
+    let score =
+        parse_score(score.to_string()).map_err(|_| anyhow!("ERR min or max is not a float"))?;
+    Ok(ScoreBound { score, exclusive })
+}
+
+/// A `min` or `max` member: `-`, `+`, or a member prefixed by `[` or `(`.
+fn parse_lex_bound(value: RespValue) -> Result<LexBound> {
+    let value = Bytes::from(value);
+    match value.first() {
+        Some(b'-') if value.len() == 1 => Ok(LexBound::Min),
+        Some(b'+') if value.len() == 1 => Ok(LexBound::Max),
+        Some(b'[') => Ok(LexBound::Inclusive(value.slice(1..))),
+        Some(b'(') => Ok(LexBound::Exclusive(value.slice(1..))),
+        _ => Err(anyhow!("ERR min or max not valid string range item")),
+    }
+}
+
+fn parse_score(value: String) -> Result<f64> {
+    match value.to_lowercase().as_str() {
+        "inf" | "+inf" => Ok(f64::INFINITY),
+        "-inf" => Ok(f64::NEG_INFINITY),
+        _ => value
+            .parse::<f64>()
+            .ok()
+            .filter(|score| !score.is_nan())
+            .ok_or_else(|| CommandError::NotAFloat.into()),
     }
 }
@@ -1,5 +1,10 @@
 use anyhow::{Result, anyhow, bail};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::db::stream_types::StreamId;
 
 #[derive(Debug, Clone)]
 pub enum XreadDuration {
@@ -11,83 +16,74 @@ pub enum XreadDuration {
 #[derive(Debug, Clone)]
 pub enum XreadStartId {
     Last,
-    Normal(String),
+    Normal(StreamId),
 }
 
 impl XreadStartId {
-    pub fn to_str(&self, last_id: &str) -> String {
+    /// The ID to read after, `$` standing for the newest entry `last_id`.
+    pub fn resolve(&self, last_id: Option<StreamId>) -> StreamId {
         match self {
-            XreadStartId::Last => last_id.into(),
-            XreadStartId::Normal(s) => s.into(),
+            XreadStartId::Last => last_id.unwrap_or(StreamId::MIN),
+            XreadStartId::Normal(id) => *id,
         }
     }
 }
 
-pub fn derive_new_stream_id(
-    requested_id_str: &str,
-    last_item_id: Option<&String>,
-) -> Result<String> {
-    let (last_ms_time, last_seq_num) = if let Some(last_id_str) = last_item_id {
-        let (ms_str, seq_str) = last_id_str
-            .split_once('-')
-            .ok_or_else(|| anyhow!("Invalid last stream ID format: {}", last_id_str))?;
-        (ms_str.parse::<u128>()?, seq_str.parse::<u64>()?)
-    } else {
-        (0, 0)
-    };
+/// The ID `XADD` is asked for: `*` for one generated from the clock,
+/// `<ms>-*` to only generate the sequence number, or an explicit ID.
+#[derive(Debug, Clone, Copy)]
+pub enum XaddId {
+    Auto,
+    AutoSequence(u64),
+    Explicit(StreamId),
+}
 
-    let (requested_timestamp_part, requested_sequence_part) = if requested_id_str == "*" {
-        ("*", "*")
-    } else {
-        requested_id_str
-            .split_once("-")
-            .ok_or_else(|| anyhow!("Invalid stream ID format: {}", requested_id_str))?
-    };
+impl FromStr for XaddId {
+    type Err = anyhow::Error;
 
-    let current_system_time_millis = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis();
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "*" {
+            return Ok(XaddId::Auto);
+        }
+        if let Some(ms) = s.strip_suffix("-*") {
+            return ms.parse().map(XaddId::AutoSequence).map_err(|_| {
+                anyhow!("ERR Invalid stream ID specified as stream command argument")
+            });
+        }
+        s.parse().map(XaddId::Explicit)
+    }
+}
 
-    let new_timestamp: u128 = if requested_timestamp_part == "*" {
-        current_system_time_millis
-    } else {
-        requested_timestamp_part
-            .parse()
-            .map_err(|_| anyhow!("Timestamp is not a valid number"))?
+/// The ID of a new entry, which has to be greater than the stream's newest
+/// entry `last_id`. Generated sequence numbers continue from it within the
+/// same millisecond, and the clock never goes back past it.
+pub fn derive_new_stream_id(requested: XaddId, last_id: Option<StreamId>) -> Result<StreamId> {
+    let next_seq = |ms: u64| match last_id {
+        Some(last_id) if last_id.ms == ms => last_id.seq.checked_add(1),
+        _ if ms == 0 => Some(1),
+        _ => Some(0),
     };
-
-    let new_sequence_number: u64 = if requested_sequence_part == "*" {
-        if last_item_id.is_some() {
-            if new_timestamp == last_ms_time {
-                last_seq_num + 1
-            } else {
-                0
-            }
-        } else if requested_timestamp_part == "*" {
-            0
-        } else {
-            1
+    let id = match requested {
+        XaddId::Auto => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .expect("Time went backwards")
+                .as_millis() as u64;
+            let ms = last_id.map_or(now, |last_id| now.max(last_id.ms));
+            next_seq(ms).map(|seq| StreamId::new(ms, seq))
         }
-    } else {
-        requested_sequence_part
-            .parse()
-            .map_err(|_| anyhow!("Sequence is not a valid number"))?
+        XaddId::AutoSequence(ms) => next_seq(ms).map(|seq| StreamId::new(ms, seq)),
+        XaddId::Explicit(id) => Some(id),
     };
 
-    if new_timestamp == 0 && new_sequence_number == 0 {
+    let Some(id) = id else {
+        bail!("ERR The stream has exhausted the last possible ID, unable to add more items")
+    };
+    if id == StreamId::MIN {
         bail!("ERR The ID specified in XADD must be greater than 0-0")
     }
-
-    if last_item_id.is_some() && new_timestamp < last_ms_time
-        || (new_timestamp == last_ms_time && new_sequence_number <= last_seq_num)
-    {
-        {
-            bail!(
-                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
-            )
-        }
+    if last_id.is_some_and(|last_id| id <= last_id) {
+        bail!("ERR The ID specified in XADD is equal or smaller than the target stream top item")
     }
-
-    Ok(format!("{new_timestamp}-{new_sequence_number}"))
+    Ok(id)
 }
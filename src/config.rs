@@ -0,0 +1,793 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+
+use crate::{
+    clients::OutputBufferLimits,
+    cluster::{SLOTS, SlotRange},
+    db::{encoding::EncodingLimits, notifications::KeyspaceEvents},
+    glob::glob_match,
+    logging::LogLevel,
+    persistence::{
+        DEFAULT_DB_FILENAME,
+        aof::{DEFAULT_AOF_FILENAME, FsyncPolicy},
+    },
+    replication::DEFAULT_PORT,
+    resp::ProtocolLimits,
+};
+
+/// Snapshot every hour after a change, every 5 minutes after 100 changes
+/// and every minute after 10000, Redis' default `save` rules.
+const DEFAULT_SAVE_POINTS: [SavePoint; 3] = [
+    SavePoint {
+        seconds: 3600,
+        changes: 1,
+    },
+    SavePoint {
+        seconds: 300,
+        changes: 100,
+    },
+    SavePoint {
+        seconds: 60,
+        changes: 10000,
+    },
+];
+
+/// The server settings, read by every subsystem through the `Db` and
+/// changed at runtime with `CONFIG SET`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The plain TCP port, 0 to only accept TLS connections.
+    pub port: u16,
+    /// The port for TLS connections, 0 for none.
+    pub tls_port: u16,
+    /// The PEM certificate chain and private key TLS connections use.
+    pub tls_cert_file: PathBuf,
+    pub tls_key_file: PathBuf,
+    /// Addresses to listen on. Those starting with `-` are skipped when
+    /// unavailable instead of failing startup.
+    pub bind: Vec<String>,
+    /// The master to replicate from on startup.
+    pub replicaof: Option<(String, u16)>,
+    /// Whether clients of a replica are kept from writing.
+    pub replica_read_only: bool,
+    pub dir: PathBuf,
+    pub dbfilename: String,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: FsyncPolicy,
+    pub save: Vec<SavePoint>,
+    pub maxmemory: usize,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    /// Seconds a client may stay idle before being disconnected, 0 for
+    /// never.
+    pub timeout: u64,
+    pub tcp_keepalive: u64,
+    /// How long a script may run before other clients get `-BUSY` replies.
+    pub lua_time_limit: Duration,
+    /// Events lasting at least this long are recorded for `LATENCY`, zero
+    /// to record none.
+    pub latency_monitor_threshold: Duration,
+    pub notify_keyspace_events: KeyspaceEvents,
+    pub protocol_limits: ProtocolLimits,
+    pub encoding_limits: EncodingLimits,
+    pub client_output_buffer_limit: OutputBufferLimits,
+    pub cluster_enabled: bool,
+    /// The slots this node serves and those it redirects clients for.
+    pub cluster_slots: Vec<SlotRange>,
+    /// The port of the cluster bus, 0 for the client port plus 10000.
+    pub cluster_port: u16,
+    /// How long a node may not answer pings before it is deemed failing.
+    pub cluster_node_timeout: Duration,
+    /// The password clients must give with `AUTH`, empty for none.
+    pub requirepass: String,
+    pub loglevel: LogLevel,
+    /// The file logs are appended to, empty for the standard output.
+    pub logfile: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: DEFAULT_PORT,
+            tls_port: 0,
+            tls_cert_file: PathBuf::new(),
+            tls_key_file: PathBuf::new(),
+            bind: vec!["127.0.0.1".to_string(), "-::1".to_string()],
+            replicaof: None,
+            replica_read_only: true,
+            dir: PathBuf::from("."),
+            dbfilename: DEFAULT_DB_FILENAME.to_string(),
+            appendonly: false,
+            appendfilename: DEFAULT_AOF_FILENAME.to_string(),
+            appendfsync: FsyncPolicy::EverySec,
+            save: DEFAULT_SAVE_POINTS.to_vec(),
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            timeout: 0,
+            tcp_keepalive: 300,
+            lua_time_limit: Duration::from_secs(5),
+            latency_monitor_threshold: Duration::ZERO,
+            notify_keyspace_events: KeyspaceEvents::default(),
+            protocol_limits: ProtocolLimits::default(),
+            encoding_limits: EncodingLimits::default(),
+            client_output_buffer_limit: OutputBufferLimits::default(),
+            cluster_enabled: false,
+            cluster_slots: vec![SlotRange {
+                start: 0,
+                end: SLOTS - 1,
+                owner: None,
+            }],
+            cluster_port: 0,
+            cluster_node_timeout: Duration::from_millis(15000),
+            requirepass: String::new(),
+            loglevel: LogLevel::Notice,
+            logfile: PathBuf::new(),
+        }
+    }
+}
+
+impl Config {
+    pub fn rdb_path(&self) -> PathBuf {
+        self.dir.join(&self.dbfilename)
+    }
+
+    /// The port the cluster bus listens on.
+    pub fn cluster_bus_port(&self) -> u16 {
+        match self.cluster_port {
+            0 => self.port.saturating_add(10000),
+            port => port,
+        }
+    }
+
+    pub fn aof_path(&self) -> PathBuf {
+        self.dir.join(&self.appendfilename)
+    }
+
+    /// Every parameter whose name matches the glob `pattern`, with its
+    /// value as `CONFIG GET` shows it.
+    pub fn get(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        let pattern = pattern.to_lowercase();
+        PARAMETERS
+            .iter()
+            .filter(|parameter| glob_match(pattern.as_bytes(), parameter.name.as_bytes()))
+            .map(|parameter| (parameter.name, (parameter.get)(self)))
+            .collect()
+    }
+
+    /// Sets the parameter `name` from its `CONFIG SET` representation.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        let parameter = find_parameter(name).ok_or_else(|| {
+            anyhow!("ERR Unknown option or number of arguments for CONFIG SET - '{name}'")
+        })?;
+        let Some(set) = parameter.set.filter(|_| parameter.mutable) else {
+            bail!(
+                "ERR CONFIG SET failed (possibly related to argument '{}') - can't set immutable config",
+                parameter.name
+            );
+        };
+        set(self, value).ok_or_else(|| {
+            anyhow!(
+                "ERR Invalid argument '{value}' for CONFIG SET '{}'",
+                parameter.name
+            )
+        })
+    }
+
+    /// Sets a parameter given when the server starts, where the ones that
+    /// cannot change at runtime are accepted too, as is `replicaof`.
+    pub fn set_startup(&mut self, name: &str, value: &str) -> Result<()> {
+        if name.eq_ignore_ascii_case("replicaof") || name.eq_ignore_ascii_case("slaveof") {
+            self.replicaof = parse_replicaof(value)
+                .ok_or_else(|| anyhow!("Expected \"<host> <port>\" for {name}, got '{value}'"))?;
+            return Ok(());
+        }
+        let set = find_parameter(name)
+            .and_then(|parameter| parameter.set)
+            .ok_or_else(|| anyhow!("Unknown option '{name}'"))?;
+        set(self, value).ok_or_else(|| anyhow!("Invalid value '{value}' for {name}"))
+    }
+
+    /// Applies the directives of a `redis.conf` style file. Returns those
+    /// skipped, as in `load`.
+    pub fn load_file(&mut self, path: &Path) -> Result<Vec<String>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read config file {}: {e}", path.display()))?;
+        self.load(&contents)
+            .map_err(|e| anyhow!("Error in config file {}, {e}", path.display()))
+    }
+
+    /// Applies `directive value...` lines, where `#` starts a comment and
+    /// values may be quoted. Directives this server has no use for are
+    /// skipped, so that a stock `redis.conf` loads, and returned for the
+    /// caller to warn about once logging is set up. As in Redis, every
+    /// `save` line adds a rule to those of the previous ones, and `save ""`
+    /// removes them all.
+    pub fn load(&mut self, contents: &str) -> Result<Vec<String>> {
+        let mut save: Option<Vec<String>> = None;
+        let mut skipped = vec![];
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| anyhow!("line {}: '{line}': {message}", number + 1);
+            let words = split_args(line).ok_or_else(|| error("Unbalanced quotes".to_string()))?;
+            let (name, values) = words.split_first().expect("a line has a directive");
+            let value = values.join(" ");
+            if name.eq_ignore_ascii_case("save") {
+                let points = save.get_or_insert_with(Vec::new);
+                if value.is_empty() {
+                    points.clear();
+                } else {
+                    parse_save_points(&value)
+                        .ok_or_else(|| error(format!("Invalid value '{value}' for save")))?;
+                    points.push(value);
+                }
+                continue;
+            }
+            if !is_startup_option(name) {
+                skipped.push(name.clone());
+                continue;
+            }
+            self.set_startup(name, &value)
+                .map_err(|e| error(e.to_string()))?;
+        }
+        if let Some(points) = save {
+            self.set_startup("save", &points.join(" "))?;
+        }
+        Ok(skipped)
+    }
+}
+
+fn is_startup_option(name: &str) -> bool {
+    ["replicaof", "slaveof"]
+        .iter()
+        .any(|option| option.eq_ignore_ascii_case(name))
+        || find_parameter(name).is_some_and(|parameter| parameter.set.is_some())
+}
+
+/// Splits a config line into words the way Redis does: words are separated
+/// by spaces, `"..."` may hold spaces and `\n`, `\"` or `\xHH` escapes,
+/// `'...'` holds everything but `\'` as is. `None` for unbalanced quotes.
+fn split_args(line: &str) -> Option<Vec<String>> {
+    let mut words = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let Some(first) = chars.next() else {
+            return Some(words);
+        };
+        let mut word = String::new();
+        match first {
+            '"' => loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => word.push('\n'),
+                        'r' => word.push('\r'),
+                        't' => word.push('\t'),
+                        'b' => word.push('\u{8}'),
+                        'a' => word.push('\u{7}'),
+                        'x' => {
+                            let hex: String = [chars.next()?, chars.next()?].iter().collect();
+                            word.push(char::from(u8::from_str_radix(&hex, 16).ok()?));
+                        }
+                        c => word.push(c),
+                    },
+                    c => word.push(c),
+                }
+            },
+            '\'' => loop {
+                match chars.next()? {
+                    '\'' => break,
+                    '\\' if chars.peek() == Some(&'\'') => word.push(chars.next()?),
+                    c => word.push(c),
+                }
+            },
+            c => {
+                word.push(c);
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    word.push(c);
+                }
+            }
+        }
+        // A closing quote must end the word.
+        if matches!(first, '"' | '\'') && chars.peek().is_some_and(|c| !c.is_whitespace()) {
+            return None;
+        }
+        words.push(word);
+    }
+}
+
+/// A parameter of the registry, with how to show and parse its value.
+struct Parameter {
+    name: &'static str,
+    get: fn(&Config) -> String,
+    /// `None` for parameters whose value is fixed.
+    set: Option<fn(&mut Config, &str) -> Option<()>>,
+    /// Whether `CONFIG SET` may change the parameter, rather than only the
+    /// startup options.
+    mutable: bool,
+}
+
+fn find_parameter(name: &str) -> Option<&'static Parameter> {
+    PARAMETERS
+        .iter()
+        .find(|parameter| parameter.name.eq_ignore_ascii_case(name))
+}
+
+const PARAMETERS: &[Parameter] = &[
+    Parameter {
+        name: "port",
+        get: |config| config.port.to_string(),
+        set: Some(|config, value| value.parse().ok().map(|port| config.port = port)),
+        mutable: false,
+    },
+    Parameter {
+        name: "tls-port",
+        get: |config| config.tls_port.to_string(),
+        set: Some(|config, value| value.parse().ok().map(|port| config.tls_port = port)),
+        mutable: false,
+    },
+    Parameter {
+        name: "tls-cert-file",
+        get: |config| config.tls_cert_file.display().to_string(),
+        set: Some(|config, value| {
+            config.tls_cert_file = PathBuf::from(value);
+            Some(())
+        }),
+        mutable: false,
+    },
+    Parameter {
+        name: "tls-key-file",
+        get: |config| config.tls_key_file.display().to_string(),
+        set: Some(|config, value| {
+            config.tls_key_file = PathBuf::from(value);
+            Some(())
+        }),
+        mutable: false,
+    },
+    Parameter {
+        name: "bind",
+        get: |config| config.bind.join(" "),
+        set: Some(|config, value| {
+            let bind: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+            (!bind.is_empty()).then(|| config.bind = bind)
+        }),
+        mutable: false,
+    },
+    Parameter {
+        name: "databases",
+        get: |_| "1".to_string(),
+        set: None,
+        mutable: false,
+    },
+    Parameter {
+        name: "dir",
+        get: |config| {
+            // Redis shows the absolute path it changed directory to.
+            std::path::absolute(&config.dir)
+                .unwrap_or_else(|_| config.dir.clone())
+                .display()
+                .to_string()
+        },
+        set: Some(|config, value| {
+            let dir = PathBuf::from(value);
+            dir.is_dir().then(|| config.dir = dir)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "dbfilename",
+        get: |config| config.dbfilename.clone(),
+        set: Some(|config, value| {
+            is_file_name(value).then(|| config.dbfilename = value.to_string())
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "appendonly",
+        get: |config| yes_no(config.appendonly),
+        set: Some(|config, value| {
+            parse_yes_no(value).map(|appendonly| config.appendonly = appendonly)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "appendfilename",
+        get: |config| config.appendfilename.clone(),
+        set: Some(|config, value| {
+            is_file_name(value).then(|| config.appendfilename = value.to_string())
+        }),
+        mutable: false,
+    },
+    Parameter {
+        name: "appendfsync",
+        get: |config| config.appendfsync.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|appendfsync| config.appendfsync = appendfsync)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "save",
+        get: |config| {
+            config
+                .save
+                .iter()
+                .map(|point| format!("{} {}", point.seconds, point.changes))
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: Some(|config, value| parse_save_points(value).map(|save| config.save = save)),
+        mutable: true,
+    },
+    Parameter {
+        name: "maxmemory",
+        get: |config| config.maxmemory.to_string(),
+        set: Some(|config, value| {
+            parse_memory(value).map(|maxmemory| config.maxmemory = maxmemory)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "maxmemory-policy",
+        get: |config| config.maxmemory_policy.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|policy| config.maxmemory_policy = policy)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "timeout",
+        get: |config| config.timeout.to_string(),
+        set: Some(|config, value| value.parse().ok().map(|timeout| config.timeout = timeout)),
+        mutable: true,
+    },
+    Parameter {
+        name: "tcp-keepalive",
+        get: |config| config.tcp_keepalive.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|interval| config.tcp_keepalive = interval)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "replica-read-only",
+        get: |config| yes_no(config.replica_read_only),
+        set: Some(|config, value| {
+            parse_yes_no(value).map(|read_only| config.replica_read_only = read_only)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "lua-time-limit",
+        get: |config| config.lua_time_limit.as_millis().to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|millis| config.lua_time_limit = Duration::from_millis(millis))
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "latency-monitor-threshold",
+        get: |config| config.latency_monitor_threshold.as_millis().to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|millis| config.latency_monitor_threshold = Duration::from_millis(millis))
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "notify-keyspace-events",
+        get: |config| config.notify_keyspace_events.to_string(),
+        set: Some(|config, value| {
+            KeyspaceEvents::parse(value).map(|events| config.notify_keyspace_events = events)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "proto-max-bulk-len",
+        get: |config| config.protocol_limits.max_bulk_len.to_string(),
+        set: Some(|config, value| {
+            parse_memory(value)
+                .filter(|&len| len >= 1024 * 1024)
+                .map(|len| config.protocol_limits.max_bulk_len = len)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "max-multibulk-len",
+        get: |config| config.protocol_limits.max_multibulk_len.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .filter(|&len| len > 0)
+                .map(|len| config.protocol_limits.max_multibulk_len = len)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "client-output-buffer-limit",
+        get: |config| config.client_output_buffer_limit.to_string(),
+        set: Some(|config, value| {
+            config
+                .client_output_buffer_limit
+                .update(value)
+                .map(|limits| config.client_output_buffer_limit = limits)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "list-max-listpack-size",
+        get: |config| config.encoding_limits.list_max_listpack_size.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .filter(|&size| size >= -5 && size != 0)
+                .map(|size| config.encoding_limits.list_max_listpack_size = size)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "hash-max-listpack-entries",
+        get: |config| config.encoding_limits.hash_max_listpack_entries.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|entries| config.encoding_limits.hash_max_listpack_entries = entries)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "hash-max-listpack-value",
+        get: |config| config.encoding_limits.hash_max_listpack_value.to_string(),
+        set: Some(|config, value| {
+            parse_memory(value).map(|len| config.encoding_limits.hash_max_listpack_value = len)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "set-max-intset-entries",
+        get: |config| config.encoding_limits.set_max_intset_entries.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|entries| config.encoding_limits.set_max_intset_entries = entries)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "set-max-listpack-entries",
+        get: |config| config.encoding_limits.set_max_listpack_entries.to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .map(|entries| config.encoding_limits.set_max_listpack_entries = entries)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "set-max-listpack-value",
+        get: |config| config.encoding_limits.set_max_listpack_value.to_string(),
+        set: Some(|config, value| {
+            parse_memory(value).map(|len| config.encoding_limits.set_max_listpack_value = len)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "cluster-enabled",
+        get: |config| yes_no(config.cluster_enabled),
+        set: Some(|config, value| {
+            parse_yes_no(value).map(|enabled| config.cluster_enabled = enabled)
+        }),
+        mutable: false,
+    },
+    Parameter {
+        name: "cluster-slots",
+        get: |config| {
+            config
+                .cluster_slots
+                .iter()
+                .map(SlotRange::to_string)
+                .collect::<Vec<_>>()
+                .join(" ")
+        },
+        set: Some(|config, value| {
+            value
+                .split_whitespace()
+                .map(|range| range.parse().ok())
+                .collect::<Option<Vec<SlotRange>>>()
+                .map(|slots| config.cluster_slots = slots)
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "cluster-port",
+        get: |config| config.cluster_port.to_string(),
+        set: Some(|config, value| value.parse().ok().map(|port| config.cluster_port = port)),
+        mutable: false,
+    },
+    Parameter {
+        name: "cluster-node-timeout",
+        get: |config| config.cluster_node_timeout.as_millis().to_string(),
+        set: Some(|config, value| {
+            value
+                .parse()
+                .ok()
+                .filter(|&millis| millis > 0)
+                .map(|millis| config.cluster_node_timeout = Duration::from_millis(millis))
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "requirepass",
+        get: |config| config.requirepass.clone(),
+        set: Some(|config, value| {
+            config.requirepass = value.to_string();
+            Some(())
+        }),
+        mutable: true,
+    },
+    Parameter {
+        name: "loglevel",
+        get: |config| config.loglevel.to_string(),
+        set: Some(|config, value| value.parse().ok().map(|level| config.loglevel = level)),
+        mutable: true,
+    },
+    Parameter {
+        name: "logfile",
+        get: |config| config.logfile.display().to_string(),
+        set: Some(|config, value| {
+            config.logfile = PathBuf::from(value);
+            Some(())
+        }),
+        mutable: false,
+    },
+];
+
+/// A `save` rule: a snapshot is taken once at least `changes` writes
+/// happened and `seconds` passed since the last one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+/// Parses `save` rules given as `<seconds> <changes>` pairs. An empty
+/// value disables snapshotting.
+fn parse_save_points(value: &str) -> Option<Vec<SavePoint>> {
+    let numbers = value
+        .split_whitespace()
+        .map(|number| number.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if numbers.len() % 2 != 0 {
+        return None;
+    }
+    Some(
+        numbers
+            .chunks(2)
+            .map(|pair| SavePoint {
+                seconds: pair[0],
+                changes: pair[1],
+            })
+            .collect(),
+    )
+}
+
+/// Which keys are evicted once `maxmemory` is reached.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaxmemoryPolicy {
+    NoEviction,
+    AllkeysLru,
+    AllkeysLfu,
+    AllkeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+const MAXMEMORY_POLICIES: [(&str, MaxmemoryPolicy); 8] = [
+    ("noeviction", MaxmemoryPolicy::NoEviction),
+    ("allkeys-lru", MaxmemoryPolicy::AllkeysLru),
+    ("allkeys-lfu", MaxmemoryPolicy::AllkeysLfu),
+    ("allkeys-random", MaxmemoryPolicy::AllkeysRandom),
+    ("volatile-lru", MaxmemoryPolicy::VolatileLru),
+    ("volatile-lfu", MaxmemoryPolicy::VolatileLfu),
+    ("volatile-random", MaxmemoryPolicy::VolatileRandom),
+    ("volatile-ttl", MaxmemoryPolicy::VolatileTtl),
+];
+
+impl FromStr for MaxmemoryPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        MAXMEMORY_POLICIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|(_, policy)| *policy)
+            .ok_or_else(|| anyhow!("Invalid maxmemory-policy '{s}'"))
+    }
+}
+
+impl fmt::Display for MaxmemoryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (name, _) = MAXMEMORY_POLICIES
+            .iter()
+            .find(|(_, policy)| policy == self)
+            .expect("every policy has a name");
+        write!(f, "{name}")
+    }
+}
+
+/// Parses `<host> <port>`, or `no one` for no master.
+fn parse_replicaof(value: &str) -> Option<Option<(String, u16)>> {
+    let (host, port) = value.trim().split_once(' ')?;
+    if host.eq_ignore_ascii_case("no") && port.trim().eq_ignore_ascii_case("one") {
+        return Some(None);
+    }
+    Some(Some((host.to_string(), port.trim().parse().ok()?)))
+}
+
+/// Parses a memory size such as `512mb`, with the same units as Redis:
+/// `k`, `m` and `g` are powers of 1000, `kb`, `mb` and `gb` powers of 1024.
+pub fn parse_memory(value: &str) -> Option<usize> {
+    let value = value.to_lowercase();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+fn parse_yes_no(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
+
+/// A file name without any directory part, as `dbfilename` must be.
+fn is_file_name(value: &str) -> bool {
+    !value.is_empty() && !value.contains('/')
+}
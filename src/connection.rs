@@ -0,0 +1,842 @@
+use std::{
+    collections::HashSet,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+use tokio::sync::{RwLock, mpsc};
+use tracing::{info, trace};
+
+use crate::{
+    acl::Acl,
+    clients::{
+        self, ClientInfo, ClientRegistry, ClientType,
+        tracking::{self, Caller},
+    },
+    cluster::Cluster,
+    commands::{
+        self, Command, command_label,
+        error::CommandError,
+        keys,
+        parser::{extract_command, parse_command},
+        serve_blocked_clients,
+    },
+    db::{Db, pubsub::MessageSender},
+    replication,
+    resp::{Protocol, RespValue},
+    scripting,
+    stats::STATS,
+};
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+const WRONGPASS: &str = "WRONGPASS invalid username-password pair or user is disabled.";
+
+/// The reply to `CLIENT SETNAME` and `HELLO SETNAME` with an invalid name.
+const INVALID_NAME: &str =
+    "ERR Client names cannot contain spaces, newlines or special characters.";
+
+#[derive(Debug, Default)]
+struct Transaction {
+    /// A command with invalid arguments is queued with its error, which
+    /// `EXEC` replies with in its place, as Redis only checks arguments
+    /// when running commands.
+    queued: Vec<(anyhow::Result<Command>, Vec<RespValue>)>,
+    aborted: bool,
+}
+
+/// The parts of the `Db` that connections use without taking its lock,
+/// handed to each of them as it is accepted.
+#[derive(Clone, Debug)]
+pub struct Shared {
+    pub clients: Arc<ClientRegistry>,
+    pub acl: Arc<Acl>,
+    pub cluster: Arc<Cluster>,
+}
+
+#[derive(Debug)]
+pub struct ConnectionState {
+    id: u64,
+    /// What `CLIENT LIST` shows of this connection, also held by the
+    /// registry in the `Db`.
+    info: Arc<ClientInfo>,
+    clients: Arc<ClientRegistry>,
+    acl: Arc<Acl>,
+    cluster: Arc<Cluster>,
+    /// The user the client runs commands as.
+    user: String,
+    /// Whether the client may run commands while the `default` user needs
+    /// a password. Clients connected before one was set keep running them,
+    /// as in Redis.
+    authenticated: bool,
+    protocol: Protocol,
+    transaction: Option<Transaction>,
+    channels: HashSet<Bytes>,
+    patterns: HashSet<Bytes>,
+    /// Replication offset right after this client's last write, which
+    /// `WAIT` waits for replicas to reach.
+    last_write_offset: u64,
+    /// Set by `DEBUG QUICKACK 1`.
+    quickack: bool,
+    /// The port a replica serves clients on, from `REPLCONF listening-port`.
+    listening_port: Option<u16>,
+    /// What `CLIENT CACHING` said for the next command.
+    caching: Option<bool>,
+    messages_sender: MessageSender,
+    messages_receiver: mpsc::UnboundedReceiver<RespValue>,
+}
+
+impl ConnectionState {
+    /// Sets up a connection from `addr` to the local address `laddr`, and
+    /// registers it as a client.
+    pub fn new(shared: Shared, addr: SocketAddr, laddr: SocketAddr) -> Self {
+        let Shared {
+            clients,
+            acl,
+            cluster,
+        } = shared;
+        let (messages_sender, messages_receiver) = mpsc::unbounded_channel();
+        let id = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed);
+        let info = Arc::new(ClientInfo::new(
+            id,
+            addr,
+            laddr,
+            clients.watch_output_limits(),
+        ));
+        let messages_sender = MessageSender::new(messages_sender, info.clone());
+        clients.register(messages_sender.clone());
+        let authenticated = acl.is_default_open();
+        Self {
+            id,
+            info,
+            clients,
+            acl,
+            cluster,
+            user: "default".to_string(),
+            authenticated,
+            protocol: Protocol::Resp2,
+            transaction: None,
+            channels: HashSet::new(),
+            patterns: HashSet::new(),
+            last_write_offset: 0,
+            quickack: false,
+            listening_port: None,
+            caching: None,
+            messages_sender,
+            messages_receiver,
+        }
+    }
+
+    pub fn info(&self) -> Arc<ClientInfo> {
+        self.info.clone()
+    }
+
+    pub fn clients(&self) -> Arc<ClientRegistry> {
+        self.clients.clone()
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Whether the client's segments are to be acknowledged right away.
+    pub fn quickack(&self) -> bool {
+        self.quickack
+    }
+
+    /// Waits for the next out-of-band frame (pub/sub messages, subscription
+    /// confirmations and the replication stream) destined to this connection.
+    pub async fn next_message(&mut self) -> Option<RespValue> {
+        let message = self.messages_receiver.recv().await?;
+        self.info.output_taken(message.encoded_size());
+        Some(message)
+    }
+
+    /// Returns an out-of-band frame if one is already queued.
+    pub fn try_next_message(&mut self) -> Option<RespValue> {
+        let message = self.messages_receiver.try_recv().ok()?;
+        self.info.output_taken(message.encoded_size());
+        Some(message)
+    }
+
+    /// Drops every server-side registration owned by this connection.
+    pub async fn close(&mut self, db: &Arc<RwLock<Db>>) {
+        let mut db_g = db.write().await;
+        self.unsubscribe_all(&mut db_g);
+        db_g.remove_replica(self.id);
+        self.clients.unregister(self.id);
+    }
+
+    /// Drops every subscription without replying for each of them.
+    fn unsubscribe_all(&mut self, db: &mut Db) {
+        for channel in self.channels.drain() {
+            db.unsubscribe(&channel, self.id);
+        }
+        for pattern in self.patterns.drain() {
+            db.punsubscribe(&pattern, self.id);
+        }
+    }
+
+    fn requires_auth(&self) -> bool {
+        !self.authenticated && !self.acl.is_default_open()
+    }
+
+    /// Switches the client to `username` when `password` is one of its
+    /// passwords.
+    fn authenticate(&mut self, username: &str, password: &str) -> bool {
+        let valid = self.acl.authenticate(username, password);
+        if valid {
+            self.set_user(username);
+            self.authenticated = true;
+        }
+        valid
+    }
+
+    fn set_user(&mut self, username: &str) {
+        self.user = username.to_string();
+        self.info.details().user = username.to_string();
+    }
+
+    fn subscription_count(&self) -> u64 {
+        (self.channels.len() + self.patterns.len()) as u64
+    }
+
+    /// Handles one client request. Returns `None` when the replies were
+    /// already queued as out-of-band frames, as (un)subscriptions do.
+    pub async fn handle_value(
+        &mut self,
+        input: RespValue,
+        db: &Arc<RwLock<Db>>,
+    ) -> Option<RespValue> {
+        let argv = match &input {
+            RespValue::Array(items) => items.clone(),
+            _ => vec![],
+        };
+        self.record_command(&argv);
+        // The keys read and changed by the command are attributed to the
+        // client, for `CLIENT TRACKING`.
+        let caching = self.caching.take();
+        let caller = Caller {
+            id: self.id,
+            tracks_reads: self
+                .clients
+                .tracking(self.id)
+                .is_some_and(|options| options.tracks_reads(caching)),
+        };
+        let reply = tracking::scope(caller, self.dispatch(input, argv, db)).await;
+        self.sync_info();
+        self.info.details().in_command = false;
+        if let Some(RespValue::SimpleError(error)) = &reply {
+            trace!(
+                client = self.id,
+                command = %self.info.details().last_command,
+                "{error}"
+            );
+        }
+        reply
+    }
+
+    async fn dispatch(
+        &mut self,
+        input: RespValue,
+        argv: Vec<RespValue>,
+        db: &Arc<RwLock<Db>>,
+    ) -> Option<RespValue> {
+        let command = match extract_command(input).and_then(|(command_name, args)| {
+            let exempt = is_allowed_unauthenticated(&command_name);
+            if self.requires_auth() && !exempt {
+                return Err(CommandError::NoAuth.into());
+            }
+            // RESP3 clients can keep issuing regular commands while subscribed.
+            if self.protocol == Protocol::Resp2
+                && self.subscription_count() > 0
+                && !is_allowed_when_subscribed(&command_name)
+            {
+                return Err(anyhow::anyhow!(
+                    "ERR Can't execute '{}': only (P)SUBSCRIBE / (P)UNSUBSCRIBE / PING / QUIT / RESET are allowed in this context",
+                    command_name.to_lowercase()
+                ));
+            }
+            let command = parse_command(command_name, args);
+            let label = command_label(&argv);
+            let argv: Vec<Bytes> = argv.iter().cloned().map(Bytes::from).collect();
+            // Unknown commands and wrong numbers of arguments still discard
+            // a transaction.
+            let command = match command {
+                Err(e) if self.transaction.is_none() || !keys::is_valid_call(&argv) => {
+                    return Err(e);
+                }
+                command => command,
+            };
+            if !exempt {
+                self.acl.check(&self.user, &label, &argv)?;
+            }
+            // Keys of slots served elsewhere get the client redirected.
+            if self.cluster.is_enabled()
+                && let Ok(keys) = keys::command_keys(&argv)
+            {
+                self.cluster.route(&keys)?;
+            }
+            Ok(command)
+        }) {
+            Ok(command) => command,
+            Err(e) => {
+                if let Some(transaction) = self.transaction.as_mut() {
+                    transaction.aborted = true;
+                }
+                return Some(RespValue::SimpleError(format!("{e}")));
+            }
+        };
+
+        // A script running past `lua-time-limit` holds the `Db`, so anything
+        // but stopping it would only wait for it.
+        if scripting::is_busy() && !matches!(command, Ok(Command::ScriptKill)) {
+            if let Some(transaction) = self.transaction.as_mut() {
+                transaction.aborted = true;
+            }
+            return Some(RespValue::SimpleError(
+                "BUSY Redis is busy running a script. You can only call SCRIPT KILL.".to_string(),
+            ));
+        }
+        let command = match command {
+            Ok(command) => command,
+            Err(e) => {
+                if let Some(transaction) = self.transaction.as_mut() {
+                    transaction.queued.push((Err(e), argv));
+                }
+                return Some(RespValue::SimpleString("QUEUED".to_string()));
+            }
+        };
+
+        STATS.command_processed();
+        // Passwords are kept from monitors.
+        if !matches!(
+            command,
+            Command::Auth { .. } | Command::Hello { .. } | Command::AclSetuser { .. }
+        ) {
+            self.clients
+                .feed_monitors(&self.info.addr.to_string(), &argv);
+        }
+        match command {
+            Command::ScriptKill if self.transaction.is_none() => Some(match scripting::kill() {
+                Ok(()) => RespValue::SimpleString("OK".to_string()),
+                Err(e) => RespValue::SimpleError(format!("{e}")),
+            }),
+            Command::Subscribe { channels } if self.transaction.is_none() => {
+                let mut db_g = db.write().await;
+                for channel in channels {
+                    db_g.subscribe(&channel, self.id, self.messages_sender.clone());
+                    self.channels.insert(channel.clone());
+                    self.push_subscription_reply("subscribe", Some(channel));
+                }
+                None
+            }
+            Command::Unsubscribe { channels } if self.transaction.is_none() => {
+                let channels = if channels.is_empty() {
+                    self.channels.iter().cloned().collect()
+                } else {
+                    channels
+                };
+                let mut db_g = db.write().await;
+                if channels.is_empty() {
+                    self.push_subscription_reply("unsubscribe", None);
+                }
+                for channel in channels {
+                    db_g.unsubscribe(&channel, self.id);
+                    self.channels.remove(&channel);
+                    self.push_subscription_reply("unsubscribe", Some(channel));
+                }
+                None
+            }
+            Command::Psubscribe { patterns } if self.transaction.is_none() => {
+                let mut db_g = db.write().await;
+                for pattern in patterns {
+                    db_g.psubscribe(&pattern, self.id, self.messages_sender.clone());
+                    self.patterns.insert(pattern.clone());
+                    self.push_subscription_reply("psubscribe", Some(pattern));
+                }
+                None
+            }
+            Command::Punsubscribe { patterns } if self.transaction.is_none() => {
+                let patterns = if patterns.is_empty() {
+                    self.patterns.iter().cloned().collect()
+                } else {
+                    patterns
+                };
+                let mut db_g = db.write().await;
+                if patterns.is_empty() {
+                    self.push_subscription_reply("punsubscribe", None);
+                }
+                for pattern in patterns {
+                    db_g.punsubscribe(&pattern, self.id);
+                    self.patterns.remove(&pattern);
+                    self.push_subscription_reply("punsubscribe", Some(pattern));
+                }
+                None
+            }
+            Command::Psync { replid, failover } if self.transaction.is_none() => {
+                let mut db_g = db.write().await;
+                // The master hands over to this replica once it has all of
+                // its writes, then replicates from it.
+                if failover {
+                    if replid != db_g.replication().replid() {
+                        return Some(RespValue::SimpleError(
+                            "ERR PSYNC FAILOVER replid must match my replid.".to_string(),
+                        ));
+                    }
+                    info!("Failover request received for replid {replid}");
+                    db_g.replication_mut().stop_replication();
+                }
+                self.info.details().replica = true;
+                replication::full_resync(
+                    &mut db_g,
+                    self.id,
+                    self.messages_sender.clone(),
+                    self.info.addr.ip().to_canonical().to_string(),
+                    self.listening_port,
+                );
+                None
+            }
+            Command::Replconf { ref args } if self.transaction.is_none() => {
+                for pair in args.chunks(2) {
+                    if let [option, port] = pair
+                        && option.eq_ignore_ascii_case("listening-port")
+                    {
+                        self.listening_port = port.parse().ok();
+                    }
+                }
+                Some(self.handle_command(command, argv, db).await)
+            }
+            Command::ReplconfAck { offset } => {
+                db.write().await.replication_mut().ack(self.id, offset);
+                None
+            }
+            // Only replicas answer GETACK, over their link to the master.
+            Command::ReplconfGetack => None,
+            Command::Wait {
+                numreplicas,
+                timeout_millis,
+            } if self.transaction.is_none() => {
+                let timeout = (timeout_millis > 0).then(|| Duration::from_millis(timeout_millis));
+                Some(
+                    match replication::wait(db, numreplicas, timeout, self.last_write_offset).await
+                    {
+                        Ok(acked) => RespValue::Integer(acked as i64),
+                        Err(e) => RespValue::SimpleError(format!("{e}")),
+                    },
+                )
+            }
+            Command::Hello {
+                protover,
+                auth,
+                setname,
+            } => Some(self.hello(protover, auth, setname, db).await),
+            Command::Reset => {
+                self.unsubscribe_all(&mut *db.write().await);
+                self.transaction = None;
+                self.protocol = Protocol::Resp2;
+                self.info.details().name.clear();
+                self.set_user("default");
+                self.clients.remove_monitor(self.id);
+                self.info.details().monitor = false;
+                self.authenticated = self.acl.is_default_open();
+                Some(RespValue::SimpleString("RESET".to_string()))
+            }
+            Command::Auth { username, password } => {
+                let username = match username {
+                    Some(username) => username,
+                    None if self.acl.is_default_open() => {
+                        return Some(RespValue::SimpleError(
+                            "ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?"
+                                .to_string(),
+                        ));
+                    }
+                    None => "default".to_string(),
+                };
+                Some(if self.authenticate(&username, &password) {
+                    RespValue::SimpleString("OK".to_string())
+                } else {
+                    RespValue::SimpleError(WRONGPASS.to_string())
+                })
+            }
+            Command::Monitor => {
+                self.clients
+                    .add_monitor(self.id, self.messages_sender.clone());
+                self.info.details().monitor = true;
+                Some(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Quit => {
+                // Closes the connection once the reply is written.
+                self.info.kill();
+                Some(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::AclWhoami => Some(RespValue::BulkString(self.user.clone().into())),
+            Command::DebugQuickack { enabled } => {
+                self.quickack = enabled;
+                Some(RespValue::SimpleString("OK".to_string()))
+            }
+            Command::Readonly | Command::Readwrite => Some(if self.cluster.is_enabled() {
+                self.info.details().readonly = matches!(command, Command::Readonly);
+                RespValue::SimpleString("OK".to_string())
+            } else {
+                RespValue::SimpleError("ERR This instance has cluster support disabled".to_string())
+            }),
+            Command::ClientId => Some(RespValue::Integer(self.id as i64)),
+            Command::ClientSetname { name } => Some(if clients::is_valid_name(&name) {
+                self.info.details().name = name;
+                RespValue::SimpleString("OK".to_string())
+            } else {
+                RespValue::SimpleError(INVALID_NAME.to_string())
+            }),
+            Command::ClientGetname => {
+                let name = self.info.details().name.clone();
+                Some(if name.is_empty() {
+                    RespValue::NullBulkString
+                } else {
+                    RespValue::BulkString(name.into())
+                })
+            }
+            Command::ClientList { client_type, ids } => Some(self.client_list(client_type, &ids)),
+            Command::ClientKill { filter, legacy } => {
+                let victims: Vec<_> = self
+                    .clients
+                    .list()
+                    .into_iter()
+                    .filter(|client| filter.matches(client, self.id))
+                    .collect();
+                for victim in &victims {
+                    victim.kill();
+                }
+                Some(match (legacy, victims.len()) {
+                    (true, 0) => RespValue::SimpleError("ERR No such client".to_string()),
+                    (true, _) => RespValue::SimpleString("OK".to_string()),
+                    (false, killed) => RespValue::Integer(killed as i64),
+                })
+            }
+            Command::ClientTracking { options } => Some(match options {
+                Some(options) => match self.clients.enable_tracking(self.id, options) {
+                    Ok(()) => RespValue::SimpleString("OK".to_string()),
+                    Err(e) => RespValue::SimpleError(format!("{e}")),
+                },
+                None => {
+                    self.clients.disable_tracking(self.id);
+                    RespValue::SimpleString("OK".to_string())
+                }
+            }),
+            Command::ClientCaching { enabled } => Some(match self.clients.tracking(self.id) {
+                Some(options) if enabled && options.optin || !enabled && options.optout => {
+                    self.caching = Some(enabled);
+                    RespValue::SimpleString("OK".to_string())
+                }
+                Some(options) if options.optin || options.optout => {
+                    RespValue::SimpleError(if enabled {
+                        "ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode."
+                            .to_string()
+                    } else {
+                        "ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode."
+                            .to_string()
+                    })
+                }
+                _ => RespValue::SimpleError(
+                    "ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled"
+                        .to_string(),
+                ),
+            }),
+            Command::ClientGetredir => Some(RespValue::Integer(
+                self.clients
+                    .tracking(self.id)
+                    .map_or(-1, |options| options.redirect.map_or(0, |id| id as i64)),
+            )),
+            Command::ClientTrackinginfo => Some(self.tracking_info()),
+            Command::ClientInfo => {
+                self.sync_info();
+                Some(RespValue::BulkString(
+                    format!("{}\n", self.info.line()).into(),
+                ))
+            }
+            Command::Ping if self.protocol == Protocol::Resp2 && self.subscription_count() > 0 => {
+                Some(RespValue::Array(vec![
+                    RespValue::BulkString("pong".into()),
+                    RespValue::BulkString(Bytes::new()),
+                ]))
+            }
+            command => Some(self.handle_command(command, argv, db).await),
+        }
+    }
+
+    async fn handle_command(
+        &mut self,
+        command: Command,
+        argv: Vec<RespValue>,
+        db: &Arc<RwLock<Db>>,
+    ) -> RespValue {
+        match command {
+            Command::Multi => {
+                if self.transaction.is_some() {
+                    return RespValue::SimpleError("ERR MULTI calls can not be nested".to_string());
+                }
+                self.transaction = Some(Transaction::default());
+                RespValue::SimpleString("OK".to_string())
+            }
+            Command::Discard => {
+                if self.transaction.take().is_none() {
+                    return RespValue::SimpleError("ERR DISCARD without MULTI".to_string());
+                }
+                RespValue::SimpleString("OK".to_string())
+            }
+            Command::Exec => {
+                let Some(transaction) = self.transaction.take() else {
+                    return RespValue::SimpleError("ERR EXEC without MULTI".to_string());
+                };
+                if transaction.aborted {
+                    return RespValue::SimpleError(
+                        "EXECABORT Transaction discarded because of previous errors.".to_string(),
+                    );
+                }
+
+                if transaction.queued.iter().any(|(command, argv)| {
+                    command
+                        .as_ref()
+                        .is_ok_and(|command| is_write(command, argv))
+                }) {
+                    self.clients.writes_unpaused().await;
+                }
+                let mut db_g = db.write().await;
+                let replies = transaction
+                    .queued
+                    .into_iter()
+                    .map(|(command, argv)| {
+                        match command
+                            .and_then(|command| command.apply_and_propagate(&mut db_g, argv))
+                        {
+                            Ok(resp_value) => resp_value,
+                            Err(e) => RespValue::SimpleError(format!("{e}")),
+                        }
+                    })
+                    .collect();
+                serve_blocked_clients(&mut db_g);
+                self.last_write_offset = db_g.replication().offset();
+                RespValue::Array(replies)
+            }
+            command => {
+                // Scripts are refused the writes they make instead.
+                if commands::is_write(&argv) && db.read().await.is_read_only_replica() {
+                    if let Some(transaction) = self.transaction.as_mut() {
+                        transaction.aborted = true;
+                    }
+                    return RespValue::SimpleError(replication::READONLY.to_string());
+                }
+                if let Some(transaction) = self.transaction.as_mut() {
+                    transaction.queued.push((Ok(command), argv));
+                    return RespValue::SimpleString("QUEUED".to_string());
+                }
+                let is_write = is_write(&command, &argv);
+                // A paused write runs once the pause ends, as during a
+                // `FAILOVER`.
+                if is_write {
+                    self.clients.writes_unpaused().await;
+                }
+                let reply = match command.execute(db.clone(), argv).await {
+                    Ok(resp_value) => resp_value,
+                    Err(e) => RespValue::SimpleError(format!("{e}")),
+                };
+                if is_write {
+                    self.last_write_offset = db.read().await.replication().offset();
+                }
+                reply
+            }
+        }
+    }
+
+    /// Authenticates and names the connection when asked to, switches it to
+    /// the requested protocol and replies with the server properties.
+    async fn hello(
+        &mut self,
+        protover: Option<u8>,
+        auth: Option<(String, String)>,
+        setname: Option<String>,
+        db: &Arc<RwLock<Db>>,
+    ) -> RespValue {
+        let protocol = match protover {
+            None => self.protocol,
+            Some(2) => Protocol::Resp2,
+            Some(3) => Protocol::Resp3,
+            Some(_) => {
+                return RespValue::SimpleError("NOPROTO unsupported protocol version".to_string());
+            }
+        };
+        match auth {
+            Some((username, password)) if !self.authenticate(&username, &password) => {
+                return RespValue::SimpleError(WRONGPASS.to_string());
+            }
+            None if self.requires_auth() => {
+                return RespValue::SimpleError(
+                    "NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time"
+                        .to_string(),
+                );
+            }
+            _ => {}
+        }
+        if let Some(name) = setname {
+            if !clients::is_valid_name(&name) {
+                return RespValue::SimpleError(INVALID_NAME.to_string());
+            }
+            self.info.details().name = name;
+        }
+        self.protocol = protocol;
+
+        let role = if db.read().await.replication().is_replica() {
+            "replica"
+        } else {
+            "master"
+        };
+        let proto = match self.protocol {
+            Protocol::Resp2 => 2,
+            Protocol::Resp3 => 3,
+        };
+        RespValue::Map(
+            [
+                ("server", RespValue::BulkString("redis".into())),
+                ("version", RespValue::BulkString("7.2.0".into())),
+                ("proto", RespValue::Integer(proto)),
+                ("id", RespValue::Integer(self.id as i64)),
+                ("mode", RespValue::BulkString(self.cluster.mode().into())),
+                ("role", RespValue::BulkString(role.into())),
+                ("modules", RespValue::Array(vec![])),
+            ]
+            .into_iter()
+            .map(|(key, value)| (RespValue::BulkString(key.into()), value))
+            .collect(),
+        )
+    }
+
+    fn client_list(&self, client_type: Option<ClientType>, ids: &[u64]) -> RespValue {
+        self.sync_info();
+        let list: String = self
+            .clients
+            .list()
+            .iter()
+            .filter(|client| {
+                client_type.is_none_or(|client_type| client.client_type() == client_type)
+            })
+            .filter(|client| ids.is_empty() || ids.contains(&client.id))
+            .map(|client| format!("{}\n", client.line()))
+            .collect();
+        RespValue::BulkString(list.into())
+    }
+
+    /// The `CLIENT TRACKINGINFO` reply: the tracking flags, the client
+    /// invalidations are redirected to and the prefixes tracked.
+    fn tracking_info(&self) -> RespValue {
+        let options = self.clients.tracking(self.id);
+        let mut flags = vec![];
+        match &options {
+            None => flags.push("off"),
+            Some(options) => {
+                flags.push("on");
+                if options.bcast {
+                    flags.push("bcast");
+                }
+                if options.optin {
+                    flags.push("optin");
+                }
+                if options.optout {
+                    flags.push("optout");
+                }
+                if options.noloop {
+                    flags.push("noloop");
+                }
+                if let Some(redirect) = options.redirect
+                    && !self
+                        .clients
+                        .list()
+                        .iter()
+                        .any(|client| client.id == redirect)
+                {
+                    flags.push("broken_redirect");
+                }
+            }
+        }
+        let redirect = options
+            .as_ref()
+            .map_or(-1, |options| options.redirect.map_or(0, |id| id as i64));
+        let prefixes = options.map_or(vec![], |options| options.prefixes);
+        RespValue::Map(vec![
+            (
+                RespValue::BulkString("flags".into()),
+                RespValue::Set(
+                    flags
+                        .into_iter()
+                        .map(|flag| RespValue::BulkString(flag.into()))
+                        .collect(),
+                ),
+            ),
+            (
+                RespValue::BulkString("redirect".into()),
+                RespValue::Integer(redirect),
+            ),
+            (
+                RespValue::BulkString("prefixes".into()),
+                RespValue::Array(prefixes.into_iter().map(RespValue::BulkString).collect()),
+            ),
+        ])
+    }
+
+    /// Marks the client active, running the command `argv`.
+    fn record_command(&self, argv: &[RespValue]) {
+        let mut details = self.info.details();
+        details.last_active = tokio::time::Instant::now();
+        details.last_command = command_label(argv);
+        details.in_command = true;
+    }
+
+    /// Copies the state `CLIENT LIST` shows into the client's info.
+    fn sync_info(&self) {
+        let mut details = self.info.details();
+        details.channels = self.channels.len();
+        details.patterns = self.patterns.len();
+        details.multi = self
+            .transaction
+            .as_ref()
+            .map(|transaction| transaction.queued.len());
+        details.protocol = self.protocol;
+    }
+
+    fn push_subscription_reply(&self, kind: &'static str, name: Option<Bytes>) {
+        let frame = RespValue::Push(vec![
+            RespValue::BulkString(kind.into()),
+            name.map_or(RespValue::NullBulkString, RespValue::BulkString),
+            RespValue::Integer(self.subscription_count() as i64),
+        ]);
+        let _ = self.messages_sender.send(frame);
+    }
+}
+
+/// Whether the command may write, scripts included, which propagate the
+/// writes they make themselves.
+fn is_write(command: &Command, argv: &[RespValue]) -> bool {
+    commands::is_write(argv)
+        || matches!(
+            command,
+            Command::Eval { .. } | Command::Evalsha { .. } | Command::Fcall { .. }
+        )
+}
+
+fn is_allowed_unauthenticated(command_name: &str) -> bool {
+    matches!(
+        command_name.to_uppercase().as_str(),
+        "AUTH" | "HELLO" | "QUIT"
+    )
+}
+
+fn is_allowed_when_subscribed(command_name: &str) -> bool {
+    matches!(
+        command_name.to_uppercase().as_str(),
+        "SUBSCRIBE" | "UNSUBSCRIBE" | "PSUBSCRIBE" | "PUNSUBSCRIBE" | "PING" | "QUIT" | "RESET"
+    )
+}
@@ -1,271 +1,1918 @@
+pub(crate) mod access;
 pub(crate) mod blocking;
+pub(crate) mod clock;
+pub(crate) mod encoding;
 pub(crate) mod error;
+pub(crate) mod expiry;
+pub(crate) mod hash;
+pub(crate) mod list;
+pub(crate) mod listpack;
+pub(crate) mod notifications;
+pub(crate) mod pubsub;
+pub(crate) mod scan;
+pub(crate) mod set;
+pub(crate) mod sorted_set;
 pub(crate) mod stream_types;
+pub(crate) mod typed;
 
-use std::{
-    collections::{HashMap, VecDeque},
-    time::Duration,
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use bytes::{Bytes, BytesMut};
+use rand::seq::IteratorRandom;
+use tokio::{
+    sync::{
+        broadcast,
+        mpsc::{self, error::TrySendError},
+        watch,
+    },
+    time::Instant,
+};
+
+use crate::{
+    acl::Acl,
+    clients::ClientRegistry,
+    cluster::Cluster,
+    config::Config,
+    latency::LatencyMonitor,
+    logging,
+    persistence::{
+        RdbState,
+        aof::{self, Aof},
+        rdb::{RdbDataset, RdbEntry},
+    },
+    replication::ReplicationState,
+    resp::{ProtocolLimits, RespValue},
+    scripting::{ScriptCache, functions::FunctionRegistry},
+    stats::STATS,
 };
 
-use tokio::{sync::mpsc, time::Instant};
+pub use self::notifications::{EventClass, KeyspaceEvent};
 
 use self::{
+    access::KeyAccess,
     blocking::{BlockingQueue, ListNotification, StreamNotification},
     error::DbError,
-    stream_types::{StreamItem, StreamList},
+    hash::Hash,
+    list::List,
+    pubsub::{MessageSender, PubSub},
+    scan::{ScanMap, ScanOptions},
+    set::Set,
+    sorted_set::{Aggregate, SortedSet, ZaddOptions, ZsetOperation, ZsetRange, weighted},
+    stream_types::{StreamId, StreamItem, StreamList, TrimStrategy},
 };
 
 #[derive(Debug)]
 pub struct Db {
-    values: HashMap<String, DbValue>,
-    expirations: HashMap<String, Instant>,
+    values: ScanMap<DbValue>,
+    /// The UNIX time in milliseconds each key with a time to live expires
+    /// at.
+    expirations: HashMap<Bytes, u64>,
+    key_access: HashMap<Bytes, KeyAccess>,
+    /// Keys left to check in the current pass of active expiration.
+    expire_scan: Vec<Bytes>,
+    /// Cleared by `DEBUG SET-ACTIVE-EXPIRE 0`, leaving expired keys until
+    /// they are accessed.
+    active_expire: bool,
     blocking_queue: BlockingQueue,
+    pubsub: PubSub,
+    /// Every keyspace event, for embedders.
+    keyspace_events: broadcast::Sender<KeyspaceEvent>,
+    config: Config,
+    /// The protocol limits of `config`, watched by connections.
+    protocol_limits: watch::Sender<ProtocolLimits>,
+    /// The `tcp-keepalive` of `config`, watched by the accept loops.
+    tcp_keepalive: watch::Sender<u64>,
+    rdb: RdbState,
+    aof: Option<Aof>,
+    replication: ReplicationState,
+    scripts: ScriptCache,
+    functions: FunctionRegistry,
+    clients: Arc<ClientRegistry>,
+    acl: Arc<Acl>,
+    cluster: Arc<Cluster>,
+    latency: LatencyMonitor,
+    /// Cleared while a command that writes runs, as only the key lookups
+    /// of reads count as keyspace hits or misses.
+    counting_lookups: bool,
+    /// Where the command being applied comes from.
+    origin: Origin,
+    /// Expired keys a replica took out of the keyspace for the read being
+    /// applied, put back once it is done.
+    hidden: Vec<(Bytes, DbValue, u64, Option<KeyAccess>)>,
+}
+
+/// Where a command comes from. A replica leaves deleting expired keys to its
+/// master, which sends a `DEL` for each of them: until then the commands of
+/// the master still see them, while those of clients do not.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Origin {
+    #[default]
+    Client,
+    /// The replication link to the master of this server.
+    Master,
 }
 
 #[derive(Clone, Debug)]
 pub enum DbValue {
-    Atom(String),
-    List(VecDeque<String>),
+    Atom(Bytes),
+    List(List),
     Stream(StreamList),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+}
+
+/// Which end of a list to pop from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+/// How many keyspace events a subscriber may fall behind by before it
+/// misses some.
+const KEYSPACE_EVENTS_CAPACITY: usize = 4096;
+
+/// Values made of more elements than this are freed on a background task
+/// by `UNLINK`, like Redis' `LAZYFREE_THRESHOLD`.
+const LAZYFREE_THRESHOLD: usize = 64;
+
+impl DbValue {
+    /// The name `TYPE` reports for the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            DbValue::Atom(_) => "string",
+            DbValue::List(_) => "list",
+            DbValue::Stream(_) => "stream",
+            DbValue::Hash(_) => "hash",
+            DbValue::Set(_) => "set",
+            DbValue::SortedSet(_) => "zset",
+        }
+    }
+
+    /// Roughly how many allocations freeing the value takes.
+    fn free_effort(&self) -> usize {
+        match self {
+            DbValue::Atom(_) => 1,
+            DbValue::List(list) => list.len(),
+            DbValue::Stream(stream) => stream.len(),
+            DbValue::Hash(hash) => hash.len(),
+            DbValue::Set(set) => set.len(),
+            DbValue::SortedSet(sorted_set) => sorted_set.len(),
+        }
+    }
+}
+
+/// The `NX`, `XX`, `GT` and `LT` flags of the `EXPIRE` family. A key
+/// without an expiration counts as having an infinite time to live.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExpireCondition {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+}
+
+impl ExpireCondition {
+    /// Whether a key whose time to live is `current` milliseconds (`None`
+    /// when persistent) may be given one of `new` milliseconds.
+    fn allows(self, current: Option<i64>, new: i64) -> bool {
+        match current {
+            None => !self.xx && !self.gt,
+            Some(_) if self.nx => false,
+            Some(current) if self.gt && new <= current => false,
+            Some(current) if self.lt && new >= current => false,
+            Some(_) => true,
+        }
+    }
 }
 
 impl Db {
-    pub fn new() -> Self {
+    pub fn new(config: Config) -> Self {
+        let acl = Acl::default();
+        if !config.requirepass.is_empty() {
+            acl.set_requirepass(&config.requirepass);
+        }
+        let clients = ClientRegistry::default();
+        clients.set_output_limits(config.client_output_buffer_limit);
+        let cluster = Cluster::new(&config);
         Self {
-            values: HashMap::new(),
+            values: ScanMap::default(),
             expirations: HashMap::new(),
+            key_access: HashMap::new(),
+            expire_scan: Vec::new(),
+            active_expire: true,
             blocking_queue: BlockingQueue::new(),
+            pubsub: PubSub::new(),
+            keyspace_events: broadcast::Sender::new(KEYSPACE_EVENTS_CAPACITY),
+            protocol_limits: watch::Sender::new(config.protocol_limits),
+            tcp_keepalive: watch::Sender::new(config.tcp_keepalive),
+            config,
+            rdb: RdbState::default(),
+            aof: None,
+            replication: ReplicationState::new(),
+            scripts: ScriptCache::default(),
+            functions: FunctionRegistry::default(),
+            clients: Arc::new(clients),
+            acl: Arc::new(acl),
+            cluster: Arc::new(cluster),
+            latency: LatencyMonitor::default(),
+            counting_lookups: true,
+            origin: Origin::Client,
+            hidden: Vec::new(),
         }
     }
 
     pub fn add_blocked_xread_client(
         &mut self,
-        key: String,
-        start: String,
+        key: Bytes,
+        start: StreamId,
         sender: mpsc::Sender<StreamNotification>,
     ) -> String {
         self.blocking_queue
             .add_blocked_xread_client(key, start, sender)
     }
 
-    pub fn add_blocked_lpop_client(
+    pub fn add_blocked_list_client(
         &mut self,
-        key: String,
+        keys: Vec<Bytes>,
+        end: ListEnd,
         sender: mpsc::Sender<ListNotification>,
     ) -> String {
-        self.blocking_queue.add_blocked_lpop_client(key, sender)
+        self.blocking_queue
+            .add_blocked_list_client(keys, end, sender)
     }
 
-    pub fn remove_blocked_client(&mut self, client_id: &str, key: &str) {
+    pub fn remove_blocked_client(&mut self, client_id: &str, key: &[u8]) {
         self.blocking_queue.remove_blocked_client(client_id, key)
     }
 
-    pub fn get(&mut self, key: &str) -> Option<DbValue> {
-        self.values.get(key).cloned()
+    /// Hands the elements pushed to lists clients are blocked on to those
+    /// clients, one element each, the longest waiting first, so that every
+    /// element goes to a single client. Returns the pops made, to propagate.
+    pub fn serve_blocked_clients(&mut self) -> Vec<(Bytes, ListEnd)> {
+        let mut served = vec![];
+        for key in self.blocking_queue.take_ready_keys() {
+            while let Some(waiter) = self.blocking_queue.first_list_waiter(&key) {
+                self.access(&key);
+                // The key may hold another type by now.
+                let Ok(Some(list)) = self.get_mut_as::<List>(&key) else {
+                    break;
+                };
+                let Some(element) = list.pop(waiter.end, 1).pop() else {
+                    break;
+                };
+                let is_empty = list.is_empty();
+                for blocked_key in &waiter.keys {
+                    self.blocking_queue
+                        .remove_blocked_client(&waiter.id, blocked_key);
+                }
+                let notification = ListNotification {
+                    key: key.clone(),
+                    element,
+                };
+                match waiter.sender.try_send(notification) {
+                    Ok(()) => {
+                        self.popped(&key, waiter.end, is_empty);
+                        served.push((key.clone(), waiter.end));
+                    }
+                    // The client went away since it was picked. The element
+                    // goes back where it was, and as the list did not
+                    // change, no event fires.
+                    Err(TrySendError::Full(notification) | TrySendError::Closed(notification)) => {
+                        let limits = self.config.encoding_limits;
+                        if let Ok(Some(list)) = self.get_mut_as::<List>(&key) {
+                            list.push(notification.element, waiter.end, &limits);
+                        }
+                    }
+                }
+            }
+        }
+        served
+    }
+
+    pub fn subscribe(&mut self, channel: &[u8], client_id: u64, sender: MessageSender) {
+        self.pubsub.subscribe(channel, client_id, sender)
+    }
+
+    pub fn unsubscribe(&mut self, channel: &[u8], client_id: u64) {
+        self.pubsub.unsubscribe(channel, client_id)
+    }
+
+    pub fn psubscribe(&mut self, pattern: &[u8], client_id: u64, sender: MessageSender) {
+        self.pubsub.psubscribe(pattern, client_id, sender)
     }
 
-    pub fn insert(&mut self, key: &str, value: DbValue) {
-        self.values.insert(key.to_owned(), value);
+    pub fn punsubscribe(&mut self, pattern: &[u8], client_id: u64) {
+        self.pubsub.punsubscribe(pattern, client_id)
     }
 
-    pub fn set_expiration(&mut self, key: &str, millis: u64) {
-        self.expirations.insert(
-            key.to_owned(),
-            Instant::now() + Duration::from_millis(millis),
-        );
+    pub fn publish(&mut self, channel: &[u8], message: &[u8]) -> u64 {
+        self.pubsub.publish(channel, message)
     }
 
-    pub fn is_expired(&mut self, key: &str) -> bool {
-        if let Some(expiration) = self.expirations.get(key)
-            && Instant::now() >= *expiration
+    pub fn pubsub_channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        self.pubsub.channels(pattern)
+    }
+
+    pub fn pubsub_numsub(&self, channel: &[u8]) -> u64 {
+        self.pubsub.numsub(channel)
+    }
+
+    pub fn pubsub_numpat(&self) -> u64 {
+        self.pubsub.numpat()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Applies `CONFIG SET`: every parameter is checked before any of them
+    /// changes, then the subsystems they affect pick up the new values.
+    pub fn set_config(&mut self, parameters: &[(String, String)]) -> anyhow::Result<()> {
+        let mut config = self.config.clone();
+        for (name, value) in parameters {
+            config.set(name, value)?;
+        }
+
+        match self.aof.as_mut() {
+            None if config.appendonly => {
+                let aof = aof::enable(self, &config.aof_path(), config.appendfsync)
+                    .map_err(|e| {
+                        anyhow::anyhow!(
+                            "ERR CONFIG SET failed (possibly related to argument 'appendonly') - {e}"
+                        )
+                    })?;
+                self.aof = Some(aof);
+            }
+            Some(_) if !config.appendonly => self.aof = None,
+            Some(aof) => aof.set_fsync(config.appendfsync)?,
+            None => {}
+        }
+        self.protocol_limits.send_replace(config.protocol_limits);
+        self.tcp_keepalive.send_replace(config.tcp_keepalive);
+        self.clients
+            .set_output_limits(config.client_output_buffer_limit);
+        if parameters
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case("requirepass"))
         {
-            return true;
+            self.acl.set_requirepass(&config.requirepass);
+        }
+        if config.cluster_slots != self.config.cluster_slots {
+            self.cluster.set_configured_slots(&config.cluster_slots);
+        }
+        self.cluster.set_node_timeout(config.cluster_node_timeout);
+        if config.loglevel != self.config.loglevel {
+            logging::set_level(config.loglevel);
         }
-        false
+        self.config = config;
+        Ok(())
     }
 
-    pub fn expire(&mut self, key: &str) {
+    /// Limits for client requests. Connections pick them up when accepted.
+    pub fn protocol_limits(&self) -> ProtocolLimits {
+        self.config.protocol_limits
+    }
+
+    /// The limits as they change, for connections to read without taking
+    /// the lock a busy script may hold.
+    pub fn watch_protocol_limits(&self) -> watch::Receiver<ProtocolLimits> {
+        self.protocol_limits.subscribe()
+    }
+
+    /// The `tcp-keepalive` interval in seconds as it changes, applied to
+    /// connections when accepted.
+    pub fn watch_tcp_keepalive(&self) -> watch::Receiver<u64> {
+        self.tcp_keepalive.subscribe()
+    }
+
+    pub fn notify_keyspace_event(&self, class: EventClass, event: &'static str, key: &[u8]) {
+        self.clients.invalidate(key);
+        if self.keyspace_events.receiver_count() > 0 {
+            let _ = self.keyspace_events.send(KeyspaceEvent {
+                class,
+                event,
+                key: Bytes::copy_from_slice(key),
+            });
+        }
+        let keyspace_events = self.config.notify_keyspace_events;
+        if !keyspace_events.is_enabled(class) {
+            return;
+        }
+        if keyspace_events.keyspace() {
+            let channel = [b"__keyspace@0__:", key].concat();
+            self.pubsub.publish(&channel, event.as_bytes());
+        }
+        if keyspace_events.keyevent() {
+            let channel = format!("__keyevent@0__:{event}");
+            self.pubsub.publish(channel.as_bytes(), key);
+        }
+    }
+
+    /// Every change to a key from now on, such as keys being set, deleted,
+    /// expiring or pushed to, whether or not `notify-keyspace-events` has
+    /// them published. Events come in the order they happen. A receiver
+    /// that falls more than a few thousand events behind gets
+    /// `RecvError::Lagged` and misses the oldest ones.
+    pub fn subscribe_keyspace_events(&self) -> broadcast::Receiver<KeyspaceEvent> {
+        self.keyspace_events.subscribe()
+    }
+
+    pub fn rdb(&self) -> &RdbState {
+        &self.rdb
+    }
+
+    pub fn set_aof(&mut self, aof: Aof) {
+        self.aof = Some(aof);
+    }
+
+    pub fn aof(&self) -> Option<&Aof> {
+        self.aof.as_ref()
+    }
+
+    pub fn aof_mut(&mut self) -> Option<&mut Aof> {
+        self.aof.as_mut()
+    }
+
+    /// Hands a successfully executed write command to the append-only file
+    /// and the replicas, and counts it towards the `save` rules.
+    pub fn propagate(&mut self, argv: &[RespValue]) {
+        self.rdb.add_change();
+        if let Some(aof) = self.aof.as_mut() {
+            aof.append(argv);
+        }
+        // A replica forwards its master's stream instead of its own writes.
+        if !self.replication.is_replica() {
+            self.replication.feed(argv);
+        }
+    }
+
+    pub fn replication(&self) -> &ReplicationState {
+        &self.replication
+    }
+
+    /// Whether this server is a replica whose clients may not write, as
+    /// `replica-read-only` has them. Writes from its master still apply.
+    pub fn is_read_only_replica(&self) -> bool {
+        self.replication.is_replica() && self.config.replica_read_only
+    }
+
+    pub fn replication_mut(&mut self) -> &mut ReplicationState {
+        &mut self.replication
+    }
+
+    pub fn scripts(&self) -> &ScriptCache {
+        &self.scripts
+    }
+
+    pub fn scripts_mut(&mut self) -> &mut ScriptCache {
+        &mut self.scripts
+    }
+
+    pub fn functions(&self) -> &FunctionRegistry {
+        &self.functions
+    }
+
+    pub fn functions_mut(&mut self) -> &mut FunctionRegistry {
+        &mut self.functions
+    }
+
+    pub fn clients(&self) -> &Arc<ClientRegistry> {
+        &self.clients
+    }
+
+    pub fn acl(&self) -> &Arc<Acl> {
+        &self.acl
+    }
+
+    pub fn cluster(&self) -> &Arc<Cluster> {
+        &self.cluster
+    }
+
+    pub fn latency(&self) -> &LatencyMonitor {
+        &self.latency
+    }
+
+    /// Records that `event` took `duration` when that reaches
+    /// `latency-monitor-threshold`.
+    pub fn record_latency(&self, event: &'static str, duration: Duration) {
+        let threshold = self.config.latency_monitor_threshold;
+        if !threshold.is_zero() && duration >= threshold {
+            self.latency.add_sample(event, duration);
+        }
+    }
+
+    pub fn add_replica(
+        &mut self,
+        id: u64,
+        sender: MessageSender,
+        ip: String,
+        listening_port: Option<u16>,
+    ) {
+        self.replication.add_replica(id, sender, ip, listening_port);
+    }
+
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replication.remove_replica(id);
+    }
+
+    /// Every key live at `now_millis` with its expiration, and the function
+    /// libraries.
+    pub fn rdb_dataset(&self, now_millis: u64) -> RdbDataset {
+        let entries = self
+            .values
+            .iter()
+            .filter_map(|(key, value)| {
+                let expires_at_millis = self.expirations.get(key).copied();
+                if expires_at_millis.is_some_and(|expiration| expiration <= now_millis) {
+                    return None;
+                }
+                Some(RdbEntry {
+                    key: key.clone(),
+                    value: value.clone(),
+                    expires_at_millis,
+                })
+            })
+            .collect();
+        RdbDataset {
+            entries,
+            functions: self
+                .functions
+                .libraries()
+                .map(|library| library.code.clone())
+                .collect(),
+        }
+    }
+
+    /// Removes every key, as `FLUSHALL` does and a replica before loading
+    /// its master's snapshot. With `lazy`, the old keyspace is freed on a
+    /// blocking task instead.
+    pub fn clear(&mut self, lazy: bool) {
+        let values = std::mem::take(&mut self.values);
+        let expirations = std::mem::take(&mut self.expirations);
+        self.key_access.clear();
+        self.expire_scan.clear();
+        self.clients.invalidate_all();
+        if lazy {
+            tokio::task::spawn_blocking(move || drop((values, expirations)));
+        }
+    }
+
+    /// The number of keys, not counting expired ones yet to be reclaimed.
+    pub fn len(&self) -> usize {
+        let now = clock::now_millis();
+        let expired = self
+            .expirations
+            .values()
+            .filter(|expiration| **expiration <= now)
+            .count();
+        self.values.len() - expired
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// How many of the keys have a time to live, and its average in
+    /// milliseconds.
+    pub fn volatile_keys(&self) -> (usize, u64) {
+        let now = clock::now_millis();
+        let ttls: Vec<u64> = self
+            .expirations
+            .values()
+            .filter(|expiration| **expiration > now)
+            .map(|expiration| expiration - now)
+            .collect();
+        let average = match ttls.len() {
+            0 => 0,
+            count => ttls.iter().sum::<u64>() / count as u64,
+        };
+        (ttls.len(), average)
+    }
+
+    /// A random key that has not expired, if any.
+    pub fn random_key(&mut self) -> Option<Bytes> {
+        while !self.values.is_empty() {
+            let index = rand::random_range(0..self.values.len());
+            let key = self.values.keys().nth(index)?.clone();
+            self.access(&key);
+            if self.values.contains_key(&key) {
+                return Some(key);
+            }
+        }
+        None
+    }
+
+    pub fn load_rdb_dataset(&mut self, dataset: RdbDataset, now_millis: u64) -> anyhow::Result<()> {
+        for code in dataset.functions {
+            self.functions.load(code, true)?;
+        }
+        for entry in dataset.entries {
+            if let Some(expires_at) = entry.expires_at_millis {
+                // A replica keeps the keys of its master's snapshot that
+                // expired, until the master deletes them.
+                if expires_at <= now_millis && !self.replication.is_replica() {
+                    continue;
+                }
+                self.set_expiration(&entry.key, expires_at);
+            }
+            self.key_access
+                .insert(entry.key.clone(), KeyAccess::default());
+            let mut value = entry.value;
+            value.compact(&self.config.encoding_limits);
+            self.values.insert(entry.key, value);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<DbValue> {
+        self.lookup(key).cloned()
+    }
+
+    /// The string at `key`, failing if the key holds another type.
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Option<Bytes>, DbError> {
+        self.access(key);
+        Ok(self.get_as::<Bytes>(key)?.cloned())
+    }
+
+    pub fn insert(&mut self, key: &[u8], value: DbValue) {
+        self.access(key);
+        if self
+            .values
+            .insert(Bytes::copy_from_slice(key), value)
+            .is_none()
+        {
+            self.added(key);
+        }
+    }
+
+    /// Adds `delta` to the integer stored at `key`, a missing key counting
+    /// as 0. The time to live is kept.
+    pub fn incr_by(&mut self, key: &[u8], delta: i64) -> Result<i64, DbError> {
+        self.access(key);
+        let current = match self.get_as::<Bytes>(key)? {
+            Some(value) => parse_integer(value).ok_or(DbError::NotAnInteger)?,
+            None => 0,
+        };
+        let value = current
+            .checked_add(delta)
+            .ok_or(DbError::IncrementOverflow)?;
+
+        self.store_atom(key, value.to_string().into());
+        self.notify_keyspace_event(EventClass::String, "incrby", key);
+        Ok(value)
+    }
+
+    /// Adds `delta` to the number stored at `key` like `incr_by`, returning
+    /// the new value as stored.
+    pub fn incr_by_float(&mut self, key: &[u8], delta: f64) -> Result<Bytes, DbError> {
+        self.access(key);
+        let current = match self.get_as::<Bytes>(key)? {
+            Some(value) => std::str::from_utf8(value)
+                .ok()
+                .and_then(|text| text.parse::<f64>().ok())
+                .filter(|current| current.is_finite())
+                .ok_or(DbError::NotAFloat)?,
+            None => 0.0,
+        };
+        let value = current + delta;
+        if !value.is_finite() {
+            return Err(DbError::NanOrInfinity);
+        }
+
+        let value = Bytes::from(value.to_string());
+        self.store_atom(key, value.clone());
+        self.notify_keyspace_event(EventClass::String, "incrbyfloat", key);
+        Ok(value)
+    }
+
+    /// Appends to the string at `key`, creating it if missing. Returns the
+    /// new length.
+    pub fn append(&mut self, key: &[u8], value: &[u8]) -> Result<usize, DbError> {
+        self.access(key);
+        let current = match self.get_as::<Bytes>(key)? {
+            Some(current) => current.as_ref(),
+            None => &[],
+        };
+        let mut appended = BytesMut::with_capacity(current.len() + value.len());
+        appended.extend_from_slice(current);
+        appended.extend_from_slice(value);
+        let length = appended.len();
+
+        self.store_atom(key, appended.freeze());
+        self.notify_keyspace_event(EventClass::String, "append", key);
+        Ok(length)
+    }
+
+    pub fn strlen(&self, key: &[u8]) -> Result<usize, DbError> {
+        match self.lookup_as::<Bytes>(key)? {
+            Some(value) => Ok(value.len()),
+            None => Ok(0),
+        }
+    }
+
+    /// The bytes of the string at `key` between `start` and `end`, both
+    /// included, where negative offsets count from the end.
+    pub fn getrange(&self, key: &[u8], start: i64, end: i64) -> Result<Bytes, DbError> {
+        let value = match self.lookup_as::<Bytes>(key)? {
+            Some(value) => value,
+            None => return Ok(Bytes::new()),
+        };
+
+        let length = value.len() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(Bytes::new());
+        }
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let end = if end < 0 { length + end } else { end }
+            .max(0)
+            .min(length - 1);
+        if start > end || length == 0 {
+            return Ok(Bytes::new());
+        }
+        Ok(value.slice(start as usize..=end as usize))
+    }
+
+    /// Overwrites the string at `key` from `offset` on, padding it with
+    /// zero bytes if it is shorter. Returns the new length.
+    pub fn setrange(&mut self, key: &[u8], offset: usize, value: &[u8]) -> Result<usize, DbError> {
+        self.access(key);
+        let current = match self.get_as::<Bytes>(key)? {
+            Some(current) => current.as_ref(),
+            // An empty write does not create the key.
+            None if value.is_empty() => return Ok(0),
+            None => &[],
+        };
+        if value.is_empty() {
+            return Ok(current.len());
+        }
+        if offset + value.len() > self.protocol_limits().max_bulk_len {
+            return Err(DbError::StringTooLong);
+        }
+
+        let mut updated = BytesMut::from(current);
+        if updated.len() < offset + value.len() {
+            updated.resize(offset + value.len(), 0);
+        }
+        updated[offset..offset + value.len()].copy_from_slice(value);
+        let length = updated.len();
+
+        self.store_atom(key, updated.freeze());
+        self.notify_keyspace_event(EventClass::String, "setrange", key);
+        Ok(length)
+    }
+
+    /// Replaces the string at `key` in place, keeping its time to live.
+    fn store_atom(&mut self, key: &[u8], value: Bytes) {
+        if self
+            .values
+            .insert(Bytes::copy_from_slice(key), DbValue::Atom(value))
+            .is_none()
+        {
+            self.added(key);
+        }
+    }
+
+    /// Makes `key` expire at the UNIX time `at_millis`.
+    pub fn set_expiration(&mut self, key: &[u8], at_millis: u64) {
+        self.expirations
+            .insert(Bytes::copy_from_slice(key), at_millis);
+    }
+
+    pub fn remove_expiration(&mut self, key: &[u8]) {
         self.expirations.remove(key);
-        self.values.remove(key);
     }
 
-    pub fn rpush(&mut self, key: &str, values: Vec<String>) -> Result<u64, DbError> {
+    /// Makes an existing key persistent. Returns whether it had a time to
+    /// live.
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.access(key);
+        if self.expirations.remove(key).is_none() {
+            return false;
+        }
+        self.notify_keyspace_event(EventClass::Generic, "persist", key);
+        true
+    }
+
+    /// Makes an existing key expire at the UNIX time `at_millis`, deleting
+    /// it right away when that has passed. Returns whether the key exists
+    /// and the condition was met.
+    pub fn expire_at(&mut self, key: &[u8], at_millis: i64, condition: ExpireCondition) -> bool {
+        self.access(key);
+        if !self.values.contains_key(key) {
+            return false;
+        }
+        let current = self
+            .expirations
+            .get(key)
+            .map(|expiration| *expiration as i64);
+        if !condition.allows(current, at_millis) {
+            return false;
+        }
+
+        if at_millis <= clock::now_millis() as i64 {
+            self.remove(key);
+            self.notify_keyspace_event(EventClass::Generic, "del", key);
+        } else {
+            self.set_expiration(key, at_millis as u64);
+            self.notify_keyspace_event(EventClass::Generic, "expire", key);
+        }
+        true
+    }
+
+    /// The remaining time to live in milliseconds, `-1` for a key without
+    /// an expiration and `-2` for a missing key.
+    pub fn pttl(&self, key: &[u8]) -> i64 {
+        if self.lookup(key).is_none() {
+            return -2;
+        }
+
+        match self.expirations.get(key) {
+            Some(expiration) => expiration.saturating_sub(clock::now_millis()) as i64,
+            None => -1,
+        }
+    }
+
+    /// The UNIX time in milliseconds the key expires at, `-1` for a key
+    /// without an expiration and `-2` for a missing key.
+    pub fn pexpiretime(&self, key: &[u8]) -> i64 {
+        if self.lookup(key).is_none() {
+            return -2;
+        }
+        self.expirations
+            .get(key)
+            .map_or(-1, |expiration| *expiration as i64)
+    }
+
+    /// Removes a key along with its expiration, returning its value.
+    pub fn del(&mut self, key: &[u8]) -> Option<DbValue> {
+        self.access(key);
+        let value = self.remove(key)?;
+        self.notify_keyspace_event(EventClass::Generic, "del", key);
+        Some(value)
+    }
+
+    /// Removes a key like `del`, but leaves freeing a large value to a
+    /// blocking task so the caller holding the lock is not held up.
+    pub fn unlink(&mut self, key: &[u8]) -> bool {
+        let Some(value) = self.del(key) else {
+            return false;
+        };
+        if value.free_effort() > LAZYFREE_THRESHOLD {
+            tokio::task::spawn_blocking(move || drop(value));
+        }
+        true
+    }
+
+    pub fn exists(&self, key: &[u8]) -> bool {
+        self.lookup(key).is_some()
+    }
+
+    /// Moves the value of `key` and its time to live to `new_key`. Unless
+    /// `replace` is set, nothing happens and `false` is returned when
+    /// `new_key` already exists.
+    pub fn rename(&mut self, key: &[u8], new_key: &[u8], replace: bool) -> Result<bool, DbError> {
+        self.access(key);
+        self.access(new_key);
+        if !self.values.contains_key(key) {
+            return Err(DbError::NoSuchKey);
+        }
+        if key == new_key {
+            return Ok(replace);
+        }
+        if !replace && self.values.contains_key(new_key) {
+            return Ok(false);
+        }
+
+        let expiration = self.expirations.get(key).copied();
+        let key_access = self.key_access.get(key).cloned();
+        let Some(value) = self.remove(key) else {
+            return Err(DbError::NoSuchKey);
+        };
+        self.notify_keyspace_event(EventClass::Generic, "rename_from", key);
+        self.put(new_key, value, expiration);
+        if let Some(key_access) = key_access {
+            self.key_access
+                .insert(Bytes::copy_from_slice(new_key), key_access);
+        }
+        self.notify_keyspace_event(EventClass::Generic, "rename_to", new_key);
+        Ok(true)
+    }
+
+    /// Copies the value of `source` and its time to live to `destination`,
+    /// which is only overwritten when `replace` is set. Returns whether the
+    /// value was copied.
+    pub fn copy(&mut self, source: &[u8], destination: &[u8], replace: bool) -> bool {
+        self.access(source);
+        self.access(destination);
+        let Some(value) = self.values.get(source).cloned() else {
+            return false;
+        };
+        if !replace && self.values.contains_key(destination) {
+            return false;
+        }
+
+        let expiration = self.expirations.get(source).copied();
+        self.put(destination, value, expiration);
+        self.notify_keyspace_event(EventClass::Generic, "copy_to", destination);
+        true
+    }
+
+    /// Stores a whole value under `key`, replacing any previous one and its
+    /// time to live, and wakes up clients blocked on the key.
+    fn put(&mut self, key: &[u8], value: DbValue, expiration: Option<u64>) {
+        let is_list = matches!(value, DbValue::List(_));
+        let key = Bytes::copy_from_slice(key);
+        match expiration {
+            Some(expiration) => self.expirations.insert(key.clone(), expiration),
+            None => self.expirations.remove(&key),
+        };
+        if self.values.insert(key.clone(), value).is_none() {
+            self.added(&key);
+        }
+        if is_list {
+            self.blocking_queue.notify_list_clients(&key);
+        }
+    }
+
+    /// One step of a keyspace iteration, skipping expired keys and keys of
+    /// another type than `type_name`.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        options: &ScanOptions,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<Bytes>) {
+        let now = clock::now_millis();
+        let (cursor, entries) = options.step(&self.values, cursor);
+        let keys = entries
+            .into_iter()
+            .filter(|(key, value)| {
+                !self.is_expired(key, now)
+                    && type_name.is_none_or(|type_name| value.type_name() == type_name)
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        (cursor, keys)
+    }
+
+    /// Deletes `key` if its time to live elapsed. Every method modifying a
+    /// key goes through here first, so writes never build on stale values.
+    pub fn access(&mut self, key: &[u8]) {
+        self.expire_if_needed(key, clock::now_millis());
+        if self.counting_lookups {
+            STATS.keyspace_lookup(self.values.contains_key(key));
+        }
+        if let Some(key_access) = self.key_access.get(key) {
+            key_access.touch(Instant::now());
+        }
+    }
+
+    /// The value of `key` for methods that only read it, which run under a
+    /// shared lock. An expired key reads as missing but is left for the
+    /// next write or the active expiration cycle to delete.
+    fn lookup(&self, key: &[u8]) -> Option<&DbValue> {
+        let value = self
+            .values
+            .get(key)
+            .filter(|_| !self.is_expired(key, clock::now_millis()));
+        if self.counting_lookups {
+            STATS.keyspace_lookup(value.is_some());
+        }
+        if value.is_some()
+            && let Some(key_access) = self.key_access.get(key)
+        {
+            key_access.touch(Instant::now());
+        }
+        value
+    }
+
+    pub fn set_counting_lookups(&mut self, counting_lookups: bool) {
+        self.counting_lookups = counting_lookups;
+    }
+
+    pub fn set_origin(&mut self, origin: Origin) {
+        self.origin = origin;
+    }
+
+    /// Looks a key up without counting as an access, as `OBJECT` does.
+    pub fn peek(&self, key: &[u8]) -> Option<(&DbValue, KeyAccess)> {
+        if self.is_expired(key, clock::now_millis()) {
+            return None;
+        }
+        let key_access = self.key_access.get(key).cloned().unwrap_or_default();
+        self.values.get(key).map(|value| (value, key_access))
+    }
+
+    fn is_expired(&self, key: &[u8], now_millis: u64) -> bool {
+        self.origin == Origin::Client
+            && self
+                .expirations
+                .get(key)
+                .is_some_and(|expiration| *expiration <= now_millis)
+    }
+
+    fn expire_if_needed(&mut self, key: &[u8], now_millis: u64) {
+        if !self.is_expired(key, now_millis) {
+            return;
+        }
+        // A replica does not delete expired keys on its own, so as to hold
+        // the same ones as its master: reads do not see them, and only the
+        // writes of a writable replica delete them, as in Redis.
+        if self.replication.is_replica() && self.counting_lookups {
+            self.hide(key);
+        } else {
+            self.expire(key);
+        }
+    }
+
+    /// Deletes an expired key, propagating its deletion so that the
+    /// replicas and the append-only file delete it too.
+    fn expire(&mut self, key: &[u8]) {
+        if self.remove(key).is_some() {
+            STATS.key_expired();
+            self.notify_keyspace_event(EventClass::Expired, "expired", key);
+            self.propagate(&[
+                RespValue::BulkString(Bytes::from_static(b"DEL")),
+                RespValue::BulkString(Bytes::copy_from_slice(key)),
+            ]);
+        }
+    }
+
+    /// Takes an expired key out of the keyspace until `unhide_expired`.
+    fn hide(&mut self, key: &[u8]) {
+        if let Some((key, value)) = self.values.remove_entry(key)
+            && let Some(expiration) = self.expirations.remove(&key)
+        {
+            let key_access = self.key_access.remove(&key);
+            self.hidden.push((key, value, expiration, key_access));
+        }
+    }
+
+    /// Puts back the expired keys a replica hid from the last command.
+    pub fn unhide_expired(&mut self) {
+        for (key, value, expiration, key_access) in std::mem::take(&mut self.hidden) {
+            if self.values.contains_key(&key) {
+                continue;
+            }
+            self.expirations.insert(key.clone(), expiration);
+            if let Some(key_access) = key_access {
+                self.key_access.insert(key.clone(), key_access);
+            }
+            self.values.insert(key, value);
+        }
+    }
+
+    pub fn rpush(&mut self, key: &[u8], values: Vec<Bytes>) -> Result<u64, DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
         let entry = self
             .values
-            .entry(key.to_owned())
-            .or_insert_with(|| DbValue::List(VecDeque::new()));
+            .get_or_insert_with(key, || DbValue::List(List::default()));
 
         if let DbValue::List(list) = entry {
-            list.extend(values);
-            self.blocking_queue.notify_lpop_clients(key);
-            Ok(list.len() as u64)
+            for value in values {
+                list.push(value, ListEnd::Right, &self.config.encoding_limits);
+            }
+            let length = list.len() as u64;
+            self.blocking_queue.notify_list_clients(key);
+            if is_new {
+                self.added(key);
+            }
+            self.notify_keyspace_event(EventClass::List, "rpush", key);
+            Ok(length)
         } else {
-            Err(DbError::KeyIsNotList(key.to_string()))
+            Err(DbError::WrongType)
         }
     }
 
-    pub fn lpush(&mut self, key: &str, values: Vec<String>) -> Result<u64, DbError> {
+    pub fn lpush(&mut self, key: &[u8], values: Vec<Bytes>) -> Result<u64, DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
         let entry = self
             .values
-            .entry(key.to_owned())
-            .or_insert_with(|| DbValue::List(VecDeque::new()));
+            .get_or_insert_with(key, || DbValue::List(List::default()));
 
         if let DbValue::List(list) = entry {
-            for value in values.into_iter() {
-                list.push_front(value);
+            for value in values {
+                list.push(value, ListEnd::Left, &self.config.encoding_limits);
+            }
+            let length = list.len() as u64;
+            self.blocking_queue.notify_list_clients(key);
+            if is_new {
+                self.added(key);
             }
-            self.blocking_queue.notify_lpop_clients(key);
-            Ok(list.len() as u64)
+            self.notify_keyspace_event(EventClass::List, "lpush", key);
+            Ok(length)
         } else {
-            Err(DbError::KeyIsNotList(key.to_string()))
+            Err(DbError::WrongType)
         }
     }
 
-    pub fn lpop(&mut self, key: &str, length: usize) -> Vec<String> {
-        if let Some(db_value) = self.values.get_mut(key)
-            && let DbValue::List(list) = db_value
-            && !list.is_empty()
-        {
-            let mut poped_list: Vec<String> = Vec::new();
-            for _ in 0..length {
-                let value = list.pop_front();
-                if let Some(value) = value {
-                    poped_list.push(value);
-                } else {
-                    break;
-                }
-            }
-            return poped_list;
+    /// Pops up to `count` elements from one end of the list at `key`,
+    /// deleting the key once the list is empty.
+    pub fn pop(&mut self, key: &[u8], end: ListEnd, count: usize) -> Result<Vec<Bytes>, DbError> {
+        self.access(key);
+        let Some(list) = self.get_mut_as::<List>(key)? else {
+            return Ok(vec![]);
+        };
+        let popped = list.pop(end, count);
+        if popped.is_empty() {
+            return Ok(popped);
         }
-        vec![]
+
+        let is_empty = list.is_empty();
+        self.popped(key, end, is_empty);
+        Ok(popped)
     }
 
-    pub fn llen(&mut self, key: &str) -> u64 {
-        if let Some(db_value) = self.values.get_mut(key)
-            && let DbValue::List(list) = db_value
-        {
-            return list.len() as u64;
+    /// Fires the event of a pop from the list at `key`, and deletes the key
+    /// if the pop emptied it.
+    fn popped(&mut self, key: &[u8], end: ListEnd, is_empty: bool) {
+        let event = match end {
+            ListEnd::Left => "lpop",
+            ListEnd::Right => "rpop",
+        };
+        self.notify_keyspace_event(EventClass::List, event, key);
+        if is_empty {
+            self.remove_empty(key);
         }
-        0
     }
 
-    pub fn lrange(&mut self, key: &str, start: isize, stop: isize) -> DbValue {
-        if let Some(db_value) = self.values.get(key)
-            && let DbValue::List(list) = db_value
-        {
-            let length = list.len();
+    /// Inserts `element` next to the first occurrence of `pivot`. Returns
+    /// the new length, `-1` when the pivot is missing and `0` when the key
+    /// is.
+    pub fn linsert(
+        &mut self,
+        key: &[u8],
+        after: bool,
+        pivot: &[u8],
+        element: Bytes,
+    ) -> Result<i64, DbError> {
+        self.access(key);
+        let limits = self.config.encoding_limits;
+        let list = match self.get_mut_as::<List>(key)? {
+            Some(list) => list,
+            None => return Ok(0),
+        };
+        let Some(position) = list.iter().position(|current| current == pivot) else {
+            return Ok(-1);
+        };
+        list.insert(position + after as usize, element, &limits);
+        let length = list.len() as i64;
+        self.notify_keyspace_event(EventClass::List, "linsert", key);
+        Ok(length)
+    }
 
-            let start = if start < 0 {
-                length as isize + start
-            } else {
-                start
-            }
-            .max(0) as usize;
+    pub fn lset(&mut self, key: &[u8], index: i64, element: Bytes) -> Result<(), DbError> {
+        self.access(key);
+        let limits = self.config.encoding_limits;
+        let list = match self.get_mut_as::<List>(key)? {
+            Some(list) => list,
+            None => return Err(DbError::NoSuchKey),
+        };
+        let index = list_index(list.len(), index).ok_or(DbError::IndexOutOfRange)?;
+        list.set(index, element, &limits);
+        self.notify_keyspace_event(EventClass::List, "lset", key);
+        Ok(())
+    }
 
-            let stop = if stop < 0 {
-                length as isize + stop
-            } else {
-                stop
-            }
-            .max(0) as usize;
+    pub fn lindex(&self, key: &[u8], index: i64) -> Result<Option<Bytes>, DbError> {
+        match self.lookup_as::<List>(key)? {
+            Some(list) => Ok(list_index(list.len(), index).and_then(|index| list.get(index))),
+            None => Ok(None),
+        }
+    }
 
-            if start < length && start < stop {
-                let stop = stop.min(list.len() - 1);
-                return DbValue::List(list.range(start..=stop).cloned().collect());
-            }
+    /// Removes occurrences of `element`: the first `count` ones when it is
+    /// positive, the last `-count` ones when negative, and all of them for
+    /// 0. Returns how many were removed.
+    pub fn lrem(&mut self, key: &[u8], count: i64, element: &[u8]) -> Result<u64, DbError> {
+        self.access(key);
+        let list = match self.get_mut_as::<List>(key)? {
+            Some(list) => list,
+            None => return Ok(0),
+        };
+        let limit = if count == 0 {
+            usize::MAX
+        } else {
+            count.unsigned_abs() as usize
+        };
+        let mut matches: Vec<usize> = list
+            .iter()
+            .enumerate()
+            .filter(|(_, current)| *current == element)
+            .map(|(index, _)| index)
+            .collect();
+        if count < 0 {
+            matches.reverse();
+        }
+        matches.truncate(limit);
+        matches.sort_unstable();
+        // Back to front, so earlier indexes stay valid.
+        for index in matches.iter().rev() {
+            list.remove(*index);
         }
-        DbValue::List(VecDeque::new())
+
+        let removed = matches.len() as u64;
+        let is_empty = list.is_empty();
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::List, "lrem", key);
+        }
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    /// Keeps only the elements between `start` and `stop`, both included,
+    /// where negative indexes count from the end.
+    pub fn ltrim(&mut self, key: &[u8], start: i64, stop: i64) -> Result<(), DbError> {
+        self.access(key);
+        let list = match self.get_mut_as::<List>(key)? {
+            Some(list) => list,
+            None => return Ok(()),
+        };
+        let length = list.len() as i64;
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let stop = if stop < 0 { length + stop } else { stop }.min(length - 1);
+        list.retain_range(start as usize..(stop + 1).max(start) as usize);
+
+        let is_empty = list.is_empty();
+        self.notify_keyspace_event(EventClass::List, "ltrim", key);
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(())
+    }
+
+    /// Positions of the elements equal to `element`, skipping the first
+    /// `rank - 1` matches, or searching from the tail for a negative rank.
+    /// At most `count` positions are returned and `maxlen` elements
+    /// compared, 0 meaning no limit for both.
+    pub fn lpos(
+        &self,
+        key: &[u8],
+        element: &[u8],
+        rank: i64,
+        count: usize,
+        maxlen: usize,
+    ) -> Result<Vec<usize>, DbError> {
+        let list = match self.lookup_as::<List>(key)? {
+            Some(list) => list,
+            None => return Ok(vec![]),
+        };
+        let list: Vec<&[u8]> = list.iter().collect();
+        let compared = if maxlen == 0 { list.len() } else { maxlen };
+        let positions: Box<dyn Iterator<Item = usize>> = if rank > 0 {
+            Box::new(0..list.len())
+        } else {
+            Box::new((0..list.len()).rev())
+        };
+        let count = if count == 0 { usize::MAX } else { count };
+        Ok(positions
+            .take(compared)
+            .filter(|index| list[*index] == element)
+            .skip(rank.unsigned_abs() as usize - 1)
+            .take(count)
+            .collect())
+    }
+
+    pub fn llen(&self, key: &[u8]) -> Result<u64, DbError> {
+        Ok(self
+            .lookup_as::<List>(key)?
+            .map_or(0, |list| list.len() as u64))
+    }
+
+    /// The elements from `start` to `stop` included, negative indexes
+    /// counting from the tail. Out of range indexes are clamped to the list,
+    /// as in Redis, after which a `start` past `stop` selects nothing.
+    pub fn lrange(&self, key: &[u8], start: i64, stop: i64) -> Result<Vec<Bytes>, DbError> {
+        let Some(list) = self.lookup_as::<List>(key)? else {
+            return Ok(vec![]);
+        };
+        let length = list.len() as i64;
+        let start = if start < 0 { length + start } else { start }.max(0);
+        let stop = if stop < 0 { length + stop } else { stop };
+        if start > stop || start >= length {
+            return Ok(vec![]);
+        }
+        Ok(list.range(start as usize, stop.min(length - 1) as usize))
     }
 
     pub fn xadd(
         &mut self,
-        key: &str,
-        id: &str,
-        values: HashMap<String, String>,
+        key: &[u8],
+        id: StreamId,
+        values: HashMap<Bytes, Bytes>,
     ) -> Result<(), DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
         let entry = self
             .values
-            .entry(key.to_string())
-            .or_insert_with(|| DbValue::Stream(StreamList(vec![])));
+            .get_or_insert_with(key, || DbValue::Stream(StreamList::default()));
 
         if let DbValue::Stream(stream) = entry {
-            let stream_item = StreamItem {
-                id: id.into(),
-                values,
-            };
-            stream.0.push(stream_item.clone());
+            stream.push(id, values.clone());
+            let stream_item = StreamItem { id, values };
             self.blocking_queue.notify_xread_clients(key, stream_item);
+            if is_new {
+                self.added(key);
+            }
+            self.notify_keyspace_event(EventClass::Stream, "xadd", key);
             Ok(())
         } else {
-            Err(DbError::KeyIsNotStream(key.to_string()))
+            Err(DbError::WrongType)
         }
     }
 
-    pub fn xfirst(&self, key: &str) -> Option<&StreamItem> {
-        if let Some(value) = self.values.get(key)
-            && let DbValue::Stream(stream_list) = value
-        {
-            stream_list.0.first()
+    /// The ID of the newest entry added to the stream at `key`.
+    pub fn xlast_id(&self, key: &[u8]) -> Result<Option<StreamId>, DbError> {
+        match self.lookup_as::<StreamList>(key)? {
+            Some(stream) => Ok(stream.last_id()),
+            None => Ok(None),
+        }
+    }
+
+    /// The entries of the stream at `key` from `start` to `end`, both
+    /// included.
+    pub fn xrange(
+        &self,
+        key: &[u8],
+        start: StreamId,
+        end: StreamId,
+    ) -> Result<Vec<StreamItem>, DbError> {
+        match self.lookup_as::<StreamList>(key)? {
+            Some(stream) => Ok(stream.range(start, end)),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// The entries of the stream at `key` added after `start`.
+    pub fn xread(&self, key: &[u8], start: StreamId) -> Result<Vec<StreamItem>, DbError> {
+        match self.lookup_as::<StreamList>(key)? {
+            Some(stream) => Ok(stream.after(start)),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn xlen(&self, key: &[u8]) -> Result<u64, DbError> {
+        match self.lookup_as::<StreamList>(key)? {
+            Some(stream) => Ok(stream.len() as u64),
+            None => Ok(0),
+        }
+    }
+
+    /// Removes the entries `ids` from the stream at `key`, returning how
+    /// many there were. Unlike other collections, a stream stays once
+    /// empty, keeping the last ID it handed out.
+    pub fn xdel(&mut self, key: &[u8], ids: &[StreamId]) -> Result<u64, DbError> {
+        self.access(key);
+        let removed = match self.get_mut_as::<StreamList>(key)? {
+            Some(stream) => ids.iter().filter(|id| stream.delete(**id)).count() as u64,
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::Stream, "xdel", key);
+        }
+        Ok(removed)
+    }
+
+    /// Removes the oldest entries of the stream at `key` as `strategy`
+    /// asks, returning how many.
+    pub fn xtrim(
+        &mut self,
+        key: &[u8],
+        strategy: TrimStrategy,
+        approximate: bool,
+    ) -> Result<u64, DbError> {
+        self.access(key);
+        let removed = match self.get_mut_as::<StreamList>(key)? {
+            Some(stream) => stream.trim(strategy, approximate) as u64,
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::Stream, "xtrim", key);
+        }
+        Ok(removed)
+    }
+
+    pub fn hset(&mut self, key: &[u8], pairs: Vec<(Bytes, Bytes)>) -> Result<u64, DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
+        let entry = self
+            .values
+            .get_or_insert_with(key, || DbValue::Hash(Hash::default()));
+
+        if let DbValue::Hash(hash) = entry {
+            let added = pairs
+                .into_iter()
+                .filter(|(field, value)| {
+                    hash.insert(field.clone(), value.clone(), &self.config.encoding_limits)
+                })
+                .count() as u64;
+            if is_new {
+                self.added(key);
+            }
+            self.notify_keyspace_event(EventClass::Hash, "hset", key);
+            Ok(added)
         } else {
-            None
+            Err(DbError::WrongType)
         }
     }
 
-    pub fn xlast(&self, key: &str) -> Option<&StreamItem> {
-        if let Some(value) = self.values.get(key)
-            && let DbValue::Stream(stream_list) = value
-        {
-            stream_list.0.last()
+    pub fn hget(&self, key: &[u8], field: &[u8]) -> Result<Option<Bytes>, DbError> {
+        match self.lookup_as::<Hash>(key)? {
+            Some(hash) => Ok(hash.get(field)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn hdel(&mut self, key: &[u8], fields: &[Bytes]) -> Result<u64, DbError> {
+        self.access(key);
+        let (removed, is_empty) = match self.get_mut_as::<Hash>(key)? {
+            Some(hash) => {
+                let removed = fields.iter().filter(|field| hash.remove(field)).count() as u64;
+                (removed, hash.is_empty())
+            }
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::Hash, "hdel", key);
+        }
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    pub fn hgetall(&self, key: &[u8]) -> Result<Vec<(Bytes, Bytes)>, DbError> {
+        match self.lookup_as::<Hash>(key)? {
+            Some(hash) => Ok(hash
+                .iter()
+                .map(|(field, value)| {
+                    (Bytes::copy_from_slice(field), Bytes::copy_from_slice(value))
+                })
+                .collect()),
+            None => Ok(vec![]),
+        }
+    }
+
+    pub fn hlen(&self, key: &[u8]) -> Result<u64, DbError> {
+        match self.lookup_as::<Hash>(key)? {
+            Some(hash) => Ok(hash.len() as u64),
+            None => Ok(0),
+        }
+    }
+
+    pub fn hscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<(Bytes, Bytes)>), DbError> {
+        Ok(self
+            .lookup_as::<Hash>(key)?
+            .map_or((0, vec![]), |hash| hash.scan(cursor, options)))
+    }
+
+    pub fn sadd(&mut self, key: &[u8], members: Vec<Bytes>) -> Result<u64, DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
+        let entry = self
+            .values
+            .get_or_insert_with(key, || DbValue::Set(Set::default()));
+
+        if let DbValue::Set(set) = entry {
+            let added = members
+                .into_iter()
+                .filter(|member| set.insert(member.clone(), &self.config.encoding_limits))
+                .count() as u64;
+            if is_new {
+                self.added(key);
+            }
+            if added > 0 {
+                self.notify_keyspace_event(EventClass::Set, "sadd", key);
+            }
+            Ok(added)
         } else {
-            None
+            Err(DbError::WrongType)
         }
     }
 
-    pub fn xrange(&mut self, key: &str, start: &str, end: &str) -> Result<&[StreamItem], DbError> {
-        let value = self.values.get(key);
+    pub fn srem(&mut self, key: &[u8], members: &[Bytes]) -> Result<u64, DbError> {
+        self.access(key);
+        let (removed, is_empty) = match self.get_mut_as::<Set>(key)? {
+            Some(set) => {
+                let removed = members.iter().filter(|member| set.remove(member)).count() as u64;
+                (removed, set.is_empty())
+            }
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::Set, "srem", key);
+        }
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    pub fn smembers(&self, key: &[u8]) -> Result<Vec<Bytes>, DbError> {
+        match self.lookup_as::<Set>(key)? {
+            Some(set) => Ok(set.iter().collect()),
+            None => Ok(vec![]),
+        }
+    }
 
-        match value {
-            Some(DbValue::Stream(stream_list)) => {
-                let first_index = stream_list
-                    .0
-                    .binary_search_by_key(&start, |stream_item| &stream_item.id)
-                    .map_err(|_| DbError::StreamStartIdNotFound(start.to_string()))?;
+    pub fn sismember(&self, key: &[u8], member: &[u8]) -> Result<bool, DbError> {
+        match self.lookup_as::<Set>(key)? {
+            Some(set) => Ok(set.contains(member)),
+            None => Ok(false),
+        }
+    }
 
-                let last_index = stream_list
-                    .0
-                    .binary_search_by_key(&end, |stream_item| &stream_item.id)
-                    .map_err(|_| DbError::StreamEndIdNotFound(end.to_string()))?;
+    pub fn smismember(&self, key: &[u8], members: &[Bytes]) -> Result<Vec<bool>, DbError> {
+        match self.lookup_as::<Set>(key)? {
+            Some(set) => Ok(members.iter().map(|member| set.contains(member)).collect()),
+            None => Ok(vec![false; members.len()]),
+        }
+    }
 
-                Ok(&stream_list.0[first_index..=last_index])
+    /// Removes and returns up to `count` random members.
+    pub fn spop(&mut self, key: &[u8], count: usize) -> Result<Vec<Bytes>, DbError> {
+        self.access(key);
+        let (popped, is_empty) = match self.get_mut_as::<Set>(key)? {
+            Some(set) => {
+                let popped: Vec<Bytes> = set
+                    .iter()
+                    .choose_multiple(&mut rand::rng(), count.min(set.len()));
+                for member in &popped {
+                    set.remove(member);
+                }
+                (popped, set.is_empty())
             }
-            Some(_) => Err(DbError::KeyIsNotStream(key.to_string())),
-            None => Err(DbError::KeyNotFound(key.to_string())),
+            None => return Ok(vec![]),
+        };
+        if !popped.is_empty() {
+            self.notify_keyspace_event(EventClass::Set, "spop", key);
+        }
+        if is_empty {
+            self.remove_empty(key);
         }
+        Ok(popped)
     }
 
-    pub fn xread(&mut self, key: &str, start: &str) -> Result<&[StreamItem], DbError> {
-        if let Some(value) = self.values.get(key) {
-            if let DbValue::Stream(stream_list) = value {
-                let search = stream_list
-                    .0
-                    .binary_search_by_key(&start, |stream_item| &stream_item.id);
+    /// Returns up to `count` distinct random members, or exactly `-count`
+    /// members that may repeat when `count` is negative.
+    pub fn srandmember(&mut self, key: &[u8], count: i64) -> Result<Vec<Bytes>, DbError> {
+        self.access(key);
+        let set = match self.get_as::<Set>(key)? {
+            Some(set) => set,
+            None => return Ok(vec![]),
+        };
+        if count >= 0 {
+            return Ok(set
+                .iter()
+                .choose_multiple(&mut rand::rng(), (count as usize).min(set.len())));
+        }
+        let members: Vec<Bytes> = set.iter().collect();
+        Ok((0..count.unsigned_abs())
+            .map(|_| members[rand::random_range(0..members.len())].clone())
+            .collect())
+    }
 
-                let first_index = match search {
-                    Ok(index) => index + 1,
-                    Err(index) => {
-                        if index > 0 {
-                            index - 1
-                        } else {
-                            0
-                        }
-                    }
-                };
-                Ok(&stream_list.0[first_index..])
+    /// Moves `member` from the `source` set to the `destination` one.
+    /// Returns whether `source` held it.
+    pub fn smove(
+        &mut self,
+        source: &[u8],
+        destination: &[u8],
+        member: Bytes,
+    ) -> Result<bool, DbError> {
+        self.access(source);
+        self.access(destination);
+        self.check_type::<Set>(destination)?;
+        match self.get_as::<Set>(source)? {
+            Some(set) if set.contains(&member) => {}
+            Some(_) | None => return Ok(false),
+        }
+        if source == destination {
+            return Ok(true);
+        }
+
+        self.srem(source, std::slice::from_ref(&member))?;
+        self.sadd(destination, vec![member])?;
+        Ok(true)
+    }
+
+    pub fn scard(&self, key: &[u8]) -> Result<u64, DbError> {
+        match self.lookup_as::<Set>(key)? {
+            Some(set) => Ok(set.len() as u64),
+            None => Ok(0),
+        }
+    }
+
+    pub fn sscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<Bytes>), DbError> {
+        Ok(self
+            .lookup_as::<Set>(key)?
+            .map_or((0, vec![]), |set| set.scan(cursor, options)))
+    }
+
+    /// Adds or updates members as `options` allow. Returns how many were
+    /// added, or also updated with `CH`, and the score the last member ended
+    /// up with, `None` if it was skipped.
+    pub fn zadd(
+        &mut self,
+        key: &[u8],
+        members: Vec<(f64, Bytes)>,
+        options: ZaddOptions,
+    ) -> Result<(u64, Option<f64>), DbError> {
+        self.access(key);
+        let is_new = !self.values.contains_key(key);
+        if is_new && options.xx {
+            return Ok((0, None));
+        }
+        let entry = self
+            .values
+            .get_or_insert_with(key, || DbValue::SortedSet(SortedSet::new()));
+        let DbValue::SortedSet(sorted_set) = entry else {
+            return Err(DbError::WrongType);
+        };
+
+        let (mut added, mut updated, mut last_score) = (0, 0, None);
+        for (score, member) in members {
+            let current = sorted_set.score(&member);
+            let score = match current {
+                Some(current) if options.incr => current + score,
+                _ => score,
+            };
+            // Only `INCR` can get there, adding opposite infinities.
+            if score.is_nan() {
+                return Err(DbError::ScoreIsNaN);
+            }
+            last_score = options.allows(current, score).then_some(score);
+            if last_score.is_none() || current == Some(score) {
+                continue;
+            }
+            if sorted_set.insert(member, score) {
+                added += 1;
             } else {
-                Err(DbError::KeyIsNotStream(key.to_string()))
+                updated += 1;
+            }
+        }
+
+        if is_new {
+            self.added(key);
+        }
+        if added + updated > 0 {
+            let event = if options.incr { "zincr" } else { "zadd" };
+            self.notify_keyspace_event(EventClass::Zset, event, key);
+        }
+        Ok((if options.ch { added + updated } else { added }, last_score))
+    }
+
+    /// Combines the sorted sets, or plain sets, at `keys`. Every input's
+    /// scores are scaled by its weight and then aggregated, except for the
+    /// difference, which keeps the first input's scores.
+    pub fn zcombine(
+        &mut self,
+        operation: ZsetOperation,
+        keys: &[Bytes],
+        weights: &[f64],
+        aggregate: Aggregate,
+    ) -> Result<SortedSet, DbError> {
+        let mut inputs = Vec::with_capacity(keys.len());
+        for key in keys {
+            self.access(key);
+            let input: HashMap<Bytes, f64> = match self.values.get(key) {
+                Some(DbValue::SortedSet(sorted_set)) => sorted_set
+                    .iter()
+                    .map(|(member, score)| (member.clone(), score))
+                    .collect(),
+                Some(DbValue::Set(set)) => set.iter().map(|member| (member, 1.0)).collect(),
+                Some(_) => return Err(DbError::WrongType),
+                None => HashMap::new(),
+            };
+            inputs.push(input);
+        }
+
+        let mut result = SortedSet::new();
+        let (first, rest) = inputs.split_first().expect("at least one input key");
+        match operation {
+            ZsetOperation::Union => {
+                let mut scores: HashMap<&Bytes, f64> = HashMap::new();
+                for (input, weight) in inputs.iter().zip(weights) {
+                    for (member, score) in input {
+                        let score = weighted(*score, *weight);
+                        scores
+                            .entry(member)
+                            .and_modify(|total| *total = aggregate.combine(*total, score))
+                            .or_insert(score);
+                    }
+                }
+                for (member, score) in scores {
+                    result.insert(member.clone(), score);
+                }
+            }
+            ZsetOperation::Inter => {
+                'members: for (member, score) in first {
+                    let mut total = weighted(*score, weights[0]);
+                    for (input, weight) in rest.iter().zip(&weights[1..]) {
+                        let Some(score) = input.get(member) else {
+                            continue 'members;
+                        };
+                        total = aggregate.combine(total, weighted(*score, *weight));
+                    }
+                    result.insert(member.clone(), total);
+                }
+            }
+            ZsetOperation::Diff => {
+                for (member, score) in first {
+                    if !rest.iter().any(|input| input.contains_key(member)) {
+                        result.insert(member.clone(), *score);
+                    }
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Replaces `destination` with the result of a sorted set operation,
+    /// deleting it when the result is empty. Returns the stored cardinality.
+    pub fn zstore(
+        &mut self,
+        destination: &[u8],
+        sorted_set: SortedSet,
+        event: &'static str,
+    ) -> usize {
+        self.access(destination);
+        let len = sorted_set.len();
+        if len == 0 {
+            if self.remove(destination).is_some() {
+                self.notify_keyspace_event(EventClass::Generic, "del", destination);
             }
         } else {
-            Err(DbError::KeyNotFound(key.to_string()))
+            self.put(destination, DbValue::SortedSet(sorted_set), None);
+            self.notify_keyspace_event(EventClass::Zset, event, destination);
+        }
+        len
+    }
+
+    pub fn zrem(&mut self, key: &[u8], members: &[Bytes]) -> Result<u64, DbError> {
+        self.access(key);
+        let (removed, is_empty) = match self.get_mut_as::<SortedSet>(key)? {
+            Some(sorted_set) => {
+                let removed = members
+                    .iter()
+                    .filter(|member| sorted_set.remove(member))
+                    .count() as u64;
+                (removed, sorted_set.is_empty())
+            }
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            self.notify_keyspace_event(EventClass::Zset, "zrem", key);
+        }
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    pub fn zscore(&self, key: &[u8], member: &[u8]) -> Result<Option<f64>, DbError> {
+        match self.lookup_as::<SortedSet>(key)? {
+            Some(sorted_set) => Ok(sorted_set.score(member)),
+            None => Ok(None),
         }
     }
+
+    pub fn zrank(&self, key: &[u8], member: &[u8]) -> Result<Option<u64>, DbError> {
+        match self.lookup_as::<SortedSet>(key)? {
+            Some(sorted_set) => Ok(sorted_set.rank(member).map(|rank| rank as u64)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn zcard(&self, key: &[u8]) -> Result<u64, DbError> {
+        match self.lookup_as::<SortedSet>(key)? {
+            Some(sorted_set) => Ok(sorted_set.len() as u64),
+            None => Ok(0),
+        }
+    }
+
+    pub fn zscan(
+        &self,
+        key: &[u8],
+        cursor: u64,
+        options: &ScanOptions,
+    ) -> Result<(u64, Vec<(Bytes, f64)>), DbError> {
+        Ok(self
+            .lookup_as::<SortedSet>(key)?
+            .map_or((0, vec![]), |sorted_set| sorted_set.scan(cursor, options)))
+    }
+
+    pub fn zrange(
+        &self,
+        key: &[u8],
+        start: isize,
+        stop: isize,
+    ) -> Result<Vec<(Bytes, f64)>, DbError> {
+        let sorted_set = match self.lookup_as::<SortedSet>(key)? {
+            Some(sorted_set) => sorted_set,
+            None => return Ok(vec![]),
+        };
+
+        Ok(sorted_set.range(&ZsetRange::Rank(start as i64, stop as i64)))
+    }
+
+    /// Removes the members within `range`, returning how many there were.
+    pub fn zremrange(&mut self, key: &[u8], range: &ZsetRange) -> Result<u64, DbError> {
+        self.access(key);
+        let (removed, is_empty) = match self.get_mut_as::<SortedSet>(key)? {
+            Some(sorted_set) => {
+                let members = sorted_set.range(range);
+                for (member, _) in &members {
+                    sorted_set.remove(member);
+                }
+                (members.len() as u64, sorted_set.is_empty())
+            }
+            None => return Ok(0),
+        };
+        if removed > 0 {
+            let event = match range {
+                ZsetRange::Rank(..) => "zremrangebyrank",
+                ZsetRange::Score(..) => "zremrangebyscore",
+                ZsetRange::Lex(..) => "zremrangebylex",
+            };
+            self.notify_keyspace_event(EventClass::Zset, event, key);
+        }
+        if is_empty {
+            self.remove_empty(key);
+        }
+        Ok(removed)
+    }
+
+    /// Returns up to `count` distinct random members with their scores, or
+    /// exactly `-count` members that may repeat when `count` is negative.
+    pub fn zrandmember(&mut self, key: &[u8], count: i64) -> Result<Vec<(Bytes, f64)>, DbError> {
+        self.access(key);
+        let sorted_set = match self.get_as::<SortedSet>(key)? {
+            Some(sorted_set) => sorted_set,
+            None => return Ok(vec![]),
+        };
+        let members = sorted_set
+            .iter()
+            .map(|(member, score)| (member.clone(), score));
+        if count >= 0 {
+            let count = (count as usize).min(sorted_set.len());
+            return Ok(members.choose_multiple(&mut rand::rng(), count));
+        }
+        let members: Vec<(Bytes, f64)> = members.collect();
+        Ok((0..count.unsigned_abs())
+            .map(|_| members[rand::random_range(0..members.len())].clone())
+            .collect())
+    }
+
+    /// Starts tracking accesses to a key that was just created.
+    fn added(&mut self, key: &[u8]) {
+        self.key_access
+            .insert(Bytes::copy_from_slice(key), KeyAccess::default());
+        self.notify_keyspace_event(EventClass::New, "new", key);
+    }
+
+    /// Removes a key along with its expiration and access metadata.
+    fn remove(&mut self, key: &[u8]) -> Option<DbValue> {
+        self.expirations.remove(key);
+        self.key_access.remove(key);
+        self.values.remove(key)
+    }
+
+    /// Collections never stay in the keyspace once their last element is gone.
+    fn remove_empty(&mut self, key: &[u8]) {
+        self.remove(key);
+        self.notify_keyspace_event(EventClass::Generic, "del", key);
+    }
+}
+
+/// Parses a string holding a 64-bit integer, rejecting forms that would not
+/// print back the same, like `+1` or `01`, as Redis does.
+pub fn parse_integer(value: &[u8]) -> Option<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|text| text.parse::<i64>().ok())
+        .filter(|integer| integer.to_string().as_bytes() == value)
+}
+
+/// Resolves a list index, negative ones counting from the end, into a
+/// position within a list of `len` elements.
+fn list_index(len: usize, index: i64) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
 }
@@ -0,0 +1,87 @@
+use std::{
+    sync::atomic::{AtomicU8, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use crate::stats::STARTED;
+
+/// Frequency counter of a new key, so it is not the first one evicted.
+const LFU_INIT_VAL: u8 = 5;
+/// How hard it gets to increment the counter as it grows, like
+/// `lfu-log-factor`.
+const LFU_LOG_FACTOR: f64 = 10.0;
+/// Idle time after which the counter is decremented, like
+/// `lfu-decay-time`.
+const LFU_DECAY_TIME: Duration = Duration::from_secs(60);
+
+/// When a key was last read or written, and a logarithmic counter of how
+/// often it is, for `OBJECT IDLETIME`/`FREQ` and eviction. Both are atomics
+/// so that reads, which only share the `Db`, still count as accesses; two
+/// reads racing may lose an increment, which the approximate counter
+/// tolerates.
+#[derive(Debug)]
+pub struct KeyAccess {
+    /// Nanoseconds since the server started.
+    last_access: AtomicU64,
+    counter: AtomicU8,
+}
+
+impl Default for KeyAccess {
+    fn default() -> Self {
+        Self {
+            last_access: AtomicU64::new(since_start(Instant::now())),
+            counter: AtomicU8::new(LFU_INIT_VAL),
+        }
+    }
+}
+
+impl Clone for KeyAccess {
+    fn clone(&self) -> Self {
+        Self {
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            counter: AtomicU8::new(self.counter.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl KeyAccess {
+    pub fn touch(&self, now: Instant) {
+        self.counter
+            .store(increment(self.frequency(now)), Ordering::Relaxed);
+        self.last_access.store(since_start(now), Ordering::Relaxed);
+    }
+
+    pub fn idle_time(&self, now: Instant) -> Duration {
+        let last_access = Duration::from_nanos(self.last_access.load(Ordering::Relaxed));
+        now.saturating_duration_since(*STARTED + last_access)
+    }
+
+    /// The frequency counter, decayed by one for every period the key went
+    /// untouched.
+    pub fn frequency(&self, now: Instant) -> u8 {
+        let periods = self.idle_time(now).as_secs() / LFU_DECAY_TIME.as_secs();
+        self.counter
+            .load(Ordering::Relaxed)
+            .saturating_sub(periods.min(u8::MAX as u64) as u8)
+    }
+}
+
+fn since_start(instant: Instant) -> u64 {
+    instant.saturating_duration_since(*STARTED).as_nanos() as u64
+}
+
+/// Increments the counter with a probability shrinking as it grows, so that
+/// 255 takes about a million accesses.
+fn increment(counter: u8) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    if rand::random::<f64>() < 1.0 / (base * LFU_LOG_FACTOR + 1.0) {
+        counter + 1
+    } else {
+        counter
+    }
+}
@@ -1,53 +1,83 @@
 use std::collections::VecDeque;
-use tokio::{sync::mpsc, time::Instant};
+
+use bytes::Bytes;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    time::Instant,
+};
 use uuid::Uuid;
 
+use super::{
+    ListEnd,
+    stream_types::{StreamId, StreamItem},
+};
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct StreamNotification {
-    pub key: String,
-    pub item: super::stream_types::StreamItem,
+    pub key: Bytes,
+    pub item: StreamItem,
 }
 
-#[allow(dead_code)]
+/// The element popped for a client blocked on lists, from the list at
+/// `key`.
 #[derive(Debug, Clone)]
 pub struct ListNotification {
-    pub key: String,
+    pub key: Bytes,
+    pub element: Bytes,
 }
 
 #[derive(Debug)]
 pub enum ClientSender {
     Stream(mpsc::Sender<StreamNotification>),
-    List(mpsc::Sender<ListNotification>),
+    /// A client blocked in `BLPOP` or `BRPOP` on all of `keys`, popping from
+    /// `end` of the first one pushed to.
+    List {
+        sender: mpsc::Sender<ListNotification>,
+        end: ListEnd,
+        keys: Vec<Bytes>,
+    },
+}
+
+/// The client blocked on a list the longest, to be handed an element.
+pub struct ListWaiter {
+    pub id: String,
+    pub end: ListEnd,
+    pub keys: Vec<Bytes>,
+    pub sender: mpsc::Sender<ListNotification>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct BlockedClient {
     id: String,
-    key: String,
+    key: Bytes,
     blocked_since: Instant,
     sender: ClientSender,
-    xread_start: Option<String>,
+    xread_start: Option<StreamId>,
 }
 
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct BlockingQueue {
-    waiting_clients: std::collections::HashMap<String, VecDeque<BlockedClient>>,
+    waiting_clients: std::collections::HashMap<Bytes, VecDeque<BlockedClient>>,
+    /// The lists pushed to while clients are blocked on them, in the order
+    /// they were first pushed to.
+    ready_keys: Vec<Bytes>,
 }
 
 impl BlockingQueue {
     pub fn new() -> Self {
         Self {
             waiting_clients: std::collections::HashMap::new(),
+            ready_keys: Vec::new(),
         }
     }
 
     pub fn add_blocked_xread_client(
         &mut self,
-        key: String,
-        start: String,
+        key: Bytes,
+        start: StreamId,
         sender: mpsc::Sender<StreamNotification>,
     ) -> String {
         let client_id = Uuid::new_v4().to_string();
@@ -65,27 +95,36 @@ impl BlockingQueue {
         client_id
     }
 
-    pub fn add_blocked_lpop_client(
+    /// Blocks a client on all of `keys` at once, behind the clients already
+    /// blocked on each of them. Returns the id it is removed with.
+    pub fn add_blocked_list_client(
         &mut self,
-        key: String,
+        keys: Vec<Bytes>,
+        end: ListEnd,
         sender: mpsc::Sender<ListNotification>,
     ) -> String {
         let client_id = Uuid::new_v4().to_string();
-        let blocked_client = BlockedClient {
-            id: client_id.clone(),
-            key: key.clone(),
-            blocked_since: Instant::now(),
-            sender: ClientSender::List(sender),
-            xread_start: None,
-        };
-        self.waiting_clients
-            .entry(key)
-            .or_default()
-            .push_back(blocked_client);
+        for key in &keys {
+            let blocked_client = BlockedClient {
+                id: client_id.clone(),
+                key: key.clone(),
+                blocked_since: Instant::now(),
+                sender: ClientSender::List {
+                    sender: sender.clone(),
+                    end,
+                    keys: keys.clone(),
+                },
+                xread_start: None,
+            };
+            self.waiting_clients
+                .entry(key.clone())
+                .or_default()
+                .push_back(blocked_client);
+        }
         client_id
     }
 
-    pub fn remove_blocked_client(&mut self, client_id: &str, key: &str) {
+    pub fn remove_blocked_client(&mut self, client_id: &str, key: &[u8]) {
         if let Some(queue) = self.waiting_clients.get_mut(key) {
             queue.retain(|client| client.id != client_id);
             if queue.is_empty() {
@@ -94,43 +133,70 @@ impl BlockingQueue {
         }
     }
 
-    pub fn notify_lpop_clients(&mut self, key: &str) {
-        if let Some(queue) = self.waiting_clients.get_mut(key) {
-            let notification = ListNotification {
-                key: key.to_string(),
-            };
-            let mut clients_to_retain = VecDeque::new();
-            for client in queue.drain(..) {
-                match &client.sender {
-                    ClientSender::List(sender) => {
-                        if sender.try_send(notification.clone()).is_ok() {
-                            clients_to_retain.push_back(client);
-                        }
-                    }
-                    ClientSender::Stream(_) => {
-                        clients_to_retain.push_back(client);
-                    }
+    /// Marks the list at `key`, which was pushed to, as ready to serve the
+    /// clients blocked on it, once the command pushing to it ran.
+    pub fn notify_list_clients(&mut self, key: &[u8]) {
+        let blocked = self.waiting_clients.get(key).is_some_and(|queue| {
+            queue
+                .iter()
+                .any(|client| matches!(client.sender, ClientSender::List { .. }))
+        });
+        if blocked && !self.ready_keys.iter().any(|ready| ready.as_ref() == key) {
+            self.ready_keys.push(Bytes::copy_from_slice(key));
+        }
+    }
+
+    pub fn take_ready_keys(&mut self) -> Vec<Bytes> {
+        std::mem::take(&mut self.ready_keys)
+    }
+
+    /// The client blocked on the list at `key` the longest. Clients that
+    /// stopped waiting without being removed are dropped on the way.
+    pub fn first_list_waiter(&mut self, key: &[u8]) -> Option<ListWaiter> {
+        let queue = self.waiting_clients.get_mut(key)?;
+        let mut gone = vec![];
+        let mut waiter = None;
+        for client in queue.iter() {
+            if let ClientSender::List { sender, end, keys } = &client.sender {
+                if sender.is_closed() {
+                    gone.push((client.id.clone(), keys.clone()));
+                    continue;
                 }
+                waiter = Some(ListWaiter {
+                    id: client.id.clone(),
+                    end: *end,
+                    keys: keys.clone(),
+                    sender: sender.clone(),
+                });
+                break;
+            }
+        }
+        for (id, keys) in gone {
+            for key in keys {
+                self.remove_blocked_client(&id, &key);
             }
-            *queue = clients_to_retain;
         }
+        waiter
     }
 
-    pub fn notify_xread_clients(&mut self, key: &str, item: super::stream_types::StreamItem) {
+    pub fn notify_xread_clients(&mut self, key: &[u8], item: StreamItem) {
         if let Some(queue) = self.waiting_clients.get_mut(key) {
             let notification = StreamNotification {
-                key: key.to_string(),
+                key: Bytes::copy_from_slice(key),
                 item,
             };
             let mut clients_to_retain = VecDeque::new();
             for client in queue.drain(..) {
                 match &client.sender {
                     ClientSender::Stream(sender) => {
-                        if sender.try_send(notification.clone()).is_ok() {
+                        if !matches!(
+                            sender.try_send(notification.clone()),
+                            Err(TrySendError::Closed(_))
+                        ) {
                             clients_to_retain.push_back(client);
                         }
                     }
-                    ClientSender::List(_) => {
+                    ClientSender::List { .. } => {
                         clients_to_retain.push_back(client);
                     }
                 }
@@ -0,0 +1,19 @@
+//! The clock expirations are kept in: UNIX time in milliseconds, so that a
+//! time to live is saved to the RDB file and sent to replicas as the
+//! deadline it is, and survives restarts.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::persistence::unix_time_millis;
+
+/// The latest time `now_millis` returned.
+static LATEST: AtomicU64 = AtomicU64::new(0);
+
+/// The current UNIX time in milliseconds, which never goes backwards: when
+/// the system clock is set back, time stands still until it catches up
+/// instead, so that expired keys do not come back and times to live do not
+/// grow.
+pub fn now_millis() -> u64 {
+    let now = unix_time_millis();
+    LATEST.fetch_max(now, Ordering::Relaxed).max(now)
+}
@@ -0,0 +1,78 @@
+use super::{DbValue, parse_integer};
+
+/// Sorted sets up to this many members would be kept as a listpack by
+/// Redis, like its `zset-max-listpack-entries` default.
+const MAX_LISTPACK_ENTRIES: usize = 128;
+/// Longest member a listpack may hold, like `zset-max-listpack-value`.
+const MAX_LISTPACK_VALUE: usize = 64;
+/// Longest string Redis embeds in its object header.
+const MAX_EMBSTR_LEN: usize = 44;
+
+/// The sizes up to which lists, hashes and sets keep a compact encoding,
+/// changed with `CONFIG SET`. Collections are converted once they grow
+/// past them and never back, except when loaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EncodingLimits {
+    /// `list-max-listpack-size`: the most elements of a listpack when
+    /// positive, or its size in bytes when negative, from 4 KB for -1 to
+    /// 64 KB for -5.
+    pub list_max_listpack_size: i64,
+    pub hash_max_listpack_entries: usize,
+    /// The longest field or value a listpack hash may hold.
+    pub hash_max_listpack_value: usize,
+    /// The most members of a set of integers kept as an intset.
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+}
+
+impl Default for EncodingLimits {
+    fn default() -> Self {
+        Self {
+            list_max_listpack_size: -2,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+        }
+    }
+}
+
+impl DbValue {
+    /// The internal encoding of the value, as reported by `OBJECT
+    /// ENCODING`. Strings and sorted sets are always stored the same way
+    /// here, so theirs is the one Redis would use for their current size
+    /// and content.
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            DbValue::Atom(value) if value.len() <= 20 && parse_integer(value).is_some() => "int",
+            DbValue::Atom(value) if value.len() <= MAX_EMBSTR_LEN => "embstr",
+            DbValue::Atom(_) => "raw",
+            DbValue::List(list) => list.encoding(),
+            DbValue::Hash(hash) => hash.encoding(),
+            DbValue::Set(set) => set.encoding(),
+            DbValue::SortedSet(sorted_set)
+                if sorted_set.len() <= MAX_LISTPACK_ENTRIES
+                    && sorted_set
+                        .iter()
+                        .all(|(member, _)| member.len() <= MAX_LISTPACK_VALUE) =>
+            {
+                "listpack"
+            }
+            DbValue::SortedSet(_) => "skiplist",
+            DbValue::Stream(_) => "stream",
+        }
+    }
+
+    /// Gives a collection its most compact encoding within `limits`, as
+    /// values loaded from a snapshot are encoded.
+    pub fn compact(&mut self, limits: &EncodingLimits) {
+        match self {
+            DbValue::List(list) => list.compact(limits),
+            DbValue::Hash(hash) => hash.compact(limits),
+            DbValue::Set(set) => set.compact(limits),
+            _ => {}
+        }
+    }
+}
@@ -2,21 +2,36 @@ use std::{error::Error, fmt};
 
 #[derive(Debug)]
 pub enum DbError {
-    KeyNotFound(String),
-    KeyIsNotStream(String),
-    KeyIsNotList(String),
-    StreamStartIdNotFound(String),
-    StreamEndIdNotFound(String),
+    NoSuchKey,
+    /// The key holds another type of value than the command works on.
+    WrongType,
+    NotAnInteger,
+    NotAFloat,
+    IncrementOverflow,
+    NanOrInfinity,
+    StringTooLong,
+    IndexOutOfRange,
+    ScoreIsNaN,
 }
 
 impl fmt::Display for DbError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            DbError::KeyNotFound(key) => write!(f, "Key '{key}' not found"),
-            DbError::KeyIsNotStream(key) => write!(f, "Key '{key}' exists but is not a stream"),
-            DbError::KeyIsNotList(key) => write!(f, "Key '{key}' exists but is not a list"),
-            DbError::StreamStartIdNotFound(id) => write!(f, "Stream start ID '{id}' not found"),
-            DbError::StreamEndIdNotFound(id) => write!(f, "Stream end ID '{id}' not found"),
+            DbError::NoSuchKey => write!(f, "ERR no such key"),
+            DbError::WrongType => write!(
+                f,
+                "WRONGTYPE Operation against a key holding the wrong kind of value"
+            ),
+            DbError::NotAnInteger => write!(f, "ERR value is not an integer or out of range"),
+            DbError::NotAFloat => write!(f, "ERR value is not a valid float"),
+            DbError::IncrementOverflow => write!(f, "ERR increment or decrement would overflow"),
+            DbError::NanOrInfinity => write!(f, "ERR increment would produce NaN or Infinity"),
+            DbError::StringTooLong => write!(
+                f,
+                "ERR string exceeds maximum allowed size (proto-max-bulk-len)"
+            ),
+            DbError::IndexOutOfRange => write!(f, "ERR index out of range"),
+            DbError::ScoreIsNaN => write!(f, "ERR resulting score is not a number (NaN)"),
         }
     }
 }
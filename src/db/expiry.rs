@@ -0,0 +1,78 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{sync::RwLock, time::Instant};
+
+use super::{Db, clock};
+
+/// How often the active expiration cycle runs, like Redis' default `hz 10`.
+const CYCLE_PERIOD: Duration = Duration::from_millis(100);
+/// Time a single cycle may spend holding the database, a quarter of the
+/// period as in Redis.
+const CYCLE_BUDGET: Duration = Duration::from_millis(25);
+/// Keys with a time to live checked per sample.
+const KEYS_PER_SAMPLE: usize = 20;
+
+/// Spawns the background task that deletes expired keys nobody accesses.
+pub fn spawn_active_expire(db: Arc<RwLock<Db>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CYCLE_PERIOD);
+        loop {
+            interval.tick().await;
+            let mut db_g = db.write().await;
+            // Replicas leave expiring to their master.
+            if !db_g.active_expire || db_g.replication().is_replica() {
+                continue;
+            }
+            let started = Instant::now();
+            db_g.active_expire_cycle(started + CYCLE_BUDGET);
+            db_g.clients().send_broadcasts();
+            db_g.record_latency("expire-cycle", started.elapsed());
+        }
+    });
+}
+
+impl Db {
+    pub fn set_active_expire(&mut self, enabled: bool) {
+        self.active_expire = enabled;
+    }
+
+    /// Checks keys with a time to live a sample at a time and deletes the
+    /// expired ones. Another sample is taken as long as more than a quarter
+    /// of the last one had expired and `deadline` is not reached. Keys are
+    /// visited in passes over a snapshot of the expirations, so that every
+    /// one of them is eventually checked. Returns the number of keys deleted.
+    pub fn active_expire_cycle(&mut self, deadline: Instant) -> usize {
+        let mut deleted = 0;
+        loop {
+            if self.expire_scan.is_empty() {
+                self.expire_scan = self.expirations.keys().cloned().collect();
+                if self.expire_scan.is_empty() {
+                    break;
+                }
+            }
+
+            let now = clock::now_millis();
+            let sample_len = KEYS_PER_SAMPLE.min(self.expire_scan.len());
+            let sample = self
+                .expire_scan
+                .split_off(self.expire_scan.len() - sample_len);
+            let mut expired = 0;
+            for key in sample {
+                if self
+                    .expirations
+                    .get(&key)
+                    .is_some_and(|expiration| *expiration <= now)
+                {
+                    self.expire(&key);
+                    expired += 1;
+                }
+            }
+            deleted += expired;
+
+            if expired * 4 <= sample_len || Instant::now() >= deadline {
+                break;
+            }
+        }
+        deleted
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use super::{
+    encoding::EncodingLimits,
+    listpack::Listpack,
+    scan::{ScanMap, ScanOptions},
+};
+
+/// A hash, kept as a listpack of fields each followed by its value until it
+/// outgrows `hash-max-listpack-entries` or `hash-max-listpack-value`, and
+/// as a hash table from then on.
+#[derive(Clone, Debug)]
+pub enum Hash {
+    Listpack(Listpack),
+    Table(ScanMap<Bytes>),
+}
+
+impl Default for Hash {
+    fn default() -> Self {
+        Hash::Listpack(Listpack::default())
+    }
+}
+
+impl Hash {
+    pub fn len(&self) -> usize {
+        match self {
+            Hash::Listpack(listpack) => listpack.len() / 2,
+            Hash::Table(hash) => hash.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Hash::Listpack(_) => "listpack",
+            Hash::Table(_) => "hashtable",
+        }
+    }
+
+    /// Every field with its value.
+    pub fn iter(&self) -> Box<dyn Iterator<Item = (&[u8], &[u8])> + '_> {
+        match self {
+            Hash::Listpack(listpack) => {
+                let mut elements = listpack.iter();
+                Box::new(std::iter::from_fn(move || {
+                    Some((elements.next()?, elements.next()?))
+                }))
+            }
+            Hash::Table(hash) => Box::new(
+                hash.iter()
+                    .map(|(field, value)| (field.as_ref(), value.as_ref())),
+            ),
+        }
+    }
+
+    /// One step of an `HSCAN`. A listpack is small enough to be returned
+    /// whole in the first step, as Redis does.
+    pub fn scan(&self, cursor: u64, options: &ScanOptions) -> (u64, Vec<(Bytes, Bytes)>) {
+        match self {
+            Hash::Listpack(_) => (
+                0,
+                self.iter()
+                    .filter(|(field, _)| options.matches(field))
+                    .map(|(field, value)| {
+                        (Bytes::copy_from_slice(field), Bytes::copy_from_slice(value))
+                    })
+                    .collect(),
+            ),
+            Hash::Table(hash) => {
+                let (cursor, pairs) = options.step(hash, cursor);
+                let pairs = pairs
+                    .into_iter()
+                    .map(|(field, value)| (field.clone(), value.clone()))
+                    .collect();
+                (cursor, pairs)
+            }
+        }
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<Bytes> {
+        match self {
+            Hash::Listpack(_) => self
+                .iter()
+                .find(|(current, _)| *current == field)
+                .map(|(_, value)| Bytes::copy_from_slice(value)),
+            Hash::Table(hash) => hash.get(field).cloned(),
+        }
+    }
+
+    /// Sets `field` to `value`, returning `true` if it was not present.
+    pub fn insert(&mut self, field: Bytes, value: Bytes, limits: &EncodingLimits) -> bool {
+        let position = self.position(&field);
+        if let Hash::Listpack(listpack) = self {
+            let fits = value.len() <= limits.hash_max_listpack_value;
+            match position {
+                Some(position) if fits => {
+                    listpack.replace(position * 2 + 1, &value);
+                    return false;
+                }
+                None if fits
+                    && field.len() <= limits.hash_max_listpack_value
+                    && listpack.len() / 2 < limits.hash_max_listpack_entries =>
+                {
+                    listpack.push(&field);
+                    listpack.push(&value);
+                    return true;
+                }
+                _ => self.convert(),
+            }
+        }
+        let Hash::Table(hash) = self else {
+            unreachable!("converted to a hash table")
+        };
+        hash.insert(field, value).is_none()
+    }
+
+    /// Removes `field`, returning whether it was present.
+    pub fn remove(&mut self, field: &[u8]) -> bool {
+        match self.position(field) {
+            Some(position) => {
+                if let Hash::Listpack(listpack) = self {
+                    listpack.drain(position * 2..position * 2 + 2);
+                }
+                true
+            }
+            None => match self {
+                Hash::Listpack(_) => false,
+                Hash::Table(hash) => hash.remove(field).is_some(),
+            },
+        }
+    }
+
+    /// The position of `field` among the pairs of a listpack, `None` when
+    /// missing or for a hash table.
+    fn position(&self, field: &[u8]) -> Option<usize> {
+        match self {
+            Hash::Listpack(_) => self.iter().position(|(current, _)| current == field),
+            Hash::Table(_) => None,
+        }
+    }
+
+    fn convert(&mut self) {
+        let hash = self
+            .iter()
+            .map(|(field, value)| (Bytes::copy_from_slice(field), Bytes::copy_from_slice(value)))
+            .collect();
+        *self = Hash::Table(hash);
+    }
+
+    /// Switches to a listpack if the hash is small enough for one, as a
+    /// hash is encoded when loaded.
+    pub fn compact(&mut self, limits: &EncodingLimits) {
+        if let Hash::Table(hash) = self
+            && hash.len() <= limits.hash_max_listpack_entries
+            && hash.iter().all(|(field, value)| {
+                field.len() <= limits.hash_max_listpack_value
+                    && value.len() <= limits.hash_max_listpack_value
+            })
+        {
+            *self = Hash::Listpack(
+                hash.iter()
+                    .flat_map(|(field, value)| [field.as_ref(), value.as_ref()])
+                    .collect(),
+            );
+        }
+    }
+}
+
+impl From<HashMap<Bytes, Bytes>> for Hash {
+    fn from(hash: HashMap<Bytes, Bytes>) -> Self {
+        Hash::Table(hash.into_iter().collect())
+    }
+}
@@ -0,0 +1,169 @@
+use std::{collections::VecDeque, ops::Range};
+
+use bytes::Bytes;
+
+use super::{ListEnd, encoding::EncodingLimits, listpack::Listpack};
+
+/// A list, kept as a listpack until it outgrows `list-max-listpack-size`
+/// and as a deque of elements from then on, like a Redis quicklist.
+#[derive(Clone, Debug)]
+pub enum List {
+    Listpack(Listpack),
+    Quicklist(VecDeque<Bytes>),
+}
+
+impl Default for List {
+    fn default() -> Self {
+        List::Listpack(Listpack::default())
+    }
+}
+
+impl List {
+    pub fn len(&self) -> usize {
+        match self {
+            List::Listpack(listpack) => listpack.len(),
+            List::Quicklist(list) => list.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            List::Listpack(_) => "listpack",
+            List::Quicklist(_) => "quicklist",
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &[u8]> + '_> {
+        match self {
+            List::Listpack(listpack) => Box::new(listpack.iter()),
+            List::Quicklist(list) => Box::new(list.iter().map(|element| element.as_ref())),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Bytes> {
+        match self {
+            List::Listpack(listpack) => listpack.get(index).map(Bytes::copy_from_slice),
+            List::Quicklist(list) => list.get(index).cloned(),
+        }
+    }
+
+    /// The elements from `start` to `stop`, both included and in range.
+    pub fn range(&self, start: usize, stop: usize) -> Vec<Bytes> {
+        match self {
+            List::Listpack(listpack) => listpack
+                .iter()
+                .skip(start)
+                .take(stop + 1 - start)
+                .map(Bytes::copy_from_slice)
+                .collect(),
+            List::Quicklist(list) => list.range(start..=stop).cloned().collect(),
+        }
+    }
+
+    pub fn push(&mut self, element: Bytes, end: ListEnd, limits: &EncodingLimits) {
+        let index = match end {
+            ListEnd::Left => 0,
+            ListEnd::Right => self.len(),
+        };
+        self.insert(index, element, limits);
+    }
+
+    /// Inserts `element` before the one at `index`, or last for `len`.
+    pub fn insert(&mut self, index: usize, element: Bytes, limits: &EncodingLimits) {
+        match self {
+            List::Listpack(listpack) => listpack.insert(index, &element),
+            List::Quicklist(list) => list.insert(index, element),
+        }
+        self.convert_if_too_big(limits);
+    }
+
+    pub fn set(&mut self, index: usize, element: Bytes, limits: &EncodingLimits) {
+        match self {
+            List::Listpack(listpack) => listpack.replace(index, &element),
+            List::Quicklist(list) => list[index] = element,
+        }
+        self.convert_if_too_big(limits);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        match self {
+            List::Listpack(listpack) => listpack.remove(index),
+            List::Quicklist(list) => {
+                list.remove(index);
+            }
+        }
+    }
+
+    /// Removes up to `count` elements from `end`, in the order they are
+    /// popped.
+    pub fn pop(&mut self, end: ListEnd, count: usize) -> Vec<Bytes> {
+        let count = count.min(self.len());
+        let range = match end {
+            ListEnd::Left => 0..count,
+            ListEnd::Right => self.len() - count..self.len(),
+        };
+        let mut popped = self.drain(range);
+        if end == ListEnd::Right {
+            popped.reverse();
+        }
+        popped
+    }
+
+    /// Keeps only the elements within `range`.
+    pub fn retain_range(&mut self, range: Range<usize>) {
+        let len = self.len();
+        if range.is_empty() {
+            self.drain(0..len);
+            return;
+        }
+        self.drain(range.end..len);
+        self.drain(0..range.start);
+    }
+
+    fn drain(&mut self, range: Range<usize>) -> Vec<Bytes> {
+        match self {
+            List::Listpack(listpack) => listpack.drain(range),
+            List::Quicklist(list) => list.drain(range).collect(),
+        }
+    }
+
+    fn convert_if_too_big(&mut self, limits: &EncodingLimits) {
+        if let List::Listpack(listpack) = self
+            && !fits_listpack(listpack.len(), listpack.size(), limits)
+        {
+            *self = List::Quicklist(listpack.iter().map(Bytes::copy_from_slice).collect());
+        }
+    }
+
+    /// Switches to a listpack if the list is small enough for one, as a
+    /// list is encoded when loaded.
+    pub fn compact(&mut self, limits: &EncodingLimits) {
+        if let List::Quicklist(list) = self {
+            let listpack: Listpack = list.iter().map(|element| element.as_ref()).collect();
+            if fits_listpack(listpack.len(), listpack.size(), limits) {
+                *self = List::Listpack(listpack);
+            }
+        }
+    }
+}
+
+/// Whether a listpack of `len` elements taking `size` bytes is within
+/// `list-max-listpack-size`, which limits the number of elements when
+/// positive and the size in bytes when negative, from 4 KB for -1 to 64 KB
+/// for -5.
+fn fits_listpack(len: usize, size: usize, limits: &EncodingLimits) -> bool {
+    match limits.list_max_listpack_size {
+        max_len if max_len > 0 => len <= max_len as usize,
+        max_size => size <= 4096 << (max_size.unsigned_abs().clamp(1, 5) - 1),
+    }
+}
+
+impl From<VecDeque<Bytes>> for List {
+    fn from(list: VecDeque<Bytes>) -> Self {
+        List::Quicklist(list)
+    }
+}
@@ -0,0 +1,142 @@
+use std::ops::Range;
+
+use bytes::Bytes;
+
+/// Elements packed one after the other in a single buffer, each behind its
+/// length as a LEB128 varint, like a Redis listpack. Small collections are
+/// kept this way instead of allocating every element on its own, at the
+/// price of finding elements with a scan from the start.
+#[derive(Clone, Debug, Default)]
+pub struct Listpack {
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl Listpack {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The size of the buffer in bytes.
+    pub fn size(&self) -> usize {
+        self.buf.len()
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            rest: &self.buf,
+            len: self.len,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&[u8]> {
+        self.iter().nth(index)
+    }
+
+    pub fn push(&mut self, element: &[u8]) {
+        self.insert(self.len, element);
+    }
+
+    /// Inserts `element` before the one at `index`, or last for `len`.
+    pub fn insert(&mut self, index: usize, element: &[u8]) {
+        let offset = self.offset(index);
+        let mut encoded = Vec::with_capacity(element.len() + 2);
+        write_varint(&mut encoded, element.len());
+        encoded.extend_from_slice(element);
+        self.buf.splice(offset..offset, encoded);
+        self.len += 1;
+    }
+
+    pub fn replace(&mut self, index: usize, element: &[u8]) {
+        self.remove(index);
+        self.insert(index, element);
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        self.drain(index..index + 1);
+    }
+
+    /// Removes the elements within `range`, returning them.
+    pub fn drain(&mut self, range: Range<usize>) -> Vec<Bytes> {
+        let start = self.offset(range.start);
+        let end = self.offset(range.end);
+        let drained: Vec<Bytes> = Iter {
+            rest: &self.buf[start..end],
+            len: range.len(),
+        }
+        .map(Bytes::copy_from_slice)
+        .collect();
+        self.buf.drain(start..end);
+        self.len -= drained.len();
+        drained
+    }
+
+    /// Where the element at `index` starts in the buffer, the end of the
+    /// buffer for `len`.
+    fn offset(&self, index: usize) -> usize {
+        assert!(index <= self.len, "listpack index out of range");
+        let mut iter = self.iter();
+        for _ in 0..index {
+            iter.next();
+        }
+        self.buf.len() - iter.rest.len()
+    }
+}
+
+impl<'a> FromIterator<&'a [u8]> for Listpack {
+    fn from_iter<I: IntoIterator<Item = &'a [u8]>>(elements: I) -> Self {
+        let mut listpack = Listpack::default();
+        for element in elements {
+            write_varint(&mut listpack.buf, element.len());
+            listpack.buf.extend_from_slice(element);
+            listpack.len += 1;
+        }
+        listpack
+    }
+}
+
+pub struct Iter<'a> {
+    rest: &'a [u8],
+    len: usize,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        if self.len == 0 {
+            return None;
+        }
+        let (length, header) = read_varint(self.rest);
+        let (element, rest) = self.rest[header..].split_at(length);
+        self.rest = rest;
+        self.len -= 1;
+        Some(element)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+/// Reads a varint, returning it with the number of bytes it took.
+fn read_varint(buf: &[u8]) -> (usize, usize) {
+    let mut value = 0;
+    for (index, byte) in buf.iter().enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            return (value, index + 1);
+        }
+    }
+    unreachable!("listpack varint runs past the buffer")
+}
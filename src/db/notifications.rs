@@ -0,0 +1,142 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+const KEYSPACE: u32 = 1 << 0;
+const KEYEVENT: u32 = 1 << 1;
+const GENERIC: u32 = 1 << 2;
+const STRING: u32 = 1 << 3;
+const LIST: u32 = 1 << 4;
+const SET: u32 = 1 << 5;
+const HASH: u32 = 1 << 6;
+const ZSET: u32 = 1 << 7;
+const EXPIRED: u32 = 1 << 8;
+const EVICTED: u32 = 1 << 9;
+const STREAM: u32 = 1 << 10;
+const KEY_MISS: u32 = 1 << 11;
+const NEW: u32 = 1 << 12;
+const ALL: u32 = GENERIC | STRING | LIST | SET | HASH | ZSET | EXPIRED | EVICTED | STREAM;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventClass {
+    Generic,
+    String,
+    List,
+    Set,
+    Hash,
+    Zset,
+    Expired,
+    Evicted,
+    Stream,
+    KeyMiss,
+    New,
+}
+
+impl EventClass {
+    fn flag(self) -> u32 {
+        match self {
+            EventClass::Generic => GENERIC,
+            EventClass::String => STRING,
+            EventClass::List => LIST,
+            EventClass::Set => SET,
+            EventClass::Hash => HASH,
+            EventClass::Zset => ZSET,
+            EventClass::Expired => EXPIRED,
+            EventClass::Evicted => EVICTED,
+            EventClass::Stream => STREAM,
+            EventClass::KeyMiss => KEY_MISS,
+            EventClass::New => NEW,
+        }
+    }
+}
+
+/// A change to a key, as published on the `__keyspace@0__` and
+/// `__keyevent@0__` channels, for embedders to follow with
+/// `Db::subscribe_keyspace_events` whatever `notify-keyspace-events` says.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyspaceEvent {
+    pub class: EventClass,
+    /// The name Redis gives the event, such as `set`, `del`, `expired` or
+    /// `lpush`.
+    pub event: &'static str,
+    pub key: Bytes,
+}
+
+/// The `notify-keyspace-events` setting, parsed from the standard
+/// flag string (e.g. `KEA`, `Ex`, `Kl$`).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KeyspaceEvents(u32);
+
+impl KeyspaceEvents {
+    pub fn parse(flags: &str) -> Option<Self> {
+        let mut bits = 0;
+        for c in flags.chars() {
+            bits |= match c {
+                'A' => ALL,
+                'g' => GENERIC,
+                '$' => STRING,
+                'l' => LIST,
+                's' => SET,
+                'h' => HASH,
+                'z' => ZSET,
+                'x' => EXPIRED,
+                'e' => EVICTED,
+                't' => STREAM,
+                'm' => KEY_MISS,
+                'n' => NEW,
+                'K' => KEYSPACE,
+                'E' => KEYEVENT,
+                _ => return None,
+            };
+        }
+        Some(Self(bits))
+    }
+
+    pub fn is_enabled(&self, class: EventClass) -> bool {
+        self.0 & (KEYSPACE | KEYEVENT) != 0 && self.0 & class.flag() != 0
+    }
+
+    pub fn keyspace(&self) -> bool {
+        self.0 & KEYSPACE != 0
+    }
+
+    pub fn keyevent(&self) -> bool {
+        self.0 & KEYEVENT != 0
+    }
+}
+
+impl fmt::Display for KeyspaceEvents {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 & ALL == ALL {
+            write!(f, "A")?;
+        } else {
+            for (flag, c) in [
+                (GENERIC, 'g'),
+                (STRING, '$'),
+                (LIST, 'l'),
+                (SET, 's'),
+                (HASH, 'h'),
+                (ZSET, 'z'),
+                (EXPIRED, 'x'),
+                (EVICTED, 'e'),
+                (STREAM, 't'),
+            ] {
+                if self.0 & flag != 0 {
+                    write!(f, "{c}")?;
+                }
+            }
+        }
+        for (flag, c) in [
+            (KEYSPACE, 'K'),
+            (KEYEVENT, 'E'),
+            (KEY_MISS, 'm'),
+            (NEW, 'n'),
+        ] {
+            if self.0 & flag != 0 {
+                write!(f, "{c}")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+use std::{collections::HashMap, sync::Arc};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, error::SendError};
+
+use crate::{clients::ClientInfo, glob::glob_match, resp::RespValue};
+
+/// Queues out-of-band frames for a connection, counting them against the
+/// output buffer limits of its client.
+#[derive(Clone, Debug)]
+pub struct MessageSender {
+    sender: mpsc::UnboundedSender<RespValue>,
+    client: Arc<ClientInfo>,
+}
+
+impl MessageSender {
+    pub fn new(sender: mpsc::UnboundedSender<RespValue>, client: Arc<ClientInfo>) -> Self {
+        Self { sender, client }
+    }
+
+    /// The client the frames are for.
+    pub fn client(&self) -> &Arc<ClientInfo> {
+        &self.client
+    }
+
+    /// Queues `frame`, failing once the connection is gone or its client
+    /// was killed, for one because it stopped reading what it is sent.
+    pub fn send(&self, frame: RespValue) -> Result<(), SendError<RespValue>> {
+        if self.client.is_killed() {
+            return Err(SendError(frame));
+        }
+        let size = frame.encoded_size();
+        self.sender.send(frame)?;
+        self.client.output_queued(size);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct PubSub {
+    channels: HashMap<Bytes, HashMap<u64, MessageSender>>,
+    patterns: HashMap<Bytes, HashMap<u64, MessageSender>>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&mut self, channel: &[u8], client_id: u64, sender: MessageSender) {
+        self.channels
+            .entry(Bytes::copy_from_slice(channel))
+            .or_default()
+            .insert(client_id, sender);
+    }
+
+    pub fn unsubscribe(&mut self, channel: &[u8], client_id: u64) {
+        if let Some(subscribers) = self.channels.get_mut(channel) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                self.channels.remove(channel);
+            }
+        }
+    }
+
+    pub fn psubscribe(&mut self, pattern: &[u8], client_id: u64, sender: MessageSender) {
+        self.patterns
+            .entry(Bytes::copy_from_slice(pattern))
+            .or_default()
+            .insert(client_id, sender);
+    }
+
+    pub fn punsubscribe(&mut self, pattern: &[u8], client_id: u64) {
+        if let Some(subscribers) = self.patterns.get_mut(pattern) {
+            subscribers.remove(&client_id);
+            if subscribers.is_empty() {
+                self.patterns.remove(pattern);
+            }
+        }
+    }
+
+    /// Delivers `message` to every channel and pattern subscriber, returning
+    /// how many receivers got it.
+    pub fn publish(&self, channel: &[u8], message: &[u8]) -> u64 {
+        let mut receivers = 0;
+
+        if let Some(subscribers) = self.channels.get(channel) {
+            for sender in subscribers.values() {
+                let frame = RespValue::Push(vec![
+                    RespValue::BulkString("message".into()),
+                    RespValue::BulkString(Bytes::copy_from_slice(channel)),
+                    RespValue::BulkString(Bytes::copy_from_slice(message)),
+                ]);
+                if sender.send(frame).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        for (pattern, subscribers) in self.patterns.iter() {
+            if !glob_match(pattern, channel) {
+                continue;
+            }
+            for sender in subscribers.values() {
+                let frame = RespValue::Push(vec![
+                    RespValue::BulkString("pmessage".into()),
+                    RespValue::BulkString(pattern.clone()),
+                    RespValue::BulkString(Bytes::copy_from_slice(channel)),
+                    RespValue::BulkString(Bytes::copy_from_slice(message)),
+                ]);
+                if sender.send(frame).is_ok() {
+                    receivers += 1;
+                }
+            }
+        }
+
+        receivers
+    }
+
+    pub fn channels(&self, pattern: Option<&[u8]>) -> Vec<Bytes> {
+        self.channels
+            .keys()
+            .filter(|channel| pattern.is_none_or(|pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn numsub(&self, channel: &[u8]) -> u64 {
+        self.channels
+            .get(channel)
+            .map_or(0, |subscribers| subscribers.len() as u64)
+    }
+
+    pub fn numpat(&self) -> u64 {
+        self.patterns.len() as u64
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeSet, HashMap, hash_map::Entry},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Deref,
+};
+
+use bytes::Bytes;
+
+use crate::glob::glob_match;
+
+/// The `MATCH` and `COUNT` options shared by the `SCAN` family.
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            pattern: None,
+            count: 10,
+        }
+    }
+}
+
+impl ScanOptions {
+    /// Picks about `count` entries of `map` for one iteration step, then
+    /// drops those whose name does not match the pattern, so a step may
+    /// return fewer entries than `count`. Returns the next cursor, `0` once
+    /// the iteration is complete.
+    pub fn step<'a, V>(&self, map: &'a ScanMap<V>, cursor: u64) -> (u64, Vec<(&'a Bytes, &'a V)>) {
+        let (cursor, mut entries) = map.scan(cursor, self.count);
+        entries.retain(|(name, _)| self.matches(name));
+        (cursor, entries)
+    }
+
+    /// Whether `name` matches the pattern, if there is one.
+    pub fn matches(&self, name: &[u8]) -> bool {
+        self.pattern
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, name))
+    }
+}
+
+/// A hash map that also keeps its keys in the order of a hash of their name,
+/// for the `SCAN` family. The cursor is the hash to resume from, so a step
+/// only visits the entries it returns, and an entry present for the whole
+/// iteration is returned no matter what gets inserted or deleted in between.
+///
+/// Lookups go through `Deref`, changes through the methods below, which keep
+/// the order in step with the map.
+#[derive(Clone, Debug)]
+pub struct ScanMap<V> {
+    map: HashMap<Bytes, V>,
+    order: BTreeSet<(u64, Bytes)>,
+}
+
+impl<V> Default for ScanMap<V> {
+    fn default() -> Self {
+        Self {
+            map: HashMap::new(),
+            order: BTreeSet::new(),
+        }
+    }
+}
+
+impl<V> Deref for ScanMap<V> {
+    type Target = HashMap<Bytes, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+impl<V> ScanMap<V> {
+    pub fn insert(&mut self, key: Bytes, value: V) -> Option<V> {
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => Some(entry.insert(value)),
+            Entry::Vacant(entry) => {
+                self.order
+                    .insert((cursor_hash(entry.key()), entry.key().clone()));
+                entry.insert(value);
+                None
+            }
+        }
+    }
+
+    /// The value at `key`, inserting `default()` first if it is missing.
+    pub fn get_or_insert_with(&mut self, key: &[u8], default: impl FnOnce() -> V) -> &mut V {
+        if !self.map.contains_key(key) {
+            self.insert(Bytes::copy_from_slice(key), default());
+        }
+        self.map.get_mut(key).expect("inserted above")
+    }
+
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut V> {
+        self.map.get_mut(key)
+    }
+
+    pub fn remove(&mut self, key: &[u8]) -> Option<V> {
+        self.remove_entry(key).map(|(_, value)| value)
+    }
+
+    pub fn remove_entry(&mut self, key: &[u8]) -> Option<(Bytes, V)> {
+        let (key, value) = self.map.remove_entry(key)?;
+        self.order.remove(&(cursor_hash(&key), key.clone()));
+        Some((key, value))
+    }
+
+    /// Up to `count` entries from `cursor` on, and more when several share
+    /// the hash of the last one, as the cursor can not tell them apart.
+    /// Returns the hash of the first entry left for the next step, or `0`
+    /// when there is none.
+    fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &V)>) {
+        let mut entries = vec![];
+        let mut last = None;
+        for (hash, key) in self.order.range((cursor, Bytes::new())..) {
+            if entries.len() >= count.max(1) && last != Some(*hash) {
+                return (*hash, entries);
+            }
+            entries.push((key, &self.map[key]));
+            last = Some(*hash);
+        }
+        (0, entries)
+    }
+}
+
+impl<V> FromIterator<(Bytes, V)> for ScanMap<V> {
+    fn from_iter<I: IntoIterator<Item = (Bytes, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+fn cursor_hash(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+
+use bytes::Bytes;
+
+use super::{
+    encoding::EncodingLimits,
+    listpack::Listpack,
+    parse_integer,
+    scan::{ScanMap, ScanOptions},
+};
+
+/// A set, kept as a sorted array of integers while every member is one and
+/// there are at most `set-max-intset-entries`, then as a listpack while
+/// within `set-max-listpack-entries` and `set-max-listpack-value`, and as a
+/// hash table from then on.
+#[derive(Clone, Debug)]
+pub enum Set {
+    Intset(Vec<i64>),
+    Listpack(Listpack),
+    Table(ScanMap<()>),
+}
+
+impl Default for Set {
+    fn default() -> Self {
+        Set::Intset(vec![])
+    }
+}
+
+impl Set {
+    pub fn len(&self) -> usize {
+        match self {
+            Set::Intset(integers) => integers.len(),
+            Set::Listpack(listpack) => listpack.len(),
+            Set::Table(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn encoding(&self) -> &'static str {
+        match self {
+            Set::Intset(_) => "intset",
+            Set::Listpack(_) => "listpack",
+            Set::Table(_) => "hashtable",
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = Bytes> + '_> {
+        match self {
+            Set::Intset(integers) => {
+                Box::new(integers.iter().map(|integer| integer.to_string().into()))
+            }
+            Set::Listpack(listpack) => Box::new(listpack.iter().map(Bytes::copy_from_slice)),
+            Set::Table(set) => Box::new(set.keys().cloned()),
+        }
+    }
+
+    /// One step of an `SSCAN`. An intset or a listpack is small enough to
+    /// be returned whole in the first step, as Redis does.
+    pub fn scan(&self, cursor: u64, options: &ScanOptions) -> (u64, Vec<Bytes>) {
+        match self {
+            Set::Table(set) => {
+                let (cursor, members) = options.step(set, cursor);
+                let members = members
+                    .into_iter()
+                    .map(|(member, _)| member.clone())
+                    .collect();
+                (cursor, members)
+            }
+            _ => (
+                0,
+                self.iter()
+                    .filter(|member| options.matches(member))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            Set::Intset(integers) => parse_integer(member)
+                .is_some_and(|integer| integers.binary_search(&integer).is_ok()),
+            Set::Listpack(listpack) => listpack.iter().any(|current| current == member),
+            Set::Table(set) => set.contains_key(member),
+        }
+    }
+
+    /// Adds `member`, returning `true` if it was not present.
+    pub fn insert(&mut self, member: Bytes, limits: &EncodingLimits) -> bool {
+        if self.contains(&member) {
+            return false;
+        }
+        match self {
+            Set::Intset(integers) => match parse_integer(&member) {
+                Some(integer) if integers.len() < limits.set_max_intset_entries => {
+                    let index = integers.partition_point(|current| *current < integer);
+                    integers.insert(index, integer);
+                    return true;
+                }
+                Some(_) => self.convert(),
+                None => {
+                    // Like Redis, an intset gaining a member that is no
+                    // integer becomes a listpack if it fits in one.
+                    let members: Vec<String> = integers.iter().map(i64::to_string).collect();
+                    if members.len() < limits.set_max_listpack_entries
+                        && member.len() <= limits.set_max_listpack_value
+                        && members
+                            .iter()
+                            .all(|current| current.len() <= limits.set_max_listpack_value)
+                    {
+                        let mut listpack: Listpack =
+                            members.iter().map(|current| current.as_bytes()).collect();
+                        listpack.push(&member);
+                        *self = Set::Listpack(listpack);
+                        return true;
+                    }
+                    self.convert();
+                }
+            },
+            Set::Listpack(listpack) => {
+                if listpack.len() < limits.set_max_listpack_entries
+                    && member.len() <= limits.set_max_listpack_value
+                {
+                    listpack.push(&member);
+                    return true;
+                }
+                self.convert();
+            }
+            Set::Table(_) => {}
+        }
+        let Set::Table(set) = self else {
+            unreachable!("converted to a hash table")
+        };
+        set.insert(member, ()).is_none()
+    }
+
+    /// Removes `member`, returning whether it was present.
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            Set::Intset(integers) => {
+                let Some(index) =
+                    parse_integer(member).and_then(|integer| integers.binary_search(&integer).ok())
+                else {
+                    return false;
+                };
+                integers.remove(index);
+                true
+            }
+            Set::Listpack(listpack) => {
+                let Some(index) = listpack.iter().position(|current| current == member) else {
+                    return false;
+                };
+                listpack.remove(index);
+                true
+            }
+            Set::Table(set) => set.remove(member).is_some(),
+        }
+    }
+
+    fn convert(&mut self) {
+        *self = Set::Table(self.iter().map(|member| (member, ())).collect());
+    }
+
+    /// Switches to an intset or a listpack if the set is small enough for
+    /// one, as a set is encoded when loaded.
+    pub fn compact(&mut self, limits: &EncodingLimits) {
+        let Set::Table(set) = self else {
+            return;
+        };
+        if set.len() <= limits.set_max_intset_entries
+            && let Some(mut integers) = set
+                .keys()
+                .map(|member| parse_integer(member))
+                .collect::<Option<Vec<_>>>()
+        {
+            integers.sort_unstable();
+            *self = Set::Intset(integers);
+        } else if set.len() <= limits.set_max_listpack_entries
+            && set
+                .keys()
+                .all(|member| member.len() <= limits.set_max_listpack_value)
+        {
+            *self = Set::Listpack(set.keys().map(|member| member.as_ref()).collect());
+        }
+    }
+}
+
+impl From<HashSet<Bytes>> for Set {
+    fn from(set: HashSet<Bytes>) -> Self {
+        Set::Table(set.into_iter().map(|member| (member, ())).collect())
+    }
+}
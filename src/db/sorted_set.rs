@@ -0,0 +1,263 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use bytes::Bytes;
+
+use super::scan::{ScanMap, ScanOptions};
+
+/// A score with the total ordering sorted sets need (`f64` is only `PartialOrd`).
+#[derive(Clone, Copy, Debug)]
+pub struct Score(pub f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// The flags of `ZADD`. `NX` and `XX` only add new members or only
+/// update existing ones, `GT` and `LT` only update a score upwards or
+/// downwards, `CH` also counts updated members and `INCR` adds to the
+/// current score instead of replacing it.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ZaddOptions {
+    pub nx: bool,
+    pub xx: bool,
+    pub gt: bool,
+    pub lt: bool,
+    pub ch: bool,
+    pub incr: bool,
+}
+
+impl ZaddOptions {
+    /// Whether a member whose score is `current` (`None` when absent) may
+    /// be given the score `new`.
+    pub fn allows(self, current: Option<f64>, new: f64) -> bool {
+        match current {
+            None => !self.xx,
+            Some(_) if self.nx => false,
+            Some(current) if self.gt && new <= current => false,
+            Some(current) if self.lt && new >= current => false,
+            Some(_) => true,
+        }
+    }
+}
+
+/// The sorted set operations, which accept plain sets as inputs whose
+/// members all score 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZsetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+
+/// How the scores a member has in several inputs are combined.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Aggregate {
+    #[default]
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    pub fn combine(self, a: f64, b: f64) -> f64 {
+        match self {
+            // Opposite infinities add up to 0, as in Redis.
+            Aggregate::Sum => zero_if_nan(a + b),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// A score scaled by its input's `WEIGHTS` entry.
+pub fn weighted(score: f64, weight: f64) -> f64 {
+    zero_if_nan(score * weight)
+}
+
+fn zero_if_nan(score: f64) -> f64 {
+    if score.is_nan() { 0.0 } else { score }
+}
+
+/// One end of a score range, excluded when written with a `(` prefix.
+#[derive(Clone, Copy, Debug)]
+pub struct ScoreBound {
+    pub score: f64,
+    pub exclusive: bool,
+}
+
+/// One end of a lexicographic range: `-` and `+` stand for the smallest
+/// and greatest strings, `[` and `(` prefix included and excluded members.
+#[derive(Clone, Debug)]
+pub enum LexBound {
+    Min,
+    Max,
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+}
+
+/// The members a range command selects: by rank, where negative ranks
+/// count from the end, by score or by member.
+#[derive(Clone, Debug)]
+pub enum ZsetRange {
+    Rank(i64, i64),
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+impl ScoreBound {
+    fn below(self, score: f64) -> bool {
+        if self.exclusive {
+            self.score < score
+        } else {
+            self.score <= score
+        }
+    }
+
+    fn above(self, score: f64) -> bool {
+        if self.exclusive {
+            self.score > score
+        } else {
+            self.score >= score
+        }
+    }
+}
+
+impl LexBound {
+    fn below(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Inclusive(bound) => bound.as_ref() <= member,
+            LexBound::Exclusive(bound) => bound.as_ref() < member,
+        }
+    }
+
+    fn above(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Inclusive(bound) => bound.as_ref() >= member,
+            LexBound::Exclusive(bound) => bound.as_ref() > member,
+        }
+    }
+}
+
+/// Members ordered by score, then lexicographically, like a Redis zset.
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: ScanMap<f64>,
+    ordered: BTreeSet<(Score, Bytes)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the score of `member`, returning `true` if it was not present.
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        let is_new = match self.scores.insert(member.clone(), score) {
+            Some(previous) => {
+                self.ordered.remove(&(Score(previous), member.clone()));
+                false
+            }
+            None => true,
+        };
+        self.ordered.insert((Score(score), member));
+        is_new
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.ordered
+                    .remove(&(Score(score), Bytes::copy_from_slice(member)));
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// One step of a `ZSCAN`.
+    pub fn scan(&self, cursor: u64, options: &ScanOptions) -> (u64, Vec<(Bytes, f64)>) {
+        let (cursor, members) = options.step(&self.scores, cursor);
+        let members = members
+            .into_iter()
+            .map(|(member, score)| (member.clone(), *score))
+            .collect();
+        (cursor, members)
+    }
+
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.ordered
+            .iter()
+            .position(|(s, m)| *s == Score(score) && m.as_ref() == member)
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Members within `range`, in ascending score order.
+    pub fn range(&self, range: &ZsetRange) -> Vec<(Bytes, f64)> {
+        let members = self.iter().map(|(member, score)| (member.clone(), score));
+        match range {
+            ZsetRange::Rank(start, stop) => {
+                let length = self.len() as i64;
+                let start = if *start < 0 { length + start } else { *start }.max(0);
+                let stop = if *stop < 0 { length + stop } else { *stop }.min(length - 1);
+                if start > stop {
+                    return vec![];
+                }
+                members
+                    .skip(start as usize)
+                    .take((stop - start + 1) as usize)
+                    .collect()
+            }
+            ZsetRange::Score(min, max) => members
+                .filter(|(_, score)| min.below(*score) && max.above(*score))
+                .collect(),
+            ZsetRange::Lex(min, max) => members
+                .filter(|(member, _)| min.below(member) && max.above(member))
+                .collect(),
+        }
+    }
+
+    /// Members in ascending score order.
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&Bytes, f64)> {
+        self.ordered.iter().map(|(score, member)| (member, score.0))
+    }
+}
+
+pub fn format_score(score: f64) -> String {
+    if score.is_infinite() {
+        if score > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        score.to_string()
+    }
+}
@@ -1,13 +1,216 @@
 use crate::resp::RespValue;
-use std::collections::HashMap;
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    str::FromStr,
+};
 
-#[derive(Clone, Debug)]
-pub struct StreamList(pub Vec<StreamItem>);
+/// A stream entry ID, `ms-seq`, ordered by its milliseconds and then its
+/// sequence number.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId::new(0, 0);
+    pub const MAX: StreamId = StreamId::new(u64::MAX, u64::MAX);
+
+    pub const fn new(ms: u64, seq: u64) -> Self {
+        Self { ms, seq }
+    }
+
+    /// Parses an ID that may leave out its sequence number, as the bounds
+    /// of `XRANGE` may, in which case it is `default_seq`.
+    pub fn parse_bound(s: &str, default_seq: u64) -> Result<Self> {
+        if s.contains('-') {
+            return s.parse();
+        }
+        s.parse()
+            .map(|ms| StreamId::new(ms, default_seq))
+            .map_err(|_| invalid_id())
+    }
+}
+
+fn invalid_id() -> anyhow::Error {
+    anyhow!("ERR Invalid stream ID specified as stream command argument")
+}
+
+impl FromStr for StreamId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split_once('-')
+            .and_then(|(ms, seq)| Some(StreamId::new(ms.parse().ok()?, seq.parse().ok()?)))
+            .ok_or_else(invalid_id)
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// Entries per chunk, like Redis' `stream-node-max-entries`.
+const CHUNK_ENTRIES: usize = 100;
+
+type Entry = (StreamId, HashMap<Bytes, Bytes>);
+
+/// The entries of a stream in chunks of up to `CHUNK_ENTRIES`, keyed by the
+/// ID of the entry each chunk started with, like the radix tree of
+/// listpacks of Redis. A range starts with a lookup of its chunk in the
+/// tree, appending only ever touches the last chunk, and trimming drops
+/// whole chunks from the front.
+#[derive(Clone, Debug, Default)]
+pub struct StreamList {
+    chunks: BTreeMap<StreamId, Vec<Entry>>,
+    len: usize,
+    /// The ID of the newest entry ever added, which new entries must be
+    /// greater than even once it was deleted.
+    last_id: Option<StreamId>,
+}
 
 #[derive(Clone, Debug)]
 pub struct StreamItem {
-    pub id: String,
-    pub values: HashMap<String, String>,
+    pub id: StreamId,
+    pub values: HashMap<Bytes, Bytes>,
+}
+
+/// How `XTRIM` trims a stream: down to a length or up to an ID.
+#[derive(Clone, Copy, Debug)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+impl StreamList {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Appends an entry, whose ID must be greater than `last_id`.
+    pub fn push(&mut self, id: StreamId, values: HashMap<Bytes, Bytes>) {
+        match self.chunks.last_entry() {
+            Some(mut chunk) if chunk.get().len() < CHUNK_ENTRIES => {
+                chunk.get_mut().push((id, values))
+            }
+            _ => {
+                self.chunks.insert(id, vec![(id, values)]);
+            }
+        }
+        self.len += 1;
+        self.last_id = Some(id);
+    }
+
+    pub fn last_id(&self) -> Option<StreamId> {
+        self.last_id
+    }
+
+    /// The entries from `start` to `end`, both included, whether or not
+    /// entries with those IDs exist.
+    pub fn range(&self, start: StreamId, end: StreamId) -> Vec<StreamItem> {
+        self.entries_from(start)
+            .take_while(|(id, _)| *id <= end)
+            .map(item)
+            .collect()
+    }
+
+    /// The entries with an ID greater than `id`, as `XREAD` returns them.
+    pub fn after(&self, id: StreamId) -> Vec<StreamItem> {
+        self.entries_from(id)
+            .skip_while(|(entry_id, _)| *entry_id == id)
+            .map(item)
+            .collect()
+    }
+
+    /// Every entry, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (StreamId, &HashMap<Bytes, Bytes>)> {
+        self.chunks
+            .values()
+            .flatten()
+            .map(|(id, values)| (*id, values))
+    }
+
+    /// Removes the entry `id`, returning whether there was one.
+    pub fn delete(&mut self, id: StreamId) -> bool {
+        let Some((&chunk_id, chunk)) = self.chunks.range_mut(..=id).next_back() else {
+            return false;
+        };
+        let Ok(index) = chunk.binary_search_by_key(&id, |(entry_id, _)| *entry_id) else {
+            return false;
+        };
+        chunk.remove(index);
+        if chunk.is_empty() {
+            self.chunks.remove(&chunk_id);
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Removes the oldest entries as `strategy` asks, returning how many.
+    /// When `approximate`, only whole chunks are dropped, so a few more
+    /// entries than asked for may be left.
+    pub fn trim(&mut self, strategy: TrimStrategy, approximate: bool) -> usize {
+        let mut removed = 0;
+        while let Some(mut chunk) = self.chunks.first_entry() {
+            let entries = chunk.get();
+            // How many of the chunk's entries go.
+            let count = match strategy {
+                TrimStrategy::MaxLen(max_len) => self.len.saturating_sub(max_len),
+                TrimStrategy::MinId(min_id) => {
+                    entries.partition_point(|(entry_id, _)| *entry_id < min_id)
+                }
+            };
+            if count >= entries.len() {
+                self.len -= entries.len();
+                removed += entries.len();
+                chunk.remove();
+                continue;
+            }
+            if !approximate && count > 0 {
+                chunk.get_mut().drain(..count);
+                self.len -= count;
+                removed += count;
+            }
+            break;
+        }
+        removed
+    }
+
+    /// The entries from the first one not older than `start` on.
+    fn entries_from(&self, start: StreamId) -> impl Iterator<Item = &Entry> {
+        let first_chunk = self
+            .chunks
+            .range(..=start)
+            .next_back()
+            .map_or(StreamId::MIN, |(chunk_id, _)| *chunk_id);
+        self.chunks
+            .range(first_chunk..)
+            .flat_map(|(_, chunk)| chunk)
+            .skip_while(move |(id, _)| *id < start)
+    }
+}
+
+impl FromIterator<Entry> for StreamList {
+    /// Builds a stream from entries given in ID order.
+    fn from_iter<I: IntoIterator<Item = Entry>>(entries: I) -> Self {
+        let mut stream = StreamList::default();
+        for (id, values) in entries {
+            stream.push(id, values);
+        }
+        stream
+    }
+}
+
+fn item((id, values): &Entry) -> StreamItem {
+    StreamItem {
+        id: *id,
+        values: values.clone(),
+    }
 }
 
 impl StreamItem {
@@ -24,7 +227,7 @@ impl StreamItem {
             .collect();
 
         RespValue::Array(vec![
-            RespValue::BulkString(self.id.clone()),
+            RespValue::BulkString(self.id.to_string().into()),
             RespValue::Array(values_array_items),
         ])
     }
@@ -0,0 +1,144 @@
+//! Typed access to the keyspace. Commands working on one type of value look
+//! their keys up as that type, so that a key holding another type fails the
+//! same way for all of them, with `WRONGTYPE`, as in Redis.
+
+use bytes::Bytes;
+
+use super::{
+    Db, DbValue, error::DbError, hash::Hash, list::List, set::Set, sorted_set::SortedSet,
+    stream_types::StreamList,
+};
+
+/// A type of value a key can hold.
+pub trait ValueType: Sized {
+    fn of(value: &DbValue) -> Option<&Self>;
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self>;
+}
+
+impl ValueType for Bytes {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::Atom(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::Atom(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for List {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for StreamList {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for Hash {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for Set {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+}
+
+impl ValueType for SortedSet {
+    fn of(value: &DbValue) -> Option<&Self> {
+        match value {
+            DbValue::SortedSet(sorted_set) => Some(sorted_set),
+            _ => None,
+        }
+    }
+
+    fn of_mut(value: &mut DbValue) -> Option<&mut Self> {
+        match value {
+            DbValue::SortedSet(sorted_set) => Some(sorted_set),
+            _ => None,
+        }
+    }
+}
+
+impl Db {
+    /// The value at `key` as a `T`, for a command reading it: `None` when
+    /// the key is missing, `WRONGTYPE` when it holds another type.
+    pub fn lookup_as<T: ValueType>(&self, key: &[u8]) -> Result<Option<&T>, DbError> {
+        self.lookup(key).map(typed).transpose()
+    }
+
+    /// The value at `key` as a `T`, for a command changing it, which has
+    /// already accessed the key.
+    pub fn get_as<T: ValueType>(&self, key: &[u8]) -> Result<Option<&T>, DbError> {
+        self.values.get(key).map(typed).transpose()
+    }
+
+    /// `get_as`, to change the value in place.
+    pub fn get_mut_as<T: ValueType>(&mut self, key: &[u8]) -> Result<Option<&mut T>, DbError> {
+        self.values
+            .get_mut(key)
+            .map(|value| T::of_mut(value).ok_or(DbError::WrongType))
+            .transpose()
+    }
+
+    /// Fails with `WRONGTYPE` unless `key` is missing or holds a `T`, for
+    /// commands that create the key when it is missing.
+    pub fn check_type<T: ValueType>(&self, key: &[u8]) -> Result<(), DbError> {
+        self.get_as::<T>(key).map(|_| ())
+    }
+}
+
+fn typed<T: ValueType>(value: &DbValue) -> Result<&T, DbError> {
+    T::of(value).ok_or(DbError::WrongType)
+}
@@ -0,0 +1,101 @@
+/// Glob-style matching with the same rules as Redis' `stringmatchlen`:
+/// `*`, `?`, `[abc]`, `[^abc]`, `[a-z]` and `\` to escape the next character.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    match_bytes(pattern, string)
+}
+
+fn match_bytes(mut pattern: &[u8], mut string: &[u8]) -> bool {
+    while !pattern.is_empty() {
+        match pattern[0] {
+            b'*' => {
+                while pattern.len() > 1 && pattern[1] == b'*' {
+                    pattern = &pattern[1..];
+                }
+                if pattern.len() == 1 {
+                    return true;
+                }
+                for i in 0..=string.len() {
+                    if match_bytes(&pattern[1..], &string[i..]) {
+                        return true;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if string.is_empty() {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            b'[' => {
+                if string.is_empty() {
+                    return false;
+                }
+                pattern = &pattern[1..];
+                let negate = pattern.first() == Some(&b'^');
+                if negate {
+                    pattern = &pattern[1..];
+                }
+
+                let mut matched = false;
+                loop {
+                    match pattern {
+                        [] => break,
+                        [b']', ..] => break,
+                        [b'\\', escaped, ..] => {
+                            if *escaped == string[0] {
+                                matched = true;
+                            }
+                            pattern = &pattern[2..];
+                        }
+                        [start, b'-', end, ..] if *end != b']' => {
+                            let (low, high) = if start <= end {
+                                (*start, *end)
+                            } else {
+                                (*end, *start)
+                            };
+                            if (low..=high).contains(&string[0]) {
+                                matched = true;
+                            }
+                            pattern = &pattern[3..];
+                        }
+                        [c, ..] => {
+                            if *c == string[0] {
+                                matched = true;
+                            }
+                            pattern = &pattern[1..];
+                        }
+                    }
+                }
+
+                if matched == negate {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            b'\\' if pattern.len() >= 2 => {
+                pattern = &pattern[1..];
+                if string.first() != Some(&pattern[0]) {
+                    return false;
+                }
+                string = &string[1..];
+            }
+            c => {
+                if string.first() != Some(&c) {
+                    return false;
+                }
+                string = &string[1..];
+            }
+        }
+        if !pattern.is_empty() {
+            pattern = &pattern[1..];
+        }
+        if string.is_empty() {
+            while pattern.first() == Some(&b'*') {
+                pattern = &pattern[1..];
+            }
+            break;
+        }
+    }
+    pattern.is_empty() && string.is_empty()
+}
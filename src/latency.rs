@@ -0,0 +1,159 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::persistence::unix_time_millis;
+
+/// Samples kept per event, as in Redis.
+const HISTORY_LEN: usize = 160;
+
+/// The spikes of one kind of event: up to `HISTORY_LEN` samples of UNIX
+/// seconds and milliseconds, oldest first, and the longest one ever seen.
+#[derive(Debug, Default)]
+struct EventHistory {
+    samples: VecDeque<(u64, u64)>,
+    max: u64,
+}
+
+/// How long the runs of one command took, counted in buckets of powers of
+/// two microseconds.
+#[derive(Debug, Default)]
+struct Histogram {
+    calls: u64,
+    /// How many runs took up to `1 << index` microseconds, and more than
+    /// half of that.
+    buckets: Vec<u64>,
+}
+
+/// A command's latency histogram as `LATENCY HISTOGRAM` reports it.
+pub struct CommandHistogram {
+    pub command: String,
+    pub calls: u64,
+    /// Each bucket's upper bound in microseconds with the number of runs
+    /// that took at most that long, for the buckets that got any.
+    pub buckets: Vec<(u64, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Latencies {
+    events: BTreeMap<&'static str, EventHistory>,
+    commands: BTreeMap<String, Histogram>,
+}
+
+/// The latency spikes of each event class, such as `command`,
+/// `expire-cycle` or `fork`, and a histogram of every command run, behind
+/// its own lock so that saving, which only borrows the `Db`, records too.
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+    latencies: Mutex<Latencies>,
+}
+
+impl LatencyMonitor {
+    /// Records that `event` took `duration`. Spikes within the same second
+    /// are kept as the longest of them.
+    pub fn add_sample(&self, event: &'static str, duration: Duration) {
+        let now = unix_time_millis() / 1000;
+        let millis = duration.as_millis() as u64;
+        let mut latencies = self.lock();
+        let history = latencies.events.entry(event).or_default();
+        history.max = history.max.max(millis);
+        match history.samples.back_mut() {
+            Some((time, latest)) if *time == now => *latest = (*latest).max(millis),
+            _ => {
+                if history.samples.len() == HISTORY_LEN {
+                    history.samples.pop_front();
+                }
+                history.samples.push_back((now, millis));
+            }
+        }
+    }
+
+    /// Counts a run of `command`, labelled as in `client|list`.
+    pub fn add_command(&self, command: &str, duration: Duration) {
+        let micros = duration.as_micros().max(1) as u64;
+        let bucket = (u64::BITS - (micros - 1).leading_zeros()) as usize;
+        let mut latencies = self.lock();
+        let histogram = match latencies.commands.get_mut(command) {
+            Some(histogram) => histogram,
+            None => latencies.commands.entry(command.to_string()).or_default(),
+        };
+        histogram.calls += 1;
+        if histogram.buckets.len() <= bucket {
+            histogram.buckets.resize(bucket + 1, 0);
+        }
+        histogram.buckets[bucket] += 1;
+    }
+
+    /// Every event with the time and duration of its latest spike and its
+    /// longest one, as in `LATENCY LATEST`.
+    pub fn latest(&self) -> Vec<(&'static str, u64, u64, u64)> {
+        self.lock()
+            .events
+            .iter()
+            .filter_map(|(&event, history)| {
+                let &(time, millis) = history.samples.back()?;
+                Some((event, time, millis, history.max))
+            })
+            .collect()
+    }
+
+    /// The spikes of `event`, oldest first.
+    pub fn history(&self, event: &str) -> Vec<(u64, u64)> {
+        self.lock()
+            .events
+            .get(event)
+            .map(|history| history.samples.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Forgets the spikes of `events`, or of all events when none is given.
+    /// Returns how many events had any.
+    pub fn reset(&self, events: &[String]) -> usize {
+        let mut latencies = self.lock();
+        if events.is_empty() {
+            let count = latencies.events.len();
+            latencies.events.clear();
+            return count;
+        }
+        events
+            .iter()
+            .filter(|event| latencies.events.remove(event.as_str()).is_some())
+            .count()
+    }
+
+    /// The histograms of `commands`, or of every command run when none is
+    /// given, ordered by command.
+    pub fn histograms(&self, commands: &[String]) -> Vec<CommandHistogram> {
+        self.lock()
+            .commands
+            .iter()
+            .filter(|(command, _)| commands.is_empty() || commands.contains(command))
+            .map(|(command, histogram)| {
+                let mut total = 0;
+                let buckets = histogram
+                    .buckets
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &count)| count > 0)
+                    .map(|(index, &count)| {
+                        total += count;
+                        (1 << index, total)
+                    })
+                    .collect();
+                CommandHistogram {
+                    command: command.clone(),
+                    calls: histogram.calls,
+                    buckets,
+                }
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Latencies> {
+        self.latencies
+            .lock()
+            .expect("latency monitor lock poisoned")
+    }
+}
@@ -0,0 +1,41 @@
+//! A Redis compatible server, usable as a library: a `server::Server`
+//! holds the dataset with its background tasks and runs the commands of
+//! in-process clients, with the same semantics as clients connected over
+//! TCP, which `server::run` serves as the `redis-server` binary does.
+//!
+//! ```no_run
+//! use codecrafters_redis::{config::Config, server::Server};
+//!
+//! # async fn example() {
+//! let server = Server::new(Config::default());
+//! let mut client = server.connect();
+//! client.call(["SET", "greeting", "hello"]).await;
+//! let greeting = client.call(["GET", "greeting"]).await;
+//! # }
+//! ```
+//!
+//! The `Db` can also be used directly under its lock, `commands` parses and
+//! applies commands to it, and `resp` encodes and decodes the protocol.
+//! `INFO` reports the memory in use only for programs whose global
+//! allocator is `memory::CountingAllocator`.
+
+pub(crate) mod acl;
+pub mod benchmark;
+pub(crate) mod clients;
+pub(crate) mod cluster;
+pub mod commands;
+pub mod config;
+pub mod connection;
+pub mod db;
+pub(crate) mod glob;
+pub(crate) mod latency;
+pub mod logging;
+pub mod memory;
+pub(crate) mod persistence;
+pub(crate) mod replication;
+pub mod resp;
+pub(crate) mod scripting;
+pub mod server;
+pub(crate) mod shutdown;
+pub(crate) mod stats;
+pub(crate) mod tls;
@@ -0,0 +1,97 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    str::FromStr,
+    sync::{Mutex, OnceLock},
+};
+
+use anyhow::{Result, anyhow};
+use tracing_subscriber::{
+    Registry, filter::LevelFilter, layer::SubscriberExt, reload, util::SubscriberInitExt,
+};
+
+use crate::config::Config;
+
+/// The `loglevel` values of Redis, from the most verbose. Connections are
+/// logged at `verbose`, persistence and replication events at `notice`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogLevel {
+    Debug,
+    Verbose,
+    Notice,
+    Warning,
+    Nothing,
+}
+
+impl LogLevel {
+    fn filter(self) -> LevelFilter {
+        match self {
+            LogLevel::Debug => LevelFilter::TRACE,
+            LogLevel::Verbose => LevelFilter::DEBUG,
+            LogLevel::Notice => LevelFilter::INFO,
+            LogLevel::Warning => LevelFilter::WARN,
+            LogLevel::Nothing => LevelFilter::OFF,
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "debug" => Ok(LogLevel::Debug),
+            "verbose" => Ok(LogLevel::Verbose),
+            "notice" => Ok(LogLevel::Notice),
+            "warning" => Ok(LogLevel::Warning),
+            "nothing" => Ok(LogLevel::Nothing),
+            _ => Err(anyhow!("Invalid log level '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogLevel::Debug => write!(f, "debug"),
+            LogLevel::Verbose => write!(f, "verbose"),
+            LogLevel::Notice => write!(f, "notice"),
+            LogLevel::Warning => write!(f, "warning"),
+            LogLevel::Nothing => write!(f, "nothing"),
+        }
+    }
+}
+
+/// Changes the level of the installed subscriber, for `CONFIG SET`.
+static LEVEL: OnceLock<reload::Handle<LevelFilter, Registry>> = OnceLock::new();
+
+/// Installs the subscriber logging at `loglevel` to `logfile`, appended
+/// to, or to the standard output when it is empty.
+pub fn init(config: &Config) -> Result<()> {
+    let (filter, handle) = reload::Layer::new(config.loglevel.filter());
+    let registry = tracing_subscriber::registry().with(filter);
+    if config.logfile.as_os_str().is_empty() {
+        registry.with(tracing_subscriber::fmt::layer()).try_init()?;
+    } else {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.logfile)
+            .map_err(|e| anyhow!("Can't open the log file {}: {e}", config.logfile.display()))?;
+        registry
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .with_ansi(false)
+                    .with_writer(Mutex::new(file)),
+            )
+            .try_init()?;
+    }
+    let _ = LEVEL.set(handle);
+    Ok(())
+}
+
+pub fn set_level(level: LogLevel) {
+    if let Some(handle) = LEVEL.get() {
+        let _ = handle.reload(level.filter());
+    }
+}
@@ -1,58 +1,73 @@
-mod commands;
-mod db;
-mod resp;
+use std::path::Path;
 
-use std::sync::Arc;
+use anyhow::{Result, anyhow, bail};
+use codecrafters_redis::{benchmark, config::Config, logging, memory, server};
+use tracing::{error, warn};
 
-use anyhow::Result;
-use commands::parser::{extract_command, parse_command};
-use db::*;
-use resp::RespValue;
-use tokio::{
-    net::{TcpListener, TcpStream},
-    sync::Mutex,
-};
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
 
-async fn handle_conn(stream: TcpStream, db: Arc<Mutex<Db>>) -> Result<()> {
-    let mut handler = resp::RespHandler::new(stream);
-
-    loop {
-        let input = handler.read_value().await?;
-        let response = if let Some(input) = input {
-            let (command_name, args) = extract_command(input)?;
-            let command = parse_command(command_name, args)?;
-            match command.execute(db.clone()).await {
-                Ok(resp_value) => resp_value,
-                Err(e) => RespValue::SimpleError(format!("{e}")),
-            }
-        } else {
-            break;
-        };
-        handler.write_value(response).await?;
+/// Parses `redis-server` style options into the server configuration: an
+/// optional config file, then `--<parameter> <value>` options overriding
+/// it, where every word up to the next option makes the value, so that
+/// both `--replicaof "<host> <port>"` and `--replicaof <host> <port>` work.
+/// Also returns the directives of the config file that were skipped.
+fn parse_args() -> Result<(Config, Vec<String>)> {
+    let mut config = Config::default();
+    let mut skipped = vec![];
+    let mut argv = std::env::args().skip(1).peekable();
+    if let Some(path) = argv.next_if(|arg| !arg.starts_with("--")) {
+        skipped = config.load_file(Path::new(&path))?;
     }
-
-    Ok(())
+    while let Some(option) = argv.next() {
+        let name = option
+            .strip_prefix("--")
+            .ok_or_else(|| anyhow!("Unexpected argument {option}"))?;
+        let mut words = vec![];
+        while let Some(word) = argv.next_if(|word| !word.starts_with("--")) {
+            words.push(word);
+        }
+        if words.is_empty() {
+            bail!("Missing value for argument {option}");
+        }
+        config.set_startup(name, &words.join(" "))?;
+    }
+    Ok((config, skipped))
 }
 
 #[tokio::main]
 async fn main() {
-    let listener = TcpListener::bind("127.0.0.1:6379").await.unwrap();
-    let db: Arc<Mutex<Db>> = Arc::new(Mutex::new(Db::new()));
-
-    loop {
-        let stream = listener.accept().await;
-        let db_for_stream = db.clone();
-        match stream {
-            Ok((stream, _add)) => {
-                tokio::spawn(async move {
-                    if let Err(e) = handle_conn(stream, db_for_stream).await {
-                        eprintln!("Error handling connection: {e}");
-                    }
-                });
-            }
-            Err(e) => {
-                eprintln!("Error accepting connection: {e}");
-            }
+    // `--benchmark` turns the binary into a load generator.
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "--benchmark").is_some() {
+        let result = match benchmark::Options::parse(args) {
+            Ok(options) => benchmark::run(options).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        return;
+    }
+    // Nothing is logged until the configuration says where to.
+    let (config, skipped) = match parse_args() {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
         }
+    };
+    if let Err(e) = logging::init(&config) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    for directive in skipped {
+        warn!("Ignoring unsupported config directive '{directive}'");
+    }
+    if let Err(e) = server::run(config).await {
+        error!("{e}");
+        std::process::exit(1);
     }
+    std::process::exit(0)
 }
@@ -0,0 +1,194 @@
+pub(crate) mod aof;
+pub(crate) mod rdb;
+
+use std::{
+    fs,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Result, bail};
+use tokio::sync::RwLock;
+use tracing::{error, info};
+
+use crate::db::Db;
+
+pub const DEFAULT_DB_FILENAME: &str = "dump.rdb";
+
+/// Seconds the `save` rules wait after a failed background save before
+/// trying again, like Redis' `CONFIG_BGSAVE_RETRY_DELAY`.
+const BGSAVE_RETRY_DELAY: u64 = 5;
+
+pub fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis() as u64
+}
+
+/// The bookkeeping of snapshots, shared with background saves.
+#[derive(Debug)]
+pub struct RdbState {
+    bgsave_in_progress: Arc<AtomicBool>,
+    last_save: Arc<AtomicU64>,
+    /// Writes since the last successful save, checked against the `save`
+    /// rules.
+    changes: Arc<AtomicU64>,
+    last_bgsave_ok: Arc<AtomicBool>,
+    last_bgsave_try: AtomicU64,
+}
+
+impl RdbState {
+    pub fn new() -> Self {
+        Self {
+            bgsave_in_progress: Arc::new(AtomicBool::new(false)),
+            last_save: Arc::new(AtomicU64::new(unix_time_millis() / 1000)),
+            changes: Arc::new(AtomicU64::new(0)),
+            last_bgsave_ok: Arc::new(AtomicBool::new(true)),
+            last_bgsave_try: AtomicU64::new(0),
+        }
+    }
+
+    pub fn last_save(&self) -> u64 {
+        self.last_save.load(Ordering::Relaxed)
+    }
+
+    pub fn bgsave_in_progress(&self) -> bool {
+        self.bgsave_in_progress.load(Ordering::Relaxed)
+    }
+
+    pub fn changes(&self) -> u64 {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    pub fn add_change(&self) {
+        self.changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn last_bgsave_ok(&self) -> bool {
+        self.last_bgsave_ok.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for RdbState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writes a snapshot of the whole keyspace synchronously.
+pub fn save(db: &Db) -> Result<()> {
+    if db.rdb().bgsave_in_progress() {
+        bail!("ERR Background save already in progress");
+    }
+    let started = Instant::now();
+    let now = unix_time_millis();
+    let data = rdb::encode(&db.rdb_dataset(now), now);
+    write_atomically(&db.config().rdb_path(), &data)?;
+    db.record_latency("save", started.elapsed());
+    info!("DB saved on disk");
+    db.rdb().last_save.store(now / 1000, Ordering::Relaxed);
+    db.rdb().changes.store(0, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Clones the keyspace while the lock is held, then encodes and writes it
+/// on a blocking thread so the server keeps serving clients.
+pub fn bgsave(db: &Db) -> Result<()> {
+    let state = db.rdb();
+    if state
+        .bgsave_in_progress
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+        .is_err()
+    {
+        bail!("ERR Background save already in progress");
+    }
+
+    let now = unix_time_millis();
+    state.last_bgsave_try.store(now / 1000, Ordering::Relaxed);
+    // Cloning the keyspace stands for Redis' fork, stalling every client.
+    let started = Instant::now();
+    let dataset = db.rdb_dataset(now);
+    db.record_latency("fork", started.elapsed());
+    let saved_changes = state.changes();
+    let path = db.config().rdb_path();
+    let bgsave_in_progress = state.bgsave_in_progress.clone();
+    let last_save = state.last_save.clone();
+    let changes = state.changes.clone();
+    let last_bgsave_ok = state.last_bgsave_ok.clone();
+
+    info!("Background saving started");
+    tokio::task::spawn_blocking(move || {
+        let data = rdb::encode(&dataset, now);
+        let result = write_atomically(&path, &data);
+        match &result {
+            Ok(()) => {
+                last_save.store(now / 1000, Ordering::Relaxed);
+                // Writes made while saving are left for the next snapshot.
+                changes.fetch_sub(saved_changes, Ordering::Relaxed);
+                info!("Background saving terminated with success");
+            }
+            Err(e) => error!("Background saving error: {e}"),
+        }
+        last_bgsave_ok.store(result.is_ok(), Ordering::Relaxed);
+        bgsave_in_progress.store(false, Ordering::Release);
+    });
+
+    Ok(())
+}
+
+/// Spawns the task that takes a background snapshot whenever one of the
+/// `save` rules is met.
+pub fn spawn_save_points(db: Arc<RwLock<Db>>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            let db_g = db.read().await;
+            let state = db_g.rdb();
+            let now = unix_time_millis() / 1000;
+            if state.bgsave_in_progress()
+                || (!state.last_bgsave_ok()
+                    && now < state.last_bgsave_try.load(Ordering::Relaxed) + BGSAVE_RETRY_DELAY)
+            {
+                continue;
+            }
+            let since_save = now.saturating_sub(state.last_save());
+            if db_g
+                .config()
+                .save
+                .iter()
+                .any(|point| state.changes() >= point.changes && since_save > point.seconds)
+                && let Err(e) = bgsave(&db_g)
+            {
+                error!("Background saving error: {e}");
+            }
+        }
+    });
+}
+
+/// Loads the dump file into `db`. A missing file is not an error: the
+/// server simply starts empty.
+pub fn load(db: &mut Db) -> Result<()> {
+    let data = match fs::read(db.config().rdb_path()) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let dataset = rdb::decode(&data)?;
+    let keys = dataset.entries.len();
+    db.load_rdb_dataset(dataset, unix_time_millis())?;
+    info!(keys, "DB loaded from disk");
+    Ok(())
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let temp_path = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    fs::write(&temp_path, data)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
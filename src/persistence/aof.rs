@@ -0,0 +1,347 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use tokio::{sync::RwLock, task::JoinHandle};
+use tracing::{error, info};
+
+use crate::{
+    commands::parser::{extract_command, parse_command},
+    db::{Db, DbValue, sorted_set::format_score},
+    persistence::{
+        rdb::{RdbDataset, RdbEntry},
+        unix_time_millis,
+    },
+    resp::{Codec, RespValue},
+};
+
+/// Lists are rewritten in batches so a single command never gets huge.
+const REWRITE_ITEMS_PER_COMMAND: usize = 64;
+
+pub const DEFAULT_AOF_FILENAME: &str = "appendonly.aof";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FsyncPolicy {
+    Always,
+    EverySec,
+    No,
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "always" => Ok(FsyncPolicy::Always),
+            "everysec" => Ok(FsyncPolicy::EverySec),
+            "no" => Ok(FsyncPolicy::No),
+            _ => Err(anyhow!("Invalid appendfsync policy '{s}'")),
+        }
+    }
+}
+
+impl fmt::Display for FsyncPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FsyncPolicy::Always => write!(f, "always"),
+            FsyncPolicy::EverySec => write!(f, "everysec"),
+            FsyncPolicy::No => write!(f, "no"),
+        }
+    }
+}
+
+/// An open append-only file receiving every successful write command.
+#[derive(Debug)]
+pub struct Aof {
+    path: PathBuf,
+    file: File,
+    fsync: FsyncPolicy,
+    sync_task: Option<JoinHandle<()>>,
+    /// Writes received while a rewrite is running, appended to the new file
+    /// before it replaces the current one.
+    rewrite_buffer: Option<Vec<u8>>,
+}
+
+impl Aof {
+    pub fn open(path: &Path, fsync: FsyncPolicy) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let sync_task = spawn_sync_task(&file, fsync)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            fsync,
+            sync_task,
+            rewrite_buffer: None,
+        })
+    }
+
+    pub fn append(&mut self, argv: &[RespValue]) {
+        let frame = RespValue::Array(argv.to_vec()).serialize();
+        if let Some(buffer) = self.rewrite_buffer.as_mut() {
+            buffer.extend_from_slice(&frame);
+        }
+        if let Err(e) = self.file.write_all(&frame) {
+            error!("Error writing to AOF: {e}");
+            return;
+        }
+        if self.fsync == FsyncPolicy::Always
+            && let Err(e) = self.file.sync_data()
+        {
+            error!("Error syncing AOF: {e}");
+        }
+    }
+
+    pub fn rewrite_in_progress(&self) -> bool {
+        self.rewrite_buffer.is_some()
+    }
+
+    /// Flushes every write appended so far to disk, whatever the fsync
+    /// policy.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Switches to another fsync policy, starting or stopping the task
+    /// that syncs every second.
+    pub fn set_fsync(&mut self, fsync: FsyncPolicy) -> Result<()> {
+        if fsync == self.fsync {
+            return Ok(());
+        }
+        if let Some(sync_task) = self.sync_task.take() {
+            sync_task.abort();
+        }
+        self.fsync = fsync;
+        self.sync_task = spawn_sync_task(&self.file, fsync)?;
+        Ok(())
+    }
+
+    /// Appends the writes buffered during the rewrite to `temp_path`, then
+    /// swaps it in place of the current file.
+    fn finish_rewrite(&mut self, temp_path: &Path) -> Result<()> {
+        let buffer = self.rewrite_buffer.take().unwrap_or_default();
+        let mut temp_file = OpenOptions::new().append(true).open(temp_path)?;
+        temp_file.write_all(&buffer)?;
+        temp_file.sync_all()?;
+        fs::rename(temp_path, &self.path)?;
+
+        if let Some(sync_task) = self.sync_task.take() {
+            sync_task.abort();
+        }
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.sync_task = spawn_sync_task(&self.file, self.fsync)?;
+        Ok(())
+    }
+}
+
+impl Drop for Aof {
+    fn drop(&mut self) {
+        if let Some(sync_task) = self.sync_task.take() {
+            sync_task.abort();
+        }
+    }
+}
+
+fn spawn_sync_task(file: &File, fsync: FsyncPolicy) -> Result<Option<JoinHandle<()>>> {
+    if fsync != FsyncPolicy::EverySec {
+        return Ok(None);
+    }
+
+    let sync_file = file.try_clone()?;
+    Ok(Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            interval.tick().await;
+            if let Err(e) = sync_file.sync_data() {
+                error!("Error syncing AOF: {e}");
+            }
+        }
+    })))
+}
+
+/// Opens the append-only file when it gets turned on at runtime. Writes
+/// made until now were never logged, so the file starts with a rewrite of
+/// the current dataset.
+pub fn enable(db: &Db, path: &Path, fsync: FsyncPolicy) -> Result<Aof> {
+    let temp_path = path.with_file_name(format!("temp-rewriteaof-{}.aof", std::process::id()));
+    write_rewrite(&temp_path, &db.rdb_dataset(unix_time_millis()))?;
+    fs::rename(&temp_path, path)?;
+    Aof::open(path, fsync)
+}
+
+/// Starts a background rewrite of the append-only file: the current
+/// keyspace is dumped as a minimal command sequence while new writes are
+/// buffered, then the new file atomically replaces the old one.
+pub fn bgrewrite(db: Arc<RwLock<Db>>, db_g: &mut Db) -> Result<()> {
+    let now = unix_time_millis();
+    let dataset = db_g.rdb_dataset(now);
+    let aof = db_g
+        .aof_mut()
+        .ok_or_else(|| anyhow!("ERR Append only file is not enabled"))?;
+    if aof.rewrite_buffer.is_some() {
+        bail!("ERR Background append only file rewriting already in progress");
+    }
+    aof.rewrite_buffer = Some(Vec::new());
+
+    let temp_path = aof
+        .path
+        .with_file_name(format!("temp-rewriteaof-bg-{}.aof", std::process::id()));
+
+    info!("Background append only file rewriting started");
+    tokio::spawn(async move {
+        let write_path = temp_path.clone();
+        let written =
+            tokio::task::spawn_blocking(move || write_rewrite(&write_path, &dataset)).await;
+
+        let mut db_g = db.write().await;
+        let Some(aof) = db_g.aof_mut() else {
+            return;
+        };
+        let result = match written {
+            Ok(Ok(())) => aof.finish_rewrite(&temp_path),
+            Ok(Err(e)) => Err(e),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) => info!("Background AOF rewrite finished successfully"),
+            Err(e) => {
+                error!("Background AOF rewrite error: {e}");
+                aof.rewrite_buffer = None;
+                let _ = fs::remove_file(&temp_path);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn write_rewrite(path: &Path, dataset: &RdbDataset) -> Result<()> {
+    let mut data = Vec::new();
+    let functions = dataset.functions.iter().map(|code| {
+        vec![
+            Bytes::from_static(b"FUNCTION"),
+            Bytes::from_static(b"LOAD"),
+            code.clone(),
+        ]
+    });
+    for argv in functions.chain(dataset.entries.iter().flat_map(rewrite_commands)) {
+        data.extend(
+            RespValue::Array(argv.into_iter().map(RespValue::BulkString).collect()).serialize(),
+        );
+    }
+    let mut file = File::create(path)?;
+    file.write_all(&data)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn rewrite_commands(entry: &RdbEntry) -> Vec<Vec<Bytes>> {
+    let key = entry.key.clone();
+    let mut commands = vec![];
+
+    match &entry.value {
+        DbValue::Atom(value) => {
+            let mut argv = vec![Bytes::from_static(b"SET"), key, value.clone()];
+            if let Some(expires_at) = entry.expires_at_millis {
+                argv.push(Bytes::from_static(b"PXAT"));
+                argv.push(expires_at.to_string().into());
+            }
+            commands.push(argv);
+        }
+        DbValue::List(list) => {
+            let items = list.iter().collect::<Vec<_>>();
+            for chunk in items.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut argv = vec![Bytes::from_static(b"RPUSH"), key.clone()];
+                argv.extend(chunk.iter().map(|item| Bytes::copy_from_slice(item)));
+                commands.push(argv);
+            }
+        }
+        DbValue::Hash(hash) => {
+            let pairs = hash.iter().collect::<Vec<_>>();
+            for chunk in pairs.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut argv = vec![Bytes::from_static(b"HSET"), key.clone()];
+                for (field, value) in chunk {
+                    argv.push(Bytes::copy_from_slice(field));
+                    argv.push(Bytes::copy_from_slice(value));
+                }
+                commands.push(argv);
+            }
+        }
+        DbValue::Set(set) => {
+            let members = set.iter().collect::<Vec<_>>();
+            for chunk in members.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut argv = vec![Bytes::from_static(b"SADD"), key.clone()];
+                argv.extend(chunk.iter().cloned());
+                commands.push(argv);
+            }
+        }
+        DbValue::SortedSet(sorted_set) => {
+            let members = sorted_set.iter().collect::<Vec<_>>();
+            for chunk in members.chunks(REWRITE_ITEMS_PER_COMMAND) {
+                let mut argv = vec![Bytes::from_static(b"ZADD"), key.clone()];
+                for (member, score) in chunk {
+                    argv.push(format_score(*score).into());
+                    argv.push((*member).clone());
+                }
+                commands.push(argv);
+            }
+        }
+        DbValue::Stream(stream) => {
+            for (id, values) in stream.iter() {
+                let mut argv = vec![
+                    Bytes::from_static(b"XADD"),
+                    key.clone(),
+                    id.to_string().into(),
+                ];
+                for (field, value) in values {
+                    argv.push(field.clone());
+                    argv.push(value.clone());
+                }
+                commands.push(argv);
+            }
+        }
+    }
+
+    // Strings carry their expiration in the SET itself.
+    if let Some(expires_at) = entry.expires_at_millis
+        && !matches!(entry.value, DbValue::Atom(_))
+    {
+        commands.push(vec![
+            Bytes::from_static(b"PEXPIREAT"),
+            entry.key.clone(),
+            expires_at.to_string().into(),
+        ]);
+    }
+
+    commands
+}
+
+/// Replays the commands stored in the append-only file through the normal
+/// command path. A missing file just means there is nothing to replay.
+pub fn replay(db: &mut Db, path: &Path) -> Result<()> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let frames = Codec::replies().decode_all(&data)?;
+    let commands = frames.len();
+    db.set_counting_lookups(false);
+    for frame in frames {
+        let (command_name, args) = extract_command(frame)?;
+        parse_command(command_name, args)?.apply(db)?;
+    }
+    db.set_counting_lookups(true);
+    info!(commands, "DB loaded from append only file");
+    Ok(())
+}
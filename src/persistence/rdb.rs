@@ -0,0 +1,1125 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+
+use crate::db::{
+    DbValue,
+    sorted_set::SortedSet,
+    stream_types::{StreamId, StreamList},
+};
+
+const RDB_VERSION: &[u8] = b"0011";
+const RDB_MAX_SUPPORTED_VERSION: u32 = 12;
+/// Checksums were introduced with RDB version 5.
+const RDB_CHECKSUM_VERSION: u32 = 5;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_HASH_ZIPMAP: u8 = 9;
+const RDB_TYPE_LIST_ZIPLIST: u8 = 10;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_ZSET_ZIPLIST: u8 = 12;
+const RDB_TYPE_HASH_ZIPLIST: u8 = 13;
+const RDB_TYPE_LIST_QUICKLIST: u8 = 14;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+/// Streams are stored with a simple field/value layout under a type code
+/// that upstream Redis does not use.
+const RDB_TYPE_STREAM_SIMPLE: u8 = 0xf0;
+
+const RDB_OPCODE_SLOT_INFO: u8 = 0xf4;
+const RDB_OPCODE_FUNCTION2: u8 = 0xf5;
+const RDB_OPCODE_MODULE_AUX: u8 = 0xf7;
+const RDB_OPCODE_IDLE: u8 = 0xf8;
+const RDB_OPCODE_FREQ: u8 = 0xf9;
+const RDB_OPCODE_AUX: u8 = 0xfa;
+const RDB_OPCODE_RESIZEDB: u8 = 0xfb;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xfd;
+const RDB_OPCODE_SELECTDB: u8 = 0xfe;
+const RDB_OPCODE_EOF: u8 = 0xff;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+const QUICKLIST_NODE_CONTAINER_PLAIN: u64 = 1;
+
+const STREAM_ITEM_FLAG_DELETED: i64 = 1 << 0;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 1 << 1;
+
+/// One key of a snapshot, with its expiration as absolute UNIX milliseconds.
+#[derive(Clone, Debug)]
+pub struct RdbEntry {
+    pub key: Bytes,
+    pub value: DbValue,
+    pub expires_at_millis: Option<u64>,
+}
+
+/// Everything a snapshot holds: the keys and the code of the function
+/// libraries.
+#[derive(Clone, Debug, Default)]
+pub struct RdbDataset {
+    pub entries: Vec<RdbEntry>,
+    pub functions: Vec<Bytes>,
+}
+
+pub fn encode(dataset: &RdbDataset, now_millis: u64) -> Vec<u8> {
+    let RdbDataset { entries, functions } = dataset;
+    let mut out = Vec::new();
+    out.extend_from_slice(b"REDIS");
+    out.extend_from_slice(RDB_VERSION);
+
+    write_aux(&mut out, "redis-ver", "7.2.0");
+    write_aux(&mut out, "redis-bits", "64");
+    write_aux(&mut out, "ctime", &(now_millis / 1000).to_string());
+
+    for code in functions {
+        out.push(RDB_OPCODE_FUNCTION2);
+        write_string(&mut out, code);
+    }
+
+    out.push(RDB_OPCODE_SELECTDB);
+    write_length(&mut out, 0);
+    out.push(RDB_OPCODE_RESIZEDB);
+    write_length(&mut out, entries.len() as u64);
+    write_length(
+        &mut out,
+        entries
+            .iter()
+            .filter(|entry| entry.expires_at_millis.is_some())
+            .count() as u64,
+    );
+
+    for entry in entries {
+        if let Some(expires_at) = entry.expires_at_millis {
+            out.push(RDB_OPCODE_EXPIRETIME_MS);
+            out.extend_from_slice(&expires_at.to_le_bytes());
+        }
+        out.push(value_type(&entry.value));
+        write_string(&mut out, &entry.key);
+        write_value(&mut out, &entry.value);
+    }
+
+    out.push(RDB_OPCODE_EOF);
+    let checksum = crc64(0, &out);
+    out.extend_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// The length of `value` once serialized, as `DEBUG OBJECT` reports it.
+pub fn serialized_length(value: &DbValue) -> usize {
+    let mut out = Vec::new();
+    write_value(&mut out, value);
+    out.len()
+}
+
+fn value_type(value: &DbValue) -> u8 {
+    match value {
+        DbValue::Atom(_) => RDB_TYPE_STRING,
+        DbValue::List(_) => RDB_TYPE_LIST,
+        DbValue::Hash(_) => RDB_TYPE_HASH,
+        DbValue::Set(_) => RDB_TYPE_SET,
+        DbValue::SortedSet(_) => RDB_TYPE_ZSET_2,
+        DbValue::Stream(_) => RDB_TYPE_STREAM_SIMPLE,
+    }
+}
+
+/// Writes `value`, which follows its type and key in a snapshot.
+fn write_value(out: &mut Vec<u8>, value: &DbValue) {
+    match value {
+        DbValue::Atom(value) => write_string(out, value),
+        DbValue::List(list) => {
+            write_length(out, list.len() as u64);
+            for item in list.iter() {
+                write_string(out, item);
+            }
+        }
+        DbValue::Hash(hash) => {
+            write_length(out, hash.len() as u64);
+            for (field, value) in hash.iter() {
+                write_string(out, field);
+                write_string(out, value);
+            }
+        }
+        DbValue::Set(set) => {
+            write_length(out, set.len() as u64);
+            for member in set.iter() {
+                write_string(out, member);
+            }
+        }
+        DbValue::SortedSet(sorted_set) => {
+            write_length(out, sorted_set.len() as u64);
+            for (member, score) in sorted_set.iter() {
+                write_string(out, member);
+                out.extend_from_slice(&score.to_le_bytes());
+            }
+        }
+        DbValue::Stream(stream) => {
+            write_length(out, stream.len() as u64);
+            for (id, values) in stream.iter() {
+                write_string(out, id.to_string());
+                write_length(out, values.len() as u64);
+                for (field, value) in values {
+                    write_string(out, field);
+                    write_string(out, value);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes an RDB file, either written by this server or by upstream Redis.
+pub fn decode(data: &[u8]) -> Result<RdbDataset> {
+    let mut reader = RdbReader { data, pos: 0 };
+
+    let magic = reader.read_bytes(9)?;
+    if &magic[..5] != b"REDIS" {
+        bail!("Not an RDB file");
+    }
+    let version: u32 = std::str::from_utf8(&magic[5..])?
+        .parse()
+        .map_err(|_| anyhow!("Invalid RDB version"))?;
+    if version > RDB_MAX_SUPPORTED_VERSION {
+        bail!("Unsupported RDB version {version}");
+    }
+
+    let mut dataset = RdbDataset::default();
+    let mut expires_at_millis = None;
+    loop {
+        let opcode = reader.read_u8()?;
+        match opcode {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_AUX => {
+                reader.read_blob()?;
+                reader.read_blob()?;
+            }
+            RDB_OPCODE_SELECTDB => {
+                reader.read_length()?;
+            }
+            RDB_OPCODE_RESIZEDB => {
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            RDB_OPCODE_SLOT_INFO => {
+                reader.read_length()?;
+                reader.read_length()?;
+                reader.read_length()?;
+            }
+            RDB_OPCODE_FUNCTION2 => {
+                dataset.functions.push(reader.read_blob()?);
+            }
+            RDB_OPCODE_IDLE => {
+                reader.read_length()?;
+            }
+            RDB_OPCODE_FREQ => {
+                reader.read_u8()?;
+            }
+            RDB_OPCODE_MODULE_AUX => bail!("RDB files with module data are not supported"),
+            RDB_OPCODE_EXPIRETIME_MS => {
+                expires_at_millis = Some(reader.read_u64_le()?);
+            }
+            RDB_OPCODE_EXPIRETIME => {
+                let bytes = reader.read_bytes(4)?;
+                expires_at_millis = Some(u32::from_le_bytes(bytes.try_into()?) as u64 * 1000);
+            }
+            value_type => {
+                let key = reader.read_blob()?;
+                let value = reader.read_value(value_type)?;
+                dataset.entries.push(RdbEntry {
+                    key,
+                    value,
+                    expires_at_millis: expires_at_millis.take(),
+                });
+            }
+        }
+    }
+
+    if version >= RDB_CHECKSUM_VERSION {
+        let checksummed_len = reader.pos;
+        let expected = reader.read_u64_le()?;
+        // A zero checksum means the writer had checksums disabled.
+        if expected != 0 && crc64(0, &data[..checksummed_len]) != expected {
+            bail!("RDB checksum mismatch");
+        }
+    }
+
+    Ok(dataset)
+}
+
+fn write_aux(out: &mut Vec<u8>, key: &str, value: &str) {
+    out.push(RDB_OPCODE_AUX);
+    write_string(out, key);
+    write_string(out, value);
+}
+
+fn write_length(out: &mut Vec<u8>, length: u64) {
+    if length < 1 << 6 {
+        out.push(length as u8);
+    } else if length < 1 << 14 {
+        out.push(0x40 | (length >> 8) as u8);
+        out.push(length as u8);
+    } else if length <= u32::MAX as u64 {
+        out.push(0x80);
+        out.extend_from_slice(&(length as u32).to_be_bytes());
+    } else {
+        out.push(0x81);
+        out.extend_from_slice(&length.to_be_bytes());
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, value: impl AsRef<[u8]>) {
+    let value = value.as_ref();
+    write_length(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+enum Length {
+    Plain(u64),
+    Encoded(u8),
+}
+
+struct RdbReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(count)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| anyhow!("Unexpected end of RDB file"))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u64_le(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into()?))
+    }
+
+    fn read_length_with_encoding(&mut self) -> Result<Length> {
+        let first = self.read_u8()?;
+        match first >> 6 {
+            0b00 => Ok(Length::Plain((first & 0x3f) as u64)),
+            0b01 => {
+                let second = self.read_u8()?;
+                Ok(Length::Plain(((first as u64 & 0x3f) << 8) | second as u64))
+            }
+            0b10 => match first {
+                0x80 => Ok(Length::Plain(
+                    u32::from_be_bytes(self.read_bytes(4)?.try_into()?) as u64,
+                )),
+                0x81 => Ok(Length::Plain(u64::from_be_bytes(
+                    self.read_bytes(8)?.try_into()?,
+                ))),
+                _ => bail!("Invalid RDB length encoding {first:#x}"),
+            },
+            _ => Ok(Length::Encoded(first & 0x3f)),
+        }
+    }
+
+    fn read_length(&mut self) -> Result<u64> {
+        match self.read_length_with_encoding()? {
+            Length::Plain(length) => Ok(length),
+            Length::Encoded(_) => bail!("Expected a plain RDB length"),
+        }
+    }
+
+    /// Reads a string object in any of its encodings (raw, integer, LZF).
+    fn read_blob(&mut self) -> Result<Bytes> {
+        match self.read_length_with_encoding()? {
+            Length::Plain(length) => Ok(Bytes::copy_from_slice(self.read_bytes(length as usize)?)),
+            Length::Encoded(RDB_ENC_INT8) => Ok((self.read_u8()? as i8).to_string().into()),
+            Length::Encoded(RDB_ENC_INT16) => {
+                Ok(i16::from_le_bytes(self.read_bytes(2)?.try_into()?)
+                    .to_string()
+                    .into())
+            }
+            Length::Encoded(RDB_ENC_INT32) => {
+                Ok(i32::from_le_bytes(self.read_bytes(4)?.try_into()?)
+                    .to_string()
+                    .into())
+            }
+            Length::Encoded(RDB_ENC_LZF) => {
+                let compressed_length = self.read_length()? as usize;
+                let length = self.read_length()? as usize;
+                Ok(lzf_decompress(self.read_bytes(compressed_length)?, length)?.into())
+            }
+            Length::Encoded(encoding) => bail!("Unsupported RDB string encoding {encoding}"),
+        }
+    }
+
+    fn read_double(&mut self) -> Result<f64> {
+        let length = self.read_u8()?;
+        match length {
+            253 => Ok(f64::NAN),
+            254 => Ok(f64::INFINITY),
+            255 => Ok(f64::NEG_INFINITY),
+            _ => Ok(std::str::from_utf8(self.read_bytes(length as usize)?)?.parse()?),
+        }
+    }
+
+    fn read_value(&mut self, value_type: u8) -> Result<DbValue> {
+        match value_type {
+            RDB_TYPE_STRING => Ok(DbValue::Atom(self.read_blob()?)),
+            RDB_TYPE_LIST => {
+                let length = self.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..length {
+                    list.push_back(self.read_blob()?);
+                }
+                Ok(DbValue::List(list.into()))
+            }
+            RDB_TYPE_LIST_ZIPLIST => Ok(DbValue::List(
+                VecDeque::from(parse_ziplist(&self.read_blob()?)?).into(),
+            )),
+            RDB_TYPE_LIST_QUICKLIST | RDB_TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..nodes {
+                    if value_type == RDB_TYPE_LIST_QUICKLIST {
+                        list.extend(parse_ziplist(&self.read_blob()?)?);
+                        continue;
+                    }
+                    let container = self.read_length()?;
+                    let node = self.read_blob()?;
+                    if container == QUICKLIST_NODE_CONTAINER_PLAIN {
+                        list.push_back(node);
+                    } else {
+                        list.extend(parse_listpack(&node)?);
+                    }
+                }
+                Ok(DbValue::List(list.into()))
+            }
+            RDB_TYPE_SET => {
+                let length = self.read_length()?;
+                let mut set = HashSet::new();
+                for _ in 0..length {
+                    set.insert(self.read_blob()?);
+                }
+                Ok(DbValue::Set(set.into()))
+            }
+            RDB_TYPE_SET_INTSET => Ok(DbValue::Set(
+                HashSet::from_iter(parse_intset(&self.read_blob()?)?).into(),
+            )),
+            RDB_TYPE_SET_LISTPACK => Ok(DbValue::Set(
+                HashSet::from_iter(parse_listpack(&self.read_blob()?)?).into(),
+            )),
+            RDB_TYPE_HASH => {
+                let length = self.read_length()?;
+                let mut hash = HashMap::new();
+                for _ in 0..length {
+                    let field = self.read_blob()?;
+                    let value = self.read_blob()?;
+                    hash.insert(field, value);
+                }
+                Ok(DbValue::Hash(hash.into()))
+            }
+            RDB_TYPE_HASH_ZIPMAP => Ok(DbValue::Hash(
+                pairs(parse_zipmap(&self.read_blob()?)?)?.into(),
+            )),
+            RDB_TYPE_HASH_ZIPLIST => Ok(DbValue::Hash(
+                pairs(parse_ziplist(&self.read_blob()?)?)?.into(),
+            )),
+            RDB_TYPE_HASH_LISTPACK => Ok(DbValue::Hash(
+                pairs(parse_listpack(&self.read_blob()?)?)?.into(),
+            )),
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let length = self.read_length()?;
+                let mut sorted_set = SortedSet::new();
+                for _ in 0..length {
+                    let member = self.read_blob()?;
+                    let score = if value_type == RDB_TYPE_ZSET_2 {
+                        f64::from_le_bytes(self.read_bytes(8)?.try_into()?)
+                    } else {
+                        self.read_double()?
+                    };
+                    sorted_set.insert(member, score);
+                }
+                Ok(DbValue::SortedSet(sorted_set))
+            }
+            RDB_TYPE_ZSET_ZIPLIST | RDB_TYPE_ZSET_LISTPACK => {
+                let items = if value_type == RDB_TYPE_ZSET_ZIPLIST {
+                    parse_ziplist(&self.read_blob()?)?
+                } else {
+                    parse_listpack(&self.read_blob()?)?
+                };
+                let mut sorted_set = SortedSet::new();
+                for (member, score) in pairs(items)? {
+                    sorted_set.insert(member, std::str::from_utf8(&score)?.parse()?);
+                }
+                Ok(DbValue::SortedSet(sorted_set))
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => self.read_stream_listpacks(value_type),
+            RDB_TYPE_STREAM_SIMPLE => {
+                let length = self.read_length()?;
+                let mut stream = StreamList::default();
+                for _ in 0..length {
+                    let id: StreamId = std::str::from_utf8(&self.read_blob()?)?.parse()?;
+                    let field_count = self.read_length()?;
+                    let mut values = HashMap::new();
+                    for _ in 0..field_count {
+                        let field = self.read_blob()?;
+                        let value = self.read_blob()?;
+                        values.insert(field, value);
+                    }
+                    stream.push(id, values);
+                }
+                Ok(DbValue::Stream(stream))
+            }
+            _ => bail!("Unsupported RDB value type {value_type}"),
+        }
+    }
+
+    /// Streams are a radix tree of listpacks keyed by their master ID,
+    /// followed by metadata and consumer groups, which are skipped.
+    fn read_stream_listpacks(&mut self, value_type: u8) -> Result<DbValue> {
+        let mut items = vec![];
+
+        let listpacks = self.read_length()?;
+        for _ in 0..listpacks {
+            let master_key = self.read_blob()?;
+            if master_key.len() != 16 {
+                bail!("Invalid stream master ID");
+            }
+            let master_ms = u64::from_be_bytes(master_key[..8].try_into()?);
+            let master_seq = u64::from_be_bytes(master_key[8..].try_into()?);
+            let entries = parse_listpack(&self.read_blob()?)?;
+            items.extend(parse_stream_listpack(master_ms, master_seq, &entries)?);
+        }
+
+        // Length and last ID.
+        for _ in 0..3 {
+            self.read_length()?;
+        }
+        if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+            // First ID, max deleted ID and entries added.
+            for _ in 0..5 {
+                self.read_length()?;
+            }
+        }
+
+        let groups = self.read_length()?;
+        for _ in 0..groups {
+            self.read_blob()?;
+            self.read_length()?;
+            self.read_length()?;
+            if value_type >= RDB_TYPE_STREAM_LISTPACKS_2 {
+                self.read_length()?;
+            }
+            let pending = self.read_length()?;
+            for _ in 0..pending {
+                self.read_bytes(16)?;
+                self.read_bytes(8)?;
+                self.read_length()?;
+            }
+            let consumers = self.read_length()?;
+            for _ in 0..consumers {
+                self.read_blob()?;
+                self.read_bytes(8)?;
+                if value_type >= RDB_TYPE_STREAM_LISTPACKS_3 {
+                    self.read_bytes(8)?;
+                }
+                let consumer_pending = self.read_length()?;
+                for _ in 0..consumer_pending {
+                    self.read_bytes(16)?;
+                }
+            }
+        }
+
+        Ok(DbValue::Stream(items.into_iter().collect()))
+    }
+}
+
+fn pairs(items: Vec<Bytes>) -> Result<HashMap<Bytes, Bytes>> {
+    if !items.len().is_multiple_of(2) {
+        bail!("Odd number of elements in encoded hash");
+    }
+    let mut items = items.into_iter();
+    let mut hash = HashMap::new();
+    while let (Some(field), Some(value)) = (items.next(), items.next()) {
+        hash.insert(field, value);
+    }
+    Ok(hash)
+}
+
+fn parse_stream_listpack(
+    master_ms: u64,
+    master_seq: u64,
+    entries: &[Bytes],
+) -> Result<Vec<(StreamId, HashMap<Bytes, Bytes>)>> {
+    let int_at = |index: usize| -> Result<i64> {
+        let entry = entries
+            .get(index)
+            .ok_or_else(|| anyhow!("Truncated stream listpack"))?;
+        std::str::from_utf8(entry)
+            .ok()
+            .and_then(|entry| entry.parse::<i64>().ok())
+            .ok_or_else(|| anyhow!("Invalid integer in stream listpack"))
+    };
+    let string_at = |index: usize| -> Result<Bytes> {
+        entries
+            .get(index)
+            .cloned()
+            .ok_or_else(|| anyhow!("Truncated stream listpack"))
+    };
+
+    // Master entry: count, deleted count, master fields, then a 0 terminator.
+    let master_field_count = int_at(2)? as usize;
+    let master_fields = (0..master_field_count)
+        .map(|i| string_at(3 + i))
+        .collect::<Result<Vec<_>>>()?;
+    let mut index = 3 + master_field_count + 1;
+
+    let mut items = vec![];
+    while index < entries.len() {
+        let flags = int_at(index)?;
+        let ms = master_ms.wrapping_add(int_at(index + 1)? as u64);
+        let seq = master_seq.wrapping_add(int_at(index + 2)? as u64);
+        index += 3;
+
+        let mut values = HashMap::new();
+        if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            for field in &master_fields {
+                values.insert(field.clone(), string_at(index)?);
+                index += 1;
+            }
+        } else {
+            let field_count = int_at(index)? as usize;
+            index += 1;
+            for _ in 0..field_count {
+                values.insert(string_at(index)?, string_at(index + 1)?);
+                index += 2;
+            }
+        }
+        // Trailing lp-count, used to walk the listpack backwards.
+        index += 1;
+
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            items.push((StreamId::new(ms, seq), values));
+        }
+    }
+    Ok(items)
+}
+
+fn slice(data: &[u8], start: usize, length: usize) -> Result<&[u8]> {
+    start
+        .checked_add(length)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| anyhow!("Truncated encoded value"))
+}
+
+fn parse_ziplist(data: &[u8]) -> Result<Vec<Bytes>> {
+    let mut items = vec![];
+    let mut pos = 10;
+    loop {
+        let prevlen = *slice(data, pos, 1)?.first().unwrap();
+        if prevlen == 0xff {
+            break;
+        }
+        pos += if prevlen < 254 { 1 } else { 5 };
+
+        let encoding = slice(data, pos, 1)?[0];
+        let item = match encoding >> 6 {
+            0b00 => {
+                let length = (encoding & 0x3f) as usize;
+                pos += 1;
+                let item = Bytes::copy_from_slice(slice(data, pos, length)?);
+                pos += length;
+                item
+            }
+            0b01 => {
+                let length =
+                    (((encoding & 0x3f) as usize) << 8) | slice(data, pos + 1, 1)?[0] as usize;
+                pos += 2;
+                let item = Bytes::copy_from_slice(slice(data, pos, length)?);
+                pos += length;
+                item
+            }
+            0b10 => {
+                let length = u32::from_be_bytes(slice(data, pos + 1, 4)?.try_into()?) as usize;
+                pos += 5;
+                let item = Bytes::copy_from_slice(slice(data, pos, length)?);
+                pos += length;
+                item
+            }
+            _ => {
+                pos += 1;
+                let (value, size) = match encoding {
+                    0xc0 => (
+                        i16::from_le_bytes(slice(data, pos, 2)?.try_into()?) as i64,
+                        2,
+                    ),
+                    0xd0 => (
+                        i32::from_le_bytes(slice(data, pos, 4)?.try_into()?) as i64,
+                        4,
+                    ),
+                    0xe0 => (i64::from_le_bytes(slice(data, pos, 8)?.try_into()?), 8),
+                    0xf0 => {
+                        let bytes = slice(data, pos, 3)?;
+                        (
+                            i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) as i64 >> 8,
+                            3,
+                        )
+                    }
+                    0xfe => (slice(data, pos, 1)?[0] as i8 as i64, 1),
+                    0xf1..=0xfd => ((encoding & 0x0f) as i64 - 1, 0),
+                    _ => bail!("Invalid ziplist encoding {encoding:#x}"),
+                };
+                pos += size;
+                value.to_string().into()
+            }
+        };
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn parse_listpack(data: &[u8]) -> Result<Vec<Bytes>> {
+    let mut items = vec![];
+    let mut pos = 6;
+    loop {
+        let encoding = slice(data, pos, 1)?[0];
+        if encoding == 0xff {
+            break;
+        }
+
+        let (item, entry_length) = if encoding & 0x80 == 0 {
+            ((encoding & 0x7f).to_string().into(), 1)
+        } else if encoding & 0xc0 == 0x80 {
+            let length = (encoding & 0x3f) as usize;
+            (
+                Bytes::copy_from_slice(slice(data, pos + 1, length)?),
+                1 + length,
+            )
+        } else if encoding & 0xe0 == 0xc0 {
+            let raw = (((encoding & 0x1f) as i64) << 8) | slice(data, pos + 1, 1)?[0] as i64;
+            let value = if raw >= 1 << 12 { raw - (1 << 13) } else { raw };
+            (value.to_string().into(), 2)
+        } else if encoding & 0xf0 == 0xe0 {
+            let length = (((encoding & 0x0f) as usize) << 8) | slice(data, pos + 1, 1)?[0] as usize;
+            (
+                Bytes::copy_from_slice(slice(data, pos + 2, length)?),
+                2 + length,
+            )
+        } else {
+            match encoding {
+                0xf0 => {
+                    let length = u32::from_le_bytes(slice(data, pos + 1, 4)?.try_into()?) as usize;
+                    (
+                        Bytes::copy_from_slice(slice(data, pos + 5, length)?),
+                        5 + length,
+                    )
+                }
+                0xf1 => (
+                    i16::from_le_bytes(slice(data, pos + 1, 2)?.try_into()?)
+                        .to_string()
+                        .into(),
+                    3,
+                ),
+                0xf2 => {
+                    let bytes = slice(data, pos + 1, 3)?;
+                    let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                    (value.to_string().into(), 4)
+                }
+                0xf3 => (
+                    i32::from_le_bytes(slice(data, pos + 1, 4)?.try_into()?)
+                        .to_string()
+                        .into(),
+                    5,
+                ),
+                0xf4 => (
+                    i64::from_le_bytes(slice(data, pos + 1, 8)?.try_into()?)
+                        .to_string()
+                        .into(),
+                    9,
+                ),
+                _ => bail!("Invalid listpack encoding {encoding:#x}"),
+            }
+        };
+
+        let backlen_size = match entry_length {
+            0..=127 => 1,
+            128..=16382 => 2,
+            16383..=2097150 => 3,
+            2097151..=268435454 => 4,
+            _ => 5,
+        };
+        pos += entry_length + backlen_size;
+        items.push(item);
+    }
+    Ok(items)
+}
+
+fn parse_intset(data: &[u8]) -> Result<Vec<Bytes>> {
+    let encoding = u32::from_le_bytes(slice(data, 0, 4)?.try_into()?) as usize;
+    let length = u32::from_le_bytes(slice(data, 4, 4)?.try_into()?) as usize;
+    (0..length)
+        .map(|i| {
+            let bytes = slice(data, 8 + i * encoding, encoding)?;
+            Ok(match encoding {
+                2 => i16::from_le_bytes(bytes.try_into()?) as i64,
+                4 => i32::from_le_bytes(bytes.try_into()?) as i64,
+                8 => i64::from_le_bytes(bytes.try_into()?),
+                _ => bail!("Invalid intset encoding {encoding}"),
+            }
+            .to_string()
+            .into())
+        })
+        .collect()
+}
+
+fn parse_zipmap(data: &[u8]) -> Result<Vec<Bytes>> {
+    let read_length = |pos: &mut usize| -> Result<Option<usize>> {
+        let first = slice(data, *pos, 1)?[0];
+        *pos += 1;
+        match first {
+            255 => Ok(None),
+            254 => {
+                let length = u32::from_le_bytes(slice(data, *pos, 4)?.try_into()?) as usize;
+                *pos += 4;
+                Ok(Some(length))
+            }
+            length => Ok(Some(length as usize)),
+        }
+    };
+
+    let mut items = vec![];
+    let mut pos = 1;
+    while let Some(key_length) = read_length(&mut pos)? {
+        items.push(Bytes::copy_from_slice(slice(data, pos, key_length)?));
+        pos += key_length;
+
+        let value_length =
+            read_length(&mut pos)?.ok_or_else(|| anyhow!("Truncated zipmap entry"))?;
+        let free = slice(data, pos, 1)?[0] as usize;
+        pos += 1;
+        items.push(Bytes::copy_from_slice(slice(data, pos, value_length)?));
+        pos += value_length + free;
+    }
+    Ok(items)
+}
+
+fn lzf_decompress(input: &[u8], length: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(length);
+    let mut pos = 0;
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+        if ctrl < 32 {
+            let run = slice(input, pos, ctrl + 1)?;
+            out.extend_from_slice(run);
+            pos += ctrl + 1;
+        } else {
+            let mut run_length = ctrl >> 5;
+            if run_length == 7 {
+                run_length += *input
+                    .get(pos)
+                    .ok_or_else(|| anyhow!("Truncated LZF data"))?
+                    as usize;
+                pos += 1;
+            }
+            let low = *input
+                .get(pos)
+                .ok_or_else(|| anyhow!("Truncated LZF data"))? as usize;
+            pos += 1;
+            let back = ((ctrl & 0x1f) << 8) + low + 1;
+            let start = out
+                .len()
+                .checked_sub(back)
+                .ok_or_else(|| anyhow!("Invalid LZF back reference"))?;
+            for i in 0..run_length + 2 {
+                out.push(out[start + i]);
+            }
+        }
+    }
+    if out.len() != length {
+        bail!("LZF data decompressed to an unexpected length");
+    }
+    Ok(out)
+}
+
+/// CRC-64/Jones as used by Redis for RDB checksums.
+fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for byte in data {
+        crc ^= *byte as u64;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0x95ac_9329_ac4b_c9b5
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    //! The fixtures under `tests/fixtures/rdb` are version 11 dumps laid out
+    //! the way Redis writes each encoding, checksum included.
+
+    use super::*;
+
+    fn fixture(name: &str) -> RdbDataset {
+        let path = format!(
+            "{}/tests/fixtures/rdb/{name}.rdb",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        decode(&std::fs::read(path).unwrap()).unwrap()
+    }
+
+    fn value<'a>(dataset: &'a RdbDataset, key: &str) -> &'a DbValue {
+        &dataset
+            .entries
+            .iter()
+            .find(|entry| entry.key == key.as_bytes())
+            .unwrap()
+            .value
+    }
+
+    fn list(dataset: &RdbDataset, key: &str) -> Vec<Vec<u8>> {
+        let DbValue::List(list) = value(dataset, key) else {
+            panic!("{key} is not a list");
+        };
+        list.iter().map(<[u8]>::to_vec).collect()
+    }
+
+    fn hash(dataset: &RdbDataset, key: &str) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let DbValue::Hash(hash) = value(dataset, key) else {
+            panic!("{key} is not a hash");
+        };
+        let mut pairs: Vec<_> = hash.iter().map(|(f, v)| (f.to_vec(), v.to_vec())).collect();
+        pairs.sort();
+        pairs
+    }
+
+    fn set(dataset: &RdbDataset, key: &str) -> Vec<Vec<u8>> {
+        let DbValue::Set(set) = value(dataset, key) else {
+            panic!("{key} is not a set");
+        };
+        let mut members: Vec<_> = set.iter().map(|member| member.to_vec()).collect();
+        members.sort();
+        members
+    }
+
+    fn zset(dataset: &RdbDataset, key: &str) -> Vec<(Vec<u8>, f64)> {
+        let DbValue::SortedSet(sorted_set) = value(dataset, key) else {
+            panic!("{key} is not a sorted set");
+        };
+        sorted_set
+            .iter()
+            .map(|(member, score)| (member.to_vec(), score))
+            .collect()
+    }
+
+    fn items(items: &[&[u8]]) -> Vec<Vec<u8>> {
+        items.iter().map(|item| item.to_vec()).collect()
+    }
+
+    #[test]
+    fn crc64_check_value() {
+        assert_eq!(crc64(0, b"123456789"), 0xe9c6_d914_c4b8_d9ca);
+    }
+
+    #[test]
+    fn checksums() {
+        let path = format!(
+            "{}/tests/fixtures/rdb/expires.rdb",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let mut data = std::fs::read(path).unwrap();
+        let checksum = data.len() - 8;
+
+        data[checksum] ^= 1;
+        assert!(decode(&data).is_err());
+
+        data[checksum..].fill(0);
+        assert_eq!(decode(&data).unwrap().entries.len(), 3);
+    }
+
+    #[test]
+    fn lzf_strings() {
+        let dataset = fixture("string-lzf");
+        let mut text = b"The quick brown fox jumps over the lazy dog. ".repeat(12);
+        text.extend(b'0'..b'{');
+        text.extend([b'-'; 300]);
+        assert!(matches!(value(&dataset, "text"), DbValue::Atom(atom) if atom == &text));
+    }
+
+    #[test]
+    fn lzf_decompress_rejects_bad_input() {
+        // A back reference before the start of the output.
+        assert!(lzf_decompress(&[0x20, 0x05], 3).is_err());
+        // A literal run longer than the input.
+        assert!(lzf_decompress(&[0x03, b'a'], 4).is_err());
+        // Output of the wrong length.
+        assert!(lzf_decompress(&[0x00, b'a', 0x20, 0x00], 5).is_err());
+        assert_eq!(
+            lzf_decompress(&[0x00, b'a', 0x20, 0x00], 4).unwrap(),
+            b"aaaa"
+        );
+    }
+
+    #[test]
+    fn expires() {
+        let dataset = fixture("expires");
+        let expiry = |key: &str| {
+            dataset
+                .entries
+                .iter()
+                .find(|entry| entry.key == key.as_bytes())
+                .unwrap()
+                .expires_at_millis
+        };
+        assert_eq!(expiry("millis"), Some(1_760_000_000_123));
+        assert_eq!(expiry("seconds"), Some(1_760_000_000_000));
+        assert_eq!(expiry("forever"), None);
+    }
+
+    #[test]
+    fn ziplists() {
+        let dataset = fixture("list-ziplist");
+        let long = "z".repeat(300);
+        let longer = "y".repeat(16384);
+        assert_eq!(
+            list(&dataset, "list"),
+            items(&[
+                b"a",
+                b"hello",
+                b"7",
+                b"-100",
+                b"1000",
+                b"100000",
+                b"70000000",
+                b"3000000000",
+                long.as_bytes(),
+                b"after",
+                longer.as_bytes(),
+                b"end",
+            ])
+        );
+    }
+
+    #[test]
+    fn quicklists() {
+        let dataset = fixture("list-quicklist");
+        assert_eq!(
+            list(&dataset, "list"),
+            items(&[b"a", b"b", b"3", b"c", b"-5"])
+        );
+
+        let dataset = fixture("list-quicklist-2");
+        assert_eq!(
+            list(&dataset, "list"),
+            items(&[b"a", b"1", b"b", b"a plain node"])
+        );
+    }
+
+    #[test]
+    fn zipmaps() {
+        let dataset = fixture("hash-zipmap");
+        let long = "x".repeat(300);
+        assert_eq!(
+            hash(&dataset, "hash"),
+            vec![
+                (b"f1".to_vec(), b"v1".to_vec()),
+                (b"f2".to_vec(), long.into_bytes()),
+                (b"f3".to_vec(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_ziplists() {
+        let dataset = fixture("hash-ziplist");
+        assert_eq!(
+            hash(&dataset, "hash"),
+            vec![
+                (b"name".to_vec(), b"redis".to_vec()),
+                (b"neg".to_vec(), b"-1".to_vec()),
+                (b"port".to_vec(), b"6379".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn listpacks() {
+        let dataset = fixture("hash-listpack");
+        let values: [&[u8]; 9] = [
+            b"5",
+            b"short",
+            b"-2000",
+            &[b'm'; 200],
+            b"30000",
+            b"-1000000",
+            b"2000000000",
+            b"-9000000000000",
+            &[b'w'; 5000],
+        ];
+        let expected: Vec<_> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| (format!("f{i}").into_bytes(), value.to_vec()))
+            .collect();
+        assert_eq!(hash(&dataset, "hash"), expected);
+
+        let dataset = fixture("set-listpack");
+        let long = "v".repeat(20000);
+        assert_eq!(
+            set(&dataset, "set"),
+            items(&[b"-1", b"1", b"a", long.as_bytes()])
+        );
+    }
+
+    #[test]
+    fn intsets() {
+        let dataset = fixture("set-intset");
+        assert_eq!(set(&dataset, "set16"), items(&[b"-2", b"1", b"300"]));
+        assert_eq!(set(&dataset, "set32"), items(&[b"-70000", b"3", b"70000"]));
+        assert_eq!(set(&dataset, "set64"), items(&[b"-1", b"5000000000"]));
+    }
+
+    #[test]
+    fn sorted_set_ziplists_and_listpacks() {
+        let dataset = fixture("zset-ziplist");
+        assert_eq!(
+            zset(&dataset, "zset"),
+            vec![
+                (b"neg".to_vec(), -3.0),
+                (b"half".to_vec(), 0.5),
+                (b"one".to_vec(), 1.0),
+            ]
+        );
+
+        let dataset = fixture("zset-listpack");
+        assert_eq!(
+            zset(&dataset, "zset"),
+            vec![
+                (b"half".to_vec(), 0.5),
+                (b"one".to_vec(), 1.0),
+                (b"big".to_vec(), 100000.0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,739 @@
+use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::{Bytes, BytesMut};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::{RwLock, watch},
+    task::JoinHandle,
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::{
+    commands::{
+        Command,
+        parser::{extract_command, parse_command},
+        serve_blocked_clients,
+    },
+    db::{Db, Origin, pubsub::MessageSender},
+    persistence::{rdb, unix_time_millis},
+    resp::{Codec, Decoded, RespValue},
+};
+
+/// Delay before reconnecting after the link to the master is lost.
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+pub const DEFAULT_PORT: u16 = 6379;
+
+/// The reply to clients writing to a read-only replica.
+pub const READONLY: &str = "READONLY You can't write against a read only replica.";
+
+#[derive(Debug)]
+pub struct Replica {
+    sender: MessageSender,
+    /// The address the replica connected from.
+    pub ip: String,
+    /// The port the replica serves clients on, when it announced it with
+    /// `REPLCONF listening-port`.
+    pub listening_port: Option<u16>,
+    /// The last offset the replica reported with `REPLCONF ACK`.
+    pub ack_offset: u64,
+    /// When the replica last acknowledged, or was sent its snapshot.
+    pub ack_time: Instant,
+}
+
+impl Replica {
+    /// The address the replica serves clients on, if it announced it.
+    fn addr(&self) -> Option<(String, u16)> {
+        self.listening_port.map(|port| (self.ip.clone(), port))
+    }
+}
+
+/// Where the link to the master stands, as `ROLE` reports it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkState {
+    /// Waiting to connect again after the link dropped.
+    Connect,
+    /// Connecting and going through the handshake.
+    Connecting,
+    /// Receiving the snapshot of the master.
+    Sync,
+    /// Applying the command stream of the master.
+    Connected,
+}
+
+impl fmt::Display for LinkState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            LinkState::Connect => "connect",
+            LinkState::Connecting => "connecting",
+            LinkState::Sync => "sync",
+            LinkState::Connected => "connected",
+        })
+    }
+}
+
+/// The master this server replicates from, and the task syncing with it.
+#[derive(Debug)]
+pub struct MasterLink {
+    pub host: String,
+    pub port: u16,
+    task: JoinHandle<()>,
+    pub state: LinkState,
+    /// When something was last received from the master.
+    pub last_io: Instant,
+    /// When the link last went down, or replication started if it was
+    /// never up.
+    pub down_since: Instant,
+}
+
+impl MasterLink {
+    fn set_state(&mut self, state: LinkState) {
+        if self.state == LinkState::Connected && state != LinkState::Connected {
+            self.down_since = Instant::now();
+        }
+        self.state = state;
+    }
+}
+
+/// What a `FAILOVER` in progress is doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailoverState {
+    /// Writes are paused until a replica acknowledges all of them.
+    WaitingForSync,
+    /// This server replicates from the replica chosen, which was asked to
+    /// become a master.
+    InProgress,
+}
+
+impl fmt::Display for FailoverState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FailoverState::WaitingForSync => "waiting-for-sync",
+            FailoverState::InProgress => "failover-in-progress",
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Failover {
+    state: FailoverState,
+    /// The task waiting for a replica to catch up.
+    task: JoinHandle<()>,
+}
+
+/// Replication state: the replication ID, the offset of the command stream
+/// sent (or, on a replica, received) so far, the replicas it is fed to and
+/// the master link when this server is itself a replica.
+#[derive(Debug)]
+pub struct ReplicationState {
+    replid: String,
+    offset: u64,
+    replicas: HashMap<u64, Replica>,
+    master: Option<MasterLink>,
+    /// Bumped on every `REPLCONF ACK` so `WAIT` can recount.
+    acks: watch::Sender<u64>,
+    failover: Option<Failover>,
+}
+
+impl ReplicationState {
+    pub fn new() -> Self {
+        Self {
+            replid: new_replid(),
+            offset: 0,
+            replicas: HashMap::new(),
+            master: None,
+            acks: watch::Sender::new(0),
+            failover: None,
+        }
+    }
+
+    pub fn is_replica(&self) -> bool {
+        self.master.is_some()
+    }
+
+    pub fn replid(&self) -> &str {
+        &self.replid
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The link to the master, on a replica.
+    pub fn master(&self) -> Option<&MasterLink> {
+        self.master.as_ref()
+    }
+
+    /// The replicas, in the order they connected.
+    pub fn replicas(&self) -> Vec<&Replica> {
+        let mut replicas: Vec<(&u64, &Replica)> = self.replicas.iter().collect();
+        replicas.sort_unstable_by_key(|(id, _)| **id);
+        replicas.into_iter().map(|(_, replica)| replica).collect()
+    }
+
+    /// The `ROLE` reply: the offset and the replicas with their
+    /// acknowledged offsets on a master, the master and the state of the
+    /// link to it on a replica.
+    pub fn role(&self) -> RespValue {
+        let bulk = |value: String| RespValue::BulkString(value.into());
+        match &self.master {
+            Some(master) => {
+                let offset = match master.state {
+                    LinkState::Connected => self.offset as i64,
+                    _ => -1,
+                };
+                RespValue::Array(vec![
+                    bulk("slave".to_string()),
+                    bulk(master.host.clone()),
+                    RespValue::Integer(master.port as i64),
+                    bulk(master.state.to_string()),
+                    RespValue::Integer(offset),
+                ])
+            }
+            None => RespValue::Array(vec![
+                bulk("master".to_string()),
+                RespValue::Integer(self.offset as i64),
+                RespValue::Array(
+                    self.replicas()
+                        .into_iter()
+                        .map(|replica| {
+                            RespValue::Array(vec![
+                                bulk(replica.ip.clone()),
+                                bulk(replica.listening_port.unwrap_or(0).to_string()),
+                                bulk(replica.ack_offset.to_string()),
+                            ])
+                        })
+                        .collect(),
+                ),
+            ]),
+        }
+    }
+
+    pub fn failover_state(&self) -> Option<FailoverState> {
+        self.failover.as_ref().map(|failover| failover.state)
+    }
+
+    /// A new replica starts out in sync with the snapshot it was sent.
+    pub fn add_replica(
+        &mut self,
+        id: u64,
+        sender: MessageSender,
+        ip: String,
+        listening_port: Option<u16>,
+    ) {
+        self.replicas.insert(
+            id,
+            Replica {
+                sender,
+                ip,
+                listening_port,
+                ack_offset: self.offset,
+                ack_time: Instant::now(),
+            },
+        );
+    }
+
+    pub fn remove_replica(&mut self, id: u64) {
+        self.replicas.remove(&id);
+    }
+
+    /// Sends a write command to every replica and advances the offset by
+    /// its encoded size.
+    pub fn feed(&mut self, argv: &[RespValue]) {
+        let frame = RespValue::Array(argv.to_vec());
+        self.offset += frame.serialize().len() as u64;
+        self.replicas
+            .retain(|_, replica| replica.sender.send(frame.clone()).is_ok());
+    }
+
+    pub fn ack(&mut self, id: u64, offset: u64) {
+        if let Some(replica) = self.replicas.get_mut(&id) {
+            replica.ack_offset = offset;
+            replica.ack_time = Instant::now();
+            self.acks.send_modify(|acks| *acks += 1);
+        }
+    }
+
+    /// How many replicas acknowledged at least `offset`.
+    pub fn acked_replicas(&self, offset: u64) -> u64 {
+        self.replicas
+            .values()
+            .filter(|replica| replica.ack_offset >= offset)
+            .count() as u64
+    }
+
+    /// Stops replicating and turns this server back into a master. The
+    /// replication ID changes since the dataset may now diverge.
+    pub fn stop_replication(&mut self) {
+        if let Some(master) = self.master.take() {
+            master.task.abort();
+            self.replid = new_replid();
+            info!("MASTER MODE enabled");
+        }
+    }
+}
+
+fn new_replid() -> String {
+    let replid = format!(
+        "{}{}",
+        uuid::Uuid::new_v4().simple(),
+        uuid::Uuid::new_v4().simple()
+    );
+    replid[..40].to_string()
+}
+
+impl Default for ReplicationState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answers `PSYNC` with a full resynchronization: `+FULLRESYNC`, then a
+/// snapshot of the keyspace, after which the connection receives every
+/// propagated write. Both happen under the same lock, so the replica sees
+/// no gap between the snapshot and the command stream.
+pub fn full_resync(
+    db: &mut Db,
+    id: u64,
+    sender: MessageSender,
+    ip: String,
+    listening_port: Option<u16>,
+) {
+    let now = unix_time_millis();
+    let snapshot = rdb::encode(&db.rdb_dataset(now), now);
+    let _ = sender.send(RespValue::SimpleString(format!(
+        "FULLRESYNC {} {}",
+        db.replication().replid(),
+        db.replication().offset()
+    )));
+    let _ = sender.send(RespValue::RdbFile(snapshot));
+    db.add_replica(id, sender, ip, listening_port);
+    info!(client = id, "Full resync of a replica, snapshot sent");
+}
+
+/// Waits until `numreplicas` replicas acknowledged `offset`, asking them
+/// with `REPLCONF GETACK`, or until the timeout expires. Returns how many
+/// replicas are caught up.
+pub async fn wait(
+    db: &Arc<RwLock<Db>>,
+    numreplicas: u64,
+    timeout: Option<Duration>,
+    offset: u64,
+) -> Result<u64> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let clients = db.read().await.clients().clone();
+    let mut acks = {
+        let mut db_g = db.write().await;
+        let replication = db_g.replication_mut();
+        if replication.is_replica() {
+            bail!("ERR WAIT cannot be used with replica instances");
+        }
+        let acked = replication.acked_replicas(offset);
+        if acked >= numreplicas {
+            return Ok(acked);
+        }
+        let acks = replication.acks.subscribe();
+        replication.feed(&[
+            RespValue::BulkString("REPLCONF".into()),
+            RespValue::BulkString("GETACK".into()),
+            RespValue::BulkString("*".into()),
+        ]);
+        acks
+    };
+
+    loop {
+        // Shutting down counts as timing out.
+        let timed_out = tokio::select! {
+            timed_out = async {
+                match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline, acks.changed())
+                        .await
+                        .is_err(),
+                    None => {
+                        let _ = acks.changed().await;
+                        false
+                    }
+                }
+            } => timed_out,
+            _ = clients.shutting_down() => true,
+        };
+        let acked = db.read().await.replication().acked_replicas(offset);
+        if timed_out || acked >= numreplicas {
+            return Ok(acked);
+        }
+    }
+}
+
+/// Starts replicating from `host:port`, replacing any previous master.
+/// Returns `false` if this server already replicates from that master.
+pub fn replicaof(db: Arc<RwLock<Db>>, db_g: &mut Db, host: String, port: u16) -> bool {
+    let replication = db_g.replication_mut();
+    if let Some(master) = &replication.master
+        && master.host == host
+        && master.port == port
+    {
+        return false;
+    }
+    start_replication(db, db_g, host, port, false);
+    true
+}
+
+/// Replaces any previous master with `host:port`. With `failover`, the
+/// first `PSYNC` asks the replica at that address to become a master, and
+/// this server turns back into one if it refuses.
+fn start_replication(db: Arc<RwLock<Db>>, db_g: &mut Db, host: String, port: u16, failover: bool) {
+    let replication = db_g.replication_mut();
+    if let Some(master) = replication.master.take() {
+        master.task.abort();
+    }
+
+    let task = tokio::spawn({
+        let host = host.clone();
+        let mut failover = failover;
+        async move {
+            loop {
+                if let Err(e) = sync_with_master(&db, &host, port, failover).await {
+                    warn!("Error replicating from {host}:{port}: {e}");
+                }
+                let mut db_g = db.write().await;
+                if failover && db_g.replication().failover.is_some() {
+                    warn!("FAILOVER to {host}:{port} failed, switching back to master mode");
+                    end_failover(&mut db_g);
+                    // Aborts this very task, which has nothing left to do.
+                    db_g.replication_mut().stop_replication();
+                    return;
+                }
+                failover = false;
+                if let Some(master) = &mut db_g.replication_mut().master {
+                    master.set_state(LinkState::Connect);
+                }
+                drop(db_g);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    });
+    info!("Connecting to MASTER {host}:{port}");
+    let now = Instant::now();
+    replication.master = Some(MasterLink {
+        host,
+        port,
+        task,
+        state: LinkState::Connect,
+        last_io: now,
+        down_since: now,
+    });
+}
+
+/// Starts a `FAILOVER`: writes are paused until a replica, `target` when
+/// given, has acknowledged all of them, then this server replicates from
+/// that replica, asking it with `PSYNC <replid> <offset> FAILOVER` to
+/// become a master. If no replica catches up within `timeout`, the
+/// failover is aborted, unless `force` has it go on with `target` anyway.
+pub fn failover(
+    db: Arc<RwLock<Db>>,
+    db_g: &mut Db,
+    target: Option<(String, u16)>,
+    timeout: Option<Duration>,
+    force: bool,
+) -> Result<()> {
+    let replication = db_g.replication_mut();
+    if replication.is_replica() {
+        bail!("ERR FAILOVER is not valid when server is a replica.");
+    }
+    if replication.failover.is_some() {
+        bail!("ERR FAILOVER already in progress.");
+    }
+    if replication.replicas.is_empty() {
+        bail!("ERR FAILOVER requires connected replicas.");
+    }
+    if let Some(target) = &target
+        && !replication
+            .replicas
+            .values()
+            .any(|replica| replica.addr().as_ref() == Some(target))
+    {
+        bail!("ERR FAILOVER target HOST and PORT is not a replica.");
+    }
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let task = tokio::spawn(wait_for_failover_target(db, target, deadline, force));
+    replication.failover = Some(Failover {
+        state: FailoverState::WaitingForSync,
+        task,
+    });
+    db_g.clients().pause_writes(true);
+    info!("FAILOVER requested, waiting for a replica to catch up");
+    Ok(())
+}
+
+/// `FAILOVER ABORT`: writes resume, and a server that already started
+/// replicating from the replica chosen turns back into a master.
+pub fn abort_failover(db_g: &mut Db) -> Result<()> {
+    let Some(state) = db_g.replication().failover_state() else {
+        bail!("ERR No failover in progress.");
+    };
+    end_failover(db_g);
+    if state == FailoverState::InProgress {
+        db_g.replication_mut().stop_replication();
+    }
+    info!("FAILOVER aborted");
+    Ok(())
+}
+
+fn end_failover(db_g: &mut Db) {
+    if let Some(failover) = db_g.replication_mut().failover.take() {
+        failover.task.abort();
+    }
+    db_g.clients().pause_writes(false);
+}
+
+/// Asks the replicas for their offsets until one of them, `target` when
+/// given, has all the writes, then switches roles with it.
+async fn wait_for_failover_target(
+    db: Arc<RwLock<Db>>,
+    target: Option<(String, u16)>,
+    deadline: Option<Instant>,
+    force: bool,
+) {
+    let (mut acks, mut offset) = {
+        let db_g = db.read().await;
+        let replication = db_g.replication();
+        (replication.acks.subscribe(), replication.offset)
+    };
+    let (host, port) = loop {
+        {
+            let mut db_g = db.write().await;
+            let replication = db_g.replication_mut();
+            let caught_up = replication
+                .replicas
+                .values()
+                .filter(|replica| replica.ack_offset >= offset)
+                .filter_map(Replica::addr)
+                .find(|addr| target.as_ref().is_none_or(|target| target == addr));
+            if let Some(addr) = caught_up {
+                break addr;
+            }
+            // Replicas acknowledge the offset preceding the GETACK.
+            offset = replication.offset;
+            replication.feed(&[
+                RespValue::BulkString("REPLCONF".into()),
+                RespValue::BulkString("GETACK".into()),
+                RespValue::BulkString("*".into()),
+            ]);
+        }
+        let timed_out = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, acks.changed())
+                .await
+                .is_err(),
+            None => {
+                let _ = acks.changed().await;
+                false
+            }
+        };
+        if timed_out {
+            if force && let Some(target) = target {
+                break target;
+            }
+            warn!("FAILOVER timed out waiting for a replica to catch up, aborting");
+            end_failover(&mut *db.write().await);
+            return;
+        }
+    };
+
+    let mut db_g = db.write().await;
+    info!("FAILOVER to {host}:{port}");
+    if let Some(failover) = &mut db_g.replication_mut().failover {
+        failover.state = FailoverState::InProgress;
+    }
+    start_replication(db.clone(), &mut db_g, host, port, true);
+}
+
+/// Performs the handshake with the master, loads the snapshot it sends and
+/// then applies the command stream until the connection drops.
+async fn sync_with_master(
+    db: &Arc<RwLock<Db>>,
+    host: &str,
+    port: u16,
+    failover: bool,
+) -> Result<()> {
+    let (listening_port, psync) = {
+        let mut db_g = db.write().await;
+        if let Some(master) = &mut db_g.replication_mut().master {
+            master.set_state(LinkState::Connecting);
+        }
+        let replication = db_g.replication();
+        let psync = if failover {
+            vec![
+                replication.replid.clone(),
+                replication.offset.to_string(),
+                "FAILOVER".to_string(),
+            ]
+        } else {
+            vec!["?".to_string(), "-1".to_string()]
+        };
+        (db_g.config().port, psync)
+    };
+    let mut link = MasterConnection {
+        stream: TcpStream::connect((host, port)).await?,
+        buffer: BytesMut::with_capacity(512),
+    };
+
+    link.send(&["PING"]).await?;
+    link.expect_line("PONG").await?;
+    link.send(&["REPLCONF", "listening-port", &listening_port.to_string()])
+        .await?;
+    link.expect_line("OK").await?;
+    link.send(&["REPLCONF", "capa", "psync2"]).await?;
+    link.expect_line("OK").await?;
+    let mut argv = vec!["PSYNC"];
+    argv.extend(psync.iter().map(String::as_str));
+    link.send(&argv).await?;
+
+    let reply = link.read_line().await?;
+    let mut parts = reply.split_whitespace();
+    let (Some("+FULLRESYNC"), Some(replid), Some(offset)) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        bail!("Unexpected reply to PSYNC: {reply}");
+    };
+    let offset: u64 = offset.parse()?;
+    if let Some(master) = &mut db.write().await.replication_mut().master {
+        master.set_state(LinkState::Sync);
+        master.last_io = Instant::now();
+    }
+    let snapshot = link.read_rdb().await?;
+    let dataset = rdb::decode(&snapshot)?;
+    {
+        let mut db_g = db.write().await;
+        db_g.clear(false);
+        db_g.functions_mut().clear();
+        db_g.load_rdb_dataset(dataset, unix_time_millis())?;
+        // Replicas of this server had the dataset just replaced, so they
+        // are dropped to resync.
+        let replicas: Vec<u64> = db_g.replication().replicas.keys().copied().collect();
+        for client in db_g.clients().list() {
+            if replicas.contains(&client.id) {
+                client.kill();
+            }
+        }
+        if db_g.replication().failover.is_some() {
+            end_failover(&mut db_g);
+            info!("FAILOVER to {host}:{port} complete");
+        }
+        let replication = db_g.replication_mut();
+        replication.replid = replid.to_string();
+        replication.offset = offset;
+        if let Some(master) = &mut replication.master {
+            master.set_state(LinkState::Connected);
+            master.last_io = Instant::now();
+        }
+    }
+    info!("MASTER <-> REPLICA sync: Finished with success");
+
+    loop {
+        let (frame, length) = link.read_frame().await?;
+        let mut db_g = db.write().await;
+        let argv = match &frame {
+            RespValue::Array(items) => items.clone(),
+            _ => vec![],
+        };
+        let (command_name, args) = extract_command(frame)?;
+        match parse_command(command_name, args) {
+            // The acknowledged offset excludes the GETACK itself.
+            Ok(Command::ReplconfGetack) => {
+                let offset = db_g.replication().offset.to_string();
+                link.send(&["REPLCONF", "ACK", &offset]).await?;
+            }
+            Ok(command) => {
+                db_g.set_origin(Origin::Master);
+                if let Err(e) = command.apply_and_propagate(&mut db_g, argv.clone()) {
+                    warn!("Error applying command from master: {e}");
+                }
+                serve_blocked_clients(&mut db_g);
+                db_g.set_origin(Origin::Client);
+            }
+            Err(e) => warn!("Error parsing command from master: {e}"),
+        }
+
+        // The stream is proxied verbatim to our own replicas.
+        let replication = db_g.replication_mut();
+        if let Some(master) = &mut replication.master {
+            master.last_io = Instant::now();
+        }
+        replication.offset += length as u64;
+        replication
+            .replicas
+            .retain(|_, replica| replica.sender.send(RespValue::Array(argv.clone())).is_ok());
+    }
+}
+
+struct MasterConnection {
+    stream: TcpStream,
+    buffer: BytesMut,
+}
+
+impl MasterConnection {
+    async fn send(&mut self, argv: &[&str]) -> Result<()> {
+        let frame = RespValue::Array(
+            argv.iter()
+                .map(|arg| RespValue::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+                .collect(),
+        );
+        self.stream.write_all(&frame.serialize()).await?;
+        Ok(())
+    }
+
+    async fn read_more(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            bail!("Connection closed by master");
+        }
+        Ok(())
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|w| w == b"\r\n") {
+                let line = self.buffer.split_to(end + 2);
+                return Ok(String::from_utf8(line[..end].to_vec())?);
+            }
+            self.read_more().await?;
+        }
+    }
+
+    async fn expect_line(&mut self, expected: &str) -> Result<()> {
+        let line = self.read_line().await?;
+        if line.strip_prefix('+') != Some(expected) {
+            bail!("Expected +{expected} from master, got {line}");
+        }
+        Ok(())
+    }
+
+    /// Reads the snapshot following `+FULLRESYNC`: `$<length>\r\n` and the
+    /// raw bytes, with no trailing CRLF.
+    async fn read_rdb(&mut self) -> Result<Vec<u8>> {
+        let header = self.read_line().await?;
+        let length: usize = header
+            .strip_prefix('$')
+            .ok_or_else(|| anyhow!("Expected an RDB payload, got {header}"))?
+            .parse()?;
+        while self.buffer.len() < length {
+            self.read_more().await?;
+        }
+        Ok(self.buffer.split_to(length).to_vec())
+    }
+
+    /// Reads the next frame of the command stream and its size in bytes.
+    async fn read_frame(&mut self) -> Result<(RespValue, usize)> {
+        loop {
+            match Codec::replies().decode(&mut self.buffer) {
+                Decoded::Complete(frame, length) => return Ok((frame, length)),
+                Decoded::Incomplete => {}
+                Decoded::Error(e) => return Err(e.into()),
+            }
+            self.read_more().await?;
+        }
+    }
+}
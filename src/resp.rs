@@ -1,19 +1,69 @@
-use anyhow::Result;
-use bytes::BytesMut;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+use std::{
+    error::Error,
+    fmt::{self, Display, Write},
+    io,
 };
 
-#[derive(Clone, Debug)]
+use anyhow::{Result, bail};
+use bytes::{BufMut, Bytes, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub use self::codec::{Codec, Decoded};
+
+pub mod codec;
+
+/// The protocol version negotiated with `HELLO`. RESP3 types are downgraded
+/// to their RESP2 equivalent for RESP2 clients.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+/// Bounds on what a client request may claim, checked as soon as a length
+/// is read so an oversized frame is rejected before it is buffered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ProtocolLimits {
+    /// `proto-max-bulk-len`: the longest bulk string accepted.
+    pub max_bulk_len: usize,
+    /// `max-multibulk-len`: the most arguments a request may have.
+    pub max_multibulk_len: usize,
+}
+
+impl Default for ProtocolLimits {
+    fn default() -> Self {
+        Self {
+            max_bulk_len: 512 * 1024 * 1024,
+            max_multibulk_len: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum RespValue {
     SimpleString(String),
     SimpleError(String),
-    Integer(u64),
-    BulkString(String),
+    Integer(i64),
+    BulkString(Bytes),
     NullBulkString,
     NullArray,
     Array(Vec<RespValue>),
+    Map(Vec<(RespValue, RespValue)>),
+    /// An unordered collection. RESP2 clients get a plain array.
+    Set(Vec<RespValue>),
+    Double(f64),
+    /// RESP2 clients get `1` or `0`.
+    Boolean(bool),
+    /// An integer too large for 64 bits, kept as its decimal digits. RESP2
+    /// clients get a bulk string.
+    BigNumber(String),
+    /// An out-of-band frame such as a pub/sub message. RESP2 clients get a
+    /// plain array.
+    Push(Vec<RespValue>),
+    /// An RDB snapshot sent during a full resync: framed like a bulk string,
+    /// but binary and without the trailing CRLF.
+    RdbFile(Vec<u8>),
 }
 
 impl From<RespValue> for String {
@@ -21,7 +71,7 @@ impl From<RespValue> for String {
         match value {
             RespValue::Integer(u) => u.to_string(),
             RespValue::SimpleString(s) => s,
-            RespValue::BulkString(s) => s,
+            RespValue::BulkString(s) => String::from_utf8_lossy(&s).into_owned(),
             _ => {
                 panic!("Cannot convert to string");
             }
@@ -29,12 +79,25 @@ impl From<RespValue> for String {
     }
 }
 
+impl From<RespValue> for Bytes {
+    fn from(value: RespValue) -> Self {
+        match value {
+            RespValue::Integer(u) => u.to_string().into(),
+            RespValue::SimpleString(s) => s.into(),
+            RespValue::BulkString(s) => s,
+            _ => {
+                panic!("Cannot convert to bytes");
+            }
+        }
+    }
+}
+
 impl From<RespValue> for isize {
     fn from(value: RespValue) -> Self {
         match value {
             RespValue::Integer(u) => u as isize,
             RespValue::SimpleString(s) => s.parse().unwrap(),
-            RespValue::BulkString(s) => s.parse().unwrap(),
+            RespValue::BulkString(s) => String::from_utf8_lossy(&s).parse().unwrap(),
             _ => {
                 panic!("Cannot convert to isize");
             }
@@ -45,9 +108,9 @@ impl From<RespValue> for isize {
 impl From<RespValue> for u64 {
     fn from(value: RespValue) -> Self {
         match value {
-            RespValue::Integer(u) => u,
+            RespValue::Integer(u) => u as u64,
             RespValue::SimpleString(s) => s.parse().unwrap(),
-            RespValue::BulkString(s) => s.parse().unwrap(),
+            RespValue::BulkString(s) => String::from_utf8_lossy(&s).parse().unwrap(),
             _ => {
                 panic!("Cannot convert to u64");
             }
@@ -60,7 +123,7 @@ impl From<RespValue> for usize {
         match value {
             RespValue::Integer(u) => u as usize,
             RespValue::SimpleString(s) => s.parse().unwrap(),
-            RespValue::BulkString(s) => s.parse().unwrap(),
+            RespValue::BulkString(s) => String::from_utf8_lossy(&s).parse().unwrap(),
             _ => {
                 panic!("Cannot convert to usize");
             }
@@ -71,7 +134,7 @@ impl From<RespValue> for usize {
 impl From<RespValue> for f64 {
     fn from(value: RespValue) -> Self {
         match value {
-            RespValue::BulkString(s) => s.parse().unwrap(),
+            RespValue::BulkString(s) => String::from_utf8_lossy(&s).parse().unwrap(),
             _ => {
                 panic!("Cannot convert to f64");
             }
@@ -80,123 +143,296 @@ impl From<RespValue> for f64 {
 }
 
 impl RespValue {
-    pub fn serialize(self) -> String {
+    pub fn serialize(&self) -> Vec<u8> {
+        self.serialize_as(Protocol::Resp2)
+    }
+
+    pub fn serialize_as(&self, protocol: Protocol) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        self.write_to(&mut out, protocol);
+        out.to_vec()
+    }
+
+    /// Encodes the value at the end of `out`, without intermediate
+    /// allocations for nested values.
+    pub fn write_to(&self, out: &mut BytesMut, protocol: Protocol) {
         match self {
-            RespValue::SimpleString(s) => format!("+{s}\r\n"),
-            RespValue::SimpleError(s) => format!("-{s}\r\n"),
-            RespValue::BulkString(s) => format!("${}\r\n{}\r\n", s.chars().count(), s),
-            RespValue::NullBulkString => "$-1\r\n".to_string(),
-            RespValue::NullArray => "*-1\r\n".to_string(),
-            RespValue::Integer(v) => format!(":{v}\r\n"),
-            RespValue::Array(v) => {
-                let length = v.len();
-                let items_serialized: String = v.into_iter().map(|item| item.serialize()).collect();
-                format!("*{length}\r\n{items_serialized}")
+            RespValue::SimpleString(s) => write_line(out, b'+', s),
+            RespValue::SimpleError(s) => write_line(out, b'-', s),
+            RespValue::BulkString(s) => {
+                write_line(out, b'$', s.len());
+                out.extend_from_slice(s);
+                out.extend_from_slice(b"\r\n");
+            }
+            RespValue::NullBulkString | RespValue::NullArray if protocol == Protocol::Resp3 => {
+                out.extend_from_slice(b"_\r\n")
+            }
+            RespValue::NullBulkString => out.extend_from_slice(b"$-1\r\n"),
+            RespValue::NullArray => out.extend_from_slice(b"*-1\r\n"),
+            RespValue::Integer(v) => write_line(out, b':', v),
+            RespValue::Array(v) => write_items(out, b'*', v, protocol),
+            RespValue::Set(v) => {
+                let type_byte = match protocol {
+                    Protocol::Resp2 => b'*',
+                    Protocol::Resp3 => b'~',
+                };
+                write_items(out, type_byte, v, protocol);
+            }
+            RespValue::Push(v) => {
+                let type_byte = match protocol {
+                    Protocol::Resp2 => b'*',
+                    Protocol::Resp3 => b'>',
+                };
+                write_items(out, type_byte, v, protocol);
+            }
+            RespValue::Map(pairs) => {
+                match protocol {
+                    Protocol::Resp2 => write_line(out, b'*', pairs.len() * 2),
+                    Protocol::Resp3 => write_line(out, b'%', pairs.len()),
+                }
+                for (key, value) in pairs {
+                    key.write_to(out, protocol);
+                    value.write_to(out, protocol);
+                }
+            }
+            RespValue::Double(v) => {
+                let formatted = if v.is_nan() {
+                    "nan".to_string()
+                } else if v.is_infinite() {
+                    if *v > 0.0 { "inf" } else { "-inf" }.to_string()
+                } else {
+                    v.to_string()
+                };
+                match protocol {
+                    Protocol::Resp2 => {
+                        RespValue::BulkString(formatted.into()).write_to(out, protocol)
+                    }
+                    Protocol::Resp3 => write_line(out, b',', formatted),
+                }
+            }
+            RespValue::Boolean(v) => match protocol {
+                Protocol::Resp2 => write_line(out, b':', *v as u8),
+                Protocol::Resp3 => write_line(out, b'#', if *v { 't' } else { 'f' }),
+            },
+            RespValue::BigNumber(v) => match protocol {
+                Protocol::Resp2 => {
+                    write_line(out, b'$', v.len());
+                    out.extend_from_slice(v.as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                }
+                Protocol::Resp3 => write_line(out, b'(', v),
+            },
+            RespValue::RdbFile(data) => {
+                write_line(out, b'$', data.len());
+                out.extend_from_slice(data);
             }
         }
     }
-}
 
-pub struct RespHandler {
-    stream: TcpStream,
-    buffer: BytesMut,
+    /// Roughly how many bytes the value takes once encoded: its payload and
+    /// a few bytes of framing per element, without encoding it.
+    pub fn encoded_size(&self) -> usize {
+        const FRAMING: usize = 8;
+        FRAMING
+            + match self {
+                RespValue::SimpleString(s) | RespValue::SimpleError(s) => s.len(),
+                RespValue::BigNumber(s) => s.len(),
+                RespValue::BulkString(s) => s.len(),
+                RespValue::RdbFile(data) => data.len(),
+                RespValue::Integer(_) | RespValue::Double(_) => 20,
+                RespValue::NullBulkString | RespValue::NullArray | RespValue::Boolean(_) => 0,
+                RespValue::Array(items) | RespValue::Set(items) | RespValue::Push(items) => {
+                    items.iter().map(RespValue::encoded_size).sum()
+                }
+                RespValue::Map(pairs) => pairs
+                    .iter()
+                    .map(|(key, value)| key.encoded_size() + value.encoded_size())
+                    .sum(),
+            }
+    }
 }
 
-impl RespHandler {
-    pub fn new(stream: TcpStream) -> Self {
-        RespHandler {
-            stream,
-            buffer: BytesMut::with_capacity(512),
-        }
-    }
+/// Writes a type byte followed by `value` and CRLF.
+fn write_line(out: &mut BytesMut, type_byte: u8, value: impl Display) {
+    out.put_u8(type_byte);
+    // Writing into a `BytesMut` cannot fail.
+    let _ = write!(out, "{value}\r\n");
+}
 
-    pub async fn read_value(&mut self) -> Result<Option<RespValue>> {
-        let bytes_read = self.stream.read_buf(&mut self.buffer).await?;
+/// The elements of an aggregate left to encode by `queue_streamed`.
+type Elements = Box<dyn Iterator<Item = RespValue> + Send>;
 
-        if bytes_read == 0 {
-            return Ok(None);
-        }
-
-        let (v, _) = parse_message(self.buffer.split())?;
-        Ok(Some(v))
+impl RespValue {
+    /// Encodes the header of an aggregate and hands back its elements for
+    /// the caller to encode in turn. Other values are encoded whole.
+    fn write_header(self, out: &mut BytesMut, protocol: Protocol) -> Option<Elements> {
+        let (type_byte, items) = match (self, protocol) {
+            (RespValue::Array(items), _)
+            | (RespValue::Set(items), Protocol::Resp2)
+            | (RespValue::Push(items), Protocol::Resp2) => (b'*', items),
+            (RespValue::Set(items), Protocol::Resp3) => (b'~', items),
+            (RespValue::Push(items), Protocol::Resp3) => (b'>', items),
+            (RespValue::Map(pairs), protocol) => {
+                match protocol {
+                    Protocol::Resp2 => write_line(out, b'*', pairs.len() * 2),
+                    Protocol::Resp3 => write_line(out, b'%', pairs.len()),
+                }
+                return Some(Box::new(
+                    pairs.into_iter().flat_map(|(key, value)| [key, value]),
+                ));
+            }
+            (value, protocol) => {
+                value.write_to(out, protocol);
+                return None;
+            }
+        };
+        write_line(out, type_byte, items.len());
+        Some(Box::new(items.into_iter()))
     }
+}
 
-    pub async fn write_value(&mut self, value: RespValue) -> Result<()> {
-        self.stream.write_all(value.serialize().as_bytes()).await?;
-
-        Ok(())
+fn write_items(out: &mut BytesMut, type_byte: u8, items: &[RespValue], protocol: Protocol) {
+    write_line(out, type_byte, items.len());
+    for item in items {
+        item.write_to(out, protocol);
     }
 }
 
-fn parse_message(buffer: BytesMut) -> Result<(RespValue, usize)> {
-    match buffer[0] as char {
-        '+' => parse_simple_string(buffer),
-        '*' => parse_array(buffer),
-        '$' => parse_bulk_string(buffer),
-        _ => Err(anyhow::anyhow!("Not a known value type {buffer:?}")),
+/// A malformed request. The connection cannot be resynchronized after one,
+/// so it is answered with an error reply and closed.
+#[derive(Debug)]
+pub struct ProtocolError(String);
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Protocol error: {}", self.0)
     }
 }
 
-fn parse_simple_string(buffer: BytesMut) -> Result<(RespValue, usize)> {
-    if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let string = String::from_utf8(line.to_vec()).unwrap();
+impl Error for ProtocolError {}
 
-        return Ok((RespValue::SimpleString(string), len + 1));
-    }
+/// Output buffers grown past this size by a large reply are released once
+/// flushed instead of being kept for the lifetime of the connection.
+const OUTPUT_BUFFER_RETAINED: usize = 64 * 1024;
 
-    Err(anyhow::anyhow!("Invalid string {buffer:?}"))
-}
+/// The room made in the read buffer before each read, as Redis does, so
+/// that a pipeline is read in one go rather than a few bytes at a time.
+const READ_CHUNK: usize = 16 * 1024;
 
-fn parse_array(buffer: BytesMut) -> Result<(RespValue, usize)> {
-    let (array_length, mut bytes_consumed) =
-        if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-            let array_length = parse_int(line)?;
+/// How much of a reply `queue_streamed` encodes before writing it out.
+const STREAM_CHUNK: usize = 64 * 1024;
 
-            (array_length, len + 1)
-        } else {
-            return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
-        };
+/// Reads requests from and writes replies to a client stream, a plain TCP
+/// one or a TLS one wrapping it.
+pub struct RespHandler<S> {
+    stream: S,
+    buffer: BytesMut,
+    codec: Codec,
+    /// Replies queued with `queue_value`, written out by `flush`.
+    output: BytesMut,
+    protocol: Protocol,
+}
 
-    let mut items = vec![];
-    for _ in 0..array_length {
-        let (array_item, len) = parse_message(BytesMut::from(&buffer[bytes_consumed..]))?;
+impl<S: AsyncRead + AsyncWrite + Unpin> RespHandler<S> {
+    pub fn new(stream: S, limits: ProtocolLimits) -> Self {
+        RespHandler {
+            stream,
+            buffer: BytesMut::with_capacity(512),
+            codec: Codec::requests(limits),
+            output: BytesMut::new(),
+            protocol: Protocol::Resp2,
+        }
+    }
 
-        items.push(array_item);
-        bytes_consumed += len;
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
     }
 
-    Ok((RespValue::Array(items), bytes_consumed))
-}
+    pub fn stream(&self) -> &S {
+        &self.stream
+    }
 
-fn parse_bulk_string(buffer: BytesMut) -> Result<(RespValue, usize)> {
-    let (bulk_str_len, bytes_consumed) = if let Some((line, len)) = read_until_crlf(&buffer[1..]) {
-        let bulk_str_len = parse_int(line)?;
+    /// Reads every complete frame available, waiting for more data while
+    /// not even one has fully arrived. A trailing partial frame stays
+    /// buffered for the next call. Returns `None` once the client closes
+    /// the connection, and a `ProtocolError` for a malformed request.
+    pub async fn read_values(&mut self) -> Result<Option<Vec<RespValue>>> {
+        loop {
+            let mut values = vec![];
+            loop {
+                match self.codec.decode(&mut self.buffer) {
+                    Decoded::Complete(value, _) => values.push(value),
+                    Decoded::Incomplete => break,
+                    // The frames before the malformed one are still served;
+                    // the error comes up on the next call.
+                    Decoded::Error(_) if !values.is_empty() => break,
+                    Decoded::Error(e) => return Err(e.into()),
+                }
+            }
+            if !values.is_empty() {
+                return Ok(Some(values));
+            }
 
-        (bulk_str_len, len + 1)
-    } else {
-        return Err(anyhow::anyhow!("Invalid array format {:?}", buffer));
-    };
+            self.buffer.reserve(READ_CHUNK);
+            let read = match self.stream.read_buf(&mut self.buffer).await {
+                // TLS clients often close without a `close_notify` alert.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+                read => read?,
+            };
+            if read == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
+                }
+                bail!("Connection closed in the middle of a frame");
+            }
+        }
+    }
 
-    let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
-    let total_parsed = end_of_bulk_str + 2;
+    /// Encodes a reply to be sent with the next `flush`, so the replies to
+    /// a pipeline go out in a single write.
+    pub fn queue_value(&mut self, value: RespValue) {
+        value.write_to(&mut self.output, self.protocol);
+    }
 
-    Ok((
-        RespValue::BulkString(String::from_utf8(
-            buffer[bytes_consumed..end_of_bulk_str].to_vec(),
-        )?),
-        total_parsed,
-    ))
-}
+    /// Like `queue_value`, but writes the output out whenever a chunk of it
+    /// is encoded, so that a reply with millions of elements is not encoded
+    /// whole before its first bytes are sent, and encoding waits for a slow
+    /// client to read. Aggregates are encoded one element at a time, each
+    /// dropped once encoded.
+    pub async fn queue_streamed(&mut self, value: RespValue) -> Result<()> {
+        let mut pending: Vec<Elements> = vec![Box::new(std::iter::once(value))];
+        while let Some(elements) = pending.last_mut() {
+            let Some(value) = elements.next() else {
+                pending.pop();
+                continue;
+            };
+            if let Some(elements) = value.write_header(&mut self.output, self.protocol) {
+                pending.push(elements);
+            }
+            if self.output.len() >= STREAM_CHUNK {
+                self.stream.write_all_buf(&mut self.output).await?;
+            }
+        }
+        Ok(())
+    }
 
-fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
-    for i in 1..buffer.len() {
-        if buffer[i - 1] == b'\r' && buffer[i] == b'\n' {
-            return Some((&buffer[0..(i - 1)], i + 1));
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.write_all_buf(&mut self.output).await?;
+        if self.output.capacity() > OUTPUT_BUFFER_RETAINED {
+            self.output = BytesMut::new();
         }
+
+        Ok(())
     }
-    None
-}
 
-fn parse_int(buffer: &[u8]) -> Result<i64> {
-    Ok(String::from_utf8(buffer.to_vec())?.parse::<i64>()?)
+    pub async fn write_value(&mut self, value: RespValue) -> Result<()> {
+        self.queue_value(value);
+        self.flush().await
+    }
+
+    /// Closes the stream, with a `close_notify` alert for TLS clients. The
+    /// client may already be gone, so failures are ignored.
+    pub async fn shutdown(&mut self) {
+        let _ = self.stream.shutdown().await;
+    }
 }
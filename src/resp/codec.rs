@@ -0,0 +1,358 @@
+//! Decoding RESP frames from bytes, with no I/O involved, so that the same
+//! code serves connections, the replication link, the append-only file and
+//! anything else holding RESP bytes.
+
+use anyhow::{Result, bail};
+use bytes::{Buf, Bytes, BytesMut};
+
+use super::{ProtocolError, ProtocolLimits, RespValue};
+
+/// Longest inline request, or multibulk header line, accepted before the
+/// request is rejected.
+const INLINE_MAX_SIZE: usize = 64 * 1024;
+
+/// What decoding the start of a buffer gave.
+#[derive(Debug)]
+pub enum Decoded {
+    /// A whole frame, which took that many bytes.
+    Complete(RespValue, usize),
+    /// The buffer holds only the start of a frame, or nothing.
+    Incomplete,
+    /// The bytes are not RESP. There is no telling where the next frame
+    /// starts, so the stream cannot be decoded any further.
+    Error(ProtocolError),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Frames {
+    Requests(ProtocolLimits),
+    Replies,
+}
+
+/// A RESP decoder for one kind of stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Codec {
+    frames: Frames,
+}
+
+impl Codec {
+    /// Decodes what clients send: arrays of bulk strings within `limits`,
+    /// or inline commands such as `PING\r\n` typed in a telnet session,
+    /// split on whitespace into the same array. Blank lines and empty
+    /// arrays (`*0` or `*-1`) are skipped without a reply, as Redis does.
+    pub fn requests(limits: ProtocolLimits) -> Self {
+        Self {
+            frames: Frames::Requests(limits),
+        }
+    }
+
+    /// Decodes what servers send: any RESP2 or RESP3 frame.
+    pub fn replies() -> Self {
+        Self {
+            frames: Frames::Replies,
+        }
+    }
+
+    /// Decodes the frame at the start of `buffer`.
+    pub fn decode_slice(&self, buffer: &[u8]) -> Decoded {
+        let decoded = match self.frames {
+            Frames::Requests(limits) => parse_request(buffer, limits),
+            Frames::Replies => parse_message(buffer),
+        };
+        match decoded {
+            Ok(Some((value, len))) => Decoded::Complete(value, len),
+            Ok(None) => Decoded::Incomplete,
+            Err(e) => Decoded::Error(ProtocolError(e.to_string())),
+        }
+    }
+
+    /// Decodes the frame at the start of `buffer`, and removes it from
+    /// there when complete. Bulk strings are copied out rather than sliced:
+    /// the arguments of a command can end up stored in the `Db`, where a
+    /// slice would keep the whole read buffer alive and stop it from ever
+    /// reusing its space.
+    pub fn decode(&self, buffer: &mut BytesMut) -> Decoded {
+        let decoded = self.decode_slice(buffer);
+        if let Decoded::Complete(_, len) = decoded {
+            buffer.advance(len);
+        }
+        decoded
+    }
+
+    /// Decodes every frame of `buffer`, which must end with a whole one.
+    pub fn decode_all(&self, mut buffer: &[u8]) -> Result<Vec<RespValue>> {
+        let mut values = vec![];
+        while !buffer.is_empty() {
+            match self.decode_slice(buffer) {
+                Decoded::Complete(value, len) => {
+                    values.push(value);
+                    buffer = &buffer[len..];
+                }
+                Decoded::Incomplete => bail!("Truncated frame at the end of the input"),
+                Decoded::Error(e) => return Err(e.into()),
+            }
+        }
+        Ok(values)
+    }
+}
+
+fn parse_request(buffer: &[u8], limits: ProtocolLimits) -> Result<Option<(RespValue, usize)>> {
+    let mut skipped = 0;
+    loop {
+        let rest = &buffer[skipped..];
+        let parsed = match rest.first() {
+            None => return Ok(None),
+            Some(b'*') => parse_multibulk(rest, limits)?,
+            Some(_) => parse_inline(rest)?,
+        };
+        match parsed {
+            Some((RespValue::Array(args), len)) if args.is_empty() => skipped += len,
+            Some((value, len)) => return Ok(Some((value, skipped + len))),
+            None => return Ok(None),
+        }
+    }
+}
+
+fn parse_multibulk(buffer: &[u8], limits: ProtocolLimits) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        if buffer.len() > INLINE_MAX_SIZE {
+            bail!("too big mbulk count string");
+        }
+        return Ok(None);
+    };
+    let count = parse_int(line)
+        .ok()
+        .filter(|&count| count <= limits.max_multibulk_len as i64)
+        .ok_or_else(|| anyhow::anyhow!("invalid multibulk length"))?;
+    let mut offset = len + 1;
+
+    let mut args = vec![];
+    for _ in 0..count.max(0) {
+        let Some(&type_byte) = buffer.get(offset) else {
+            return Ok(None);
+        };
+        if type_byte != b'$' {
+            bail!("expected '$', got '{}'", type_byte as char);
+        }
+        match read_until_crlf(&buffer[offset + 1..]) {
+            Some((line, _))
+                if parse_int(line).is_ok_and(|len| len > limits.max_bulk_len as i64) =>
+            {
+                bail!("invalid bulk length");
+            }
+            None if buffer.len() - offset > INLINE_MAX_SIZE => {
+                bail!("too big bulk count string");
+            }
+            _ => {}
+        }
+        let Some((arg, len)) = parse_bulk_string(&buffer[offset..])? else {
+            return Ok(None);
+        };
+        if !matches!(arg, RespValue::BulkString(_)) {
+            bail!("invalid bulk length");
+        }
+        args.push(arg);
+        offset += len;
+    }
+    Ok(Some((RespValue::Array(args), offset)))
+}
+
+fn parse_inline(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some(end) = buffer.iter().position(|&byte| byte == b'\n') else {
+        if buffer.len() > INLINE_MAX_SIZE {
+            bail!("too big inline request");
+        }
+        return Ok(None);
+    };
+    let args = buffer[..end]
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| RespValue::BulkString(Bytes::copy_from_slice(arg)))
+        .collect::<Vec<_>>();
+    Ok(Some((RespValue::Array(args), end + 1)))
+}
+
+/// Parses the frame at the start of `buffer`, returning it with the number
+/// of bytes it spans, or `None` if the buffer holds only part of it.
+fn parse_message(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some(&type_byte) = buffer.first() else {
+        return Ok(None);
+    };
+    match type_byte {
+        b'+' => parse_simple_string(buffer),
+        b'-' => parse_simple_error(buffer),
+        b':' => parse_integer(buffer),
+        b'$' => parse_bulk_string(buffer),
+        b'*' | b'~' | b'>' => parse_array(buffer),
+        b'%' => parse_map(buffer),
+        b'_' => parse_null(buffer),
+        b',' => parse_double(buffer),
+        b'#' => parse_boolean(buffer),
+        b'(' => parse_big_number(buffer),
+        _ => bail!("unexpected type byte '{}'", type_byte as char),
+    }
+}
+
+/// Reads the line following the type byte, returning it with the number of
+/// bytes consumed including the type byte and the CRLF.
+fn parse_line(buffer: &[u8]) -> Result<Option<(String, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    Ok(Some((String::from_utf8(line.to_vec())?, len + 1)))
+}
+
+fn parse_simple_string(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    Ok(parse_line(buffer)?.map(|(line, len)| (RespValue::SimpleString(line), len)))
+}
+
+fn parse_simple_error(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    Ok(parse_line(buffer)?.map(|(line, len)| (RespValue::SimpleError(line), len)))
+}
+
+fn parse_integer(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = parse_line(buffer)? else {
+        return Ok(None);
+    };
+    Ok(Some((RespValue::Integer(line.parse()?), len)))
+}
+
+fn parse_null(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = parse_line(buffer)? else {
+        return Ok(None);
+    };
+    if !line.is_empty() {
+        bail!("invalid null {line:?}");
+    }
+    Ok(Some((RespValue::NullBulkString, len)))
+}
+
+fn parse_double(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = parse_line(buffer)? else {
+        return Ok(None);
+    };
+    Ok(Some((RespValue::Double(line.parse()?), len)))
+}
+
+fn parse_boolean(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = parse_line(buffer)? else {
+        return Ok(None);
+    };
+    let value = match line.as_str() {
+        "t" => true,
+        "f" => false,
+        _ => bail!("invalid boolean {line:?}"),
+    };
+    Ok(Some((RespValue::Boolean(value), len)))
+}
+
+fn parse_big_number(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = parse_line(buffer)? else {
+        return Ok(None);
+    };
+    let digits = line.strip_prefix(['-', '+']).unwrap_or(&line);
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        bail!("invalid big number {line:?}");
+    }
+    Ok(Some((RespValue::BigNumber(line), len)))
+}
+
+/// Parses `count` consecutive frames starting at `offset`.
+fn parse_items(
+    buffer: &[u8],
+    count: usize,
+    mut offset: usize,
+) -> Result<Option<(Vec<RespValue>, usize)>> {
+    let mut items = vec![];
+    for _ in 0..count {
+        let Some((item, len)) = parse_message(&buffer[offset..])? else {
+            return Ok(None);
+        };
+
+        items.push(item);
+        offset += len;
+    }
+    Ok(Some((items, offset)))
+}
+
+/// Arrays, sets and pushes share the same framing.
+fn parse_array(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let array_length = parse_int(line).map_err(|_| anyhow::anyhow!("invalid multibulk length"))?;
+    if array_length < 0 {
+        return Ok(Some((RespValue::NullArray, len + 1)));
+    }
+
+    let Some((items, bytes_consumed)) = parse_items(buffer, array_length as usize, len + 1)? else {
+        return Ok(None);
+    };
+    let value = match buffer[0] {
+        b'~' => RespValue::Set(items),
+        b'>' => RespValue::Push(items),
+        _ => RespValue::Array(items),
+    };
+    Ok(Some((value, bytes_consumed)))
+}
+
+fn parse_map(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let map_length = parse_int(line)?;
+    if map_length < 0 {
+        bail!("invalid map length {map_length}");
+    }
+
+    let Some((items, bytes_consumed)) = parse_items(buffer, map_length as usize * 2, len + 1)?
+    else {
+        return Ok(None);
+    };
+    let mut items = items.into_iter();
+    let mut pairs = vec![];
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        pairs.push((key, value));
+    }
+    Ok(Some((RespValue::Map(pairs), bytes_consumed)))
+}
+
+fn parse_bulk_string(buffer: &[u8]) -> Result<Option<(RespValue, usize)>> {
+    let Some((line, len)) = read_until_crlf(&buffer[1..]) else {
+        return Ok(None);
+    };
+    let bulk_str_len = parse_int(line).map_err(|_| anyhow::anyhow!("invalid bulk length"))?;
+    let bytes_consumed = len + 1;
+    if bulk_str_len < 0 {
+        return Ok(Some((RespValue::NullBulkString, bytes_consumed)));
+    }
+
+    let end_of_bulk_str = bytes_consumed + bulk_str_len as usize;
+    let total_parsed = end_of_bulk_str + 2;
+    if buffer.len() < total_parsed {
+        return Ok(None);
+    }
+    if &buffer[end_of_bulk_str..total_parsed] != b"\r\n" {
+        bail!("bulk string not terminated by CRLF");
+    }
+
+    Ok(Some((
+        RespValue::BulkString(Bytes::copy_from_slice(
+            &buffer[bytes_consumed..end_of_bulk_str],
+        )),
+        total_parsed,
+    )))
+}
+
+fn read_until_crlf(buffer: &[u8]) -> Option<(&[u8], usize)> {
+    for i in 1..buffer.len() {
+        if buffer[i - 1] == b'\r' && buffer[i] == b'\n' {
+            return Some((&buffer[0..(i - 1)], i + 1));
+        }
+    }
+    None
+}
+
+fn parse_int(buffer: &[u8]) -> Result<i64> {
+    Ok(String::from_utf8(buffer.to_vec())?.parse::<i64>()?)
+}
@@ -0,0 +1,454 @@
+pub(crate) mod functions;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib, Table, Value, Variadic};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+use crate::{
+    commands::{
+        Command, is_write,
+        parser::{extract_command, parse_command},
+    },
+    db::{Db, sorted_set::format_score},
+    replication::READONLY,
+    resp::RespValue,
+};
+
+/// VM instructions between two checks of the running script's time and
+/// of `SCRIPT KILL`.
+const HOOK_INSTRUCTIONS: u32 = 10_000;
+
+/// The state of the running script that other clients look at without the
+/// `Db` lock, which the script holds.
+struct RunningScript {
+    active: AtomicBool,
+    /// Set once the script has run past `lua-time-limit`, from then on
+    /// other clients are refused with `-BUSY` instead of waiting.
+    busy: AtomicBool,
+    wrote: AtomicBool,
+    kill: AtomicBool,
+}
+
+static RUNNING: RunningScript = RunningScript {
+    active: AtomicBool::new(false),
+    busy: AtomicBool::new(false),
+    wrote: AtomicBool::new(false),
+    kill: AtomicBool::new(false),
+};
+
+/// Marks a script as running for as long as it is alive.
+struct RunningGuard;
+
+impl RunningGuard {
+    fn start() -> Self {
+        RUNNING.kill.store(false, Ordering::SeqCst);
+        RUNNING.wrote.store(false, Ordering::SeqCst);
+        RUNNING.active.store(true, Ordering::SeqCst);
+        RunningGuard
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        RUNNING.active.store(false, Ordering::SeqCst);
+        RUNNING.busy.store(false, Ordering::SeqCst);
+        RUNNING.kill.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Whether a script has been running for longer than `lua-time-limit`.
+pub fn is_busy() -> bool {
+    RUNNING.busy.load(Ordering::SeqCst)
+}
+
+/// Asks the running script to stop, which only scripts that did not write
+/// yet may be asked, as stopping the others would leave half their writes.
+pub fn kill() -> Result<()> {
+    if !RUNNING.active.load(Ordering::SeqCst) {
+        return Err(anyhow!("NOTBUSY No scripts in execution right now."));
+    }
+    if RUNNING.wrote.load(Ordering::SeqCst) {
+        return Err(anyhow!(
+            "UNKILLABLE Sorry the script already executed write commands against the dataset. You can either wait the script termination or kill the server in a hard way."
+        ));
+    }
+    RUNNING.kill.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Script bodies by the hex SHA1 digest `EVALSHA` refers to them with.
+#[derive(Debug, Default)]
+pub struct ScriptCache {
+    scripts: HashMap<String, Bytes>,
+}
+
+impl ScriptCache {
+    /// Caches `body`, returning its digest.
+    pub fn insert(&mut self, body: Bytes) -> String {
+        let sha1 = sha1_hex(&body);
+        self.scripts.insert(sha1.clone(), body);
+        sha1
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<Bytes> {
+        self.scripts.get(&sha1.to_lowercase()).cloned()
+    }
+
+    pub fn contains(&self, sha1: &str) -> bool {
+        self.scripts.contains_key(&sha1.to_lowercase())
+    }
+
+    pub fn clear(&mut self) {
+        self.scripts.clear();
+    }
+}
+
+pub fn sha1_hex(body: &[u8]) -> String {
+    sha1_smol::Sha1::from(body).digest().to_string()
+}
+
+/// What a run executes.
+#[derive(Clone, Copy)]
+enum Entry<'a> {
+    Script(&'a [u8]),
+    /// The function `name` of the library made of `code`.
+    Function {
+        code: &'a [u8],
+        name: &'a str,
+    },
+}
+
+/// Runs a script with its `KEYS` and `ARGV` tables. The database stays
+/// locked for the whole run, so that scripts are atomic, and every write
+/// the script makes through `redis.call` is propagated on its own.
+pub fn eval(db: &mut Db, body: &[u8], keys: Vec<Bytes>, args: Vec<Bytes>) -> Result<RespValue> {
+    run_blocking(db, Entry::Script(body), &keys, &args, false)
+}
+
+/// Calls the function `name` of the library made of `code` with the keys
+/// and arguments as its two parameters, the way `eval` runs scripts. A
+/// `read_only` function may not call commands that write.
+pub fn fcall(
+    db: &mut Db,
+    code: &[u8],
+    name: &str,
+    keys: Vec<Bytes>,
+    args: Vec<Bytes>,
+    read_only: bool,
+) -> Result<RespValue> {
+    run_blocking(db, Entry::Function { code, name }, &keys, &args, read_only)
+}
+
+fn run_blocking(
+    db: &mut Db,
+    entry: Entry,
+    keys: &[Bytes],
+    args: &[Bytes],
+    read_only: bool,
+) -> Result<RespValue> {
+    // A long script must not hold up the other tasks of its runtime worker,
+    // or nobody could be told the server is busy, nor kill the script.
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| run(db, entry, keys, args, read_only))
+        }
+        _ => run(db, entry, keys, args, read_only),
+    }
+}
+
+fn run(
+    db: &mut Db,
+    entry: Entry,
+    keys: &[Bytes],
+    args: &[Bytes],
+    read_only: bool,
+) -> Result<RespValue> {
+    let lua = new_lua()?;
+    let time_limit = db.config().lua_time_limit;
+    let db = RefCell::new(db);
+    let callbacks = lua.create_table().map_err(|e| anyhow!("ERR {e}"))?;
+
+    let _running = RunningGuard::start();
+    let started = Instant::now();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| {
+            if started.elapsed() >= time_limit {
+                RUNNING.busy.store(true, Ordering::SeqCst);
+            }
+            if RUNNING.kill.load(Ordering::SeqCst) {
+                return Err(mlua::Error::RuntimeError(
+                    "ERR Script killed by user with SCRIPT KILL...".to_string(),
+                ));
+            }
+            Ok(())
+        },
+    );
+
+    let result = lua.scope(|scope| {
+        let redis = lua.create_table()?;
+        redis.set(
+            "error_reply",
+            lua.create_function(|lua, message: mlua::String| reply_table(lua, "err", message))?,
+        )?;
+        redis.set(
+            "status_reply",
+            lua.create_function(|lua, message: mlua::String| reply_table(lua, "ok", message))?,
+        )?;
+        redis.set(
+            "sha1hex",
+            lua.create_function(|_, body: mlua::String| Ok(sha1_hex(body.as_bytes())))?,
+        )?;
+
+        let globals = lua.globals();
+        globals.set("redis", redis.clone())?;
+        if let Entry::Script(_) = entry {
+            globals.set("KEYS", to_lua_strings(&lua, keys)?)?;
+            globals.set("ARGV", to_lua_strings(&lua, args)?)?;
+        }
+        sandbox(&lua)?;
+
+        // The top level of a library only registers its functions, and
+        // runs before any command can be called.
+        if let Entry::Function { code, .. } = entry {
+            redis.set(
+                "register_function",
+                scope.create_function(|_, args: Variadic<Value>| {
+                    let registration = functions::parse_registration(args)?;
+                    callbacks.set(registration.function.name, registration.callback)
+                })?,
+            )?;
+            lua.load(functions::library_body(code))
+                .set_name("=user_function")
+                .exec()?;
+            redis.set("register_function", Value::Nil)?;
+        }
+
+        redis.set(
+            "call",
+            scope.create_function(|lua, argv: Variadic<mlua::String>| {
+                match call(&mut db.borrow_mut(), &argv, read_only) {
+                    RespValue::SimpleError(message) => Err(mlua::Error::RuntimeError(message)),
+                    reply => to_lua(lua, reply),
+                }
+            })?,
+        )?;
+        redis.set(
+            "pcall",
+            scope.create_function(|lua, argv: Variadic<mlua::String>| {
+                to_lua(lua, call(&mut db.borrow_mut(), &argv, read_only))
+            })?,
+        )?;
+
+        let value: Value = match entry {
+            Entry::Script(body) => lua.load(body).set_name("=user_script").eval()?,
+            Entry::Function { name, .. } => callbacks
+                .get::<_, mlua::Function>(name)?
+                .call((to_lua_strings(&lua, keys)?, to_lua_strings(&lua, args)?))?,
+        };
+        Ok(from_lua(value))
+    });
+
+    // Lua messages may carry a stack traceback, which an error reply
+    // cannot hold.
+    result.or_else(|e| match e {
+        // A failed `redis.call` replies with the command's own error.
+        mlua::Error::CallbackError { ref cause, .. } => match root_cause(cause) {
+            mlua::Error::RuntimeError(message) => Ok(RespValue::SimpleError(message.clone())),
+            cause => Err(anyhow!(
+                "ERR Error running script: {}",
+                first_line(&cause.to_string())
+            )),
+        },
+        mlua::Error::SyntaxError { message, .. } => Err(anyhow!(
+            "ERR Error compiling script: {}",
+            first_line(&message)
+        )),
+        mlua::Error::RuntimeError(message) => Err(anyhow!(
+            "ERR Error running script: {}",
+            first_line(&message)
+        )),
+        e => Err(anyhow!(
+            "ERR Error running script: {}",
+            first_line(&e.to_string())
+        )),
+    })
+}
+
+/// Checks that `body` compiles, as `SCRIPT LOAD` does before caching it.
+pub fn compile(body: &[u8]) -> Result<()> {
+    new_lua()?
+        .load(body)
+        .set_name("=user_script")
+        .into_function()
+        .map(|_| ())
+        .map_err(|e| match e {
+            mlua::Error::SyntaxError { message, .. } => {
+                anyhow!("ERR Error compiling script: {}", first_line(&message))
+            }
+            e => anyhow!("ERR {}", first_line(&e.to_string())),
+        })
+}
+
+/// Makes creating globals an error: scripts must not leave state behind,
+/// nor rely on it.
+fn sandbox(lua: &Lua) -> mlua::Result<()> {
+    lua.load(
+        "setmetatable(_G, { __newindex = function(_, name) \
+            error(\"Script attempted to create global variable '\" .. tostring(name) .. \"'\", 2) \
+        end })",
+    )
+    .set_name("=sandbox")
+    .exec()
+}
+
+/// A Lua state with only the libraries that cannot reach outside the
+/// server.
+fn new_lua() -> Result<Lua> {
+    Lua::new_with(
+        StdLib::TABLE | StdLib::STRING | StdLib::MATH,
+        LuaOptions::default(),
+    )
+    .map_err(|e| anyhow!("ERR {e}"))
+}
+
+fn first_line(message: &str) -> &str {
+    message.lines().next().unwrap_or_default()
+}
+
+/// Runs one command on behalf of a script, errors included in the reply.
+fn call(db: &mut Db, argv: &[mlua::String], read_only: bool) -> RespValue {
+    if argv.is_empty() {
+        return RespValue::SimpleError(
+            "ERR Please specify at least one argument for this redis lib call".to_string(),
+        );
+    }
+    let argv: Vec<RespValue> = argv
+        .iter()
+        .map(|arg| RespValue::BulkString(Bytes::copy_from_slice(arg.as_bytes())))
+        .collect();
+    let command = extract_command(RespValue::Array(argv.clone()))
+        .and_then(|(name, args)| parse_command(name, args));
+    let result = match command {
+        Ok(
+            Command::Eval { .. }
+            | Command::Evalsha { .. }
+            | Command::Fcall { .. }
+            | Command::FunctionLoad { .. }
+            | Command::FunctionDelete { .. }
+            | Command::FunctionFlush
+            | Command::Shutdown { .. },
+        ) => Err(anyhow!("ERR This Redis command is not allowed from script")),
+        Ok(_) if read_only && is_write(&argv) => Err(anyhow!(
+            "ERR Write commands are not allowed from read-only scripts."
+        )),
+        Ok(_) if is_write(&argv) && db.is_read_only_replica() => Err(anyhow!(READONLY)),
+        Ok(command) => {
+            db.clients().feed_monitors("lua", &argv);
+            if is_write(&argv) {
+                RUNNING.wrote.store(true, Ordering::SeqCst);
+            }
+            command.apply_and_propagate(db, argv)
+        }
+        Err(e) => Err(e),
+    };
+    result.unwrap_or_else(|e| RespValue::SimpleError(format!("{e}")))
+}
+
+fn root_cause(mut error: &mlua::Error) -> &mlua::Error {
+    while let mlua::Error::CallbackError { cause, .. } = error {
+        error = cause;
+    }
+    error
+}
+
+fn reply_table<'lua>(
+    lua: &'lua Lua,
+    field: &str,
+    message: mlua::String,
+) -> mlua::Result<Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set(field, message)?;
+    Ok(table)
+}
+
+fn to_lua_strings<'lua>(lua: &'lua Lua, values: &[Bytes]) -> mlua::Result<Table<'lua>> {
+    lua.create_sequence_from(
+        values
+            .iter()
+            .map(|value| lua.create_string(value))
+            .collect::<mlua::Result<Vec<_>>>()?,
+    )
+}
+
+/// Converts a command reply the way Redis hands it to scripts: nulls become
+/// `false`, status and error replies `{ok = ...}` and `{err = ...}` tables.
+fn to_lua(lua: &Lua, reply: RespValue) -> mlua::Result<Value<'_>> {
+    Ok(match reply {
+        RespValue::SimpleString(status) => {
+            Value::Table(reply_table(lua, "ok", lua.create_string(status)?)?)
+        }
+        RespValue::SimpleError(message) => {
+            Value::Table(reply_table(lua, "err", lua.create_string(message)?)?)
+        }
+        RespValue::Integer(integer) => Value::Integer(integer),
+        RespValue::Boolean(boolean) => Value::Integer(boolean as i64),
+        RespValue::BulkString(bytes) => Value::String(lua.create_string(&bytes)?),
+        RespValue::RdbFile(bytes) => Value::String(lua.create_string(&bytes)?),
+        RespValue::Double(double) => Value::String(lua.create_string(format_score(double))?),
+        RespValue::BigNumber(digits) => Value::String(lua.create_string(digits)?),
+        RespValue::NullBulkString | RespValue::NullArray => Value::Boolean(false),
+        RespValue::Array(items) | RespValue::Set(items) | RespValue::Push(items) => {
+            let table = lua.create_table()?;
+            for item in items {
+                table.raw_push(to_lua(lua, item)?)?;
+            }
+            Value::Table(table)
+        }
+        RespValue::Map(pairs) => {
+            let table = lua.create_table()?;
+            for (key, value) in pairs {
+                table.raw_push(to_lua(lua, key)?)?;
+                table.raw_push(to_lua(lua, value)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
+
+/// Converts what a script returns into its reply: numbers are truncated to
+/// integers, `false` and `nil` become a null, and arrays stop at their first
+/// `nil`.
+fn from_lua(value: Value) -> RespValue {
+    match value {
+        Value::Boolean(true) => RespValue::Integer(1),
+        Value::Integer(integer) => RespValue::Integer(integer),
+        Value::Number(number) => RespValue::Integer(number as i64),
+        Value::String(string) => RespValue::BulkString(Bytes::copy_from_slice(string.as_bytes())),
+        Value::Table(table) => {
+            if let Ok(mlua::Value::String(message)) = table.raw_get("err") {
+                return RespValue::SimpleError(message.to_string_lossy().into_owned());
+            }
+            if let Ok(mlua::Value::String(status)) = table.raw_get("ok") {
+                return RespValue::SimpleString(status.to_string_lossy().into_owned());
+            }
+            let mut items = vec![];
+            for index in 1.. {
+                match table.raw_get::<_, Value>(index) {
+                    Ok(Value::Nil) | Err(_) => break,
+                    Ok(item) => items.push(from_lua(item)),
+                }
+            }
+            RespValue::Array(items)
+        }
+        _ => RespValue::NullBulkString,
+    }
+}
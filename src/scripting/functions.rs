@@ -0,0 +1,334 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use mlua::{HookTriggers, Table, Value, Variadic};
+
+use super::{HOOK_INSTRUCTIONS, first_line, new_lua, root_cause, sandbox};
+
+/// Time the top level of a library may run for on `FUNCTION LOAD`.
+const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The flags a function may be registered with. Only `no-writes` changes
+/// anything here, the others are accepted for compatibility.
+const FUNCTION_FLAGS: [&str; 5] = [
+    "no-writes",
+    "allow-oom",
+    "allow-stale",
+    "no-cluster",
+    "allow-cross-slot-keys",
+];
+
+/// A library of functions loaded with `FUNCTION LOAD`.
+#[derive(Clone, Debug)]
+pub struct FunctionLibrary {
+    pub name: String,
+    /// The code as loaded, metadata line included.
+    pub code: Bytes,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub description: Option<String>,
+    pub flags: Vec<String>,
+}
+
+impl Function {
+    /// Whether the function was registered with `no-writes`, which makes it
+    /// callable with `FCALL_RO` but forbids it any write.
+    pub fn is_read_only(&self) -> bool {
+        self.flags.iter().any(|flag| flag == "no-writes")
+    }
+}
+
+/// The loaded libraries by name.
+#[derive(Debug, Default)]
+pub struct FunctionRegistry {
+    libraries: BTreeMap<String, FunctionLibrary>,
+}
+
+impl FunctionRegistry {
+    /// Loads the library `code` declares, replacing the one with the same
+    /// name only with `replace`. Returns the library name.
+    pub fn load(&mut self, code: Bytes, replace: bool) -> Result<String> {
+        let name = library_name(&code)?;
+        if !replace && self.libraries.contains_key(&name) {
+            bail!("ERR Library '{name}' already exists");
+        }
+        let library = load_library(name.clone(), code)?;
+        for function in &library.functions {
+            if self.libraries.values().any(|other| {
+                other.name != name && other.functions.iter().any(|f| f.name == function.name)
+            }) {
+                bail!("ERR Function {} already exists", function.name);
+            }
+        }
+        self.libraries.insert(name.clone(), library);
+        Ok(name)
+    }
+
+    /// The function called `name` and the library it belongs to.
+    pub fn get(&self, name: &str) -> Option<(&FunctionLibrary, &Function)> {
+        self.libraries.values().find_map(|library| {
+            library
+                .functions
+                .iter()
+                .find(|function| function.name == name)
+                .map(|function| (library, function))
+        })
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        self.libraries
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| anyhow!("ERR Library not found"))
+    }
+
+    pub fn clear(&mut self) {
+        self.libraries.clear();
+    }
+
+    pub fn libraries(&self) -> impl Iterator<Item = &FunctionLibrary> {
+        self.libraries.values()
+    }
+
+    pub fn function_count(&self) -> usize {
+        self.libraries
+            .values()
+            .map(|library| library.functions.len())
+            .sum()
+    }
+}
+
+/// Reads the library name from the `#!lua name=<name>` line code starts
+/// with.
+fn library_name(code: &[u8]) -> Result<String> {
+    let Some(metadata) = code.strip_prefix(b"#!") else {
+        bail!("ERR Missing library metadata");
+    };
+    let line = metadata.split(|&byte| byte == b'\n').next().unwrap_or(b"");
+    let line = String::from_utf8_lossy(line);
+    let mut parts = line.split_whitespace();
+    let engine = parts.next().unwrap_or_default();
+
+    let mut name = None;
+    for part in parts {
+        match part.strip_prefix("name=") {
+            Some(value) => name = Some(value.to_string()),
+            None => bail!("ERR Invalid metadata value given: {part}"),
+        }
+    }
+    if !engine.eq_ignore_ascii_case("lua") {
+        bail!("ERR Engine '{engine}' not found");
+    }
+    let name = name.ok_or_else(|| anyhow!("ERR Library name was not given"))?;
+    if !is_valid_name(&name) {
+        bail!(
+            "ERR Library names can only contain letters, numbers, or underscores(_) and must be at least one character long"
+        );
+    }
+    Ok(name)
+}
+
+/// The code of a library without its metadata line, which is not Lua. The
+/// line break is kept so that error messages have the right line numbers.
+pub(super) fn library_body(code: &[u8]) -> &[u8] {
+    let start = code
+        .iter()
+        .position(|&byte| byte == b'\n')
+        .unwrap_or(code.len());
+    &code[start..]
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_')
+}
+
+/// Runs the top level of a library, which may do nothing but register
+/// its functions.
+fn load_library(name: String, code: Bytes) -> Result<FunctionLibrary> {
+    let lua = new_lua()?;
+    let started = Instant::now();
+    lua.set_hook(
+        HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS),
+        move |_, _| {
+            if started.elapsed() >= LOAD_TIMEOUT {
+                return Err(mlua::Error::RuntimeError(
+                    "FUNCTION LOAD timeout".to_string(),
+                ));
+            }
+            Ok(())
+        },
+    );
+
+    let functions = RefCell::new(Vec::<Function>::new());
+    let result = lua.scope(|scope| {
+        let redis = lua.create_table()?;
+        redis.set(
+            "register_function",
+            scope.create_function(|_, args: Variadic<Value>| {
+                let registration = parse_registration(args)?;
+                let mut functions = functions.borrow_mut();
+                if functions
+                    .iter()
+                    .any(|function| function.name == registration.function.name)
+                {
+                    return Err(mlua::Error::RuntimeError(
+                        "Function already exists in the library".to_string(),
+                    ));
+                }
+                functions.push(registration.function);
+                Ok(())
+            })?,
+        )?;
+        lua.globals().set("redis", redis)?;
+        sandbox(&lua)?;
+        lua.load(library_body(&code))
+            .set_name("=user_function")
+            .exec()
+    });
+
+    result.map_err(|e| match e {
+        mlua::Error::SyntaxError { message, .. } => {
+            anyhow!("ERR Error compiling function: {}", first_line(&message))
+        }
+        e => match root_cause(&e) {
+            mlua::Error::RuntimeError(message) => {
+                anyhow!("ERR Error registering functions: {}", first_line(message))
+            }
+            cause => anyhow!(
+                "ERR Error registering functions: {}",
+                first_line(&cause.to_string())
+            ),
+        },
+    })?;
+
+    let functions = functions.into_inner();
+    if functions.is_empty() {
+        bail!("ERR No functions registered");
+    }
+    Ok(FunctionLibrary {
+        name,
+        code,
+        functions,
+    })
+}
+
+/// A function as `redis.register_function` received it.
+pub(super) struct Registration<'lua> {
+    pub callback: mlua::Function<'lua>,
+    pub function: Function,
+}
+
+/// Reads the arguments of `redis.register_function`, either a name and a
+/// callback or a table with the named arguments `function_name`,
+/// `callback`, `flags` and `description`.
+pub(super) fn parse_registration(args: Variadic<Value>) -> mlua::Result<Registration> {
+    let error = |message: &str| mlua::Error::RuntimeError(message.to_string());
+
+    let mut name = None;
+    let mut callback = None;
+    let mut flags = vec![];
+    let mut description = None;
+    match args.len() {
+        1 => {
+            let Value::Table(table) = &args[0] else {
+                return Err(error(
+                    "calling redis.register_function with a single argument is only applicable to Lua table (representing named arguments).",
+                ));
+            };
+            for pair in table.clone().pairs::<Value, Value>() {
+                let (key, value) = pair?;
+                let Value::String(key) = key else {
+                    return Err(error(
+                        "named argument key given to redis.register_function is not a string",
+                    ));
+                };
+                match key.as_bytes() {
+                    b"function_name" => name = Some(value),
+                    b"callback" => callback = Some(value),
+                    b"description" => match value {
+                        Value::String(value) => {
+                            description = Some(value.to_string_lossy().into_owned())
+                        }
+                        _ => {
+                            return Err(error(
+                                "description argument given to redis.register_function must be a string",
+                            ));
+                        }
+                    },
+                    b"flags" => {
+                        let Value::Table(value) = value else {
+                            return Err(error(
+                                "flags argument to redis.register_function must be a table representing function flags",
+                            ));
+                        };
+                        flags = parse_flags(value)?;
+                    }
+                    _ => return Err(error("unknown argument given to redis.register_function")),
+                }
+            }
+        }
+        2 => {
+            name = Some(args[0].clone());
+            callback = Some(args[1].clone());
+        }
+        _ => {
+            return Err(error(
+                "wrong number of arguments to redis.register_function",
+            ));
+        }
+    }
+
+    let Some(Value::String(name)) = name else {
+        return Err(error(
+            "redis.register_function must get a function name argument",
+        ));
+    };
+    let Some(Value::Function(callback)) = callback else {
+        return Err(error(
+            "redis.register_function must get a callback argument",
+        ));
+    };
+    let name = name.to_string_lossy().into_owned();
+    if !is_valid_name(&name) {
+        return Err(error(
+            "Function names can only contain letters, numbers, or underscores(_) and must be at least one character long",
+        ));
+    }
+    Ok(Registration {
+        callback,
+        function: Function {
+            name,
+            description,
+            flags,
+        },
+    })
+}
+
+fn parse_flags(table: Table) -> mlua::Result<Vec<String>> {
+    let mut flags = vec![];
+    for flag in table.sequence_values::<Value>() {
+        let flag = match flag? {
+            Value::String(flag) => flag.to_string_lossy().into_owned(),
+            _ => return Err(mlua::Error::RuntimeError("unknown flag given".to_string())),
+        };
+        if !FUNCTION_FLAGS.contains(&flag.as_str()) {
+            return Err(mlua::Error::RuntimeError("unknown flag given".to_string()));
+        }
+        if !flags.contains(&flag) {
+            flags.push(flag);
+        }
+    }
+    Ok(flags)
+}
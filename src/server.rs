@@ -0,0 +1,489 @@
+//! Serving clients: over TCP and TLS with `run`, as the `redis-server`
+//! binary does, or in process through a `Server` embedded in another Tokio
+//! application.
+
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, LazyLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow, bail};
+use bytes::Bytes;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+    sync::{RwLock, watch},
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    clients, cluster,
+    config::Config,
+    connection::{ConnectionState, Shared},
+    db::{Db, expiry},
+    persistence::{
+        self,
+        aof::{self, Aof},
+    },
+    replication,
+    resp::{self, ProtocolError, ProtocolLimits, RespValue},
+    shutdown, stats,
+    stats::STATS,
+    tls,
+};
+
+/// The address in-process clients show in `CLIENT LIST`.
+const IN_PROCESS_ADDR: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::LOCALHOST), 0);
+
+/// The dataset with the tasks that maintain it: active expiry, save
+/// points, idle client timeouts and replication from `replicaof`.
+pub struct Server {
+    db: Arc<RwLock<Db>>,
+    shared: Shared,
+    protocol_limits: watch::Receiver<ProtocolLimits>,
+    tcp_keepalive: watch::Receiver<u64>,
+}
+
+impl Server {
+    /// Loads the dataset `config` points at, from the append-only file when
+    /// `appendonly` is on and from the RDB file otherwise, and starts the
+    /// background tasks. Must be called within a Tokio runtime.
+    pub fn new(config: Config) -> Self {
+        LazyLock::force(&stats::STARTED);
+        let replicaof = config.replicaof.clone();
+        let mut db = Db::new(config);
+        let aof_path = db.config().aof_path();
+        if db.config().appendonly {
+            if let Err(e) = aof::replay(&mut db, &aof_path) {
+                error!("Error replaying {}: {e}", aof_path.display());
+            }
+            match Aof::open(&aof_path, db.config().appendfsync) {
+                Ok(aof) => db.set_aof(aof),
+                Err(e) => error!("Error opening {}: {e}", aof_path.display()),
+            }
+        } else if let Err(e) = persistence::load(&mut db) {
+            error!("Error loading {}: {e}", db.config().rdb_path().display());
+        }
+        let shared = Shared {
+            clients: db.clients().clone(),
+            acl: db.acl().clone(),
+            cluster: db.cluster().clone(),
+        };
+        let protocol_limits = db.watch_protocol_limits();
+        let tcp_keepalive = db.watch_tcp_keepalive();
+        let db = Arc::new(RwLock::new(db));
+        if let Some((host, port)) = replicaof {
+            // No task holds the lock yet.
+            let mut db_g = db.try_write().expect("the Db was just created");
+            replication::replicaof(db.clone(), &mut db_g, host, port);
+        }
+        expiry::spawn_active_expire(db.clone());
+        persistence::spawn_save_points(db.clone());
+        clients::spawn_idle_timeout(db.clone());
+        Self {
+            db,
+            shared,
+            protocol_limits,
+            tcp_keepalive,
+        }
+    }
+
+    /// The dataset, for direct access under its lock.
+    pub fn db(&self) -> &Arc<RwLock<Db>> {
+        &self.db
+    }
+
+    /// Registers an in-process client, which runs commands like a
+    /// connected one, `AUTH`, transactions and subscriptions included.
+    pub fn connect(&self) -> Client {
+        Client {
+            state: ConnectionState::new(self.shared.clone(), IN_PROCESS_ADDR, IN_PROCESS_ADDR),
+            db: self.db.clone(),
+        }
+    }
+
+    /// Serves a client connected over `stream`, any byte stream such as a
+    /// Unix socket or an in-memory `DuplexStream`, until it disconnects or
+    /// is killed, as if it came from a TCP connection.
+    pub fn serve<S: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
+        &self,
+        stream: S,
+    ) -> impl Future<Output = Result<()>> + Send + 'static {
+        let handler = resp::RespHandler::new(AnyStream(stream), *self.protocol_limits.borrow());
+        let state = ConnectionState::new(self.shared.clone(), IN_PROCESS_ADDR, IN_PROCESS_ADDR);
+        serve(handler, state, self.db.clone())
+    }
+
+    /// Accepts clients on `listeners`, each with the TLS acceptor wrapping
+    /// its connections if any, until the returned tasks are aborted.
+    fn accept(
+        &self,
+        listeners: Vec<(TcpListener, Option<TlsAcceptor>)>,
+    ) -> Vec<tokio::task::JoinHandle<()>> {
+        listeners
+            .into_iter()
+            .map(|(listener, tls)| {
+                tokio::spawn(accept_loop(
+                    listener,
+                    tls,
+                    self.db.clone(),
+                    self.protocol_limits.clone(),
+                    self.tcp_keepalive.clone(),
+                    self.shared.clone(),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// A client running commands in process, without a connection.
+pub struct Client {
+    state: ConnectionState,
+    db: Arc<RwLock<Db>>,
+}
+
+impl Client {
+    /// Runs the command made of `args`, the name first. Returns `None` when
+    /// the replies come as out-of-band frames instead, as for `SUBSCRIBE`.
+    pub async fn call<I>(&mut self, args: I) -> Option<RespValue>
+    where
+        I: IntoIterator,
+        I::Item: Into<Bytes>,
+    {
+        let args = args
+            .into_iter()
+            .map(|arg| RespValue::BulkString(arg.into()))
+            .collect();
+        self.state
+            .handle_value(RespValue::Array(args), &self.db)
+            .await
+    }
+
+    /// Waits for the next out-of-band frame, such as a pub/sub message.
+    pub async fn next_message(&mut self) -> Option<RespValue> {
+        self.state.next_message().await
+    }
+
+    /// Unregisters the client, dropping its subscriptions.
+    pub async fn close(mut self) {
+        self.state.close(&self.db).await;
+    }
+}
+
+/// Listens for clients as `config` says, the cluster bus included, and
+/// serves them until a signal asks to stop; then lets the connections
+/// finish their commands and saves the dataset.
+pub async fn run(config: Config) -> Result<()> {
+    let listeners = bind(&config).await?;
+    let bus_listeners = if config.cluster_enabled {
+        bind_port(&config, config.cluster_bus_port())
+            .await
+            .map_err(|e| anyhow!("Could not open the cluster bus: {e}"))?
+    } else {
+        vec![]
+    };
+    info!(
+        port = config.port,
+        tls_port = config.tls_port,
+        "Ready to accept connections"
+    );
+
+    let server = Server::new(config);
+    if server.shared.cluster.is_enabled() {
+        cluster::bus::spawn(server.shared.cluster.clone(), bus_listeners);
+    }
+    let accept_loops = server.accept(listeners);
+    // Stop taking connections and let the open ones finish their commands,
+    // then save under the `Db` lock.
+    let signal = shutdown::signal_received().await;
+    for accept_loop in &accept_loops {
+        accept_loop.abort();
+    }
+    warn!("Received {signal}, scheduling shutdown");
+    shutdown::drain(&server.shared.clients).await;
+    let db_g = server.db.read().await;
+    shutdown::prepare(&db_g, None)
+}
+
+async fn handle_conn(
+    stream: TcpStream,
+    tls: Option<TlsAcceptor>,
+    db: Arc<RwLock<Db>>,
+    protocol_limits: watch::Receiver<ProtocolLimits>,
+    shared: Shared,
+) -> Result<()> {
+    let (addr, laddr) = (stream.peer_addr()?, stream.local_addr()?);
+    let limits = *protocol_limits.borrow();
+    match tls {
+        Some(acceptor) => {
+            let stream = acceptor
+                .accept(stream)
+                .await
+                .map_err(|e| anyhow!("TLS handshake with {addr} failed: {e}"))?;
+            let state = ConnectionState::new(shared, addr, laddr);
+            serve(resp::RespHandler::new(stream, limits), state, db).await
+        }
+        None => {
+            let state = ConnectionState::new(shared, addr, laddr);
+            serve(resp::RespHandler::new(stream, limits), state, db).await
+        }
+    }
+}
+
+/// A stream clients are served over, with the TCP socket under it if any.
+trait ClientStream: AsyncRead + AsyncWrite + Unpin {
+    fn tcp(&self) -> Option<&TcpStream>;
+}
+
+impl ClientStream for TcpStream {
+    fn tcp(&self) -> Option<&TcpStream> {
+        Some(self)
+    }
+}
+
+impl ClientStream for tokio_rustls::server::TlsStream<TcpStream> {
+    fn tcp(&self) -> Option<&TcpStream> {
+        Some(self.get_ref().0)
+    }
+}
+
+/// A stream with no TCP socket to tune under it.
+struct AnyStream<S>(S);
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ClientStream for AnyStream<S> {
+    fn tcp(&self) -> Option<&TcpStream> {
+        None
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for AnyStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for AnyStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Makes the kernel acknowledge the client's next segments right away
+/// rather than delay the ACK. Linux drops out of quick ACK mode on its own,
+/// so it is renewed after every reply.
+fn quickack(stream: &TcpStream) {
+    #[cfg(target_os = "linux")]
+    if let Err(e) = socket2::SockRef::from(stream).set_quickack(true) {
+        warn!("Failed to set TCP_QUICKACK: {e}");
+    }
+}
+
+/// Sends replies as soon as they are written, instead of holding small
+/// ones back until the client acknowledges the previous ones.
+fn nodelay(stream: &TcpStream) {
+    if let Err(e) = stream.set_nodelay(true) {
+        warn!("Failed to set TCP_NODELAY: {e}");
+    }
+}
+
+/// Has the kernel probe a connection idle for `interval` seconds, then
+/// every third of that up to 3 times, as Redis does for `tcp-keepalive`, so
+/// that peers gone without closing the connection are detected. 0 leaves
+/// keepalive off.
+fn keepalive(stream: &TcpStream, interval: u64) {
+    if interval == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(interval);
+    let keepalive = socket2::TcpKeepalive::new().with_time(interval);
+    #[cfg(target_os = "linux")]
+    let keepalive = keepalive
+        .with_interval((interval / 3).max(Duration::from_secs(1)))
+        .with_retries(3);
+    if let Err(e) = socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+        warn!("Failed to set SO_KEEPALIVE: {e}");
+    }
+}
+
+/// Serves the requests of a client until it disconnects or is killed.
+async fn serve<S: ClientStream>(
+    mut handler: resp::RespHandler<S>,
+    mut state: ConnectionState,
+    db: Arc<RwLock<Db>>,
+) -> Result<()> {
+    let info = state.info();
+    let clients = state.clients();
+    STATS.client_connected();
+    debug!(client = info.id, addr = %info.addr, "Accepted connection");
+
+    let result = async {
+        loop {
+            tokio::select! {
+                biased;
+                _ = info.killed() => break,
+                // Replies to the commands run were flushed, and blocked
+                // ones were woken up with the reply of a timeout.
+                _ = clients.shutting_down() => break,
+                Some(message) = state.next_message() => {
+                    // A client that stopped reading may be killed for its
+                    // output piling up while the write is stuck.
+                    tokio::select! {
+                        biased;
+                        _ = info.killed() => break,
+                        written = handler.write_value(message) => written?,
+                    }
+                }
+                inputs = handler.read_values() => {
+                    let inputs = match inputs {
+                        Ok(Some(inputs)) => inputs,
+                        Ok(None) => break,
+                        Err(e) if e.is::<ProtocolError>() => {
+                            handler.write_value(RespValue::SimpleError(format!("ERR {e}"))).await?;
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    };
+                    let mut killed = false;
+                    for input in inputs {
+                        // A killed client is closed even while blocked in a
+                        // command.
+                        let response = tokio::select! {
+                            biased;
+                            _ = info.killed() => {
+                                killed = true;
+                                break;
+                            }
+                            response = state.handle_value(input, &db) => response,
+                        };
+                        handler.set_protocol(state.protocol());
+                        // Frames queued by this command, such as subscription
+                        // confirmations, precede the replies to later ones.
+                        while let Some(message) = state.try_next_message() {
+                            handler.queue_value(message);
+                        }
+                        if let Some(response) = response {
+                            handler.queue_streamed(response).await?;
+                        }
+                    }
+                    handler.flush().await?;
+                    if state.quickack()
+                        && let Some(stream) = handler.stream().tcp()
+                    {
+                        quickack(stream);
+                    }
+                    if killed {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    state.close(&db).await;
+    handler.shutdown().await;
+    debug!(client = info.id, addr = %info.addr, "Client closed connection");
+    result
+}
+
+/// Listens on `port` and, for TLS connections, `tls-port` unless they are
+/// 0. The TLS listeners come with the acceptor wrapping their connections.
+async fn bind(config: &Config) -> Result<Vec<(TcpListener, Option<TlsAcceptor>)>> {
+    let mut listeners = vec![];
+    if config.port != 0 {
+        for listener in bind_port(config, config.port).await? {
+            listeners.push((listener, None));
+        }
+    }
+    if config.tls_port != 0 {
+        let acceptor = tls::acceptor(config)?;
+        for listener in bind_port(config, config.tls_port).await? {
+            listeners.push((listener, Some(acceptor.clone())));
+        }
+    }
+    if listeners.is_empty() {
+        bail!("No port to listen on");
+    }
+    Ok(listeners)
+}
+
+/// Listens on `port` of every `bind` address. Those starting with `-` may
+/// be unavailable, such as IPv6 ones on a host without IPv6.
+async fn bind_port(config: &Config, port: u16) -> Result<Vec<TcpListener>> {
+    let mut listeners = vec![];
+    for address in &config.bind {
+        let (address, optional) = match address.strip_prefix('-') {
+            Some(address) => (address, true),
+            None => (address.as_str(), false),
+        };
+        let host = match address {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            address => address,
+        };
+        match TcpListener::bind((host, port)).await {
+            Ok(listener) => listeners.push(listener),
+            Err(_) if optional => {}
+            Err(e) => bail!("Could not listen on {address}:{port}: {e}"),
+        }
+    }
+    if listeners.is_empty() {
+        bail!("No address to listen on");
+    }
+    Ok(listeners)
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    db: Arc<RwLock<Db>>,
+    protocol_limits: watch::Receiver<ProtocolLimits>,
+    tcp_keepalive: watch::Receiver<u64>,
+    shared: Shared,
+) {
+    loop {
+        let stream = listener.accept().await;
+        let db_for_stream = db.clone();
+        let protocol_limits = protocol_limits.clone();
+        let shared = shared.clone();
+        let tls = tls.clone();
+        match stream {
+            Ok((stream, _add)) => {
+                nodelay(&stream);
+                keepalive(&stream, *tcp_keepalive.borrow());
+                tokio::spawn(async move {
+                    if let Err(e) =
+                        handle_conn(stream, tls, db_for_stream, protocol_limits, shared).await
+                    {
+                        warn!("Error handling connection: {e}");
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("Error accepting connection: {e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::{clients::ClientRegistry, db::Db, persistence};
+
+/// How long the connections have to close before the server exits anyway.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+const DRAIN_CHECK_PERIOD: Duration = Duration::from_millis(10);
+
+/// Closes every connection once its current command is done and its
+/// replies are written, waking blocked commands as if they timed out, and
+/// waits up to `DRAIN_TIMEOUT` for all of them to be gone.
+pub async fn drain(clients: &ClientRegistry) {
+    clients.shut_down();
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    while clients.count() > 0 {
+        if Instant::now() >= deadline {
+            warn!(
+                clients = clients.count(),
+                "Exiting without waiting for the remaining clients"
+            );
+            return;
+        }
+        tokio::time::sleep(DRAIN_CHECK_PERIOD).await;
+    }
+}
+
+/// Persists what must outlive the server before it exits: an RDB snapshot
+/// when `save` asks for one or, by default, when save points are
+/// configured, and every write of the append-only file.
+pub fn prepare(db: &Db, save: Option<bool>) -> Result<()> {
+    if save.unwrap_or(!db.config().save.is_empty()) {
+        info!("Saving the final RDB snapshot before exiting");
+        persistence::save(db).map_err(|e| anyhow!("Error saving the DB on shutdown: {e}"))?;
+    }
+    if let Some(aof) = db.aof() {
+        aof.sync()
+            .map_err(|e| anyhow!("Error syncing the AOF on shutdown: {e}"))?;
+    }
+    info!("Redis is now ready to exit, bye bye...");
+    Ok(())
+}
+
+/// Completes on `SIGTERM` or `SIGINT`, with the name of the signal.
+pub async fn signal_received() -> &'static str {
+    let mut terminate = signal(SignalKind::terminate()).expect("SIGTERM handler");
+    let mut interrupt = signal(SignalKind::interrupt()).expect("SIGINT handler");
+    tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    }
+}
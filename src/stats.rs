@@ -0,0 +1,56 @@
+use std::sync::{
+    LazyLock,
+    atomic::{AtomicU64, Ordering},
+};
+
+use tokio::time::Instant;
+
+/// When the server started, for the uptime `INFO` reports.
+pub static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// A random identifier of this run of the server.
+pub static RUN_ID: LazyLock<String> = LazyLock::new(|| {
+    sha1_smol::Sha1::from(uuid::Uuid::new_v4().as_bytes())
+        .digest()
+        .to_string()
+});
+
+/// Server-wide counters, kept outside the `Db` so connections can update
+/// them without taking its lock.
+pub struct Stats {
+    pub connections_received: AtomicU64,
+    pub commands_processed: AtomicU64,
+    pub keyspace_hits: AtomicU64,
+    pub keyspace_misses: AtomicU64,
+    pub expired_keys: AtomicU64,
+}
+
+pub static STATS: Stats = Stats {
+    connections_received: AtomicU64::new(0),
+    commands_processed: AtomicU64::new(0),
+    keyspace_hits: AtomicU64::new(0),
+    keyspace_misses: AtomicU64::new(0),
+    expired_keys: AtomicU64::new(0),
+};
+
+impl Stats {
+    pub fn client_connected(&self) {
+        self.connections_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn command_processed(&self) {
+        self.commands_processed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn keyspace_lookup(&self, hit: bool) {
+        if hit {
+            self.keyspace_hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.keyspace_misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn key_expired(&self) {
+        self.expired_keys.fetch_add(1, Ordering::Relaxed);
+    }
+}
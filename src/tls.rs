@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use anyhow::{Result, anyhow};
+use tokio_rustls::{
+    TlsAcceptor,
+    rustls::{
+        ServerConfig,
+        pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    },
+};
+
+use crate::config::Config;
+
+/// The acceptor wrapping connections to `tls-port`, which presents the
+/// certificate chain of `tls-cert-file` signed with the key of
+/// `tls-key-file`. Clients are not asked for a certificate.
+pub fn acceptor(config: &Config) -> Result<TlsAcceptor> {
+    let cert_file = &config.tls_cert_file;
+    let certs = CertificateDer::pem_file_iter(cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            anyhow!(
+                "Could not load TLS certificate {}: {e}",
+                cert_file.display()
+            )
+        })?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificate in {}", cert_file.display()));
+    }
+    let key_file = &config.tls_key_file;
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .map_err(|e| anyhow!("Could not load TLS key {}: {e}", key_file.display()))?;
+    let server_config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| anyhow!("Invalid TLS certificate or key: {e}"))?;
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
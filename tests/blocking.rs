@@ -0,0 +1,168 @@
+mod common;
+
+use std::time::Duration;
+
+use codecrafters_redis::resp::RespValue;
+use common::{TestServer, array, bulk, int};
+
+#[tokio::test]
+async fn blpop_returns_a_pushed_element() {
+    let server = TestServer::start();
+    let mut blocked = server.connect();
+    let mut pusher = server.connect();
+    blocked.send(&["BLPOP", "queue", "0"]).await;
+    assert!(!blocked.has_reply_within(Duration::from_millis(50)).await);
+    assert_eq!(pusher.call(&["RPUSH", "queue", "job"]).await, int(1));
+    assert_eq!(blocked.read().await, array(&["queue", "job"]));
+    assert_eq!(pusher.call(&["LLEN", "queue"]).await, int(0));
+}
+
+#[tokio::test]
+async fn concurrent_pushes_wake_every_blocked_client() {
+    let server = TestServer::start();
+    let mut blocked = vec![];
+    for _ in 0..10 {
+        let mut client = server.connect();
+        client.send(&["BLPOP", "queue", "0"]).await;
+        blocked.push(client);
+    }
+    assert!(!blocked[0].has_reply_within(Duration::from_millis(50)).await);
+    let pushers: Vec<_> = (0..10)
+        .map(|i| {
+            let mut pusher = server.connect();
+            tokio::spawn(async move {
+                let command = if i % 2 == 0 { "RPUSH" } else { "LPUSH" };
+                pusher.call(&[command, "queue", &format!("job{i}")]).await;
+            })
+        })
+        .collect();
+    for pusher in pushers {
+        pusher.await.unwrap();
+    }
+
+    let mut jobs = vec![];
+    for client in &mut blocked {
+        let RespValue::Array(reply) = client.read().await else {
+            panic!("unexpected BLPOP reply");
+        };
+        jobs.push(reply[1].clone());
+    }
+    jobs.sort_by_key(|job| format!("{job:?}"));
+    jobs.dedup();
+    assert_eq!(jobs.len(), 10);
+    assert_eq!(server.connect().call(&["LLEN", "queue"]).await, int(0));
+}
+
+#[tokio::test]
+async fn blocked_clients_stay_queued_while_a_wake_up_is_pending() {
+    let server = TestServer::start();
+    let mut blocked = server.connect();
+    let mut pusher = server.connect();
+    blocked.send(&["BLPOP", "queue", "0"]).await;
+    assert!(!blocked.has_reply_within(Duration::from_millis(50)).await);
+
+    // Both elements are taken before the blocked client wakes up, the second
+    // push finding it already notified.
+    pusher.call(&["MULTI"]).await;
+    for command in [
+        &["RPUSH", "queue", "a"][..],
+        &["LPOP", "queue"],
+        &["RPUSH", "queue", "b"],
+        &["LPOP", "queue"],
+    ] {
+        pusher.call(command).await;
+    }
+    pusher.call(&["EXEC"]).await;
+    assert!(!blocked.has_reply_within(Duration::from_millis(50)).await);
+
+    assert_eq!(pusher.call(&["RPUSH", "queue", "c"]).await, int(1));
+    assert_eq!(blocked.read().await, array(&["queue", "c"]));
+}
+
+#[tokio::test]
+async fn blpop_times_out() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.call(&["BLPOP", "queue", "0.1"]).await,
+        RespValue::NullArray
+    );
+}
+
+#[tokio::test]
+async fn blpop_serves_an_existing_element_right_away() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.call(&["RPUSH", "queue", "a", "b"]).await;
+    assert_eq!(
+        client.call(&["BLPOP", "other", "queue", "0"]).await,
+        array(&["queue", "a"])
+    );
+}
+
+#[tokio::test]
+async fn xread_block_returns_an_added_entry() {
+    let server = TestServer::start();
+    let mut reader = server.connect();
+    let mut writer = server.connect();
+    reader
+        .send(&["XREAD", "BLOCK", "0", "STREAMS", "stream", "$"])
+        .await;
+    assert!(!reader.has_reply_within(Duration::from_millis(50)).await);
+    writer
+        .call(&["XADD", "stream", "1-1", "field", "value"])
+        .await;
+    let reply = reader.read().await;
+    let RespValue::Array(streams) = reply else {
+        panic!("unexpected XREAD reply {reply:?}");
+    };
+    assert_eq!(streams.len(), 1);
+}
+
+#[tokio::test]
+async fn blocked_clients_are_served_one_element_each_in_order() {
+    let server = TestServer::start();
+    let mut blocked = vec![];
+    for _ in 0..3 {
+        let mut client = server.connect();
+        client.send(&["BLPOP", "queue", "0"]).await;
+        // Each client blocks before the next one does.
+        assert!(!client.has_reply_within(Duration::from_millis(50)).await);
+        blocked.push(client);
+    }
+    let mut pusher = server.connect();
+
+    assert_eq!(pusher.call(&["RPUSH", "queue", "a"]).await, int(1));
+    assert_eq!(blocked[0].read().await, array(&["queue", "a"]));
+    assert!(!blocked[1].has_reply_within(Duration::from_millis(50)).await);
+    assert!(!blocked[2].has_reply_within(Duration::from_millis(50)).await);
+
+    assert_eq!(pusher.call(&["RPUSH", "queue", "b", "c"]).await, int(2));
+    assert_eq!(blocked[1].read().await, array(&["queue", "b"]));
+    assert_eq!(blocked[2].read().await, array(&["queue", "c"]));
+    assert_eq!(pusher.call(&["LLEN", "queue"]).await, int(0));
+}
+
+#[tokio::test]
+async fn blocked_clients_are_served_once_the_pushing_command_ran() {
+    let server = TestServer::start();
+    let mut blocked = server.connect();
+    let mut pusher = server.connect();
+    blocked.send(&["BLPOP", "first", "second", "0"]).await;
+    assert!(!blocked.has_reply_within(Duration::from_millis(50)).await);
+
+    // The transaction pops what it pushed before the blocked client is
+    // served, and the client is served from a single key.
+    pusher.call(&["MULTI"]).await;
+    pusher.call(&["RPUSH", "first", "a"]).await;
+    pusher.call(&["LPOP", "first"]).await;
+    pusher.call(&["RPUSH", "second", "b"]).await;
+    pusher.call(&["RPUSH", "first", "c"]).await;
+    assert_eq!(
+        pusher.call(&["EXEC"]).await,
+        RespValue::Array(vec![int(1), bulk("a"), int(1), int(1)])
+    );
+    // The key pushed to first is served first.
+    assert_eq!(blocked.read().await, array(&["first", "c"]));
+    assert_eq!(pusher.call(&["LPOP", "second"]).await, bulk("b"));
+}
@@ -0,0 +1,73 @@
+use bytes::BytesMut;
+use codecrafters_redis::resp::{Codec, Decoded, ProtocolLimits, RespValue};
+
+/// Decodes `header` followed by more digits than any header line may
+/// hold, with no CRLF, returning the error it is rejected with.
+fn unterminated(header: &[u8]) -> String {
+    let codec = Codec::requests(ProtocolLimits::default());
+    let mut buffer = header.to_vec();
+    assert!(matches!(codec.decode_slice(&buffer), Decoded::Incomplete));
+    buffer.extend(std::iter::repeat_n(b'1', 64 * 1024 + 1));
+    match codec.decode_slice(&buffer) {
+        Decoded::Error(e) => e.to_string(),
+        decoded => panic!("unterminated header decoded as {decoded:?}"),
+    }
+}
+
+#[test]
+fn unterminated_multibulk_count_is_rejected() {
+    assert_eq!(
+        unterminated(b"*"),
+        "Protocol error: too big mbulk count string"
+    );
+}
+
+#[test]
+fn unterminated_bulk_count_is_rejected() {
+    assert_eq!(
+        unterminated(b"*1\r\n$"),
+        "Protocol error: too big bulk count string"
+    );
+}
+
+#[test]
+fn empty_requests_are_skipped() {
+    let codec = Codec::requests(ProtocolLimits::default());
+    assert!(matches!(
+        codec.decode_slice(b"*0\r\n*-1\r\n\r\n"),
+        Decoded::Incomplete
+    ));
+    let buffer = b"*0\r\n*-1\r\n\r\n*1\r\n$4\r\nPING\r\n";
+    match codec.decode_slice(buffer) {
+        Decoded::Complete(value, len) => {
+            assert_eq!(
+                value,
+                RespValue::Array(vec![RespValue::BulkString("PING".into())])
+            );
+            assert_eq!(len, buffer.len());
+        }
+        decoded => panic!("PING decoded as {decoded:?}"),
+    }
+}
+
+#[test]
+fn decoded_arguments_do_not_share_the_read_buffer() {
+    let codec = Codec::requests(ProtocolLimits::default());
+    let mut buffer = BytesMut::from(&b"*3\r\n$3\r\nSET\r\n$1\r\nk\r\n$5\r\nvalue\r\n"[..]);
+    let start = buffer.as_ptr() as usize;
+    let end = start + buffer.capacity();
+    let Decoded::Complete(RespValue::Array(args), _) = codec.decode(&mut buffer) else {
+        panic!("SET was not decoded");
+    };
+    assert!(buffer.is_empty());
+    for arg in args {
+        let RespValue::BulkString(arg) = arg else {
+            panic!("argument decoded as {arg:?}");
+        };
+        let ptr = arg.as_ptr() as usize;
+        assert!(
+            !(start..end).contains(&ptr),
+            "{arg:?} points into the buffer"
+        );
+    }
+}
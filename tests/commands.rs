@@ -0,0 +1,562 @@
+mod common;
+
+use std::collections::HashSet;
+
+use codecrafters_redis::resp::RespValue;
+use common::{TestClient, TestServer, array, bulk, int, is_error, ok};
+
+#[tokio::test]
+async fn strings() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["SET", "key", "value"]).await, ok());
+    assert_eq!(client.call(&["GET", "key"]).await, bulk("value"));
+    assert_eq!(client.call(&["APPEND", "key", "s"]).await, int(6));
+    assert_eq!(client.call(&["INCR", "counter"]).await, int(1));
+    assert_eq!(
+        client.call(&["GET", "missing"]).await,
+        RespValue::NullBulkString
+    );
+}
+
+#[tokio::test]
+async fn expirations() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["SET", "key", "value"]).await, ok());
+    assert_eq!(client.call(&["EXPIRETIME", "key"]).await, int(-1));
+    assert_eq!(client.call(&["PEXPIRETIME", "missing"]).await, int(-2));
+
+    // 2100-01-01T00:00:00Z.
+    assert_eq!(
+        client.call(&["PEXPIREAT", "key", "4102444800000"]).await,
+        int(1)
+    );
+    // Expirations are kept as the UNIX time they were given as.
+    assert_eq!(
+        client.call(&["PEXPIRETIME", "key"]).await,
+        int(4102444800000)
+    );
+    assert_eq!(
+        client.call(&["EXPIREAT", "key", "4102444801"]).await,
+        int(1)
+    );
+    assert_eq!(client.call(&["EXPIRETIME", "key"]).await, int(4102444801));
+
+    assert_eq!(client.call(&["PERSIST", "key"]).await, int(1));
+    assert_eq!(client.call(&["PERSIST", "key"]).await, int(0));
+    assert_eq!(client.call(&["PERSIST", "missing"]).await, int(0));
+    assert_eq!(client.call(&["TTL", "key"]).await, int(-1));
+
+    // A deadline in the past deletes the key.
+    assert_eq!(client.call(&["EXPIREAT", "key", "1"]).await, int(1));
+    assert_eq!(client.call(&["EXISTS", "key"]).await, int(0));
+}
+
+#[tokio::test]
+async fn lists() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["RPUSH", "list", "a", "b", "c"]).await, int(3));
+    assert_eq!(client.call(&["LPUSH", "list", "z"]).await, int(4));
+    assert_eq!(
+        client.call(&["LRANGE", "list", "0", "-1"]).await,
+        array(&["z", "a", "b", "c"])
+    );
+    assert_eq!(client.call(&["LPOP", "list"]).await, bulk("z"));
+    assert_eq!(client.call(&["LLEN", "list"]).await, int(3));
+}
+
+#[tokio::test]
+async fn lrange_clamps_indexes() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["RPUSH", "list", "a", "b", "c"]).await, int(3));
+    for (start, stop, expected) in [
+        ("0", "0", &["a"][..]),
+        ("-1", "-1", &["c"]),
+        ("1", "1", &["b"]),
+        ("-100", "1", &["a", "b"]),
+        ("1", "100", &["b", "c"]),
+        ("-100", "100", &["a", "b", "c"]),
+        ("2", "1", &[]),
+        ("3", "5", &[]),
+        ("0", "-4", &[]),
+        ("-100", "-50", &[]),
+    ] {
+        assert_eq!(
+            client.call(&["LRANGE", "list", start, stop]).await,
+            array(expected),
+            "LRANGE list {start} {stop}"
+        );
+    }
+    assert_eq!(
+        client.call(&["LRANGE", "missing", "0", "-1"]).await,
+        array(&[])
+    );
+    assert!(is_error(
+        &client.call(&["LRANGE", "list", "a", "1"]).await,
+        "ERR value is not an integer or out of range"
+    ));
+}
+
+#[tokio::test]
+async fn zrange_rejects_non_integer_indexes() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["ZADD", "zset", "1", "a"]).await, int(1));
+    assert!(is_error(
+        &client.call(&["ZRANGE", "zset", "a", "b"]).await,
+        "ERR value is not an integer or out of range"
+    ));
+    // The connection survives, within a transaction too.
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert_eq!(
+        client.call(&["ZRANGE", "zset", "0", "x"]).await,
+        RespValue::SimpleString("QUEUED".to_string())
+    );
+    let RespValue::Array(replies) = client.call(&["EXEC"]).await else {
+        panic!("EXEC replies with an array");
+    };
+    assert!(is_error(
+        &replies[0],
+        "ERR value is not an integer or out of range"
+    ));
+    assert_eq!(
+        client.call(&["ZRANGE", "zset", "0", "-1"]).await,
+        array(&["a"])
+    );
+}
+
+/// The command line `template` with `{}` replaced by the name of the
+/// element churned at `step`.
+fn churn(template: &str, step: usize) -> Vec<String> {
+    template
+        .replace("{}", &format!("churn:{step}"))
+        .split(' ')
+        .map(str::to_string)
+        .collect()
+}
+
+/// Runs `args` with owned arguments.
+async fn call(client: &mut TestClient, args: &[String]) -> RespValue {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    client.call(&args).await
+}
+
+#[tokio::test]
+async fn scans_return_every_element_present_throughout() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    let names: Vec<String> = (0..500).map(|i| format!("kept:{i}")).collect();
+    // Enough elements for hash tables rather than listpacks.
+    let creates: [(&[&str], &str); 4] = [
+        (&["MSET"], "v"),
+        (&["HSET", "hash"], "v"),
+        (&["SADD", "set"], ""),
+        (&["ZADD", "zset"], "1"),
+    ];
+    for (command, value) in creates {
+        let mut args: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+        for name in &names {
+            match command[0] {
+                "SADD" => args.push(name.clone()),
+                "ZADD" => args.extend([value.to_string(), name.clone()]),
+                _ => args.extend([name.clone(), value.to_string()]),
+            }
+        }
+        assert!(!is_error(&call(&mut client, &args).await, "ERR"));
+    }
+
+    // Each iteration adds and removes other elements between its steps.
+    let scans: [(&[&str], bool, &str, &str); 4] = [
+        (&["SCAN"], false, "SET {} v", "DEL {}"),
+        (&["HSCAN", "hash"], true, "HSET hash {} v", "HDEL hash {}"),
+        (&["SSCAN", "set"], false, "SADD set {}", "SREM set {}"),
+        (&["ZSCAN", "zset"], true, "ZADD zset 1 {}", "ZREM zset {}"),
+    ];
+    for (command, pairs, add, remove) in scans {
+        let mut seen = HashSet::new();
+        let mut cursor = "0".to_string();
+        let mut steps = 0;
+        loop {
+            let mut args: Vec<String> = command.iter().map(|arg| arg.to_string()).collect();
+            args.extend([cursor.clone(), "COUNT".to_string(), "10".to_string()]);
+            let RespValue::Array(reply) = call(&mut client, &args).await else {
+                panic!("{} replies with an array", command[0]);
+            };
+            let [RespValue::BulkString(next), RespValue::Array(elements)] = &reply[..] else {
+                panic!("{} replied {reply:?}", command[0]);
+            };
+            let names = elements.iter().step_by(if pairs { 2 } else { 1 });
+            seen.extend(names.map(|name| String::from(name.clone())));
+            steps += 1;
+
+            call(&mut client, &churn(add, steps)).await;
+            call(&mut client, &churn(remove, steps - 1)).await;
+
+            cursor = String::from_utf8(next.to_vec()).expect("a numeric cursor");
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert!(steps > 10, "{} took {steps} steps", command[0]);
+        for name in &names {
+            assert!(seen.contains(name), "{} missed {name}", command[0]);
+        }
+    }
+}
+
+#[tokio::test]
+async fn debug_sleep_on_a_current_thread_runtime() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["DEBUG", "SLEEP", "0.01"]).await, ok());
+    assert_eq!(client.call(&["DEBUG", "SLEEP", "0"]).await, ok());
+}
+
+#[tokio::test]
+async fn transactions() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert_eq!(
+        client.call(&["SET", "key", "1"]).await,
+        RespValue::SimpleString("QUEUED".to_string())
+    );
+    assert_eq!(
+        client.call(&["INCR", "key"]).await,
+        RespValue::SimpleString("QUEUED".to_string())
+    );
+    assert_eq!(
+        client.call(&["EXEC"]).await,
+        RespValue::Array(vec![ok(), int(2)])
+    );
+}
+
+#[tokio::test]
+async fn transaction_errors() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    let queued = RespValue::SimpleString("QUEUED".to_string());
+
+    // Unknown commands and wrong numbers of arguments discard the
+    // transaction when queued.
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert_eq!(client.call(&["SET", "key", "1"]).await, queued);
+    assert!(matches!(
+        client.call(&["NOSUCHCOMMAND"]).await,
+        RespValue::SimpleError(_)
+    ));
+    assert!(is_error(&client.call(&["EXEC"]).await, "EXECABORT"));
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert!(is_error(
+        &client.call(&["SET", "key"]).await,
+        "ERR wrong number"
+    ));
+    assert!(is_error(&client.call(&["EXEC"]).await, "EXECABORT"));
+    assert_eq!(
+        client.call(&["GET", "key"]).await,
+        RespValue::NullBulkString
+    );
+
+    // Other errors only show in the reply of EXEC, in place of the command
+    // that failed, and the rest still runs.
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert_eq!(client.call(&["SET", "key", "a"]).await, queued);
+    assert_eq!(
+        client.call(&["SET", "key", "b", "NOSUCHOPTION"]).await,
+        queued
+    );
+    assert_eq!(client.call(&["INCR", "key"]).await, queued);
+    assert_eq!(client.call(&["APPEND", "key", "c"]).await, queued);
+    let RespValue::Array(replies) = client.call(&["EXEC"]).await else {
+        panic!("EXEC replies with an array");
+    };
+    assert_eq!(replies.len(), 4);
+    assert_eq!(replies[0], ok());
+    assert!(is_error(&replies[1], "ERR syntax error"));
+    assert!(is_error(&replies[2], "ERR"));
+    assert_eq!(replies[3], int(2));
+    assert_eq!(client.call(&["GET", "key"]).await, bulk("ac"));
+}
+
+#[tokio::test]
+async fn errors() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert!(matches!(
+        client.call(&["NOSUCHCOMMAND"]).await,
+        RespValue::SimpleError(_)
+    ));
+    client.call(&["SET", "key", "value"]).await;
+    assert!(is_error(&client.call(&["INCR", "key"]).await, "ERR"));
+}
+
+#[tokio::test]
+async fn wrong_type_errors() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    client.call(&["SET", "string", "value"]).await;
+    client.call(&["RPUSH", "list", "a"]).await;
+    client
+        .call(&["XADD", "stream", "1-1", "field", "value"])
+        .await;
+    client.call(&["SADD", "set", "member"]).await;
+    for command in [
+        &["RPUSH", "string", "a"][..],
+        &["LPOP", "string"],
+        &["BLPOP", "string", "0"],
+        &["LLEN", "stream"],
+        &["LRANGE", "string", "0", "-1"],
+        &["GET", "list"],
+        &["INCR", "list"],
+        &["XLEN", "list"],
+        &["HGET", "set", "field"],
+        &["SADD", "list", "member"],
+        &["ZADD", "set", "1", "member"],
+        &["SMOVE", "set", "list", "member"],
+    ] {
+        assert_eq!(
+            client.call(command).await,
+            RespValue::SimpleError(
+                "WRONGTYPE Operation against a key holding the wrong kind of value".to_string()
+            ),
+            "{command:?}"
+        );
+    }
+    // MGET replies nil for keys that are not strings.
+    assert_eq!(
+        client.call(&["MGET", "list", "string"]).await,
+        RespValue::Array(vec![RespValue::NullBulkString, bulk("value")])
+    );
+}
+
+#[tokio::test]
+async fn error_messages() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    let error = |message: &str| RespValue::SimpleError(message.to_string());
+    assert_eq!(
+        client.call(&["GET"]).await,
+        error("ERR wrong number of arguments for 'get' command")
+    );
+    assert_eq!(
+        client.call(&["RPUSH", "list"]).await,
+        error("ERR wrong number of arguments for 'rpush' command")
+    );
+    assert_eq!(
+        client.call(&["OBJECT", "ENCODING"]).await,
+        error("ERR wrong number of arguments for 'object|encoding' command")
+    );
+    assert_eq!(
+        client.call(&["NOSUCHCOMMAND", "a", "b"]).await,
+        error("ERR unknown command 'NOSUCHCOMMAND', with args beginning with: 'a' 'b' ")
+    );
+    assert_eq!(
+        client.call(&["PUBSUB", "nosuch"]).await,
+        error("ERR unknown subcommand 'nosuch'. Try PUBSUB HELP.")
+    );
+    assert_eq!(
+        client
+            .call(&["ZRANGE", "zset", "0", "1", "NOSUCHOPTION"])
+            .await,
+        error("ERR syntax error")
+    );
+    assert_eq!(
+        client.call(&["INCRBY", "counter", "a"]).await,
+        error("ERR value is not an integer or out of range")
+    );
+    assert_eq!(
+        client
+            .call(&["XREAD", "BLOCK", "abc", "STREAMS", "stream", "0"])
+            .await,
+        error("ERR timeout is not an integer or out of range")
+    );
+    assert_eq!(
+        client.call(&["XREAD", "STREAMS", "a", "b", "0"]).await,
+        error(
+            "ERR Unbalanced 'xread' list of streams: for each stream key an ID or '$' must be specified."
+        )
+    );
+    assert_eq!(
+        client.call(&["XADD", "stream", "*", "field"]).await,
+        error("ERR wrong number of arguments for 'xadd' command")
+    );
+    assert_eq!(
+        client
+            .call(&["HSET", "hash", "field", "value", "other"])
+            .await,
+        error("ERR wrong number of arguments for 'hset' command")
+    );
+    assert_eq!(
+        client
+            .call(&["PEXPIRE", "key", "9223372036854775807"])
+            .await,
+        error("ERR invalid expire time in 'pexpire' command")
+    );
+}
+
+#[tokio::test]
+async fn pubsub() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut publisher = server.connect();
+    assert_eq!(
+        subscriber.call(&["SUBSCRIBE", "news"]).await,
+        RespValue::Array(vec![bulk("subscribe"), bulk("news"), int(1)])
+    );
+    assert_eq!(publisher.call(&["PUBLISH", "news", "hello"]).await, int(1));
+    assert_eq!(
+        subscriber.read().await,
+        array(&["message", "news", "hello"])
+    );
+}
+
+/// Connects as `username`, whose password is `password`.
+async fn login(server: &TestServer, username: &str) -> TestClient {
+    let mut client = server.connect();
+    assert_eq!(client.call(&["AUTH", username, "password"]).await, ok());
+    client
+}
+
+#[tokio::test]
+async fn acl_categories() {
+    let server = TestServer::start();
+    let mut admin = server.connect();
+    assert_eq!(admin.call(&["SET", "key", "value"]).await, ok());
+    assert_eq!(
+        admin
+            .call(&[
+                "ACL",
+                "SETUSER",
+                "reader",
+                "on",
+                ">password",
+                "~*",
+                "+@read"
+            ])
+            .await,
+        ok()
+    );
+    assert_eq!(
+        admin
+            .call(&[
+                "ACL",
+                "SETUSER",
+                "writer",
+                "on",
+                ">password",
+                "~*",
+                "+@all",
+                "-@dangerous",
+                "-del",
+            ])
+            .await,
+        ok()
+    );
+
+    let mut reader = login(&server, "reader").await;
+    assert_eq!(reader.call(&["GET", "key"]).await, bulk("value"));
+    assert_eq!(reader.call(&["EXISTS", "key"]).await, int(1));
+    let reply = reader.call(&["SET", "key", "other"]).await;
+    assert!(
+        matches!(&reply, RespValue::SimpleError(error) if error == "NOPERM User reader has no permissions to run the 'set' command"),
+        "{reply:?}"
+    );
+    assert!(is_error(&reader.call(&["PERSIST", "key"]).await, "NOPERM"));
+    assert!(is_error(
+        &reader.call(&["HSET", "hash", "f", "v"]).await,
+        "NOPERM"
+    ));
+
+    let mut writer = login(&server, "writer").await;
+    assert_eq!(writer.call(&["SET", "key", "other"]).await, ok());
+    assert_eq!(writer.call(&["PERSIST", "key"]).await, int(0));
+    assert!(is_error(&writer.call(&["DEL", "key"]).await, "NOPERM"));
+    assert!(is_error(&writer.call(&["FLUSHALL"]).await, "NOPERM"));
+    // Subcommands are checked against their own categories.
+    assert!(matches!(
+        writer.call(&["CLIENT", "ID"]).await,
+        RespValue::Integer(_)
+    ));
+    let reply = writer.call(&["CLIENT", "KILL", "ID", "1"]).await;
+    assert!(
+        matches!(&reply, RespValue::SimpleError(error) if error.ends_with("'client|kill' command")),
+        "{reply:?}"
+    );
+
+    assert!(is_error(
+        &admin
+            .call(&["ACL", "SETUSER", "reader", "+@nosuchcategory"])
+            .await,
+        "ERR"
+    ));
+    assert_eq!(
+        admin.call(&["ACL", "SETUSER", "reader", "+@write"]).await,
+        ok()
+    );
+    assert_eq!(reader.call(&["SET", "key", "again"]).await, ok());
+}
+
+#[tokio::test]
+async fn acl_key_patterns() {
+    let server = TestServer::start();
+    let mut admin = server.connect();
+    assert_eq!(
+        admin
+            .call(&[
+                "ACL",
+                "SETUSER",
+                "cache",
+                "on",
+                ">password",
+                "~cache:*",
+                "+@all"
+            ])
+            .await,
+        ok()
+    );
+    let mut cache = login(&server, "cache").await;
+    assert_eq!(cache.call(&["SET", "cache:a", "value"]).await, ok());
+    assert_eq!(
+        cache.call(&["MGET", "cache:a", "cache:b"]).await,
+        RespValue::Array(vec![bulk("value"), RespValue::NullBulkString])
+    );
+    assert_eq!(
+        cache.call(&["SET", "other", "value"]).await,
+        RespValue::SimpleError("NOPERM No permissions to access a key".to_string())
+    );
+    assert!(is_error(
+        &cache.call(&["MGET", "cache:a", "other"]).await,
+        "NOPERM"
+    ));
+    assert_eq!(admin.call(&["EXISTS", "other"]).await, int(0));
+}
+
+#[tokio::test]
+async fn in_process_client() {
+    let server = TestServer::start();
+    let mut client = server.server().connect();
+    assert_eq!(client.call(["SET", "key", "value"]).await, Some(ok()));
+    assert_eq!(client.call(["GET", "key"]).await, Some(bulk("value")));
+    let mut remote = server.connect();
+    assert_eq!(remote.call(&["GET", "key"]).await, bulk("value"));
+    client.close().await;
+}
+
+#[tokio::test]
+async fn unix_socket() {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
+
+    let server = TestServer::start();
+    let (mut client, served) = UnixStream::pair().expect("socket pair");
+    tokio::spawn(server.server().serve(served));
+    client.write_all(b"*1\r\n$4\r\nPING\r\n").await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+}
@@ -0,0 +1,161 @@
+//! Runs the server in process for end-to-end tests: clients talk RESP to it
+//! over in-memory duplex streams instead of sockets.
+
+#![allow(dead_code)]
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use bytes::BytesMut;
+use codecrafters_redis::{
+    config::Config,
+    resp::{Codec, Decoded, RespValue},
+    server::Server,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream, duplex},
+    time::timeout,
+};
+
+/// How long a reply may take before a test fails rather than hangs.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TestServer {
+    server: Server,
+    config: Config,
+    dir: PathBuf,
+}
+
+impl TestServer {
+    /// Starts a server with an empty dataset, in a directory of its own and
+    /// without save points.
+    pub fn start() -> Self {
+        Self::with_config(Config::default())
+    }
+
+    pub fn with_config(mut config: Config) -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "redis-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).expect("test directory");
+        config.dir = dir.clone();
+        config.save.clear();
+        Self {
+            server: Server::new(config.clone()),
+            config,
+            dir,
+        }
+    }
+
+    /// Replaces the server with a fresh one on the same directory, as a
+    /// restart would, so that it loads whatever the previous one persisted.
+    pub fn restart(&mut self) {
+        self.server = Server::new(self.config.clone());
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn server(&self) -> &Server {
+        &self.server
+    }
+
+    /// Connects a client, served by a task of its own.
+    pub fn connect(&self) -> TestClient {
+        let (client, server) = duplex(64 * 1024);
+        tokio::spawn(self.server.serve(server));
+        TestClient {
+            stream: client,
+            buffer: BytesMut::new(),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub struct TestClient {
+    stream: DuplexStream,
+    buffer: BytesMut,
+}
+
+impl TestClient {
+    /// Sends a command without waiting for its reply, to pipeline several.
+    pub async fn send(&mut self, args: &[&str]) {
+        let command = RespValue::Array(
+            args.iter()
+                .map(|arg| RespValue::BulkString(arg.to_string().into()))
+                .collect(),
+        );
+        self.stream
+            .write_all(&command.serialize())
+            .await
+            .expect("server closed the connection");
+    }
+
+    /// Reads the next reply or pushed frame.
+    pub async fn read(&mut self) -> RespValue {
+        timeout(REPLY_TIMEOUT, async {
+            loop {
+                match Codec::replies().decode(&mut self.buffer) {
+                    Decoded::Complete(value, _) => return value,
+                    Decoded::Incomplete => {}
+                    Decoded::Error(e) => panic!("malformed reply: {e}"),
+                }
+                let read = self
+                    .stream
+                    .read_buf(&mut self.buffer)
+                    .await
+                    .expect("read failed");
+                assert!(read > 0, "server closed the connection");
+            }
+        })
+        .await
+        .expect("timed out waiting for a reply")
+    }
+
+    /// Whether a frame arrives within `wait`.
+    pub async fn has_reply_within(&mut self, wait: Duration) -> bool {
+        !self.buffer.is_empty()
+            || timeout(wait, self.stream.read_buf(&mut self.buffer))
+                .await
+                .is_ok()
+    }
+
+    pub async fn call(&mut self, args: &[&str]) -> RespValue {
+        self.send(args).await;
+        self.read().await
+    }
+}
+
+pub fn ok() -> RespValue {
+    RespValue::SimpleString("OK".to_string())
+}
+
+pub fn bulk(value: &str) -> RespValue {
+    RespValue::BulkString(value.to_string().into())
+}
+
+pub fn int(value: i64) -> RespValue {
+    RespValue::Integer(value)
+}
+
+pub fn array(items: &[&str]) -> RespValue {
+    RespValue::Array(items.iter().map(|item| bulk(item)).collect())
+}
+
+/// Whether `reply` is an error starting with `prefix`.
+pub fn is_error(reply: &RespValue, prefix: &str) -> bool {
+    matches!(reply, RespValue::SimpleError(error) if error.starts_with(prefix))
+}
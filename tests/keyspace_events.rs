@@ -0,0 +1,122 @@
+mod common;
+
+use std::time::Duration;
+
+use codecrafters_redis::db::{EventClass, KeyspaceEvent};
+use codecrafters_redis::resp::RespValue;
+use common::{TestServer, array, int, ok};
+use tokio::{sync::broadcast::Receiver, time::timeout};
+
+async fn next_event(events: &mut Receiver<KeyspaceEvent>) -> (EventClass, &'static str, String) {
+    let event = timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("no keyspace event")
+        .expect("keyspace events closed");
+    (
+        event.class,
+        event.event,
+        String::from_utf8(event.key.to_vec()).expect("UTF-8 key"),
+    )
+}
+
+#[tokio::test]
+async fn changes_are_received_without_notify_keyspace_events() {
+    let server = TestServer::start();
+    let mut events = server
+        .server()
+        .db()
+        .read()
+        .await
+        .subscribe_keyspace_events();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["SET", "key", "value"]).await, ok());
+    assert_eq!(client.call(&["RPUSH", "list", "a", "b"]).await, int(2));
+    assert_eq!(client.call(&["DEL", "key"]).await, int(1));
+
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::New, "new", "key".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::String, "set", "key".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::New, "new", "list".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::List, "rpush", "list".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::Generic, "del", "key".to_string())
+    );
+}
+
+#[tokio::test]
+async fn expirations_are_received() {
+    let server = TestServer::start();
+    let mut events = server
+        .server()
+        .db()
+        .read()
+        .await
+        .subscribe_keyspace_events();
+    let mut client = server.connect();
+    assert_eq!(
+        client.call(&["SET", "key", "value", "PX", "20"]).await,
+        ok()
+    );
+
+    assert_eq!(next_event(&mut events).await.1, "new");
+    assert_eq!(next_event(&mut events).await.1, "set");
+    assert_eq!(next_event(&mut events).await.1, "expire");
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::Expired, "expired", "key".to_string())
+    );
+}
+
+#[tokio::test]
+async fn serving_a_blocked_client_fires_a_pop() {
+    let server = TestServer::start();
+    let mut events = server
+        .server()
+        .db()
+        .read()
+        .await
+        .subscribe_keyspace_events();
+    let mut blocked = server.connect();
+    let mut timed_out = server.connect();
+    let mut pusher = server.connect();
+
+    assert_eq!(
+        timed_out.call(&["BLPOP", "list", "0.01"]).await,
+        RespValue::NullArray
+    );
+    blocked.send(&["BLPOP", "list", "0"]).await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(pusher.call(&["LPUSH", "list", "a", "b"]).await, int(2));
+    assert_eq!(blocked.read().await, array(&["list", "b"]));
+    assert_eq!(
+        pusher.call(&["LRANGE", "list", "0", "-1"]).await,
+        array(&["a"])
+    );
+
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::New, "new", "list".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::List, "lpush", "list".to_string())
+    );
+    assert_eq!(
+        next_event(&mut events).await,
+        (EventClass::List, "lpop", "list".to_string())
+    );
+    // Nothing for the client that timed out, nor pushes of elements back.
+    assert!(events.try_recv().is_err());
+}
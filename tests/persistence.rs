@@ -0,0 +1,126 @@
+mod common;
+
+use std::time::Duration;
+
+use codecrafters_redis::{config::Config, resp::RespValue};
+use common::{TestClient, TestServer, array, bulk, int, ok};
+
+fn appendonly() -> TestServer {
+    TestServer::with_config(Config {
+        appendonly: true,
+        ..Config::default()
+    })
+}
+
+/// The remaining time to live of `key` in seconds, as `TTL` reports it.
+async fn ttl(client: &mut TestClient, key: &str) -> i64 {
+    match client.call(&["TTL", key]).await {
+        RespValue::Integer(ttl) => ttl,
+        reply => panic!("unexpected TTL reply {reply:?}"),
+    }
+}
+
+/// Waits for a `BGREWRITEAOF` to finish.
+async fn wait_for_rewrite(client: &mut TestClient) {
+    loop {
+        let info = String::from(client.call(&["INFO", "persistence"]).await);
+        if info.contains("aof_rewrite_in_progress:0") {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn writes_are_replayed_from_the_append_only_file() {
+    let mut server = appendonly();
+    let mut client = server.connect();
+    assert_eq!(
+        client.call(&["SET", "string", "value", "EX", "100"]).await,
+        ok()
+    );
+    assert_eq!(client.call(&["RPUSH", "list", "a", "b", "c"]).await, int(3));
+    assert_eq!(client.call(&["LPOP", "list"]).await, bulk("a"));
+    assert_eq!(
+        client
+            .call(&["SET", "persisted", "value", "EX", "100"])
+            .await,
+        ok()
+    );
+    assert_eq!(client.call(&["PERSIST", "persisted"]).await, int(1));
+    assert_eq!(client.call(&["SET", "deleted", "value"]).await, ok());
+    assert_eq!(client.call(&["DEL", "deleted"]).await, int(1));
+    drop(client);
+
+    server.restart();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["GET", "string"]).await, bulk("value"));
+    assert!((98..=100).contains(&ttl(&mut client, "string").await));
+    assert_eq!(
+        client.call(&["LRANGE", "list", "0", "-1"]).await,
+        array(&["b", "c"])
+    );
+    assert_eq!(client.call(&["GET", "persisted"]).await, bulk("value"));
+    assert_eq!(ttl(&mut client, "persisted").await, -1);
+    assert_eq!(client.call(&["EXISTS", "deleted"]).await, int(0));
+}
+
+#[tokio::test]
+async fn a_rewritten_append_only_file_is_replayed() {
+    let mut server = appendonly();
+    let aof = server.dir().join(Config::default().appendfilename);
+    let mut client = server.connect();
+    for _ in 0..100 {
+        client.call(&["INCR", "counter"]).await;
+    }
+    assert_eq!(
+        client.call(&["SET", "string", "value", "EX", "100"]).await,
+        ok()
+    );
+    assert_eq!(client.call(&["RPUSH", "list", "a", "b"]).await, int(2));
+    assert_eq!(
+        client.call(&["HSET", "hash", "field", "value"]).await,
+        int(1)
+    );
+    assert_eq!(client.call(&["PEXPIRE", "hash", "100000"]).await, int(1));
+    assert_eq!(client.call(&["SADD", "set", "member"]).await, int(1));
+    assert_eq!(
+        client.call(&["ZADD", "zset", "1.5", "member"]).await,
+        int(1)
+    );
+    assert_eq!(
+        client
+            .call(&["XADD", "stream", "1-1", "field", "value"])
+            .await,
+        bulk("1-1")
+    );
+    let before = std::fs::metadata(&aof).unwrap().len();
+
+    assert_eq!(
+        client.call(&["BGREWRITEAOF"]).await,
+        RespValue::SimpleString("Background append only file rewriting started".to_string())
+    );
+    wait_for_rewrite(&mut client).await;
+    assert!(std::fs::metadata(&aof).unwrap().len() < before);
+    assert_eq!(client.call(&["SET", "after", "rewrite"]).await, ok());
+    drop(client);
+
+    server.restart();
+    let mut client = server.connect();
+    assert_eq!(client.call(&["GET", "counter"]).await, bulk("100"));
+    assert_eq!(client.call(&["GET", "string"]).await, bulk("value"));
+    assert!((98..=100).contains(&ttl(&mut client, "string").await));
+    assert_eq!(
+        client.call(&["LRANGE", "list", "0", "-1"]).await,
+        array(&["a", "b"])
+    );
+    assert_eq!(client.call(&["HGET", "hash", "field"]).await, bulk("value"));
+    assert!((98..=100).contains(&ttl(&mut client, "hash").await));
+    assert_eq!(client.call(&["SMEMBERS", "set"]).await, array(&["member"]));
+    assert_eq!(
+        client.call(&["ZSCORE", "zset", "member"]).await,
+        bulk("1.5")
+    );
+    assert_eq!(client.call(&["XLEN", "stream"]).await, int(1));
+    assert_eq!(client.call(&["GET", "after"]).await, bulk("rewrite"));
+}
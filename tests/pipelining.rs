@@ -0,0 +1,67 @@
+mod common;
+
+use common::{TestServer, array, bulk, int, ok};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, duplex};
+
+#[tokio::test]
+async fn replies_come_in_order() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    for index in 0..100 {
+        client
+            .send(&["SET", &format!("key:{index}"), &index.to_string()])
+            .await;
+        client.send(&["INCR", &format!("key:{index}")]).await;
+    }
+    for index in 0..100 {
+        assert_eq!(client.read().await, ok());
+        assert_eq!(client.read().await, int(index + 1));
+    }
+    assert_eq!(client.call(&["GET", "key:99"]).await, bulk("100"));
+}
+
+#[tokio::test]
+async fn empty_requests_get_no_reply() {
+    let server = TestServer::start();
+    let (mut client, served) = duplex(1024);
+    tokio::spawn(server.server().serve(served));
+    client.write_all(b"*0\r\n*-1\r\nPING\r\n").await.unwrap();
+    let mut reply = [0; 7];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"+PONG\r\n");
+    client.write_all(b"*0\r\nECHO done\r\n").await.unwrap();
+    let mut reply = [0; 10];
+    client.read_exact(&mut reply).await.unwrap();
+    assert_eq!(&reply, b"$4\r\ndone\r\n");
+}
+
+#[tokio::test]
+async fn clients_see_each_other_writes() {
+    let server = TestServer::start();
+    let mut writer = server.connect();
+    let mut reader = server.connect();
+    writer.send(&["SET", "shared", "1"]).await;
+    writer.send(&["INCRBY", "shared", "41"]).await;
+    assert_eq!(writer.read().await, ok());
+    assert_eq!(writer.read().await, int(42));
+    assert_eq!(reader.call(&["GET", "shared"]).await, bulk("42"));
+}
+
+#[tokio::test]
+async fn large_replies_are_streamed_in_order() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    let elements: Vec<String> = (0..50_000)
+        .map(|index| format!("element:{index}"))
+        .collect();
+    for chunk in elements.chunks(1000) {
+        let mut args = vec!["RPUSH", "big"];
+        args.extend(chunk.iter().map(String::as_str));
+        client.call(&args).await;
+    }
+    client.send(&["LRANGE", "big", "0", "-1"]).await;
+    client.send(&["LLEN", "big"]).await;
+    let expected: Vec<&str> = elements.iter().map(String::as_str).collect();
+    assert_eq!(client.read().await, array(&expected));
+    assert_eq!(client.read().await, int(50_000));
+}
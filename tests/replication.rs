@@ -0,0 +1,137 @@
+mod common;
+
+use std::time::Duration;
+
+use codecrafters_redis::config::Config;
+use codecrafters_redis::resp::RespValue;
+use common::{TestClient, TestServer, bulk, int, is_error, ok};
+
+/// A replica of a master that never answers, so that nothing but its own
+/// clients touch its dataset.
+fn replica() -> TestServer {
+    TestServer::with_config(Config {
+        replicaof: Some(("127.0.0.1".to_string(), 1)),
+        ..Config::default()
+    })
+}
+
+/// The `expired_keys` of `INFO stats`.
+async fn expired_keys(client: &mut TestClient) -> u64 {
+    let info = String::from(client.call(&["INFO", "stats"]).await);
+    info.lines()
+        .find_map(|line| line.strip_prefix("expired_keys:"))
+        .expect("expired_keys in INFO")
+        .trim()
+        .parse()
+        .expect("a number")
+}
+
+#[tokio::test]
+async fn replicas_refuse_writes_from_clients() {
+    let server = replica();
+    let mut client = server.connect();
+    assert!(is_error(
+        &client.call(&["SET", "key", "value"]).await,
+        "READONLY"
+    ));
+    assert_eq!(
+        client.call(&["GET", "key"]).await,
+        RespValue::NullBulkString
+    );
+    assert!(is_error(
+        &client.call(&["PERSIST", "key"]).await,
+        "READONLY"
+    ));
+
+    assert_eq!(client.call(&["MULTI"]).await, ok());
+    assert!(is_error(
+        &client.call(&["INCR", "counter"]).await,
+        "READONLY"
+    ));
+    assert!(is_error(&client.call(&["EXEC"]).await, "EXECABORT"));
+
+    let reply = client
+        .call(&["EVAL", "return redis.call('SET', 'key', 'value')", "0"])
+        .await;
+    assert!(matches!(reply, RespValue::SimpleError(error) if error.contains("READONLY")));
+}
+
+#[tokio::test]
+async fn replicas_accept_writes_unless_read_only() {
+    let server = replica();
+    let mut client = server.connect();
+    assert_eq!(
+        client
+            .call(&["CONFIG", "SET", "replica-read-only", "no"])
+            .await,
+        ok()
+    );
+    assert_eq!(client.call(&["SET", "key", "value"]).await, ok());
+}
+
+#[tokio::test]
+async fn replicas_leave_expired_keys_to_their_master() {
+    let server = replica();
+    let mut client = server.connect();
+    assert_eq!(
+        client
+            .call(&["CONFIG", "SET", "replica-read-only", "no"])
+            .await,
+        ok()
+    );
+    assert_eq!(
+        client.call(&["SET", "kept", "value", "PX", "10"]).await,
+        ok()
+    );
+    assert_eq!(
+        client.call(&["SET", "written", "value", "PX", "10"]).await,
+        ok()
+    );
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // Reads see expired keys as missing without deleting them.
+    assert_eq!(
+        client.call(&["GET", "kept"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(client.call(&["EXISTS", "kept", "written"]).await, int(0));
+    assert_eq!(expired_keys(&mut client).await, 0);
+
+    // Writes of a writable replica delete them.
+    assert_eq!(client.call(&["APPEND", "written", "new"]).await, int(3));
+    assert_eq!(client.call(&["GET", "written"]).await, bulk("new"));
+    assert_eq!(expired_keys(&mut client).await, 1);
+}
+
+#[tokio::test]
+async fn role_reports_masters_and_replicas() {
+    let server = TestServer::start();
+    let mut client = server.connect();
+    assert_eq!(
+        client.call(&["ROLE"]).await,
+        RespValue::Array(vec![bulk("master"), int(0), RespValue::Array(vec![])])
+    );
+
+    let server = replica();
+    let mut client = server.connect();
+    let RespValue::Array(role) = client.call(&["ROLE"]).await else {
+        panic!("ROLE replies with an array");
+    };
+    assert_eq!(role[..3], [bulk("slave"), bulk("127.0.0.1"), int(1)]);
+    assert!(matches!(&role[3], RespValue::BulkString(state) if state.starts_with(b"connect")));
+    assert_eq!(role[4], int(-1));
+
+    let info = String::from(client.call(&["INFO", "replication"]).await);
+    for field in [
+        "role:slave",
+        "master_host:127.0.0.1",
+        "master_port:1",
+        "master_link_status:down",
+        "master_last_io_seconds_ago:-1",
+        "master_link_down_since_seconds:",
+        "slave_read_only:1",
+        "connected_slaves:0",
+    ] {
+        assert!(info.contains(field), "{field} missing from {info}");
+    }
+}
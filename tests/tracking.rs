@@ -0,0 +1,221 @@
+mod common;
+
+use std::time::Duration;
+
+use codecrafters_redis::resp::RespValue;
+use common::{TestClient, TestServer, array, bulk, int, is_error, ok};
+
+fn invalidate(keys: RespValue) -> RespValue {
+    RespValue::Push(vec![bulk("invalidate"), keys])
+}
+
+async fn resp3_client(server: &TestServer) -> TestClient {
+    let mut client = server.connect();
+    client.call(&["HELLO", "3"]).await;
+    client
+}
+
+async fn client_id(client: &mut TestClient) -> String {
+    let RespValue::Integer(id) = client.call(&["CLIENT", "ID"]).await else {
+        panic!("CLIENT ID is not an integer");
+    };
+    id.to_string()
+}
+
+#[tokio::test]
+async fn keys_read_are_invalidated_once() {
+    let server = TestServer::start();
+    let mut tracked = resp3_client(&server).await;
+    let mut other = server.connect();
+    assert_eq!(tracked.call(&["CLIENT", "TRACKING", "ON"]).await, ok());
+    assert_eq!(other.call(&["SET", "key", "1"]).await, ok());
+    assert_eq!(tracked.call(&["GET", "key"]).await, bulk("1"));
+
+    assert_eq!(other.call(&["SET", "key", "2"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(array(&["key"])));
+
+    // The key is forgotten until it is read again.
+    assert_eq!(other.call(&["SET", "key", "3"]).await, ok());
+    assert!(!tracked.has_reply_within(Duration::from_millis(100)).await);
+    assert_eq!(tracked.call(&["GET", "key"]).await, bulk("3"));
+    assert_eq!(other.call(&["DEL", "key"]).await, int(1));
+    assert_eq!(tracked.read().await, invalidate(array(&["key"])));
+
+    assert_eq!(
+        tracked.call(&["GET", "key"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(other.call(&["FLUSHALL"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(RespValue::NullBulkString));
+}
+
+#[tokio::test]
+async fn invalidations_are_redirected_to_resp2_subscribers() {
+    let server = TestServer::start();
+    let mut subscriber = server.connect();
+    let mut tracked = server.connect();
+    let mut other = server.connect();
+    let id = client_id(&mut subscriber).await;
+    subscriber
+        .call(&["SUBSCRIBE", "__redis__:invalidate"])
+        .await;
+    assert_eq!(
+        tracked
+            .call(&["CLIENT", "TRACKING", "ON", "REDIRECT", &id])
+            .await,
+        ok()
+    );
+    assert_eq!(
+        tracked.call(&["CLIENT", "GETREDIR"]).await,
+        int(id.parse().unwrap())
+    );
+    assert_eq!(
+        tracked.call(&["GET", "key"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(other.call(&["SET", "key", "1"]).await, ok());
+
+    assert_eq!(
+        subscriber.read().await,
+        RespValue::Array(vec![
+            bulk("message"),
+            bulk("__redis__:invalidate"),
+            array(&["key"]),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn tracking_options() {
+    let server = TestServer::start();
+    let mut tracked = resp3_client(&server).await;
+    assert!(is_error(
+        &tracked
+            .call(&["CLIENT", "TRACKING", "ON", "OPTIN", "OPTOUT"])
+            .await,
+        "ERR You can't use both OPTIN and OPTOUT"
+    ));
+    assert!(is_error(
+        &tracked
+            .call(&["CLIENT", "TRACKING", "ON", "REDIRECT", "1000"])
+            .await,
+        "ERR"
+    ));
+    assert_eq!(tracked.call(&["CLIENT", "GETREDIR"]).await, int(-1));
+
+    // With NOLOOP the keys the client changes itself are not invalidated.
+    assert_eq!(
+        tracked.call(&["CLIENT", "TRACKING", "ON", "NOLOOP"]).await,
+        ok()
+    );
+    assert_eq!(
+        tracked.call(&["GET", "key"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(tracked.call(&["SET", "key", "1"]).await, ok());
+    assert!(!tracked.has_reply_within(Duration::from_millis(100)).await);
+
+    // With OPTIN only the reads following CLIENT CACHING yes are tracked.
+    assert_eq!(tracked.call(&["CLIENT", "TRACKING", "OFF"]).await, ok());
+    assert_eq!(
+        tracked.call(&["CLIENT", "TRACKING", "ON", "OPTIN"]).await,
+        ok()
+    );
+    let mut other = server.connect();
+    assert_eq!(
+        tracked.call(&["GET", "untracked"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(tracked.call(&["CLIENT", "CACHING", "YES"]).await, ok());
+    assert_eq!(
+        tracked.call(&["GET", "tracked"]).await,
+        RespValue::NullBulkString
+    );
+    assert_eq!(other.call(&["SET", "untracked", "1"]).await, ok());
+    assert_eq!(other.call(&["SET", "tracked", "1"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(array(&["tracked"])));
+
+    assert_eq!(
+        tracked.call(&["CLIENT", "TRACKINGINFO"]).await,
+        RespValue::Map(vec![
+            (
+                bulk("flags"),
+                RespValue::Set(vec![bulk("on"), bulk("optin")])
+            ),
+            (bulk("redirect"), int(0)),
+            (bulk("prefixes"), RespValue::Array(vec![])),
+        ])
+    );
+}
+
+#[tokio::test]
+async fn bcast_invalidates_keys_under_prefixes() {
+    let server = TestServer::start();
+    let mut tracked = resp3_client(&server).await;
+    let mut other = server.connect();
+    assert!(is_error(
+        &tracked
+            .call(&["CLIENT", "TRACKING", "ON", "PREFIX", "user:"])
+            .await,
+        "ERR PREFIX option requires BCAST mode to be enabled"
+    ));
+    assert!(is_error(
+        &tracked
+            .call(&[
+                "CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "a", "PREFIX", "ab"
+            ])
+            .await,
+        "ERR Prefix 'a' overlaps with another provided prefix 'ab'"
+    ));
+    assert_eq!(
+        tracked
+            .call(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:"])
+            .await,
+        ok()
+    );
+    assert_eq!(
+        tracked
+            .call(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "session:"])
+            .await,
+        ok()
+    );
+
+    // Keys are invalidated without having been read, every time they change.
+    assert_eq!(other.call(&["SET", "user:1", "a"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(array(&["user:1"])));
+    assert_eq!(other.call(&["SET", "user:1", "b"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(array(&["user:1"])));
+    assert_eq!(other.call(&["SET", "other", "a"]).await, ok());
+    assert_eq!(other.call(&["DEL", "session:1"]).await, int(0));
+    assert_eq!(other.call(&["SET", "session:1", "a"]).await, ok());
+    assert_eq!(tracked.read().await, invalidate(array(&["session:1"])));
+    // The keys a command changes are sent together once it ran.
+    assert_eq!(
+        other
+            .call(&["MSET", "user:2", "a", "user:3", "b", "other", "c"])
+            .await,
+        ok()
+    );
+    assert_eq!(
+        tracked.read().await,
+        invalidate(array(&["user:2", "user:3"]))
+    );
+
+    assert!(is_error(
+        &tracked
+            .call(&["CLIENT", "TRACKING", "ON", "BCAST", "PREFIX", "user:1"])
+            .await,
+        "ERR Prefix 'user:1' overlaps with an existing prefix 'user:'"
+    ));
+    assert_eq!(
+        tracked.call(&["CLIENT", "TRACKINGINFO"]).await,
+        RespValue::Map(vec![
+            (
+                bulk("flags"),
+                RespValue::Set(vec![bulk("on"), bulk("bcast")])
+            ),
+            (bulk("redirect"), int(0)),
+            (bulk("prefixes"), array(&["user:", "session:"])),
+        ])
+    );
+}